@@ -1,6 +1,9 @@
 use clap::{ ArgGroup, Parser, Subcommand };
 use lazy_static::lazy_static;
 use parking_lot::Mutex;
+use std::time::Duration;
+
+use crate::{ daemon, metadata };
 
 const MAX_CONSECUTIVE: &str = "40";
 const DEFAULT_LANG: &str = "en";
@@ -20,6 +23,9 @@ lazy_static! {
     /// Indicates whether the `quiet` mode is enabled.
     pub(crate) static ref ARGS_QUIET: bool = ARGS.lock().quiet;
 
+    /// Indicates whether chapters are only reported instead of downloaded.
+    pub(crate) static ref ARGS_DRY_RUN: bool = ARGS.lock().dry_run;
+
     /// Indicates whether logging is enabled.
     pub(crate) static ref ARGS_LOG: bool = ARGS.lock().log;
 
@@ -35,6 +41,9 @@ lazy_static! {
     /// The current working directory as specified by the user.
     pub(crate) static ref ARGS_CWD: String = ARGS.lock().cwd.clone();
 
+    /// Indicates whether files of the program are kept next to the executable.
+    pub(crate) static ref ARGS_PORTABLE: bool = ARGS.lock().portable;
+
     /// Indicates whether the database sorting is disabled.
     pub(crate) static ref ARGS_UNSORTED: bool = ARGS.lock().unsorted;
 
@@ -62,6 +71,168 @@ lazy_static! {
     /// Indicates whether the server mode is enabled.
     pub(crate) static ref ARGS_SERVER: bool = ARGS.lock().server;
 
+    /// Indicates whether to recompress all downloaded files.
+    pub(crate) static ref ARGS_RECOMPRESS: bool = match ARGS.lock().subcommands {
+        Some(Commands::Database { recompress, .. }) => recompress,
+        Some(_) => false,
+        None => false,
+    };
+
+    /// Format of the output of database commands; `table`, `json` or `ndjson`.
+    pub(crate) static ref ARGS_OUTPUT: String = match ARGS.lock().subcommands {
+        Some(Commands::Database { ref output, .. }) => output.clone(),
+        Some(_) => String::from("table"),
+        None => String::from("table"),
+    };
+
+    /// The number of manga whose chapters are fetched at once by `--check` and `--update`.
+    pub(crate) static ref ARGS_PARALLEL: usize = match ARGS.lock().subcommands {
+        Some(Commands::Database { parallel, .. }) => parallel as usize,
+        Some(Commands::Daemon { parallel, .. }) => parallel as usize,
+        Some(_) => 1,
+        None => 1,
+    };
+
+    /// The time between updates of the daemon and the most that is randomly added to it, if the daemon is run.
+    pub(crate) static ref ARGS_DAEMON: Option<(Duration, Duration)> = match ARGS.lock().subcommands {
+        Some(Commands::Daemon { every, jitter, .. }) => Some((every, jitter)),
+        Some(_) => None,
+        None => None,
+    };
+
+    /// The command sent to a running instance with `ctl`, if any.
+    pub(crate) static ref ARGS_CTL: Option<CtlCommand> = match ARGS.lock().subcommands {
+        Some(Commands::Ctl { ref command }) => Some(command.clone()),
+        Some(_) => None,
+        None => None,
+    };
+
+    /// Indicates whether every manga is checked with all of its chapters.
+    pub(crate) static ref ARGS_FULL: bool = match ARGS.lock().subcommands {
+        Some(Commands::Database { full, .. }) => full,
+        Some(_) => false,
+        None => false,
+    };
+
+    /// The ID of the run whose logs are shown, if any.
+    pub(crate) static ref ARGS_LOG_ID: Option<String> = match ARGS.lock().subcommands {
+        Some(Commands::Database { ref log_id, .. }) => log_id.clone(),
+        Some(_) => None,
+        None => None,
+    };
+
+    /// The section of the logs that is shown, if any.
+    pub(crate) static ref ARGS_LOG_SECTION: Option<String> = match ARGS.lock().subcommands {
+        Some(Commands::Database { ref log_section, .. }) => log_section.clone(),
+        Some(_) => None,
+        None => None,
+    };
+
+    /// The file to export the library to; `Some(None)` prints it.
+    pub(crate) static ref ARGS_EXPORT: Option<Option<String>> = match ARGS.lock().subcommands {
+        Some(Commands::Database { ref export, .. }) => export.clone(),
+        Some(_) => None,
+        None => None,
+    };
+
+    /// The old and new start of the folders of relocated manga, if any.
+    pub(crate) static ref ARGS_RELOCATE: Option<Vec<String>> = match ARGS.lock().subcommands {
+        Some(Commands::Database { ref relocate, .. }) => relocate.clone(),
+        Some(_) => None,
+        None => None,
+    };
+
+    /// The root of the library; `Some(None)` removes it.
+    pub(crate) static ref ARGS_ROOT: Option<Option<String>> = match ARGS.lock().subcommands {
+        Some(Commands::Database { ref root, .. }) => root.clone(),
+        Some(_) => None,
+        None => None,
+    };
+
+    /// The ID or name of the manga to remove from the library, if any.
+    pub(crate) static ref ARGS_REMOVE: Option<String> = match ARGS.lock().subcommands {
+        Some(Commands::Database { ref remove, .. }) => remove.clone(),
+        Some(_) => None,
+        None => None,
+    };
+
+    /// The ID or name of the manga to pause, if any.
+    pub(crate) static ref ARGS_PAUSE: Option<String> = match ARGS.lock().subcommands {
+        Some(Commands::Database { ref pause, .. }) => pause.clone(),
+        Some(_) => None,
+        None => None,
+    };
+
+    /// The ID or name of the manga to resume, if any.
+    pub(crate) static ref ARGS_RESUME: Option<String> = match ARGS.lock().subcommands {
+        Some(Commands::Database { ref resume, .. }) => resume.clone(),
+        Some(_) => None,
+        None => None,
+    };
+
+    /// The manga, preference and value to set, if any.
+    pub(crate) static ref ARGS_SET: Option<Vec<String>> = match ARGS.lock().subcommands {
+        Some(Commands::Database { ref set, .. }) => set.clone(),
+        Some(_) => None,
+        None => None,
+    };
+
+    /// The manga and preference to unset, if any.
+    pub(crate) static ref ARGS_UNSET: Option<Vec<String>> = match ARGS.lock().subcommands {
+        Some(Commands::Database { ref unset, .. }) => unset.clone(),
+        Some(_) => None,
+        None => None,
+    };
+
+    /// The folder to rebuild the library from, if any.
+    pub(crate) static ref ARGS_REBUILD: Option<String> = match ARGS.lock().subcommands {
+        Some(Commands::Database { ref rebuild, .. }) => rebuild.clone(),
+        Some(_) => None,
+        None => None,
+    };
+
+    /// The archive and page to mark as a credit page, if any.
+    pub(crate) static ref ARGS_MARK_CREDIT: Option<Vec<String>> = match ARGS.lock().subcommands {
+        Some(Commands::Database { ref mark_credit, .. }) => mark_credit.clone(),
+        Some(_) => None,
+        None => None,
+    };
+
+    /// The archive and page to remove from credit pages, if any.
+    pub(crate) static ref ARGS_UNMARK_CREDIT: Option<Vec<String>> = match ARGS.lock().subcommands {
+        Some(Commands::Database { ref unmark_credit, .. }) => unmark_credit.clone(),
+        Some(_) => None,
+        None => None,
+    };
+
+    /// The event and the command of a hook to add, if any.
+    pub(crate) static ref ARGS_HOOK: Option<Vec<String>> = match ARGS.lock().subcommands {
+        Some(Commands::Settings { ref hook, .. }) => hook.clone(),
+        Some(_) => None,
+        None => None,
+    };
+
+    /// The event and the URL of a webhook to add, if any.
+    pub(crate) static ref ARGS_WEBHOOK: Option<Vec<String>> = match ARGS.lock().subcommands {
+        Some(Commands::Settings { ref webhook, .. }) => webhook.clone(),
+        Some(_) => None,
+        None => None,
+    };
+
+    /// The event and optionally the target of hooks to remove, if any.
+    pub(crate) static ref ARGS_REMOVE_HOOK: Option<Vec<String>> = match ARGS.lock().subcommands {
+        Some(Commands::Settings { ref remove_hook, .. }) => remove_hook.clone(),
+        Some(_) => None,
+        None => None,
+    };
+
+    /// Indicates whether to show the hooks.
+    pub(crate) static ref ARGS_SHOW_HOOKS: bool = match ARGS.lock().subcommands {
+        Some(Commands::Settings { show_hooks, .. }) => show_hooks,
+        Some(_) => false,
+        None => false,
+    };
+
     /// Indicates whether to reset the application.
     pub(crate) static ref ARGS_RESET: bool = match ARGS.lock().subcommands {
         Some(Commands::App { reset, .. }) => reset,
@@ -89,6 +260,13 @@ lazy_static! {
         Some(_) => false,
         None => false,
     };
+
+    /// The backup of the library to restore; `Some(None)` restores the newest backup.
+    pub(crate) static ref ARGS_RESTORE_BACKUP: Option<Option<String>> = match ARGS.lock().subcommands {
+        Some(Commands::App { ref restore_backup, .. }) => restore_backup.clone(),
+        Some(_) => None,
+        None => None,
+    };
 }
 
 /// Mangadex Manga downloader
@@ -154,6 +332,21 @@ pub(crate) struct ParserArgs {
     )]
     pub(crate) folder: String,
 
+    /// Template of chapter names, e.g. `Vol.{volume:02}/{manga} - Ch.{chapter:03.1} [{group}]`.
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        next_line_help = true,
+        help = "template of chapter names inside folder; overrides name template setting
+- variables: {manga}, {volume}, {chapter}, {title}, {group}, {lang}, {id}
+- {chapter:03} pads number to 3 digits, {chapter:03.1} also shows at least 1 decimal
+- parts in <> are left out when variable inside is empty
+- / creates subfolders
+[default: {manga} - <Vol.{volume} >Ch.{chapter}< - {title}>]
+"
+    )]
+    pub(crate) name_template: Option<String>,
+
     /// Download only the specified volume.
     #[arg(
         short,
@@ -205,10 +398,162 @@ pub(crate) struct ParserArgs {
     )]
     pub(crate) max_consecutive: String,
 
+    /// How downloaded chapters are archived; `volume` bundles chapters of the same volume into one archive.
+    #[arg(
+        long,
+        value_name = "BUNDLE",
+        default_value_t = String::from("chapter"),
+        value_parser = ["chapter", "volume"],
+        next_line_help = true,
+        help = "how chapters are archived;\n- chapter: one cbz file per chapter\n- volume: one cbz file per volume named <Manga> - Vol.N.cbz with chapter subfolders\n"
+    )]
+    pub(crate) bundle: String,
+
+    /// Compression method used for cbz files; overrides the `compression` setting.
+    #[arg(
+        long,
+        value_name = "METHOD",
+        value_parser = ["stored", "deflate", "zstd"],
+        next_line_help = true,
+        help = "compression method of cbz files; overrides compression setting\n[default: stored]\n"
+    )]
+    pub(crate) compression: Option<String>,
+
+    /// Compression level; 0 - 9 for deflate, -7 - 22 for zstd.
+    #[arg(
+        long,
+        value_name = "LEVEL",
+        allow_hyphen_values = true,
+        next_line_help = true,
+        help = "compression level of cbz files; 0 - 9 for deflate, -7 - 22 for zstd; overrides compression level setting\n"
+    )]
+    pub(crate) compression_level: Option<i32>,
+
+    /// Format pages are converted to after download; `keep` leaves the original format.
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        default_value_t = String::from("keep"),
+        value_parser = ["keep", "webp", "jpeg", "avif"],
+        next_line_help = true,
+        help = "convert downloaded pages to supplied format; webp is lossless\nrequires transcode feature\n"
+    )]
+    pub(crate) image_format: String,
+
+    /// Quality of converted jpeg and avif pages.
+    #[arg(
+        long,
+        value_name = "QUALITY",
+        value_parser = clap::value_parser!(u8).range(1..=100),
+        next_line_help = true,
+        help = "quality of converted jpeg and avif pages; 1 - 100; can't be used with webp, which is lossless\n[default: 80]\n"
+    )]
+    pub(crate) image_quality: Option<u8>,
+
+    /// Maximum width of pages; larger pages are scaled down.
+    #[arg(
+        long,
+        value_name = "PIXELS",
+        next_line_help = true,
+        help = "scale down pages wider than supplied width; keeps aspect ratio\nrequires transcode feature\n"
+    )]
+    pub(crate) max_width: Option<u32>,
+
+    /// Maximum height of pages; larger pages are scaled down.
+    #[arg(
+        long,
+        value_name = "PIXELS",
+        next_line_help = true,
+        help = "scale down pages taller than supplied height; keeps aspect ratio\nrequires transcode feature\n"
+    )]
+    pub(crate) max_height: Option<u32>,
+
+    /// Convert pages to grayscale, e.g. for e-ink readers.
+    #[arg(
+        long,
+        next_line_help = true,
+        help = "convert pages to grayscale; useful for e-ink readers\nrequires transcode feature"
+    )]
+    pub(crate) grayscale: bool,
+
+    /// Reading direction of the manga; decides the order of split spreads and the rotation of spreads.
+    #[arg(
+        long,
+        value_name = "DIRECTION",
+        value_parser = ["rtl", "ltr"],
+        next_line_help = true,
+        help = "reading direction of manga; used when splitting or rotating spreads; saved for manga in database\n[default: rtl]\n"
+    )]
+    pub(crate) direction: Option<String>,
+
+    /// Aspect ratio (height / width) of the target screen; strips taller than twice the screen are split.
+    #[arg(
+        long,
+        value_name = "RATIO",
+        next_line_help = true,
+        help = "split long strips into screen sized pages; supply aspect ratio (height / width) of screen e.g. 1.33\npages taller than twice the screen are split; saved for manga in database\nrequires transcode feature\n"
+    )]
+    pub(crate) split_tall: Option<f32>,
+
+    /// Stop splitting long strips of a manga.
+    #[arg(
+        long,
+        conflicts_with = "split_tall",
+        next_line_help = true,
+        help = "stop splitting long strips; saved for manga in database"
+    )]
+    pub(crate) no_split_tall: bool,
+
+    /// What happens with double-page spreads.
+    #[arg(
+        long,
+        value_name = "SPREAD",
+        value_parser = ["keep", "split", "rotate"],
+        next_line_help = true,
+        help = "split double-page spreads into two pages or rotate them; saved for manga in database\nrequires transcode feature\n[default: keep]\n"
+    )]
+    pub(crate) spread: Option<String>,
+
+    /// Crop uniform borders of pages.
+    #[arg(
+        long,
+        next_line_help = true,
+        help = "crop uniform borders of pages; saved for manga in database\nrequires transcode feature"
+    )]
+    pub(crate) crop: bool,
+
+    /// Stop cropping borders of pages of a manga.
+    #[arg(
+        long,
+        conflicts_with = "crop",
+        next_line_help = true,
+        help = "stop cropping uniform borders of pages; saved for manga in database"
+    )]
+    pub(crate) no_crop: bool,
+
+    /// Drop pages matching credit pages marked for the chapter's scanlation group.
+    #[arg(
+        long,
+        next_line_help = true,
+        help = "drop first and last pages of chapters that look like credit pages marked for scanlation group
+mark credit pages with database --mark-credit
+requires transcode feature"
+    )]
+    pub(crate) strip_credits: bool,
+
     /// Download manga even if it already exists.
     #[arg(long, next_line_help = true, help = "download manga even if it already exists")]
     pub(crate) force: bool,
 
+    /// Report which chapters would be downloaded without writing anything.
+    #[arg(
+        long,
+        global = true,
+        next_line_help = true,
+        help = "show which chapters would be downloaded, downloaded again or skipped and why,\nwithout writing any files or changing library; works with downloads and database --update\n"
+    )]
+    pub(crate) dry_run: bool,
+
     /// Start offset for downloading chapters. For example, "50" starts from chapter 50.
     #[arg(
         short,
@@ -242,6 +587,14 @@ pub(crate) struct ParserArgs {
     )]
     pub(crate) cwd: String,
 
+    /// Keep the library, the database and logs next to the executable.
+    #[arg(
+        long,
+        next_line_help = true,
+        help = "keep library, database and logs next to executable instead of data directories;\ncreating file named portable next to executable does the same\n"
+    )]
+    pub(crate) portable: bool,
+
     /// Print URL in a program-readable format.
     #[arg(
         short,
@@ -306,6 +659,8 @@ pub(crate) struct ParserArgs {
 }
 
 /// Enum representing the available subcommands for the application.
+// Parsed once per process, so the size of the options of `database` doesn't matter
+#[allow(clippy::large_enum_variant)]
 #[derive(Subcommand, Clone, Debug)]
 pub(crate) enum Commands {
     /// Subcommands related to database management.
@@ -337,6 +692,221 @@ pub(crate) enum Commands {
         /// Show current logs in the database.
         #[arg(long, next_line_help = true, help = "Shows current logs in database")]
         show_log: bool,
+
+        /// Show the logs of one run instead of asking for it.
+        #[arg(
+            long,
+            value_name = "ID",
+            next_line_help = true,
+            help = "show logs of run with supplied id instead of asking for it; latest is the run started last"
+        )]
+        log_id: Option<String>,
+
+        /// Show one section of the logs of a run instead of asking for it.
+        #[arg(
+            long,
+            value_name = "SECTION",
+            next_line_help = true,
+            help = "show only supplied section of logs of run, e.g. chapter name or General
+[default: will show all sections]"
+        )]
+        log_section: Option<String>,
+
+        /// Format of the output of database commands.
+        #[arg(
+            long,
+            value_name = "FORMAT",
+            default_value_t = String::from("table"),
+            value_parser = ["table", "json", "ndjson"],
+            next_line_help = true,
+            help = "format of output of --check, --update, --show, --show-all and --show-log;\n- table: text\n- json: one array of records\n- ndjson: one record per line\nmessages are printed to stderr with json and ndjson\n"
+        )]
+        output: String,
+
+        /// Number of manga fetched at once by `--check` and `--update`.
+        #[arg(
+            long,
+            value_name = "COUNT",
+            default_value_t = 4,
+            value_parser = clap::value_parser!(u8).range(1..=16),
+            next_line_help = true,
+            help = "number of manga whose chapters are fetched at once by --check and --update; 1 - 16;\nrequests stay within rate limit of MangaDex, downloads are done one manga at a time\n"
+        )]
+        parallel: u8,
+
+        /// Check every manga with all of its chapters.
+        #[arg(
+            long,
+            next_line_help = true,
+            help = "check every manga with all of its chapters, even manga without changed chapters since they were last found up to date, e.g. after files were deleted\n"
+        )]
+        full: bool,
+
+        /// Recompress all downloaded files with the current compression settings.
+        #[arg(
+            long,
+            next_line_help = true,
+            help = "Recompress all cbz files in database in place with current compression method and level"
+        )]
+        recompress: bool,
+
+        /// Export the library in the format of `dat.json`.
+        #[arg(
+            long,
+            value_name = "FILE",
+            next_line_help = true,
+            help = "export library in the format of dat.json used by older versions\n[default: will print library]"
+        )]
+        export: Option<Option<String>>,
+
+        /// Remove a manga from the library; its files are kept.
+        #[arg(
+            long,
+            value_name = "MANGA",
+            next_line_help = true,
+            help = "remove manga from library, so it is no longer checked or updated; MANGA is id or name of manga
+downloaded files are kept"
+        )]
+        remove: Option<String>,
+
+        /// Pause updates of a manga.
+        #[arg(
+            long,
+            value_name = "MANGA",
+            next_line_help = true,
+            help = "pause manga, so it is skipped by --check and --update until it is resumed; MANGA is id or name of manga"
+        )]
+        pause: Option<String>,
+
+        /// Resume updates of a paused manga.
+        #[arg(
+            long,
+            value_name = "MANGA",
+            next_line_help = true,
+            help = "resume paused manga; MANGA is id or name of manga"
+        )]
+        resume: Option<String>,
+
+        /// Set a preference of a manga used by `--update`.
+        #[arg(
+            long,
+            num_args = 3,
+            value_names = ["MANGA", "PREFERENCE", "VALUE"],
+            next_line_help = true,
+            help = "set preference of manga used by --check and --update instead of arguments; MANGA is id or name of manga
+- language: language of chapters
+- saver: true or false; download images of lower quality
+- group: id or name of scanlation group whose chapters are preferred over chapters of other groups
+- bundle: chapter or volume; how chapters are archived
+- format: keep, webp, jpeg or avif; format pages are converted to, requires transcode feature
+- folder: folder of manga; files are not moved"
+        )]
+        set: Option<Vec<String>>,
+
+        /// Remove a preference of a manga, so that the arguments are used again.
+        #[arg(
+            long,
+            num_args = 2,
+            value_names = ["MANGA", "PREFERENCE"],
+            next_line_help = true,
+            help = "remove preference of manga, so that arguments are used again; PREFERENCE is saver, group, bundle or format"
+        )]
+        unset: Option<Vec<String>>,
+
+        /// Rebuild the library from the metadata stored in the archives of a folder.
+        #[arg(
+            long,
+            value_name = "FOLDER",
+            next_line_help = true,
+            help = "search folder and its subfolders for downloaded chapters and add them to library; chapters that are already in library are kept
+dates of rebuilt chapters are fetched by next database --check"
+        )]
+        rebuild: Option<String>,
+
+        /// Change the folders of manga that were moved.
+        #[arg(
+            long,
+            num_args = 2,
+            value_names = ["OLD", "NEW"],
+            next_line_help = true,
+            help = "change folders of manga starting with OLD to start with NEW, e.g. after library was moved to another drive"
+        )]
+        relocate: Option<Vec<String>>,
+
+        /// Set the root of the library; folders of manga inside the root are stored relative to it.
+        #[arg(
+            long,
+            value_name = "FOLDER",
+            next_line_help = true,
+            help = "set root of library; folders of manga inside root are stored relative to it, so library can be moved with its root
+[default: will remove root]"
+        )]
+        root: Option<Option<String>>,
+
+        /// Mark a page of a downloaded chapter as a credit page of the chapter's scanlation group.
+        #[arg(
+            long,
+            num_args = 2,
+            value_names = ["ARCHIVE", "PAGE"],
+            next_line_help = true,
+            help = "mark page of downloaded chapter as credit page of chapter's scanlation group; PAGE is number of page starting from 1
+marked pages are dropped from new chapters of the group with --strip-credits
+requires transcode feature"
+        )]
+        mark_credit: Option<Vec<String>>,
+
+        /// Remove a page of a downloaded chapter from the credit pages of the chapter's scanlation group.
+        #[arg(
+            long,
+            num_args = 2,
+            value_names = ["ARCHIVE", "PAGE"],
+            next_line_help = true,
+            help = "remove page of downloaded chapter from credit pages of chapter's scanlation group
+requires transcode feature"
+        )]
+        unmark_credit: Option<Vec<String>>,
+    },
+
+    /// Stay running and update the library on a schedule.
+    Daemon {
+        /// Time between updates of the library.
+        #[arg(
+            long,
+            value_name = "DURATION",
+            default_value = "6h",
+            value_parser = daemon::parse_duration,
+            next_line_help = true,
+            help = "time between updates of library, e.g. 90s, 30m, 6h or 1d; at least a minute"
+        )]
+        every: Duration,
+
+        /// Most time randomly added to the time between updates.
+        #[arg(
+            long,
+            value_name = "DURATION",
+            default_value = "15m",
+            value_parser = daemon::parse_duration,
+            next_line_help = true,
+            help = "most time randomly added to time between updates, so that updates don't start at the same time every day"
+        )]
+        jitter: Duration,
+
+        /// The number of manga whose chapters are fetched at once.
+        #[arg(
+            long,
+            value_name = "COUNT",
+            default_value_t = 4,
+            value_parser = clap::value_parser!(u8).range(1..=16),
+            next_line_help = true,
+            help = "fetch COUNT manga at once; requests stay within rate limit of MangaDex"
+        )]
+        parallel: u8,
+    },
+
+    /// Send a command to a running daemon, web or server instance.
+    Ctl {
+        #[command(subcommand)]
+        command: CtlCommand,
     },
 
     /// Subcommands related to application settings.
@@ -348,6 +918,82 @@ pub(crate) enum Commands {
             help = "set default name of folder\n[default: Will remove current folder setting]"
         )]
         folder: Option<Option<String>>,
+
+        /// Set the default template of chapter names.
+        #[arg(
+            long,
+            value_name = "TEMPLATE",
+            next_line_help = true,
+            help = "set default template of chapter names; see --name-template
+[default: Will remove current name template setting]"
+        )]
+        name_template: Option<Option<String>>,
+
+        /// Set the default compression method.
+        #[arg(
+            long,
+            value_name = "METHOD",
+            value_parser = ["stored", "deflate", "zstd"],
+            next_line_help = true,
+            help = "set default compression method of cbz files\n[default: Will remove current compression setting]"
+        )]
+        compression: Option<Option<String>>,
+
+        /// Set the default compression level.
+        #[arg(
+            long,
+            value_name = "LEVEL",
+            allow_hyphen_values = true,
+            next_line_help = true,
+            help = "set default compression level of cbz files\n[default: Will remove current compression level setting]"
+        )]
+        compression_level: Option<Option<i32>>,
+
+        /// Set the Komga or Kavita server asked to scan folders of manga after chapters are added.
+        #[arg(
+            long,
+            num_args = 0..=3,
+            value_names = ["KIND", "URL", "API_KEY"],
+            next_line_help = true,
+            help = "ask Komga or Kavita server to scan folders of manga after chapters are added; KIND is komga or kavita
+[default: Will remove current scan server setting]"
+        )]
+        scan_server: Option<Vec<String>>,
+
+        /// Run a shell command when an event happens.
+        #[arg(
+            long,
+            num_args = 2,
+            value_names = ["EVENT", "COMMAND"],
+            next_line_help = true,
+            help = "run shell command when event happens; EVENT is new-chapter, chapter-downloaded, manga-finished, error or * for all
+details of event are in MDOWN_* environment variables"
+        )]
+        hook: Option<Vec<String>>,
+
+        /// Send a JSON payload to a URL when an event happens.
+        #[arg(
+            long,
+            num_args = 2,
+            value_names = ["EVENT", "URL"],
+            next_line_help = true,
+            help = "send details of event as JSON in POST request to URL when event happens; EVENT is same as in --hook"
+        )]
+        webhook: Option<Vec<String>>,
+
+        /// Remove the hooks of an event.
+        #[arg(
+            long,
+            num_args = 1..=2,
+            value_names = ["EVENT", "TARGET"],
+            next_line_help = true,
+            help = "remove hooks of event; only the hook with command or URL TARGET is removed if it is given"
+        )]
+        remove_hook: Option<Vec<String>>,
+
+        /// Show the hooks.
+        #[arg(long, help = "show hooks")]
+        show_hooks: bool,
     },
 
     /// Subcommands related to application management.
@@ -356,25 +1002,63 @@ pub(crate) enum Commands {
         #[arg(long, next_line_help = true, help = "Force first time setup")]
         force_setup: bool,
 
-        /// Force delete the `.lock` file, which prevents running another instance.
+        /// Force delete the lock files of manga.
         #[arg(
             long,
             next_line_help = true,
-            help = "force to delete *.lock file which is stopping from running another instance of program;\nNOTE that if you already have one instance running it will fail to delete the original file and thus it will crash"
+            help = "force to delete lock files of manga;\nNOTE use it only when no other instance is running"
         )]
         force_delete: bool,
 
-        /// Delete `dat.json`.
-        #[arg(long, next_line_help = true, help = "Delete dat.json")]
+        /// Delete the library of downloaded manga.
+        #[arg(long, next_line_help = true, help = "Delete library of downloaded manga")]
         delete: bool,
 
         /// Delete all files created by the program.
         #[arg(long, next_line_help = true, help = "Delete all files created by program")]
         reset: bool,
+
+        /// Restore the library from a backup.
+        #[arg(
+            long,
+            value_name = "BACKUP",
+            next_line_help = true,
+            help = "restore library from backup; BACKUP is name of file in backups folder\n[default: will restore newest backup]"
+        )]
+        restore_backup: Option<Option<String>>,
     },
     Default,
 }
 
+/// Commands sent to a running instance with `ctl`.
+#[derive(Subcommand, Clone, Debug, PartialEq)]
+pub(crate) enum CtlCommand {
+    /// Manage manga queued for download.
+    Queue {
+        #[command(subcommand)]
+        command: QueueCommand,
+    },
+    /// Show what the instance is doing and the queued manga.
+    Status,
+    /// Cancel a queued manga; a manga being downloaded stops after the current chapter.
+    Cancel {
+        /// The handle returned when the manga was queued.
+        handle: String,
+    },
+    /// Update the library now instead of at the next scheduled update; only supported by the daemon.
+    CheckNow,
+}
+
+/// Commands of `ctl queue`.
+#[derive(Subcommand, Clone, Debug, PartialEq)]
+pub(crate) enum QueueCommand {
+    /// Queue a manga for download; prints the handle of the download.
+    Add {
+        /// URL or ID of the manga.
+        url: String,
+    },
+}
+
 /// Enum for different types of values used in the application.
 pub(crate) enum Value {
     /// A boolean value.
@@ -383,6 +1067,9 @@ pub(crate) enum Value {
 
     /// A string value.
     Str(String),
+
+    /// An optional integer value.
+    Int(Option<i32>),
 }
 
 /// Structure representing the parsed command-line arguments.
@@ -391,17 +1078,36 @@ pub(crate) struct Args {
     pub(crate) lang: String,
     pub(crate) title: String,
     pub(crate) folder: String,
+    pub(crate) name_template: String,
     pub(crate) volume: String,
     pub(crate) chapter: String,
     pub(crate) saver: bool,
     pub(crate) stat: bool,
     pub(crate) quiet: bool,
     pub(crate) max_consecutive: String,
+    pub(crate) bundle: String,
+    pub(crate) compression: String,
+    pub(crate) compression_level: Option<i32>,
+    pub(crate) image_format: String,
+    #[cfg_attr(not(feature = "transcode"), allow(dead_code))]
+    pub(crate) image_quality: Option<u8>,
+    pub(crate) max_width: Option<u32>,
+    pub(crate) max_height: Option<u32>,
+    pub(crate) grayscale: bool,
+    pub(crate) direction: Option<String>,
+    pub(crate) split_tall: Option<f32>,
+    pub(crate) no_split_tall: bool,
+    pub(crate) spread: Option<String>,
+    pub(crate) crop: bool,
+    pub(crate) no_crop: bool,
+    pub(crate) strip_credits: bool,
     pub(crate) force: bool,
+    pub(crate) dry_run: bool,
     pub(crate) offset: String,
     pub(crate) database_offset: String,
     pub(crate) unsorted: bool,
     pub(crate) cwd: String,
+    pub(crate) portable: bool,
     pub(crate) encode: String,
     pub(crate) log: bool,
     pub(crate) check: bool,
@@ -428,9 +1134,83 @@ impl Args {
     /// * `typ` - The type of value to update.
     /// * `to` - The new value to set.
     pub(crate) fn change(&mut self, typ: &str, to: Value) {
-        if let ("folder", Value::Str(value)) = (typ, to) {
-            self.folder = value;
+        match (typ, to) {
+            ("folder", Value::Str(value)) => {
+                self.folder = value;
+            }
+            ("name_template", Value::Str(value)) => {
+                self.name_template = value;
+            }
+            ("compression", Value::Str(value)) => {
+                self.compression = value;
+            }
+            ("compression_level", Value::Int(value)) => {
+                self.compression_level = value;
+            }
+            ("bundle", Value::Str(value)) => {
+                self.bundle = value;
+            }
+            ("image_format", Value::Str(value)) => {
+                self.image_format = value;
+            }
+            _ => (),
+        }
+    }
+
+    /// Returns whether any processing of downloaded pages was requested.
+    pub(crate) fn image_processing(&self) -> bool {
+        self.image_format != "keep" ||
+            self.max_width.is_some() ||
+            self.max_height.is_some() ||
+            self.grayscale
+    }
+
+    /// Returns whether any layout option was supplied.
+    pub(crate) fn layout_supplied(&self) -> bool {
+        self.direction.is_some() ||
+            self.split_tall.is_some() ||
+            self.no_split_tall ||
+            self.spread.is_some() ||
+            self.crop ||
+            self.no_crop
+    }
+
+    /// Applies the supplied layout options on top of a layout, e.g. one stored in the library.
+    ///
+    /// # Arguments
+    ///
+    /// * `layout` - The layout to start from; `None` starts from the default layout.
+    ///
+    /// # Returns
+    ///
+    /// The resulting layout, or `None` if neither a layout nor any layout option was supplied.
+    pub(crate) fn apply_layout(
+        &self,
+        layout: Option<metadata::LayoutMetadata>
+    ) -> Option<metadata::LayoutMetadata> {
+        if layout.is_none() && !self.layout_supplied() {
+            return None;
+        }
+        let mut layout = layout.unwrap_or_default();
+        if let Some(ref direction) = self.direction {
+            layout.direction = direction.clone();
+        }
+        if self.split_tall.is_some() {
+            layout.split_tall = self.split_tall;
+        }
+        if self.no_split_tall {
+            layout.split_tall = None;
+        }
+        if let Some(ref spread) = self.spread {
+            layout.spread = spread.clone();
+        }
+        if self.crop {
+            layout.crop = true;
+        }
+        if self.no_crop {
+            layout.crop = false;
         }
+        Some(layout)
     }
 
     /// Creates an `Args` instance from the command-line arguments.
@@ -449,25 +1229,47 @@ impl Args {
             lang: args.lang,
             title: args.title,
             folder: args.folder,
+            name_template: args.name_template.unwrap_or_default(),
             volume: args.volume,
             chapter: args.chapter,
             saver: args.saver,
             stat: args.stat,
-            quiet: args.quiet,
+            // Chapters are reported as text and the server downloads manga queued with `ctl` in the background,
+            // so the screen of downloads isn't drawn
+            quiet: args.quiet || args.dry_run || args.server,
             max_consecutive: args.max_consecutive,
+            bundle: args.bundle,
+            compression: args.compression.unwrap_or_default(),
+            compression_level: args.compression_level,
+            image_format: args.image_format,
+            image_quality: args.image_quality,
+            max_width: args.max_width,
+            max_height: args.max_height,
+            grayscale: args.grayscale,
+            direction: args.direction,
+            split_tall: args.split_tall,
+            no_split_tall: args.no_split_tall,
+            spread: args.spread,
+            crop: args.crop,
+            no_crop: args.no_crop,
+            strip_credits: args.strip_credits,
             force: args.force,
+            dry_run: args.dry_run,
             offset: args.offset,
             database_offset: args.database_offset,
             unsorted: args.unsorted,
             cwd: args.cwd,
+            portable: args.portable,
             encode: args.encode,
             log: args.log,
             check: match subcommands {
                 Commands::Database { check, .. } => *check,
                 _ => false,
             },
+            // The daemon updates the library like `database --update`
             update: match subcommands {
                 Commands::Database { update, .. } => *update,
+                Commands::Daemon { .. } => true,
                 _ => false,
             },
             show: match subcommands {
@@ -530,17 +1332,35 @@ impl Args {
             lang: lang,
             title: title,
             folder: folder,
+            name_template: ARGS.lock().name_template.clone(),
             volume: volume,
             chapter: chapter,
             saver: saver,
             stat: stat,
             quiet: *ARGS_QUIET,
             max_consecutive: max_consecutive,
+            bundle: ARGS.lock().bundle.clone(),
+            compression: ARGS.lock().compression.clone(),
+            compression_level: ARGS.lock().compression_level,
+            image_format: ARGS.lock().image_format.clone(),
+            image_quality: ARGS.lock().image_quality,
+            max_width: ARGS.lock().max_width,
+            max_height: ARGS.lock().max_height,
+            grayscale: ARGS.lock().grayscale,
+            direction: ARGS.lock().direction.clone(),
+            split_tall: ARGS.lock().split_tall,
+            no_split_tall: ARGS.lock().no_split_tall,
+            spread: ARGS.lock().spread.clone(),
+            crop: ARGS.lock().crop,
+            no_crop: ARGS.lock().no_crop,
+            strip_credits: ARGS.lock().strip_credits,
             force: force,
+            dry_run: *ARGS_DRY_RUN,
             offset: offset,
             database_offset: database_offset,
             unsorted: *ARGS_UNSORTED,
             cwd: ARGS_CWD.to_string(),
+            portable: *ARGS_PORTABLE,
             encode: ARGS_ENCODE.to_string(),
             log: *ARGS_LOG,
             check: *ARGS_CHECK,
@@ -0,0 +1,275 @@
+use image::{ imageops::FilterType, DynamicImage };
+use std::{ f64::consts::PI, fs, path::Path };
+
+use crate::{ args, db, debug, error::MdownError, image_func, metadata, zip_func };
+
+/// Size of the grayscale thumbnail the perceptual hash is computed from.
+const HASH_SIZE: usize = 32;
+
+/// Number of low frequencies in each direction that make up the hash.
+const HASH_FREQUENCIES: usize = 8;
+
+/// Largest number of differing bits for which two pages are considered the same page.
+const HASH_THRESHOLD: u32 = 10;
+
+/// Number of pages at the start and at the end of a chapter that are checked for credit pages.
+const CHECKED_PAGES: usize = 3;
+
+/// Computes the perceptual hash of a page.
+///
+/// The page is reduced to a 32x32 grayscale thumbnail, transformed with a discrete cosine transform
+/// and every bit of the hash tells whether one of the 64 lowest frequencies is above their median.
+/// Pages that differ only in size, format, compression or color produce hashes that differ in few bits.
+///
+/// # Parameters
+/// - `image: &DynamicImage`: The page.
+///
+/// # Returns
+/// `u64`: The perceptual hash.
+pub(crate) fn perceptual_hash(image: &DynamicImage) -> u64 {
+    let thumbnail = image
+        .resize_exact(HASH_SIZE as u32, HASH_SIZE as u32, FilterType::Triangle)
+        .to_luma8();
+
+    let mut cosines = [[0.0; HASH_SIZE]; HASH_FREQUENCIES];
+    for (frequency, row) in cosines.iter_mut().enumerate() {
+        for (x, cosine) in row.iter_mut().enumerate() {
+            *cosine = (((2 * x + 1) as f64) * (frequency as f64) * PI / ((2 * HASH_SIZE) as f64)).cos();
+        }
+    }
+
+    // Transform rows first, then columns of the transformed rows
+    let mut rows = [[0.0; HASH_FREQUENCIES]; HASH_SIZE];
+    for (y, row) in rows.iter_mut().enumerate() {
+        for (u, value) in row.iter_mut().enumerate() {
+            *value = (0..HASH_SIZE)
+                .map(|x| (thumbnail.get_pixel(x as u32, y as u32).0[0] as f64) * cosines[u][x])
+                .sum();
+        }
+    }
+    let mut coefficients = Vec::with_capacity(HASH_FREQUENCIES * HASH_FREQUENCIES);
+    for column_cosines in cosines.iter() {
+        let mut sums = [0.0; HASH_FREQUENCIES];
+        for (row, cosine) in rows.iter().zip(column_cosines.iter()) {
+            for (sum, value) in sums.iter_mut().zip(row.iter()) {
+                *sum += value * cosine;
+            }
+        }
+        coefficients.extend(sums);
+    }
+
+    // The first coefficient is the average brightness and would skew the median
+    let mut sorted = coefficients[1..].to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let median = sorted[sorted.len() / 2];
+
+    coefficients
+        .iter()
+        .enumerate()
+        .filter(|(_index, coefficient)| **coefficient > median)
+        .fold(0, |hash, (index, _coefficient)| hash | (1 << index))
+}
+
+/// Checks whether a hash belongs to one of the known credit pages.
+///
+/// # Parameters
+/// - `hash: u64`: The perceptual hash of the page.
+/// - `known: &[u64]`: The perceptual hashes of the known credit pages.
+///
+/// # Returns
+/// `bool`: Returns `true` if the page differs from a known credit page in at most `HASH_THRESHOLD` bits.
+pub(crate) fn is_credit_page(hash: u64, known: &[u64]) -> bool {
+    known.iter().any(|known| (hash ^ known).count_ones() <= HASH_THRESHOLD)
+}
+
+/// Drops the credit pages of the chapter's scanlation group from a downloaded chapter.
+///
+/// Only the first and last pages are checked, and a chapter is never emptied.
+/// Names of the dropped pages are recorded in the chapter's `_metadata`.
+///
+/// # Parameters
+/// - `folder_path: &str`: The chapter folder in the cache.
+///
+/// # Returns
+/// `Result<(), MdownError>`: Returns `Ok(())` if the chapter was checked, or an `MdownError` if a page or the metadata cannot be read.
+pub(crate) fn strip_chapter(folder_path: &str) -> Result<(), MdownError> {
+    if !args::ARGS.lock().strip_credits {
+        return Ok(());
+    }
+    let metadata_path = Path::new(folder_path).join("_metadata");
+    let content = match fs::read_to_string(&metadata_path) {
+        Ok(content) => content,
+        Err(err) => {
+            return Err(MdownError::IoError(err, metadata_path.to_string_lossy().to_string()));
+        }
+    };
+    let group = match serde_json::from_str::<metadata::ChapterMetadataIn>(&content) {
+        Ok(chapter_metadata) => chapter_metadata.scanlation.name,
+        Err(err) => {
+            return Err(MdownError::JsonError(err.to_string()));
+        }
+    };
+    let known = match db::read_credit_pages(&group) {
+        Ok(known) => known,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    if known.is_empty() {
+        return Ok(());
+    }
+
+    let pages = match image_func::get_pages(folder_path) {
+        Ok(pages) => pages,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    let mut candidates: Vec<usize> = (0..pages.len().min(CHECKED_PAGES))
+        .chain(pages.len().saturating_sub(CHECKED_PAGES)..pages.len())
+        .collect();
+    candidates.sort();
+    candidates.dedup();
+
+    let mut credit_pages = Vec::new();
+    for index in candidates {
+        let (image, _format) = match image_func::open_page(&pages[index]) {
+            Ok(page) => page,
+            Err(err) => {
+                return Err(err);
+            }
+        };
+        if is_credit_page(perceptual_hash(&image), &known) {
+            credit_pages.push(&pages[index]);
+        }
+    }
+    if credit_pages.is_empty() || credit_pages.len() == pages.len() {
+        return Ok(());
+    }
+    debug!("dropping credit pages of {}: {:?}", group, credit_pages);
+
+    let mut removed_pages = Vec::new();
+    for page in credit_pages {
+        if let Err(err) = fs::remove_file(page) {
+            return Err(MdownError::IoError(err, page.to_string_lossy().to_string()));
+        }
+        if let Some(name) = page.file_name() {
+            removed_pages.push(name.to_string_lossy().to_string());
+        }
+    }
+    image_func::update_metadata(folder_path, |chapter_metadata| {
+        chapter_metadata.removed_pages.extend(removed_pages);
+    })
+}
+
+/// Marks or unmarks a page of a downloaded chapter as a credit page of its scanlation group.
+///
+/// # Parameters
+/// - `archive: &str`: The path to the chapter or volume archive.
+/// - `page: &str`: The number of the page, starting from 1.
+/// - `mark: bool`: Whether to mark the page; `false` removes matching credit pages of the group.
+///
+/// # Returns
+/// `Result<(), MdownError>`: Returns `Ok(())` if the library of credit pages was updated, or an `MdownError` on failure.
+fn mark_page(archive: &str, page: &str, mark: bool) -> Result<(), MdownError> {
+    let page = match page.parse::<usize>() {
+        Ok(page) => page,
+        Err(err) => {
+            return Err(MdownError::ConversionError(err.to_string()));
+        }
+    };
+    let (name, content, chapter_metadata) = match
+        zip_func::extract_page_from_zip(archive, page, &image_func::PAGE_EXTENSIONS)
+    {
+        Ok(page) => page,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    let image = match image::load_from_memory(&content) {
+        Ok(image) => image,
+        Err(err) => {
+            return Err(MdownError::CustomError(err.to_string(), String::from("ImageError")));
+        }
+    };
+    let hash = perceptual_hash(&image);
+    let group = chapter_metadata.scanlation.name;
+
+    if mark {
+        match db::add_credit_page(&group, hash) {
+            Ok(true) => println!("Marked {} as credit page of {}", name, group),
+            Ok(false) => println!("{} is already marked as credit page of {}", name, group),
+            Err(err) => {
+                return Err(err);
+            }
+        }
+        return Ok(());
+    }
+
+    let known = match db::read_credit_pages(&group) {
+        Ok(known) => known,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    let matching: Vec<u64> = known
+        .into_iter()
+        .filter(|known| is_credit_page(hash, &[*known]))
+        .collect();
+    match db::remove_credit_pages(&group, &matching) {
+        Ok(removed) => {
+            println!("Removed {} credit page(s) of {} matching {}", removed, group, name);
+            Ok(())
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Handles the `database --mark-credit` and `database --unmark-credit` arguments.
+///
+/// # Returns
+/// `Result<(), MdownError>`: Returns `Ok(())` if the library of credit pages was updated, or an `MdownError` on failure.
+pub(crate) fn resolve_mark() -> Result<(), MdownError> {
+    for (values, mark) in [
+        (args::ARGS_MARK_CREDIT.clone(), true),
+        (args::ARGS_UNMARK_CREDIT.clone(), false),
+    ] {
+        if let Some(values) = values {
+            match mark_page(&values[0], &values[1], mark) {
+                Ok(()) => (),
+                Err(err) => {
+                    return Err(err);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// Keeps the hash of a page stable when it is scaled and converted to grayscale
+#[test]
+fn test_perceptual_hash_similar_pages() {
+    let page = DynamicImage::ImageRgb8(
+        image::RgbImage::from_fn(400, 600, |x, y| {
+            let value = if (x / 50 + y / 75) % 2 == 0 { 230 } else { 20 };
+            image::Rgb([value, value / 2, 255 - value])
+        })
+    );
+    let hash = perceptual_hash(&page);
+    let scaled = page.resize_exact(200, 300, FilterType::Lanczos3).grayscale();
+    assert!(is_credit_page(perceptual_hash(&scaled), &[hash]));
+}
+
+// Tells different pages apart
+#[test]
+fn test_perceptual_hash_different_pages() {
+    let checkers = DynamicImage::ImageLuma8(
+        image::GrayImage::from_fn(400, 600, |x, y| {
+            image::Luma([if (x / 50 + y / 75) % 2 == 0 { 230 } else { 20 }])
+        })
+    );
+    let gradient = DynamicImage::ImageLuma8(
+        image::GrayImage::from_fn(400, 600, |x, y| image::Luma([((x + y) % 256) as u8]))
+    );
+    assert!(!is_credit_page(perceptual_hash(&checkers), &[perceptual_hash(&gradient)]));
+}
@@ -0,0 +1,616 @@
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use serde::{ Deserialize, Serialize };
+use serde_json::{ json, Value };
+use std::{ collections::VecDeque, time::Duration };
+use tokio::{
+    io::{ AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader },
+    sync::Notify,
+};
+
+use crate::{
+    args::{ self, CtlCommand, QueueCommand },
+    daemon,
+    error::MdownError,
+    getter,
+    log,
+    resolute,
+    utils,
+};
+
+/// Number of finished downloads shown by `ctl status`.
+const FINISHED_COUNT: usize = 20;
+
+/// Longest request read from a connection, in bytes.
+const MAX_REQUEST: u64 = 64 * 1024;
+
+/// Longest time a request is waited for, so that an idle connection isn't kept open.
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+lazy_static! {
+    /// Manga waiting to be downloaded.
+    static ref QUEUE: Mutex<VecDeque<Job>> = Mutex::new(VecDeque::new());
+
+    /// The manga being downloaded.
+    static ref CURRENT: Mutex<Option<Job>> = Mutex::new(None);
+
+    /// The last downloads that finished, newest last.
+    static ref FINISHED: Mutex<VecDeque<Job>> = Mutex::new(VecDeque::new());
+
+    /// Whether the manga being downloaded was cancelled.
+    static ref CANCELLED: Mutex<bool> = Mutex::new(false);
+
+    /// Wakes up whoever downloads queued manga.
+    pub(crate) static ref QUEUED: Notify = Notify::new();
+
+    /// Held while a manga is downloaded, as downloads share the state of the program.
+    pub(crate) static ref DOWNLOAD: tokio::sync::Mutex<()> = tokio::sync::Mutex::new(());
+}
+
+/// A request sent to a running instance; one JSON object per line.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+pub(crate) enum Request {
+    QueueAdd {
+        url: String,
+    },
+    Status,
+    Cancel {
+        handle: String,
+    },
+    CheckNow,
+}
+
+impl From<&CtlCommand> for Request {
+    fn from(command: &CtlCommand) -> Request {
+        match command {
+            CtlCommand::Queue { command: QueueCommand::Add { url } } =>
+                Request::QueueAdd { url: url.clone() },
+            CtlCommand::Status => Request::Status,
+            CtlCommand::Cancel { handle } => Request::Cancel { handle: handle.clone() },
+            CtlCommand::CheckNow => Request::CheckNow,
+        }
+    }
+}
+
+/// A manga queued with `ctl queue add`.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub(crate) struct Job {
+    pub(crate) handle: String,
+    pub(crate) url: String,
+    pub(crate) id: String,
+    /// `queued`, `running`, `done`, `failed` or `cancelled`.
+    pub(crate) state: String,
+    /// Name of the downloaded manga or why it failed.
+    pub(crate) message: Option<String>,
+    pub(crate) queued_at: String,
+}
+
+/// Returns whether the manga being downloaded was cancelled; downloads stop before the next chapter.
+pub(crate) fn is_cancelled() -> bool {
+    *CANCELLED.lock()
+}
+
+/// Returns the kind of the running instance.
+fn get_mode() -> &'static str {
+    if args::ARGS_DAEMON.is_some() {
+        "daemon"
+    } else if *args::ARGS_WEB {
+        "web"
+    } else {
+        "server"
+    }
+}
+
+/// Returns the ID of a manga from its URL or ID.
+fn get_id(url: &str) -> Option<String> {
+    let id = match utils::resolve_regex(url) {
+        Some(id) => id.as_str().to_string(),
+        None => url.to_string(),
+    };
+    match utils::is_valid_uuid(&id) {
+        true => Some(id),
+        false => None,
+    }
+}
+
+/// Returns the response telling that a request failed.
+fn error_response(message: &str) -> Value {
+    json!({ "status": "error", "message": message })
+}
+
+/// Answers a request.
+///
+/// # Arguments
+/// * `request` - The request.
+///
+/// # Returns
+/// * `Value` - The response; `status` is `ok` or `error`.
+fn handle_request(request: Request) -> Value {
+    match request {
+        Request::QueueAdd { url } => {
+            let id = match get_id(&url) {
+                Some(id) => id,
+                None => {
+                    return error_response(&format!("didn't find ID of manga in {}", url));
+                }
+            };
+            let mut queue = QUEUE.lock();
+            let pending = queue
+                .iter()
+                .chain(CURRENT.lock().iter())
+                .find(|job| job.id == id)
+                .map(|job| job.handle.clone());
+            if let Some(handle) = pending {
+                return error_response(&format!("{} is already queued as {}", id, handle));
+            }
+            let handle = utils::generate_random_id(8).into_string();
+            queue.push_back(Job {
+                handle: handle.clone(),
+                url,
+                id,
+                state: String::from("queued"),
+                message: None,
+                queued_at: chrono::Utc::now().to_rfc3339(),
+            });
+            QUEUED.notify_one();
+            json!({ "status": "ok", "handle": handle })
+        }
+        Request::Status => {
+            let (updating, next_update) = match get_mode() {
+                "daemon" => (*daemon::UPDATING.lock(), daemon::NEXT_UPDATE.lock().clone()),
+                _ => (false, None),
+            };
+            let current = CURRENT.lock().clone();
+            let queue = QUEUE.lock().clone();
+            let finished = FINISHED.lock().clone();
+            json!({
+                "status": "ok",
+                "mode": get_mode(),
+                "pid": std::process::id(),
+                "updating": updating,
+                "next_update": next_update,
+                "current": current,
+                "queue": queue,
+                "finished": finished,
+            })
+        }
+        Request::Cancel { handle } => {
+            let mut queue = QUEUE.lock();
+            if let Some(index) = queue.iter().position(|job| job.handle == handle) {
+                if let Some(mut job) = queue.remove(index) {
+                    job.state = String::from("cancelled");
+                    finish(job);
+                }
+                return json!({ "status": "ok", "message": "removed from queue" });
+            }
+            let running = match CURRENT.lock().as_ref() {
+                Some(job) => job.handle == handle,
+                None => false,
+            };
+            if running {
+                *CANCELLED.lock() = true;
+                return json!({ "status": "ok", "message": "stopping after current chapter" });
+            }
+            error_response(&format!("didn't find queued manga {}", handle))
+        }
+        Request::CheckNow =>
+            match get_mode() {
+                "daemon" => {
+                    daemon::check_now();
+                    json!({ "status": "ok" })
+                }
+                mode => error_response(&format!("check-now is only supported by daemon, not {}", mode)),
+            }
+    }
+}
+
+/// Keeps a finished download for `ctl status`.
+fn finish(job: Job) {
+    let mut finished = FINISHED.lock();
+    finished.push_back(job);
+    while finished.len() > FINISHED_COUNT {
+        finished.pop_front();
+    }
+}
+
+/// Downloads a manga like the web interface does.
+///
+/// # Arguments
+/// * `id` - The ID of the manga.
+///
+/// # Returns
+/// * `Result<String, MdownError>` - Returns the name of the manga, or an `MdownError` if it can't be downloaded.
+async fn download(id: &str) -> Result<String, MdownError> {
+    let manga_json = match getter::get_manga_json(id).await {
+        Ok(json) => json,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    match utils::get_json(&manga_json) {
+        Ok(Value::Object(obj)) => {
+            *resolute::MANGA_ID.lock() = id.to_string();
+            resolute::resolve(obj, id).await
+        }
+        Ok(_) => Err(MdownError::JsonError(String::from("Could not parse manga json"))),
+        Err(err) => Err(err),
+    }
+}
+
+/// Downloads queued manga one at a time until the queue is empty or the daemon is stopped.
+pub(crate) async fn run_queue() {
+    loop {
+        if daemon::is_stopping() {
+            break;
+        }
+        let mut job = match QUEUE.lock().pop_front() {
+            Some(job) => job,
+            None => {
+                break;
+            }
+        };
+        job.state = String::from("running");
+        *CURRENT.lock() = Some(job.clone());
+        log!(&format!("Downloading {} queued as {}", job.url, job.handle));
+
+        let result = {
+            let _download = DOWNLOAD.lock().await;
+            download(&job.id).await
+        };
+        let cancelled = std::mem::take(&mut *CANCELLED.lock());
+        match result {
+            Ok(name) => {
+                job.state = String::from(if cancelled { "cancelled" } else { "done" });
+                job.message = Some(name);
+            }
+            Err(err) => {
+                job.state = String::from("failed");
+                job.message = Some(err.to_string());
+            }
+        }
+        log!(&format!("Download queued as {} {}", job.handle, job.state));
+        *CURRENT.lock() = None;
+        finish(job);
+
+        match daemon::reset_cache() {
+            Ok(()) => (),
+            Err(err) => log!(&format!("Error: {}", err)),
+        }
+    }
+}
+
+/// Downloads manga as they are queued; used by web and server, as the daemon downloads them between updates.
+async fn work() {
+    loop {
+        QUEUED.notified().await;
+        run_queue().await;
+    }
+}
+
+/// Reads a request of at most `MAX_REQUEST` bytes.
+///
+/// # Arguments
+/// * `reader` - The connection.
+/// * `timeout` - The longest time the request is waited for.
+///
+/// # Returns
+/// * `Result<String, String>` - Returns the line of the request, or why it can't be read.
+async fn read_request<R>(reader: R, timeout: Duration) -> Result<String, String>
+    where R: AsyncRead + Unpin
+{
+    let mut line = String::new();
+    let mut reader = BufReader::new(reader.take(MAX_REQUEST + 1));
+    match tokio::time::timeout(timeout, reader.read_line(&mut line)).await {
+        Ok(Ok(_n)) if (line.len() as u64) > MAX_REQUEST =>
+            Err(format!("request is longer than {} bytes", MAX_REQUEST)),
+        Ok(Ok(_n)) => Ok(line),
+        Ok(Err(err)) => Err(format!("failed to read request: {}", err)),
+        Err(_elapsed) => Err(format!("no request in {}ms", timeout.as_millis())),
+    }
+}
+
+/// Answers one request read from a connection.
+///
+/// # Arguments
+/// * `stream` - The connection.
+async fn answer<S>(stream: S) where S: AsyncRead + AsyncWrite + Unpin {
+    let (reader, mut writer) = tokio::io::split(stream);
+    let response = match read_request(reader, READ_TIMEOUT).await {
+        Ok(line) =>
+            match serde_json::from_str::<Request>(&line) {
+                Ok(request) => handle_request(request),
+                Err(err) => error_response(&format!("invalid request: {}", err)),
+            }
+        Err(err) => error_response(&err),
+    };
+    match writer.write_all(format!("{}\n", response).as_bytes()).await {
+        Ok(()) => (),
+        Err(_err) => (),
+    }
+    match writer.shutdown().await {
+        Ok(()) => (),
+        Err(_err) => (),
+    }
+}
+
+/// Sends a request over a connection and reads the response.
+///
+/// # Arguments
+/// * `stream` - The connection.
+/// * `request` - The request.
+///
+/// # Returns
+/// * `Result<Value, MdownError>` - Returns the response, or an `MdownError` if it can't be sent or read.
+async fn exchange<S>(stream: S, request: &Request) -> Result<Value, MdownError>
+    where S: AsyncRead + AsyncWrite + Unpin
+{
+    let line = match serde_json::to_string(request) {
+        Ok(line) => line,
+        Err(err) => {
+            return Err(MdownError::JsonError(err.to_string()));
+        }
+    };
+    let (reader, mut writer) = tokio::io::split(stream);
+    match writer.write_all(format!("{}\n", line).as_bytes()).await {
+        Ok(()) => (),
+        Err(err) => {
+            return Err(MdownError::IoError(err, String::from("ctl")));
+        }
+    }
+    let mut response = String::new();
+    match BufReader::new(reader).read_line(&mut response).await {
+        Ok(_n) => (),
+        Err(err) => {
+            return Err(MdownError::IoError(err, String::from("ctl")));
+        }
+    }
+    match serde_json::from_str(&response) {
+        Ok(value) => Ok(value),
+        Err(err) => Err(MdownError::JsonError(err.to_string())),
+    }
+}
+
+/// Returns the name of the named pipe; instances with different folders of the program use different pipes.
+#[cfg(windows)]
+fn get_pipe_name() -> Result<String, MdownError> {
+    match getter::get_ctl_path() {
+        Ok(path) => {
+            let name: String = path
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                .collect();
+            Ok(format!(r"\\.\pipe\mdown-{}", name))
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Answers requests on the control socket until the program exits.
+#[cfg(unix)]
+async fn listen() -> Result<(), MdownError> {
+    use std::{ fs, path::Path };
+    use tokio::net::{ UnixListener, UnixStream };
+
+    let path = match getter::get_ctl_path() {
+        Ok(path) => path,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    if Path::new(&path).exists() {
+        // A socket left behind by an instance that didn't exit cleanly is replaced
+        if UnixStream::connect(&path).await.is_ok() {
+            return Err(
+                MdownError::CustomError(
+                    format!("another instance is listening on {}", path),
+                    String::from("Ctl")
+                )
+            );
+        }
+        match fs::remove_file(&path) {
+            Ok(()) => (),
+            Err(err) => {
+                return Err(MdownError::IoError(err, path));
+            }
+        }
+    }
+    let listener = match UnixListener::bind(&path) {
+        Ok(listener) => listener,
+        Err(err) => {
+            return Err(MdownError::IoError(err, path));
+        }
+    };
+    log!(&format!("Listening for ctl commands on {}", path));
+    loop {
+        match listener.accept().await {
+            Ok((stream, _addr)) => {
+                tokio::spawn(answer(stream));
+            }
+            Err(err) => log!(&format!("Error accepting ctl connection: {}", err)),
+        }
+    }
+}
+
+/// Answers requests on the named pipe until the program exits.
+#[cfg(windows)]
+async fn listen() -> Result<(), MdownError> {
+    use tokio::net::windows::named_pipe::ServerOptions;
+
+    let name = match get_pipe_name() {
+        Ok(name) => name,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    let mut server = match ServerOptions::new().first_pipe_instance(true).create(&name) {
+        Ok(server) => server,
+        Err(err) => {
+            return Err(MdownError::IoError(err, name));
+        }
+    };
+    log!(&format!("Listening for ctl commands on {}", name));
+    loop {
+        match server.connect().await {
+            Ok(()) => (),
+            Err(err) => {
+                return Err(MdownError::IoError(err, name));
+            }
+        }
+        let client = server;
+        server = match ServerOptions::new().create(&name) {
+            Ok(server) => server,
+            Err(err) => {
+                return Err(MdownError::IoError(err, name));
+            }
+        };
+        tokio::spawn(answer(client));
+    }
+}
+
+/// Starts answering `ctl` commands and, outside of the daemon, downloading queued manga.
+pub(crate) fn serve() {
+    tokio::spawn(async {
+        match listen().await {
+            Ok(()) => (),
+            Err(err) => log!(&format!("ctl commands aren't available: {}", err)),
+        }
+    });
+    if args::ARGS_DAEMON.is_none() {
+        tokio::spawn(work());
+    }
+}
+
+/// Removes the control socket when the program exits.
+pub(crate) fn stop_serving() {
+    #[cfg(unix)]
+    if let Ok(path) = getter::get_ctl_path() {
+        match std::fs::remove_file(&path) {
+            Ok(()) => (),
+            Err(_err) => (),
+        }
+    }
+}
+
+/// Sends the command given to `ctl` to the running instance and prints its response.
+///
+/// # Returns
+/// * `Result<(), MdownError>` - Returns `Ok(())` if the command succeeded, or an `MdownError` if no instance is running or the command failed.
+pub(crate) async fn send() -> Result<(), MdownError> {
+    let request = match args::ARGS_CTL.as_ref() {
+        Some(command) => Request::from(command),
+        None => {
+            return Ok(());
+        }
+    };
+
+    #[cfg(unix)]
+    let stream = {
+        let path = match getter::get_ctl_path() {
+            Ok(path) => path,
+            Err(err) => {
+                return Err(err);
+            }
+        };
+        match tokio::net::UnixStream::connect(&path).await {
+            Ok(stream) => stream,
+            Err(err) => {
+                return Err(
+                    MdownError::CustomError(
+                        format!("no running daemon, web or server instance found ({})", err),
+                        String::from("Ctl")
+                    )
+                );
+            }
+        }
+    };
+    #[cfg(windows)]
+    let stream = {
+        let name = match get_pipe_name() {
+            Ok(name) => name,
+            Err(err) => {
+                return Err(err);
+            }
+        };
+        match tokio::net::windows::named_pipe::ClientOptions::new().open(&name) {
+            Ok(stream) => stream,
+            Err(err) => {
+                return Err(
+                    MdownError::CustomError(
+                        format!("no running daemon, web or server instance found ({})", err),
+                        String::from("Ctl")
+                    )
+                );
+            }
+        }
+    };
+
+    let response = match exchange(stream, &request).await {
+        Ok(response) => response,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    if response.get("status").and_then(Value::as_str) != Some("ok") {
+        let message = response
+            .get("message")
+            .and_then(Value::as_str)
+            .unwrap_or("unknown error")
+            .to_string();
+        return Err(MdownError::CustomError(message, String::from("Ctl")));
+    }
+    match serde_json::to_string_pretty(&response) {
+        Ok(response) => println!("{}", response),
+        Err(err) => {
+            return Err(MdownError::JsonError(err.to_string()));
+        }
+    }
+    Ok(())
+}
+
+// Reads requests in the format sent by other instances
+#[test]
+fn test_request_format() {
+    let request: Request = serde_json
+        ::from_str(r#"{"command":"queue-add","url":"https://mangadex.org/title/x"}"#)
+        .unwrap();
+    assert_eq!(request, Request::QueueAdd { url: String::from("https://mangadex.org/title/x") });
+    assert_eq!(serde_json::to_string(&Request::CheckNow).unwrap(), r#"{"command":"check-now"}"#);
+    assert!(serde_json::from_str::<Request>(r#"{"command":"unknown"}"#).is_err());
+}
+
+// Queues manga once and cancels them by their handle
+#[tokio::test]
+async fn test_queue_over_connection() {
+    let id = "123e4567-e89b-12d3-a456-426614174000";
+    let (client, server) = tokio::io::duplex(4096);
+    tokio::spawn(answer(server));
+    let url = format!("https://mangadex.org/title/{}", id);
+    let response = exchange(client, &Request::QueueAdd { url: url.clone() }).await.unwrap();
+    assert_eq!(response["status"], "ok");
+    let handle = response["handle"].as_str().unwrap().to_string();
+
+    let again = handle_request(Request::QueueAdd { url: String::from(id) });
+    assert_eq!(again["status"], "error");
+    assert_eq!(handle_request(Request::QueueAdd { url: String::from("x") })["status"], "error");
+
+    assert_eq!(QUEUE.lock()[0].handle, handle);
+    assert_eq!(handle_request(Request::Cancel { handle: handle.clone() })["status"], "ok");
+    assert_eq!(handle_request(Request::Cancel { handle })["status"], "error");
+    assert!(QUEUE.lock().is_empty());
+    assert_eq!(FINISHED.lock()[0].state, "cancelled");
+}
+
+// Refuses requests that are too long or never sent
+#[tokio::test]
+async fn test_read_request_limits() {
+    let long = format!("{}\n", "x".repeat((MAX_REQUEST as usize) + 10));
+    let err = read_request(long.as_bytes(), READ_TIMEOUT).await.unwrap_err();
+    assert!(err.contains("longer than"));
+    let line = read_request(&b"{\"command\":\"status\"}\n"[..], READ_TIMEOUT).await.unwrap();
+    assert_eq!(line, "{\"command\":\"status\"}\n");
+
+    let (_client, server) = tokio::io::duplex(64);
+    let err = read_request(server, Duration::from_millis(50)).await.unwrap_err();
+    assert!(err.starts_with("no request"));
+}
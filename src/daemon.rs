@@ -0,0 +1,314 @@
+use chrono::Local;
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use rand::Rng;
+use std::{ path::Path, process::exit, time::Duration };
+use tokio::{ sync::Notify, time::Instant };
+
+use crate::{
+    args,
+    ctl,
+    error::{ MdownError, SUSPENDED },
+    getter,
+    hooks,
+    library,
+    log,
+    resolute,
+    utils::{ self, LockState },
+};
+
+/// Shortest time between updates, so that MangaDex isn't asked about the whole library all the time.
+const MIN_EVERY: Duration = Duration::from_secs(60);
+
+lazy_static! {
+    /// Whether the daemon was asked to stop.
+    static ref STOPPING: Mutex<bool> = Mutex::new(false);
+
+    /// Wakes the daemon up when it is asked to stop or to update while waiting for the next update.
+    static ref WAKE: Notify = Notify::new();
+
+    /// Whether an update was asked for with `ctl check-now`.
+    static ref CHECK_NOW: Mutex<bool> = Mutex::new(false);
+
+    /// Whether the library is being updated.
+    pub(crate) static ref UPDATING: Mutex<bool> = Mutex::new(false);
+
+    /// When the next update starts.
+    pub(crate) static ref NEXT_UPDATE: Mutex<Option<String>> = Mutex::new(None);
+}
+
+/// Returns whether the daemon was asked to stop; downloads stop before the next chapter.
+pub(crate) fn is_stopping() -> bool {
+    *STOPPING.lock()
+}
+
+/// Asks the daemon to update the library now instead of at the next scheduled update.
+pub(crate) fn check_now() {
+    *CHECK_NOW.lock() = true;
+    WAKE.notify_one();
+}
+
+/// Parses a duration such as `90s`, `30m`, `6h` or `1d`; a number without unit is in seconds.
+///
+/// # Arguments
+/// * `value` - The duration.
+///
+/// # Returns
+/// * `Ok(Duration)` - The duration.
+/// * `Err(String)` - If the number or the unit isn't valid.
+pub(crate) fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let (number, unit) = value.split_at(
+        value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len())
+    );
+    let number: u64 = match number.parse() {
+        Ok(number) => number,
+        Err(_err) => {
+            return Err(format!("'{}' doesn't start with a number", value));
+        }
+    };
+    let seconds = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        unit => {
+            return Err(format!("unknown unit '{}'; use s, m, h or d", unit));
+        }
+    };
+    match number.checked_mul(seconds) {
+        Some(seconds) => Ok(Duration::from_secs(seconds)),
+        None => Err(format!("'{}' is too long", value)),
+    }
+}
+
+/// Returns the time to wait for the next update.
+///
+/// # Arguments
+/// * `every` - The time between updates.
+/// * `jitter` - The most time added to it.
+/// * `fraction` - The part of `jitter` that is added, from 0 to 1.
+fn next_delay(every: Duration, jitter: Duration, fraction: f64) -> Duration {
+    every + jitter.mul_f64(fraction.clamp(0.0, 1.0))
+}
+
+/// Waits for SIGTERM or Ctrl+C.
+async fn wait_for_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{ signal, SignalKind };
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = terminate.recv() => (),
+                    _ = tokio::signal::ctrl_c() => (),
+                }
+            }
+            Err(err) => {
+                log!(&format!("Failed to listen for SIGTERM: {}", err));
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+/// Asks the daemon to stop on the first signal and exits at once on the second one.
+async fn handle_signals() {
+    loop {
+        wait_for_signal().await;
+        if is_stopping() {
+            log!("Signal received again; exiting without finishing current chapter");
+            exit(1);
+        }
+        log!("Signal received; stopping after current chapter is downloaded");
+        *STOPPING.lock() = true;
+        WAKE.notify_one();
+    }
+}
+
+/// Logs the errors suspended by an update, so that they aren't printed all at once when the daemon stops.
+fn log_suspended() {
+    let suspended: Vec<MdownError> = SUSPENDED.lock().drain(..).collect();
+    for err in suspended.iter() {
+        log!(&format!("Error: {}", err));
+        hooks::fire(hooks::Event { message: err.to_string(), ..hooks::Event::new("error") });
+    }
+}
+
+/// Drops what a cycle of the daemon left behind, so that it doesn't pile up while the daemon runs.
+///
+/// Logs are only drained by the log handler of `--log`, so they are dropped without it. The library is backed up again the next time a cycle changes it, and hooks are read again.
+fn end_cycle() {
+    if !*args::ARGS_LOG {
+        resolute::LOGS.lock().clear();
+        resolute::HANDLE_ID_END.lock().clear();
+    }
+    library::forget_backup();
+    hooks::reload();
+}
+
+/// Empties the cache folder after an update or a download, so that the next one starts with a new one.
+///
+/// # Returns
+/// * `Result<(), MdownError>` - Returns `Ok(())` if the cache folder is ready, or an `MdownError` if it can't be created.
+pub(crate) fn reset_cache() -> Result<(), MdownError> {
+    match utils::remove_cache() {
+        Ok(()) => (),
+        Err(err) => {
+            return Err(err);
+        }
+    }
+    if !Path::new(".cache").exists() {
+        return utils::create_cache_folder();
+    }
+    Ok(())
+}
+
+/// Updates the library like `database --update` on a schedule until SIGTERM or Ctrl+C is received.
+///
+/// Only one daemon runs at a time. A signal lets the chapter being downloaded be finished and archived, then the library is saved and the daemon exits.
+///
+/// # Returns
+/// * `Result<(), MdownError>` - Returns `Ok(())` when the daemon was stopped, or an `MdownError` if it can't start.
+pub(crate) async fn start() -> Result<(), MdownError> {
+    let (every, jitter) = match *args::ARGS_DAEMON {
+        Some(schedule) => schedule,
+        None => {
+            return Ok(());
+        }
+    };
+    if every < MIN_EVERY {
+        return Err(
+            MdownError::CustomError(
+                String::from("time between updates has to be at least a minute"),
+                String::from("Daemon")
+            )
+        );
+    }
+
+    let _daemon_lock = match getter::get_lock_path("daemon") {
+        Ok(path) =>
+            match utils::acquire_lock(&path) {
+                Ok(LockState::Acquired(guard)) => guard,
+                Ok(LockState::Held(owner)) => {
+                    return Err(
+                        MdownError::CustomError(
+                            format!(
+                                "daemon is already running as process {} on {} started at {}",
+                                owner.pid,
+                                owner.host,
+                                owner.started
+                            ),
+                            String::from("Daemon")
+                        )
+                    );
+                }
+                Err(err) => {
+                    return Err(err);
+                }
+            }
+        Err(err) => {
+            return Err(err);
+        }
+    };
+
+    tokio::spawn(handle_signals());
+    ctl::serve();
+    log!(
+        &format!(
+            "Daemon started; updating library every {}s with up to {}s added",
+            every.as_secs(),
+            jitter.as_secs()
+        )
+    );
+
+    let mut next = Instant::now();
+    loop {
+        let asked = std::mem::take(&mut *CHECK_NOW.lock());
+        if asked || Instant::now() >= next {
+            log!("Updating library");
+            *UPDATING.lock() = true;
+            match resolute::resolve_check().await {
+                Ok(()) => log!("Library updated"),
+                Err(err) => log!(&format!("Updating library failed: {}", err)),
+            }
+            *UPDATING.lock() = false;
+            log_suspended();
+            match reset_cache() {
+                Ok(()) => (),
+                Err(err) => {
+                    return Err(err);
+                }
+            }
+            if is_stopping() {
+                break;
+            }
+
+            let delay = next_delay(every, jitter, rand::thread_rng().gen::<f64>());
+            next = Instant::now() + delay;
+            let time = match chrono::Duration::from_std(delay) {
+                Ok(delay) => (Local::now() + delay).format("%Y-%m-%d %H:%M:%S").to_string(),
+                Err(_err) => String::from("later"),
+            };
+            log!(&format!("Next update at {}", time));
+            *NEXT_UPDATE.lock() = Some(time);
+        }
+
+        // Manga queued with `ctl queue add` are downloaded between updates
+        ctl::run_queue().await;
+        log_suspended();
+        end_cycle();
+        if is_stopping() {
+            break;
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep_until(next) => (),
+            _ = WAKE.notified() => (),
+            _ = ctl::QUEUED.notified() => (),
+        }
+        if is_stopping() {
+            break;
+        }
+    }
+
+    ctl::stop_serving();
+    log!("Daemon stopped");
+    *resolute::ENDED.lock() = true;
+    Ok(())
+}
+
+// Reads durations with and without units
+#[test]
+fn test_parse_duration() {
+    assert_eq!(parse_duration("90s"), Ok(Duration::from_secs(90)));
+    assert_eq!(parse_duration("30m"), Ok(Duration::from_secs(30 * 60)));
+    assert_eq!(parse_duration("6h"), Ok(Duration::from_secs(6 * 60 * 60)));
+    assert_eq!(parse_duration("1d"), Ok(Duration::from_secs(24 * 60 * 60)));
+    assert_eq!(parse_duration("45"), Ok(Duration::from_secs(45)));
+    assert!(parse_duration("h").is_err());
+    assert!(parse_duration("5w").is_err());
+    assert!(parse_duration("99999999999999999999d").is_err());
+}
+
+// Adds at most the whole jitter to the time between updates
+#[test]
+fn test_next_delay() {
+    let every = Duration::from_secs(600);
+    let jitter = Duration::from_secs(60);
+    assert_eq!(next_delay(every, jitter, 0.0), every);
+    assert_eq!(next_delay(every, jitter, 0.5), Duration::from_secs(630));
+    assert_eq!(next_delay(every, jitter, 2.0), Duration::from_secs(660));
+}
+
+// Drops logs that no log handler writes
+#[test]
+fn test_end_cycle() {
+    resolute::LOGS.lock().push(crate::metadata::Log::new("Updating library"));
+    end_cycle();
+    assert!(resolute::LOGS.lock().is_empty());
+}
@@ -1,7 +1,7 @@
 use rusqlite::{ Connection, OptionalExtension, params };
-use std::{ io::{ Read, Write }, process::Command, result::Result };
+use std::{ io::{ Read, Write }, path::Path, process::Command, result::Result };
 
-use crate::{ args, download, debug, error::MdownError, getter, metadata };
+use crate::{ args, download, debug, error::MdownError, getter, metadata, scan, template, zip_func };
 
 include!(concat!(env!("OUT_DIR"), "/data_json.rs"));
 
@@ -14,6 +14,9 @@ include!(concat!(env!("OUT_DIR"), "/data_json.rs"));
 /// - `data`: A text field that cannot be null, intended to store the resource's data.
 /// - `is_binary`: A boolean field indicating whether the resource data is binary.
 ///
+/// It also creates the `credit_pages` table holding the perceptual hashes of credit pages marked by the user
+/// for each scanlation group, and the `hooks` table holding the commands and URLs told about events.
+///
 /// # Arguments
 /// * `conn` - A reference to a `Connection` object representing the database connection.
 ///
@@ -43,6 +46,39 @@ fn initialize_db(conn: &Connection) -> Result<(), MdownError> {
             return Err(MdownError::DatabaseError(err));
         }
     }
+    match
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS credit_pages (
+            id INTEGER PRIMARY KEY,
+            scanlation_group TEXT NOT NULL,
+            hash INTEGER NOT NULL,
+            UNIQUE(scanlation_group, hash)
+        )",
+            []
+        )
+    {
+        Ok(_) => (),
+        Err(err) => {
+            return Err(MdownError::DatabaseError(err));
+        }
+    }
+    match
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS hooks (
+            id INTEGER PRIMARY KEY,
+            event TEXT NOT NULL,
+            kind TEXT NOT NULL,
+            target TEXT NOT NULL,
+            UNIQUE(event, kind, target)
+        )",
+            []
+        )
+    {
+        Ok(_) => (),
+        Err(err) => {
+            return Err(MdownError::DatabaseError(err));
+        }
+    }
     Ok(())
 }
 /// Reads a resource from the database by its name.
@@ -365,7 +401,7 @@ pub(crate) async fn init() -> Result<(), MdownError> {
                 // Execute yt-dlp to process the file
                 for _ in 0..2 {
                     match
-                        Command::new(".\\yt-dlp_min.exe")
+                        Command::new(Path::new(".").join("yt-dlp_min.exe"))
                             .arg(url)
                             .arg("--output")
                             .arg(name)
@@ -689,6 +725,55 @@ async fn download_yt_dlp(full_path: &str) -> Result<(), MdownError> {
     Ok(())
 }
 
+/// Writes a setting to the database, or removes it when no value is supplied.
+///
+/// # Arguments
+/// * `conn` - A reference to the SQLite database connection.
+/// * `name` - The name of the setting.
+/// * `value` - `Some(Some(value))` stores the value, `Some(None)` removes the setting and `None` leaves it untouched.
+///
+/// # Returns
+/// * `Result<(), MdownError>` - Returns `Ok(())` on success, or an `MdownError` on failure.
+fn update_setting(
+    conn: &Connection,
+    name: &str,
+    value: Option<Option<String>>
+) -> Result<(), MdownError> {
+    match value {
+        Some(Some(value)) =>
+            match write_resource(conn, name, value.as_bytes(), false) {
+                Ok(_id) => Ok(()),
+                Err(err) => Err(err),
+            }
+        Some(None) =>
+            match delete_resource(conn, name) {
+                Ok(_id) => Ok(()),
+                Err(err) => Err(err),
+            }
+        None => Ok(()),
+    }
+}
+
+/// Reads a text setting from the database.
+///
+/// # Arguments
+/// * `conn` - A reference to the SQLite database connection.
+/// * `name` - The name of the setting.
+///
+/// # Returns
+/// * `Result<Option<String>, MdownError>` - Returns the stored value, `None` if the setting is not set, or an `MdownError` on failure.
+fn read_setting(conn: &Connection, name: &str) -> Result<Option<String>, MdownError> {
+    match read_resource(conn, name) {
+        Ok(Some(value)) =>
+            match String::from_utf8(value) {
+                Ok(value) => Ok(Some(value)),
+                Err(err) => Err(MdownError::CustomError(err.to_string(), String::from("Base64Error"))),
+            }
+        Ok(None) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
 /// Sets up settings by configuring database access and updating settings based on command-line arguments.
 ///
 /// This function performs the following tasks:
@@ -745,25 +830,93 @@ pub(crate) fn setup_settings() -> Result<metadata::Settings, MdownError> {
 
     // Update settings in the database based on command-line arguments
     match args::ARGS.lock().subcommands.clone() {
-        Some(args::Commands::Settings { folder }) => {
-            match folder {
-                Some(Some(folder)) => {
-                    match write_resource(&conn, "folder", folder.as_bytes(), false) {
-                        Ok(_id) => (),
-                        Err(err) => {
-                            return Err(err);
+        Some(
+            args::Commands::Settings {
+                folder,
+                name_template,
+                compression,
+                compression_level,
+                scan_server,
+                ..
+            },
+        ) => {
+            let stored_compression = match read_setting(&conn, "compression") {
+                Ok(value) => value,
+                Err(err) => {
+                    return Err(err);
+                }
+            };
+            // A level belongs to its method, so it is dropped when only the method is changed
+            let compression_level = match compression_level {
+                None if
+                    compression.is_some() &&
+                    compression.clone().flatten() != stored_compression
+                => Some(None),
+                level => level,
+            };
+            let stored_level = match read_setting(&conn, "compression_level") {
+                Ok(value) => value,
+                Err(err) => {
+                    return Err(err);
+                }
+            };
+            // The settings are checked as they will be stored before anything is written
+            let new_compression = match &compression {
+                Some(value) => value.clone(),
+                None => stored_compression,
+            };
+            let new_level = match compression_level {
+                Some(level) => level,
+                None =>
+                    match stored_level.map(|level| level.parse::<i32>()) {
+                        Some(Ok(level)) => Some(level),
+                        Some(Err(err)) => {
+                            return Err(MdownError::ConversionError(err.to_string()));
                         }
+                        None => None,
                     }
+            };
+            match
+                zip_func::get_compression(
+                    new_compression.as_deref().unwrap_or("stored"),
+                    new_level
+                )
+            {
+                Ok(_compression) => (),
+                Err(err) => {
+                    return Err(err);
                 }
-                Some(None) => {
-                    match delete_resource(&conn, "folder") {
-                        Ok(_id) => (),
+            }
+            let compression_level = compression_level.map(|level|
+                level.map(|level| level.to_string())
+            );
+            // Without values the scan server is removed
+            let scan_server = match scan_server {
+                Some(values) if values.is_empty() => Some(None),
+                Some(values) =>
+                    match scan::parse_server(&values) {
+                        Ok(server) => Some(Some(server)),
                         Err(err) => {
                             return Err(err);
                         }
                     }
+                None => None,
+            };
+            for (name, value) in [
+                ("folder", folder),
+                ("name_template", name_template),
+                ("compression", compression),
+                ("compression_level", compression_level),
+                ("scan_kind", scan_server.clone().map(|server| server.map(|server| server.kind))),
+                ("scan_url", scan_server.clone().map(|server| server.map(|server| server.url))),
+                ("scan_key", scan_server.map(|server| server.map(|server| server.key))),
+            ] {
+                match update_setting(&conn, name, value) {
+                    Ok(()) => (),
+                    Err(err) => {
+                        return Err(err);
+                    }
                 }
-                None => (),
             }
         }
         Some(_) => (),
@@ -771,31 +924,378 @@ pub(crate) fn setup_settings() -> Result<metadata::Settings, MdownError> {
     }
 
     // Read the folder setting from the database
-    let folder = match read_resource(&conn, "folder") {
-        Ok(Some(value)) =>
-            match
-                String::from_utf8(value).map_err(|e|
-                    MdownError::CustomError(e.to_string(), String::from("Base64Error"))
-                )
-            {
-                Ok(folder) => {
-                    debug!("folder from database: {:?}", folder);
-                    folder
+    let folder = match read_setting(&conn, "folder") {
+        Ok(Some(folder)) => {
+            debug!("folder from database: {:?}", folder);
+            folder
+        }
+        Ok(None) => args::ARGS.lock().folder.clone(),
+        Err(err) => {
+            return Err(err);
+        }
+    };
+
+    // Name template supplied on the command line takes precedence over the stored setting
+    let name_template = match args::ARGS.lock().name_template.clone() {
+        value if !value.is_empty() => value,
+        _ =>
+            match read_setting(&conn, "name_template") {
+                Ok(Some(value)) => value,
+                Ok(None) => String::new(),
+                Err(err) => {
+                    return Err(err);
                 }
+            }
+    };
+    match template::validate(&folder, &name_template) {
+        Ok(()) => (),
+        Err(err) => {
+            return Err(err);
+        }
+    }
+
+    // Compression supplied on the command line takes precedence over the stored setting
+    let stored_compression = match read_setting(&conn, "compression") {
+        Ok(Some(value)) => value,
+        Ok(None) => String::from("stored"),
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    let compression = match args::ARGS.lock().compression.clone() {
+        value if !value.is_empty() => value,
+        _ => stored_compression.clone(),
+    };
+    let compression_level = match args::ARGS.lock().compression_level {
+        Some(level) => Some(level),
+        // The stored level belongs to the stored method
+        None if compression != stored_compression => None,
+        None =>
+            match read_setting(&conn, "compression_level") {
+                Ok(Some(value)) =>
+                    match value.parse::<i32>() {
+                        Ok(level) => Some(level),
+                        Err(err) => {
+                            return Err(MdownError::ConversionError(err.to_string()));
+                        }
+                    }
+                Ok(None) => None,
                 Err(err) => {
                     return Err(err);
                 }
             }
-        Ok(None) => args::ARGS.lock().folder.clone(),
+    };
+    match zip_func::get_compression(&compression, compression_level) {
+        Ok(_compression) => (),
         Err(err) => {
             return Err(err);
         }
+    }
+
+    let mut scan_values = Vec::new();
+    for name in ["scan_kind", "scan_url", "scan_key"] {
+        match read_setting(&conn, name) {
+            Ok(value) => scan_values.push(value),
+            Err(err) => {
+                return Err(err);
+            }
+        }
+    }
+    let scan_server = match scan_values.as_slice() {
+        [Some(kind), Some(url), Some(key)] =>
+            Some(metadata::ScanServerMetadata {
+                kind: kind.clone(),
+                url: url.clone(),
+                key: key.clone(),
+            }),
+        _ => None,
     };
 
     // Create and return the settings object
-    let settings = metadata::Settings { folder };
+    let settings = metadata::Settings {
+        folder,
+        name_template,
+        compression,
+        compression_level,
+        scan_server,
+    };
 
     debug!("{:?}\n", settings);
 
     Ok(settings)
 }
+
+/// Opens the database and makes sure its tables exist.
+///
+/// # Returns
+/// * `Result<Connection, MdownError>` - Returns the connection, or an `MdownError` on failure.
+fn open_db() -> Result<Connection, MdownError> {
+    let db_path = match getter::get_db_path() {
+        Ok(path) => path,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    let conn = match Connection::open(&db_path) {
+        Ok(conn) => conn,
+        Err(err) => {
+            return Err(MdownError::DatabaseError(err));
+        }
+    };
+    match initialize_db(&conn) {
+        Ok(()) => Ok(conn),
+        Err(err) => Err(err),
+    }
+}
+
+/// Reads the hashes of the credit pages marked for a scanlation group.
+///
+/// # Arguments
+/// * `group` - The name of the scanlation group.
+///
+/// # Returns
+/// * `Result<Vec<u64>, MdownError>` - Returns the perceptual hashes, or an `MdownError` on failure.
+#[cfg(feature = "transcode")]
+pub(crate) fn read_credit_pages(group: &str) -> Result<Vec<u64>, MdownError> {
+    let conn = match open_db() {
+        Ok(conn) => conn,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    let mut stmt = match conn.prepare("SELECT hash FROM credit_pages WHERE scanlation_group = ?1") {
+        Ok(stmt) => stmt,
+        Err(err) => {
+            return Err(MdownError::DatabaseError(err));
+        }
+    };
+    let rows = match stmt.query_map(params![group], |row| row.get::<_, i64>(0)) {
+        Ok(rows) => rows,
+        Err(err) => {
+            return Err(MdownError::DatabaseError(err));
+        }
+    };
+    let mut hashes = Vec::new();
+    for row in rows {
+        match row {
+            // Hashes are stored bit for bit as signed integers
+            Ok(hash) => hashes.push(hash as u64),
+            Err(err) => {
+                return Err(MdownError::DatabaseError(err));
+            }
+        }
+    }
+    Ok(hashes)
+}
+
+/// Adds the hash of a credit page to the credit pages of a scanlation group.
+///
+/// # Arguments
+/// * `group` - The name of the scanlation group.
+/// * `hash` - The perceptual hash of the page.
+///
+/// # Returns
+/// * `Result<bool, MdownError>` - Returns `Ok(false)` if the hash was already marked, or an `MdownError` on failure.
+#[cfg(feature = "transcode")]
+pub(crate) fn add_credit_page(group: &str, hash: u64) -> Result<bool, MdownError> {
+    let conn = match open_db() {
+        Ok(conn) => conn,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    match
+        conn.execute(
+            "INSERT OR IGNORE INTO credit_pages (scanlation_group, hash) VALUES (?1, ?2)",
+            params![group, hash as i64]
+        )
+    {
+        Ok(changed) => Ok(changed > 0),
+        Err(err) => Err(MdownError::DatabaseError(err)),
+    }
+}
+
+/// Removes hashes from the credit pages of a scanlation group.
+///
+/// # Arguments
+/// * `group` - The name of the scanlation group.
+/// * `hashes` - The perceptual hashes to remove.
+///
+/// # Returns
+/// * `Result<usize, MdownError>` - Returns the number of removed hashes, or an `MdownError` on failure.
+#[cfg(feature = "transcode")]
+pub(crate) fn remove_credit_pages(group: &str, hashes: &[u64]) -> Result<usize, MdownError> {
+    let conn = match open_db() {
+        Ok(conn) => conn,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    let mut removed = 0;
+    for hash in hashes {
+        match
+            conn.execute(
+                "DELETE FROM credit_pages WHERE scanlation_group = ?1 AND hash = ?2",
+                params![group, *hash as i64]
+            )
+        {
+            Ok(changed) => {
+                removed += changed;
+            }
+            Err(err) => {
+                return Err(MdownError::DatabaseError(err));
+            }
+        }
+    }
+    Ok(removed)
+}
+
+/// Reads the hooks in the order they were added.
+///
+/// # Returns
+/// * `Result<Vec<metadata::HookMetadata>, MdownError>` - Returns the hooks, or an `MdownError` on failure.
+pub(crate) fn read_hooks() -> Result<Vec<metadata::HookMetadata>, MdownError> {
+    match open_db() {
+        Ok(conn) => query_hooks(&conn),
+        Err(err) => Err(err),
+    }
+}
+
+/// Reads the hooks from a database in the order they were added.
+///
+/// # Arguments
+/// * `conn` - The connection to the database.
+///
+/// # Returns
+/// * `Result<Vec<metadata::HookMetadata>, MdownError>` - Returns the hooks, or an `MdownError` on failure.
+fn query_hooks(conn: &Connection) -> Result<Vec<metadata::HookMetadata>, MdownError> {
+    let mut stmt = match conn.prepare("SELECT event, kind, target FROM hooks ORDER BY id") {
+        Ok(stmt) => stmt,
+        Err(err) => {
+            return Err(MdownError::DatabaseError(err));
+        }
+    };
+    let rows = match
+        stmt.query_map([], |row| {
+            Ok(metadata::HookMetadata {
+                event: row.get(0)?,
+                kind: row.get(1)?,
+                target: row.get(2)?,
+            })
+        })
+    {
+        Ok(rows) => rows,
+        Err(err) => {
+            return Err(MdownError::DatabaseError(err));
+        }
+    };
+    let mut hooks = Vec::new();
+    for row in rows {
+        match row {
+            Ok(hook) => hooks.push(hook),
+            Err(err) => {
+                return Err(MdownError::DatabaseError(err));
+            }
+        }
+    }
+    Ok(hooks)
+}
+
+/// Adds a hook.
+///
+/// # Arguments
+/// * `hook` - The hook.
+///
+/// # Returns
+/// * `Result<bool, MdownError>` - Returns `Ok(false)` if the hook was already added, or an `MdownError` on failure.
+pub(crate) fn add_hook(hook: &metadata::HookMetadata) -> Result<bool, MdownError> {
+    match open_db() {
+        Ok(conn) => insert_hook(&conn, hook),
+        Err(err) => Err(err),
+    }
+}
+
+/// Adds a hook to a database.
+///
+/// # Arguments
+/// * `conn` - The connection to the database.
+/// * `hook` - The hook.
+///
+/// # Returns
+/// * `Result<bool, MdownError>` - Returns `Ok(false)` if the hook was already added, or an `MdownError` on failure.
+fn insert_hook(conn: &Connection, hook: &metadata::HookMetadata) -> Result<bool, MdownError> {
+    match
+        conn.execute(
+            "INSERT OR IGNORE INTO hooks (event, kind, target) VALUES (?1, ?2, ?3)",
+            params![hook.event, hook.kind, hook.target]
+        )
+    {
+        Ok(changed) => Ok(changed > 0),
+        Err(err) => Err(MdownError::DatabaseError(err)),
+    }
+}
+
+/// Removes the hooks of an event.
+///
+/// # Arguments
+/// * `event` - The event.
+/// * `target` - The command or URL of the hook to remove; all hooks of the event are removed if it is `None`.
+///
+/// # Returns
+/// * `Result<usize, MdownError>` - Returns the number of removed hooks, or an `MdownError` on failure.
+pub(crate) fn remove_hooks(event: &str, target: Option<&str>) -> Result<usize, MdownError> {
+    match open_db() {
+        Ok(conn) => delete_hooks(&conn, event, target),
+        Err(err) => Err(err),
+    }
+}
+
+/// Removes the hooks of an event from a database.
+///
+/// # Arguments
+/// * `conn` - The connection to the database.
+/// * `event` - The event.
+/// * `target` - The command or URL of the hook to remove; all hooks of the event are removed if it is `None`.
+///
+/// # Returns
+/// * `Result<usize, MdownError>` - Returns the number of removed hooks, or an `MdownError` on failure.
+fn delete_hooks(conn: &Connection, event: &str, target: Option<&str>) -> Result<usize, MdownError> {
+    let removed = match target {
+        Some(target) =>
+            conn.execute(
+                "DELETE FROM hooks WHERE event = ?1 AND target = ?2",
+                params![event, target]
+            ),
+        None => conn.execute("DELETE FROM hooks WHERE event = ?1", params![event]),
+    };
+    match removed {
+        Ok(removed) => Ok(removed),
+        Err(err) => Err(MdownError::DatabaseError(err)),
+    }
+}
+
+// Reads back hooks in the order they were added and removes them by event and target
+#[test]
+fn test_hooks_round_trip() {
+    let conn = Connection::open_in_memory().unwrap();
+    initialize_db(&conn).unwrap();
+    let hook = |event: &str, kind: &str, target: &str| metadata::HookMetadata {
+        event: event.to_string(),
+        kind: kind.to_string(),
+        target: target.to_string(),
+    };
+    let first = hook("new-chapter", "command", "notify-send new");
+    let second = hook("*", "webhook", "http://localhost/hook");
+    let third = hook("new-chapter", "command", "echo new");
+    assert!(insert_hook(&conn, &first).unwrap());
+    assert!(insert_hook(&conn, &second).unwrap());
+    assert!(insert_hook(&conn, &third).unwrap());
+    assert!(!insert_hook(&conn, &first).unwrap());
+    assert_eq!(query_hooks(&conn).unwrap(), vec![first.clone(), second.clone(), third]);
+
+    assert_eq!(delete_hooks(&conn, "new-chapter", Some("echo new")).unwrap(), 1);
+    assert_eq!(query_hooks(&conn).unwrap(), vec![first, second.clone()]);
+    assert_eq!(delete_hooks(&conn, "new-chapter", None).unwrap(), 1);
+    assert_eq!(delete_hooks(&conn, "error", None).unwrap(), 0);
+    assert_eq!(query_hooks(&conn).unwrap(), vec![second]);
+}
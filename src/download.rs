@@ -1,7 +1,10 @@
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
 use serde_json::Value;
 use std::{
     fs::{ self, File, OpenOptions },
     io::Write,
+    path::Path,
     sync::Arc,
     thread::sleep,
     time::{ Duration, Instant },
@@ -16,10 +19,39 @@ use crate::{
     log,
     MAXPOINTS,
     metadata,
-    resolute::{ CURRENT_PAGE, MWD },
+    resolute::CURRENT_PAGE,
     string,
     utils,
 };
+
+/// Time between requests to the MangaDex API; it allows about five requests per second.
+const API_INTERVAL: Duration = Duration::from_millis(200);
+
+lazy_static! {
+    /// Time at which the next request to the MangaDex API may be sent.
+    static ref NEXT_API_REQUEST: Mutex<Instant> = Mutex::new(Instant::now());
+}
+
+/// Reserves the time at which a request may be sent to the MangaDex API.
+///
+/// # Arguments
+/// * `next` - The time at which the next request may be sent; it is moved past the reserved time.
+/// * `now` - The current time.
+///
+/// # Returns
+/// * `Instant` - The time at which the request may be sent.
+fn reserve_api_request(next: &mut Instant, now: Instant) -> Instant {
+    let at = std::cmp::max(*next, now);
+    *next = at + API_INTERVAL;
+    at
+}
+
+/// Waits until a request may be sent to the MangaDex API, so that requests sent at once stay within its rate limit.
+async fn wait_for_api() {
+    let at = reserve_api_request(&mut NEXT_API_REQUEST.lock(), Instant::now());
+    tokio::time::sleep_until(at.into()).await;
+}
+
 /// Creates and configures a `reqwest::Client` for making HTTP requests.
 ///
 /// This function sets up a `reqwest::Client` with a custom user-agent string. The client can be used to make
@@ -104,7 +136,7 @@ pub(crate) async fn get_response(
             return Err(MdownError::ConversionError(err.to_string()));
         }
     };
-    let url = format!("\\{}\\{}\\{}", mode, c_hash, cover_hash);
+    let url = format!("/{}/{}/{}", mode, c_hash, cover_hash);
 
     let full_url = match base_url.join(&url) {
         Ok(url) => url,
@@ -196,6 +228,10 @@ pub(crate) fn get_perc(percentage: i64) -> String {
 /// }
 /// ```
 pub(crate) async fn get_response_client(full_url: &str) -> Result<reqwest::Response, MdownError> {
+    // Manga may be fetched at once by `database --check`
+    if full_url.starts_with("https://api.mangadex.org/") {
+        wait_for_api().await;
+    }
     let client = match get_client() {
         Ok(client) => client,
         Err(err) => {
@@ -274,19 +310,11 @@ pub(crate) async fn download_cover(
     let (total_size, _) = get_size(&response);
 
     // Create or open the file to save the cover image
-    let mut file = if args::ARGS.lock().update {
-        match File::create("_cover.png") {
-            Ok(file) => file,
-            Err(err) => {
-                return Err(MdownError::IoError(err, format!("{}\\_cover.png", MWD.lock())));
-            }
-        }
-    } else {
-        match File::create(format!("{}\\_cover.png", folder)) {
-            Ok(file) => file,
-            Err(err) => {
-                return Err(MdownError::IoError(err, format!("{}\\_cover.png", folder)));
-            }
+    let cover_path = Path::new(&*folder).join("_cover.png").to_string_lossy().to_string();
+    let mut file = match File::create(&cover_path) {
+        Ok(file) => file,
+        Err(err) => {
+            return Err(MdownError::IoError(err, cover_path));
         }
     };
 
@@ -307,7 +335,7 @@ pub(crate) async fn download_cover(
         match file.write_all(&chunk) {
             Ok(()) => (),
             Err(err) => {
-                suspend_error(MdownError::IoError(err, format!("{}\\_cover.png", folder)));
+                suspend_error(MdownError::IoError(err, cover_path.clone()));
             }
         }
         downloaded += chunk.len() as u64;
@@ -413,19 +441,11 @@ pub(crate) async fn download_stat(id: &str, manga_name: &str) -> Result<(), Mdow
     };
 
     // Create or open the file for saving statistics
-    let mut file = if args::ARGS.lock().update {
-        match File::create("_statistics.md") {
-            Ok(file) => file,
-            Err(err) => {
-                return Err(MdownError::IoError(err, format!("{}\\_statistics.md", MWD.lock())));
-            }
-        }
-    } else {
-        match File::create(format!("{}\\_statistics.md", folder)) {
-            Ok(file) => file,
-            Err(err) => {
-                return Err(MdownError::IoError(err, format!("{}\\_statistics.md", folder)));
-            }
+    let statistics_path = Path::new(&folder).join("_statistics.md").to_string_lossy().to_string();
+    let mut file = match File::create(&statistics_path) {
+        Ok(file) => file,
+        Err(err) => {
+            return Err(MdownError::IoError(err, statistics_path));
         }
     };
 
@@ -487,7 +507,7 @@ pub(crate) async fn download_stat(id: &str, manga_name: &str) -> Result<(), Mdow
     match file.write_all(data.as_bytes()) {
         Ok(()) => (),
         Err(err) => {
-            return Err(MdownError::IoError(err, format!("{}\\_statistics.md", folder)));
+            return Err(MdownError::IoError(err, statistics_path));
         }
     }
 
@@ -613,7 +633,7 @@ pub(crate) async fn download_image(
     start: u32
 ) -> Result<(), MdownError> {
     let page_str = page.to_string() + &" ".repeat(3 - page.to_string().len());
-    let lock_file = format!(".cache\\{}.lock", folder_name);
+    let lock_file = getter::get_cache_path(&format!("{}.lock", folder_name));
     if
         *args::ARGS_WEB ||
         *args::ARGS_GUI ||
@@ -657,7 +677,7 @@ pub(crate) async fn download_image(
     let interval = Duration::from_millis(100);
     let mut last_check_time = Instant::now();
 
-    while fs::metadata(format!(".cache\\{}.lock", lock_file)).is_ok() {
+    while fs::metadata(getter::get_cache_path(&format!("{}.lock", lock_file))).is_ok() {
         sleep(Duration::from_millis(10));
     }
     let mut lock_file_inst = match
@@ -665,12 +685,16 @@ pub(crate) async fn download_image(
             .read(true)
             .write(true)
             .create(true)
-            .open(format!(".cache\\{}_{}_final.lock", folder_name, page))
+            .truncate(true)
+            .open(getter::get_cache_path(&format!("{}_{}_final.lock", folder_name, page)))
     {
         Ok(lock_file) => lock_file,
         Err(err) => {
             return Err(
-                MdownError::IoError(err, format!(".cache\\{}_{}_final.lock", folder_name, page))
+                MdownError::IoError(
+                    err,
+                    getter::get_cache_path(&format!("{}_{}_final.lock", folder_name, page))
+                )
             );
         }
     };
@@ -678,7 +702,10 @@ pub(crate) async fn download_image(
         Ok(()) => (),
         Err(err) => {
             suspend_error(
-                MdownError::IoError(err, format!(".cache\\{}_{}_final.lock", folder_name, page))
+                MdownError::IoError(
+                    err,
+                    getter::get_cache_path(&format!("{}_{}_final.lock", folder_name, page))
+                )
             );
         }
     }
@@ -711,14 +738,15 @@ pub(crate) async fn download_image(
                         .read(true)
                         .write(true)
                         .create(true)
-                        .open(format!(".cache\\{}_{}.lock", folder_name, page))
+                        .truncate(true)
+                        .open(getter::get_cache_path(&format!("{}_{}.lock", folder_name, page)))
                 {
                     Ok(file) => file,
                     Err(err) => {
                         return Err(
                             MdownError::IoError(
                                 err,
-                                format!(".cache\\{}_{}.lock", folder_name, page)
+                                getter::get_cache_path(&format!("{}_{}.lock", folder_name, page))
                             )
                         );
                     }
@@ -729,7 +757,7 @@ pub(crate) async fn download_image(
                         suspend_error(
                             MdownError::IoError(
                                 err,
-                                format!(".cache\\{}_{}.lock", folder_name, page)
+                                getter::get_cache_path(&format!("{}_{}.lock", folder_name, page))
                             )
                         );
                     }
@@ -818,18 +846,21 @@ pub(crate) async fn download_image(
             .read(true)
             .write(true)
             .create(true)
-            .open(format!(".cache\\{}_{}.lock", folder_name, page))
+            .truncate(true)
+            .open(getter::get_cache_path(&format!("{}_{}.lock", folder_name, page)))
     {
         Ok(file) => file,
         Err(err) => {
-            return Err(MdownError::IoError(err, format!(".cache\\{}_{}.lock", folder_name, page)));
+            return Err(
+                MdownError::IoError(err, getter::get_cache_path(&format!("{}_{}.lock", folder_name, page)))
+            );
         }
     };
     match lock_file.write(format!("{}", (downloaded as f64) / 1024.0 / 1024.0).as_bytes()) {
         Ok(_size) => (),
         Err(err) => {
             suspend_error(
-                MdownError::IoError(err, format!(".cache\\{}_{}.lock", folder_name, page))
+                MdownError::IoError(err, getter::get_cache_path(&format!("{}_{}.lock", folder_name, page)))
             );
         }
     }
@@ -845,26 +876,27 @@ pub(crate) async fn download_image(
     }
 
     if *args::ARGS_GUI {
-        match fs::create_dir_all(".cache\\preview") {
+        let preview_folder = getter::get_cache_path("preview");
+        match fs::create_dir_all(&preview_folder) {
             Ok(()) => (),
             Err(err) => {
-                return Err(MdownError::IoError(err, format!(".cache\\preview\\{}", full_path)));
+                return Err(MdownError::IoError(err, preview_folder));
             }
         }
-        let target_file = std::path::Path::new(".cache\\preview").join("preview.png");
+        let target_file = getter::get_preview_path();
 
-        if target_file.exists() {
+        if Path::new(&target_file).exists() {
             match fs::remove_file(&target_file) {
                 Ok(()) => (),
                 Err(err) => {
-                    return Err(MdownError::IoError(err, format!(".cache\\preview\\{}", full_path)));
+                    return Err(MdownError::IoError(err, target_file));
                 }
             };
         }
-        match fs::copy(full_path, target_file) {
+        match fs::copy(full_path, &target_file) {
             Ok(_) => (),
             Err(err) => {
-                return Err(MdownError::IoError(err, format!(".cache\\preview\\{}", full_path)));
+                return Err(MdownError::IoError(err, target_file));
             }
         };
     }
@@ -894,3 +926,16 @@ async fn test_get_response_client_empty_url() {
     let response = get_response_client(url).await;
     assert!(response.is_err());
 }
+
+// Spaces requests to the API out, but doesn't delay requests after a pause
+#[test]
+fn test_reserve_api_request() {
+    let now = Instant::now();
+    let mut next = now;
+    assert_eq!(reserve_api_request(&mut next, now), now);
+    assert_eq!(reserve_api_request(&mut next, now), now + API_INTERVAL);
+    assert_eq!(reserve_api_request(&mut next, now), now + API_INTERVAL * 2);
+    let later = now + Duration::from_secs(10);
+    assert_eq!(reserve_api_request(&mut next, later), later);
+    assert_eq!(next, later + API_INTERVAL);
+}
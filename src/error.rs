@@ -193,6 +193,10 @@ pub(crate) fn handle_error(err: &MdownError, from: Option<String>) {
         }
         error => eprintln!("Error: {}{}", error, to),
     }
+    crate::hooks::fire(crate::hooks::Event {
+        message: format!("{}{}", err, to),
+        ..crate::hooks::Event::new("error")
+    });
 }
 
 /// A macro to simplify error handling by calling `handle_error` with optional origin information.
@@ -222,7 +226,7 @@ macro_rules! handle_error {
 pub(crate) fn handle_suspended() {
     let suspended = SUSPENDED.lock();
     if !suspended.is_empty() {
-        println!("Suspended errors:");
+        eprintln!("Suspended errors:");
         for i in suspended.iter() {
             handle_error!(i, String::from("suspended"));
         }
@@ -0,0 +1,335 @@
+use chrono::{ DateTime, FixedOffset, Utc };
+use serde::Deserialize;
+use serde_json::Value;
+use std::{ collections::HashMap, fs };
+
+use crate::{
+    debug,
+    error::MdownError,
+    getter,
+    library,
+    metadata::MangaMetadata,
+    resolute,
+    version_manager::get_current_version,
+};
+
+/// Number of chapters listed in a feed.
+const ENTRIES: usize = 50;
+
+/// When a logged download started and ended.
+type Run = (DateTime<FixedOffset>, DateTime<FixedOffset>);
+
+/// A logged run as read for a feed; runs in progress have no end and runs of web have no manga.
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+#[serde(default)]
+struct LoggedRun {
+    id: Option<String>,
+    time_start: Option<String>,
+    time_end: Option<String>,
+}
+
+/// A chapter of the library listed in a feed.
+#[derive(Debug, Clone, PartialEq)]
+struct Entry {
+    manga: String,
+    manga_id: String,
+    chapter_id: String,
+    number: String,
+    /// When the chapter was last updated on MangaDex.
+    published: DateTime<FixedOffset>,
+    /// When the chapter was added to the library.
+    downloaded: DateTime<FixedOffset>,
+}
+
+/// Escapes text put into XML.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Reads the runs of the download log, leaving out runs that can't be read.
+///
+/// # Arguments
+/// * `json` - The download log.
+fn parse_logs(json: Value) -> Vec<LoggedRun> {
+    let runs = match json {
+        Value::Object(runs) => runs,
+        _ => {
+            return Vec::new();
+        }
+    };
+    runs.into_iter()
+        .filter_map(|(handle, run)| {
+            match serde_json::from_value::<LoggedRun>(run) {
+                Ok(run) => Some(run),
+                Err(err) => {
+                    debug!("logged run {} not used in feed: {}", handle, err);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// Reads the download log; it is empty if nothing was logged with `--log`.
+///
+/// # Returns
+/// * `Result<Vec<LoggedRun>, MdownError>` - Returns the logged runs, or an `MdownError` if the log can't be read.
+fn read_logs() -> Result<Vec<LoggedRun>, MdownError> {
+    let log_path = match getter::get_log_path() {
+        Ok(path) => path,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    if fs::metadata(&log_path).is_err() {
+        return Ok(Vec::new());
+    }
+    match resolute::get_dat_content(&log_path) {
+        Ok(json) => Ok(parse_logs(json)),
+        Err(err) => Err(err),
+    }
+}
+
+/// Returns when the logged downloads of each manga started and ended, oldest first.
+///
+/// # Arguments
+/// * `logs` - The download log.
+fn get_runs(logs: &[LoggedRun]) -> HashMap<String, Vec<Run>> {
+    let mut runs: HashMap<String, Vec<Run>> = HashMap::new();
+    for run in logs.iter() {
+        let (id, time_start) = match (&run.id, &run.time_start) {
+            (Some(id), Some(time_start)) => (id, time_start),
+            _ => {
+                continue;
+            }
+        };
+        let start = match DateTime::parse_from_rfc3339(time_start) {
+            Ok(start) => start,
+            Err(_err) => {
+                continue;
+            }
+        };
+        // Runs that didn't end are taken as ending when they started
+        let end = run.time_end
+            .as_deref()
+            .and_then(|time_end| DateTime::parse_from_rfc3339(time_end).ok())
+            .unwrap_or(start);
+        runs.entry(id.clone()).or_default().push((start, end));
+    }
+    for times in runs.values_mut() {
+        times.sort();
+    }
+    runs
+}
+
+/// Returns the chapters of manga, newest downloaded first.
+///
+/// A chapter is taken as downloaded at the end of the first logged download of its manga that started after the chapter was updated; without such a download, it is taken as downloaded when it was updated.
+///
+/// # Arguments
+/// * `manga` - The manga of the feed.
+/// * `logs` - The download log.
+fn get_entries(manga: &[MangaMetadata], logs: &[LoggedRun]) -> Vec<Entry> {
+    let runs = get_runs(logs);
+    let mut entries = Vec::new();
+    for item in manga.iter() {
+        let times = runs.get(&item.id);
+        for chapter in item.chapters.iter() {
+            let published = match DateTime::parse_from_rfc3339(&chapter.updated_at) {
+                Ok(published) => published,
+                Err(_err) => {
+                    continue;
+                }
+            };
+            let downloaded = times
+                .and_then(|times| times.iter().find(|(start, _end)| *start >= published))
+                .map(|(_start, end)| *end)
+                .unwrap_or(published);
+            entries.push(Entry {
+                manga: item.name.clone(),
+                manga_id: item.id.clone(),
+                chapter_id: chapter.id.clone(),
+                number: chapter.number.clone(),
+                published,
+                downloaded,
+            });
+        }
+    }
+    entries.sort_by(|a, b| b.downloaded.cmp(&a.downloaded).then_with(|| b.published.cmp(&a.published)));
+    entries.truncate(ENTRIES);
+    entries
+}
+
+/// Writes an Atom feed.
+///
+/// # Arguments
+/// * `title` - The title of the feed.
+/// * `id` - The ID of the feed.
+/// * `entries` - The chapters listed in the feed.
+/// * `now` - The time the feed is updated at if it lists no chapters.
+fn render(title: &str, id: &str, entries: &[Entry], now: DateTime<FixedOffset>) -> String {
+    let updated = entries
+        .iter()
+        .map(|entry| entry.downloaded)
+        .max()
+        .unwrap_or(now);
+    let mut feed = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    feed.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+    feed.push_str(&format!("  <title>{}</title>\n", escape(title)));
+    feed.push_str(&format!("  <id>{}</id>\n", escape(id)));
+    feed.push_str(&format!("  <updated>{}</updated>\n", updated.to_rfc3339()));
+    feed.push_str("  <author><name>mdown</name></author>\n");
+    feed.push_str(
+        &format!("  <generator version=\"{}\">mdown</generator>\n", escape(&get_current_version()))
+    );
+    for entry in entries.iter() {
+        let title = format!("{} - Ch.{}", entry.manga, entry.number);
+        feed.push_str("  <entry>\n");
+        feed.push_str(&format!("    <title>{}</title>\n", escape(&title)));
+        feed.push_str(&format!("    <id>urn:mdown:chapter:{}</id>\n", escape(&entry.chapter_id)));
+        feed.push_str(
+            &format!(
+                "    <link href=\"https://mangadex.org/chapter/{}\"/>\n",
+                escape(&entry.chapter_id)
+            )
+        );
+        feed.push_str(&format!("    <published>{}</published>\n", entry.published.to_rfc3339()));
+        feed.push_str(&format!("    <updated>{}</updated>\n", entry.downloaded.to_rfc3339()));
+        feed.push_str(
+            &format!(
+                "    <summary>Chapter {} of {} ({}) was added to the library</summary>\n",
+                escape(&entry.number),
+                escape(&entry.manga),
+                escape(&entry.manga_id)
+            )
+        );
+        feed.push_str("  </entry>\n");
+    }
+    feed.push_str("</feed>\n");
+    feed
+}
+
+/// Returns the Atom feed of chapters added to the library.
+///
+/// # Arguments
+/// * `manga_id` - The manga whose chapters are listed; chapters of all manga are listed if it is `None`.
+///
+/// # Returns
+/// * `Result<Option<String>, MdownError>` - Returns the feed, `None` if the manga isn't in the library, or an `MdownError` if the library can't be read.
+pub(crate) fn get_feed(manga_id: Option<&str>) -> Result<Option<String>, MdownError> {
+    let dat = match library::load() {
+        Ok(dat) => dat,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    // A log that can't be read only leaves out the times of downloads
+    let logs = match read_logs() {
+        Ok(logs) => logs,
+        Err(err) => {
+            debug!("download log not used in feed: {}", err);
+            Vec::new()
+        }
+    };
+    let now = Utc::now().fixed_offset();
+    match manga_id {
+        Some(manga_id) => {
+            let manga: Vec<MangaMetadata> = dat.data
+                .into_iter()
+                .filter(|item| item.id == manga_id)
+                .collect();
+            let name = match manga.first() {
+                Some(item) => item.name.clone(),
+                None => {
+                    return Ok(None);
+                }
+            };
+            let entries = get_entries(&manga, &logs);
+            Ok(Some(render(&name, &format!("urn:mdown:manga:{}", manga_id), &entries, now)))
+        }
+        None => {
+            let entries = get_entries(&dat.data, &logs);
+            Ok(Some(render("mdown library", "urn:mdown:library", &entries, now)))
+        }
+    }
+}
+
+// Takes chapters as downloaded by the first logged download after they were updated
+#[test]
+fn test_get_entries() {
+    use crate::metadata::ChapterMetadata;
+
+    let manga: MangaMetadata = serde_json::from_value(
+        serde_json::json!({
+            "name": "Test & Co",
+            "id": "mid",
+            "chapters": [
+                { "number": "1", "updated_at": "2024-01-01T00:00:00+00:00", "id": "c1" },
+                { "number": "2", "updated_at": "2024-02-01T00:00:00+00:00", "id": "c2" },
+                { "number": "3", "updated_at": "2024-03-01T00:00:00+00:00", "id": "c3" }
+            ],
+            "mwd": "/tmp/Test",
+            "cover": true,
+            "date": [],
+            "available_languages": [],
+            "current_language": "en",
+            "theme": [],
+            "genre": []
+        })
+    ).unwrap();
+    assert_eq!(manga.chapters[0], ChapterMetadata::new("1", "2024-01-01T00:00:00+00:00", "c1"));
+    // As written by the log handler: a finished run, a run in progress, a run of web and a broken run
+    let logs = parse_logs(
+        serde_json::json!({
+            "a1b2c3d4e5f6a7b8": {
+                "logs": { "1": ["2024-01-05 00:00:01  Downloading"] },
+                "type": "downloader",
+                "time_start": "2024-01-05T00:00:00+00:00",
+                "time_end": "2024-01-05T01:00:00+00:00",
+                "name": "Test & Co",
+                "id": "mid",
+                "mwd": "/tmp/Test"
+            },
+            "b1b2c3d4e5f6a7b8": {
+                "logs": {},
+                "type": "downloader",
+                "time_start": "2024-02-10T00:00:00+00:00",
+                "time_end": null,
+                "name": "Test & Co",
+                "id": "mid",
+                "mwd": "/tmp/Test"
+            },
+            "c1c2c3d4e5": {
+                "logs": { "": ["2024-02-11 00:00:00  Started"] },
+                "type": "web",
+                "time_start": "2024-02-11T00:00:00+00:00",
+                "time_end": null
+            },
+            "d1d2c3d4e5f6a7b8": {
+                "type": "downloader",
+                "time_start": 5,
+                "id": "mid"
+            }
+        })
+    );
+    assert_eq!(logs.len(), 3);
+
+    let entries = get_entries(&[manga], &logs);
+    let order: Vec<&str> = entries
+        .iter()
+        .map(|entry| entry.number.as_str())
+        .collect();
+    assert_eq!(order, ["3", "2", "1"]);
+    assert_eq!(entries[0].downloaded.to_rfc3339(), "2024-03-01T00:00:00+00:00");
+    assert_eq!(entries[1].downloaded.to_rfc3339(), "2024-02-10T00:00:00+00:00");
+    assert_eq!(entries[2].downloaded.to_rfc3339(), "2024-01-05T01:00:00+00:00");
+
+    let feed = render("Test & Co", "urn:mdown:manga:mid", &entries, Utc::now().fixed_offset());
+    assert!(feed.contains("<title>Test &amp; Co - Ch.3</title>"));
+    assert!(feed.contains("<updated>2024-03-01T00:00:00+00:00</updated>\n  <author>"));
+    assert_eq!(feed.matches("<entry>").count(), 3);
+}
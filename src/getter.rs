@@ -1,5 +1,5 @@
 use serde_json::Value;
-use std::process::exit;
+use std::{ fs, path::{ Path, PathBuf }, process::exit };
 
 use crate::{
     args::{ self, ARGS },
@@ -10,6 +10,7 @@ use crate::{
     metadata,
     resolute,
     string,
+    template,
     utils,
 };
 
@@ -76,41 +77,318 @@ pub(crate) fn get_exe_path() -> Result<String, MdownError> {
     Ok(path)
 }
 
-pub(crate) fn get_dat_path() -> Result<String, MdownError> {
+/// Returns the path to a file stored next to the executable, where older versions and portable mode keep them.
+///
+/// Older versions joined these paths with `\`, which on systems other than Windows became part of
+/// the file name next to the folder of the executable; such files are moved to the correct place.
+///
+/// # Arguments
+/// * `name` - The name of the file.
+///
+/// # Returns
+/// * `Ok(String)` - The path to the file.
+/// * `Err(MdownError)` - If the folder of the executable can't be determined.
+fn get_exe_file_path(name: &str) -> Result<String, MdownError> {
     let path = match get_exe_path() {
         Ok(value) => value,
         Err(err) => {
             return Err(err);
         }
     };
-    Ok(format!("{}\\dat.json", path))
+    let file = Path::new(&path).join(name);
+    if cfg!(not(windows)) {
+        let legacy = format!("{}\\{}", path, name);
+        if !file.exists() && Path::new(&legacy).exists() {
+            match fs::rename(&legacy, &file) {
+                Ok(()) => debug!("moved {} to {}", legacy, file.display()),
+                Err(err) => eprintln!("Error: moving {} to {} {}", legacy, file.display(), err),
+            }
+        }
+    }
+    Ok(file.to_string_lossy().to_string())
 }
-pub(crate) fn get_db_path() -> Result<String, MdownError> {
-    let path = match get_exe_path() {
-        Ok(value) => value,
+
+/// Folders in which the program keeps its files.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Dirs {
+    /// Holds the database with settings and resources.
+    pub(crate) config: PathBuf,
+    /// Holds the library and `log.json`.
+    pub(crate) data: PathBuf,
+    /// Holds files that can be deleted at any time.
+    pub(crate) cache: PathBuf,
+}
+
+/// Selects one of the folders of the program.
+type DirSelector = fn(&Dirs) -> &PathBuf;
+
+/// Files that older versions kept next to the executable and the folder each of them belongs to.
+const MIGRATED_FILES: [(&str, DirSelector); 3] = [
+    ("resources.db", |dirs| &dirs.config),
+    ("dat.json", |dirs| &dirs.data),
+    ("log.json", |dirs| &dirs.data),
+];
+
+/// Resolves the folders of the program.
+///
+/// In portable mode every folder is the folder of the executable. Otherwise `MDOWN_HOME` is used if it is set,
+/// then the XDG base directories on Linux, `~/Library` on macOS and `%APPDATA%` with `%LOCALAPPDATA%` on Windows.
+/// If none of them can be found, the folder of the executable is used.
+///
+/// # Arguments
+/// * `exe_path` - The folder of the executable.
+/// * `portable` - Whether portable mode is enabled.
+/// * `var` - Looks up an environment variable.
+///
+/// # Returns
+/// * `Dirs` - The folders of the program.
+fn resolve_dirs(exe_path: &Path, portable: bool, var: impl Fn(&str) -> Option<String>) -> Dirs {
+    let portable_dirs = || Dirs {
+        config: exe_path.to_path_buf(),
+        data: exe_path.to_path_buf(),
+        cache: exe_path.to_path_buf(),
+    };
+    // Relative paths in these variables are invalid and ignored, as the XDG specification requires
+    let absolute = |name: &str| {
+        var(name)
+            .map(PathBuf::from)
+            .filter(|path| path.is_absolute())
+    };
+
+    if portable {
+        return portable_dirs();
+    }
+    if let Some(home) = absolute("MDOWN_HOME") {
+        return Dirs {
+            config: home.clone(),
+            data: home.clone(),
+            cache: home.join("cache"),
+        };
+    }
+
+    if cfg!(windows) {
+        match (absolute("APPDATA"), absolute("LOCALAPPDATA")) {
+            (Some(roaming), Some(local)) =>
+                Dirs {
+                    config: roaming.join("mdown"),
+                    data: roaming.join("mdown"),
+                    cache: local.join("mdown"),
+                },
+            _ => portable_dirs(),
+        }
+    } else if cfg!(target_os = "macos") {
+        match absolute("HOME") {
+            Some(home) =>
+                Dirs {
+                    config: home.join("Library").join("Application Support").join("mdown"),
+                    data: home.join("Library").join("Application Support").join("mdown"),
+                    cache: home.join("Library").join("Caches").join("mdown"),
+                },
+            None => portable_dirs(),
+        }
+    } else {
+        let home = absolute("HOME");
+        let xdg = |name: &str, fallback: &[&str]| {
+            match absolute(name) {
+                Some(path) => Some(path.join("mdown")),
+                None =>
+                    home
+                        .as_ref()
+                        .map(|home| fallback.iter().fold(home.clone(), |path, part| path.join(part)))
+                        .map(|path| path.join("mdown")),
+            }
+        };
+        match
+            (
+                xdg("XDG_CONFIG_HOME", &[".config"]),
+                xdg("XDG_DATA_HOME", &[".local", "share"]),
+                xdg("XDG_CACHE_HOME", &[".cache"]),
+            )
+        {
+            (Some(config), Some(data), Some(cache)) => Dirs { config, data, cache },
+            _ => portable_dirs(),
+        }
+    }
+}
+
+/// Checks whether portable mode is enabled, either with `--portable` or with a file named `portable` next to the executable.
+///
+/// # Arguments
+/// * `exe_path` - The folder of the executable.
+///
+/// # Returns
+/// * `bool` - Whether files of the program are kept next to the executable.
+fn is_portable(exe_path: &Path) -> bool {
+    *args::ARGS_PORTABLE || exe_path.join("portable").is_file()
+}
+
+/// Returns the folders of the program for the current environment.
+///
+/// # Returns
+/// * `Ok(Dirs)` - The folders of the program.
+/// * `Err(MdownError)` - If the folder of the executable can't be determined.
+pub(crate) fn get_dirs() -> Result<Dirs, MdownError> {
+    let exe_path = match get_exe_path() {
+        Ok(value) => PathBuf::from(value),
         Err(err) => {
             return Err(err);
         }
     };
-    Ok(format!("{}\\resources.db", path))
+    Ok(resolve_dirs(&exe_path, is_portable(&exe_path), |name| std::env::var(name).ok()))
 }
-pub(crate) fn get_log_path() -> Result<String, MdownError> {
-    let path: String = match get_exe_path() {
-        Ok(value) => value,
+
+/// Returns the path to a file in one of the folders of the program and creates the folder if it doesn't exist.
+///
+/// # Arguments
+/// * `name` - The name of the file.
+/// * `dir` - Selects the folder of the file.
+///
+/// # Returns
+/// * `Ok(String)` - The path to the file.
+/// * `Err(MdownError)` - If the folder can't be determined or created.
+fn get_file_path(name: &str, dir: DirSelector) -> Result<String, MdownError> {
+    let dirs = match get_dirs() {
+        Ok(dirs) => dirs,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    let folder = dir(&dirs);
+    if let Err(err) = fs::create_dir_all(folder) {
+        return Err(MdownError::IoError(err, folder.to_string_lossy().to_string()));
+    }
+    Ok(folder.join(name).to_string_lossy().to_string())
+}
+
+/// Moves files that older versions kept next to the executable to the folders of the program.
+///
+/// Files are only moved when they don't exist in their new folder yet. When the folder of the executable
+/// is read-only, files are copied instead.
+///
+/// # Returns
+/// * `Ok(())` - If all files are in place.
+/// * `Err(MdownError)` - If a folder can't be determined or a file can't be copied.
+pub(crate) fn migrate_files() -> Result<(), MdownError> {
+    let dirs = match get_dirs() {
+        Ok(dirs) => dirs,
         Err(err) => {
             return Err(err);
         }
     };
-    Ok(format!("{}\\log.json", path))
+    for (name, dir) in MIGRATED_FILES {
+        let legacy = match get_exe_file_path(name) {
+            Ok(path) => PathBuf::from(path),
+            Err(err) => {
+                return Err(err);
+            }
+        };
+        let target = dir(&dirs).join(name);
+        if target == legacy || target.exists() || !legacy.is_file() {
+            continue;
+        }
+        if let Err(err) = fs::create_dir_all(dir(&dirs)) {
+            return Err(MdownError::IoError(err, dir(&dirs).to_string_lossy().to_string()));
+        }
+        if fs::rename(&legacy, &target).is_err() {
+            if let Err(err) = fs::copy(&legacy, &target) {
+                return Err(MdownError::IoError(err, target.to_string_lossy().to_string()));
+            }
+            if let Err(err) = fs::remove_file(&legacy) {
+                eprintln!("Error: removing {} after copying it {}", legacy.display(), err);
+            }
+        }
+        println!("Moved {} to {}", legacy.display(), target.display());
+    }
+    Ok(())
+}
+
+pub(crate) fn get_dat_path() -> Result<String, MdownError> {
+    get_file_path("dat.json", |dirs| &dirs.data)
+}
+pub(crate) fn get_library_path() -> Result<String, MdownError> {
+    get_file_path("library.db", |dirs| &dirs.data)
+}
+pub(crate) fn get_db_path() -> Result<String, MdownError> {
+    get_file_path("resources.db", |dirs| &dirs.config)
+}
+pub(crate) fn get_log_path() -> Result<String, MdownError> {
+    get_file_path("log.json", |dirs| &dirs.data)
 }
 pub(crate) fn get_log_lock_path() -> Result<String, MdownError> {
-    let path: String = match get_exe_path() {
-        Ok(value) => value,
+    get_file_path("log.lock", |dirs| &dirs.cache)
+}
+pub(crate) fn get_ctl_path() -> Result<String, MdownError> {
+    get_file_path("ctl.sock", |dirs| &dirs.cache)
+}
+
+/// Returns the folder holding lock files of manga and creates it if it doesn't exist.
+///
+/// # Returns
+/// * `Ok(String)` - The path to the folder.
+/// * `Err(MdownError)` - If the folder can't be determined or created.
+pub(crate) fn get_lock_folder() -> Result<String, MdownError> {
+    let path = match get_file_path("locks", |dirs| &dirs.cache) {
+        Ok(path) => path,
         Err(err) => {
             return Err(err);
         }
     };
-    Ok(format!("{}\\log.lock", path))
+    match fs::create_dir_all(&path) {
+        Ok(()) => Ok(path),
+        Err(err) => Err(MdownError::IoError(err, path)),
+    }
+}
+
+/// Returns the folder holding backups of the library and creates it if it doesn't exist.
+///
+/// # Returns
+/// * `Ok(String)` - The path to the folder.
+/// * `Err(MdownError)` - If the folder can't be determined or created.
+pub(crate) fn get_backup_folder() -> Result<String, MdownError> {
+    let path = match get_file_path("backups", |dirs| &dirs.data) {
+        Ok(path) => path,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    match fs::create_dir_all(&path) {
+        Ok(()) => Ok(path),
+        Err(err) => Err(MdownError::IoError(err, path)),
+    }
+}
+
+/// Returns the path to a lock file; processes locking different names can run side by side.
+///
+/// # Arguments
+/// * `name` - The name of the lock, e.g. the ID of a manga.
+///
+/// # Returns
+/// * `Ok(String)` - The path to the lock file.
+/// * `Err(MdownError)` - If the folder of lock files can't be determined or created.
+pub(crate) fn get_lock_path(name: &str) -> Result<String, MdownError> {
+    match get_lock_folder() {
+        Ok(folder) => Ok(Path::new(&folder).join(format!("{}.lock", name)).to_string_lossy().to_string()),
+        Err(err) => Err(err),
+    }
+}
+
+/// Returns the path to a file or folder in the `.cache` folder of the current directory.
+///
+/// # Arguments
+/// * `name` - The name of the file or folder.
+///
+/// # Returns
+/// * `String` - The path to the file or folder.
+pub(crate) fn get_cache_path(name: &str) -> String {
+    Path::new(".cache").join(name).to_string_lossy().to_string()
+}
+
+/// Returns the path to the preview of the page that was downloaded last, shown by the gui.
+///
+/// # Returns
+/// * `String` - The path to the preview image.
+pub(crate) fn get_preview_path() -> String {
+    Path::new(".cache").join("preview").join("preview.png").to_string_lossy().to_string()
 }
 
 /// Extracts query parameters from a URL path.
@@ -166,9 +444,10 @@ pub(crate) fn get_query(parts: Vec<&str>) -> std::collections::HashMap<String, S
 /// Retrieves the folder name based on the current ARGS settings.
 ///
 /// This function processes the folder name from the global `ARGS` configuration and returns it as a
-/// static string slice. It utilizes `utils::process_filename` to process the folder name. If the
-/// processed folder name equals "name", it returns the value from `resolute::MANGA_NAME`. Otherwise,
-/// it returns the processed folder name itself.
+/// static string slice. It utilizes `template::render_folder` to process the folder name. If the
+/// folder name equals "name", it returns the value from `resolute::MANGA_NAME`. Folder names containing
+/// variables such as `{manga}` are filled in; any other folder name is returned processed by
+/// `utils::process_filename`.
 ///
 /// # Returns
 /// * A `&'static str` representing the folder name. This string is guaranteed to be valid for the
@@ -190,12 +469,8 @@ pub(crate) fn get_query(parts: Vec<&str>) -> std::collections::HashMap<String, S
 /// * Ensure that `utils::process_filename` and the `ARGS` global configuration are properly initialized
 ///   before calling this function. Misconfigured or uninitialized values could lead to incorrect results.
 pub(crate) fn get_folder_name() -> &'static str {
-    let folder_name = utils::process_filename(&ARGS.lock().folder.clone());
-    if folder_name == "name" {
-        Box::leak(resolute::MANGA_NAME.lock().clone().into_boxed_str())
-    } else {
-        Box::leak(folder_name.into_boxed_str())
-    }
+    let folder_name = template::render_folder(&ARGS.lock().folder.clone());
+    Box::leak(folder_name.into_boxed_str())
 }
 
 /// Retrieves and processes the manga name from the given JSON `title_data`.
@@ -624,8 +899,8 @@ pub(crate) fn get_scanlation_group(json: &Vec<metadata::ChapterRelResponse>) ->
 ///
 /// # Returns
 ///
-/// * `Ok((String, usize))` - On success, returns a tuple containing the combined JSON response as a `String` and the number
-///   of items retrieved in the current session as `usize`.
+/// * `Ok((String, usize, Vec<String>))` - On success, returns a tuple containing the combined JSON response as a `String`, the number
+///   of items retrieved in the current session as `usize` and the times data was fetched, in format %Y-%m-%d %H:%M:%S.
 /// * `Err(MdownError)` - On failure, returns an error of type `MdownError`.
 ///
 /// # Errors
@@ -646,12 +921,17 @@ pub(crate) fn get_scanlation_group(json: &Vec<metadata::ChapterRelResponse>) ->
 /// let manga_id = "123456";
 /// let offset = 0;
 /// match get_manga(manga_id, offset).await {
-///     Ok((json, count)) => println!("Fetched {} items: {}", count, json),
+///     Ok((json, count, _dates)) => println!("Fetched {} items: {}", count, json),
 ///     Err(e) => eprintln!("Error fetching manga data: {:?}", e),
 /// }
 /// ```
-pub(crate) async fn get_manga(id: &str, offset: u32) -> Result<(String, usize), MdownError> {
+pub(crate) async fn get_manga(
+    id: &str,
+    offset: u32
+) -> Result<(String, usize, Vec<String>), MdownError> {
     let mut times = 0;
+    // Several manga are fetched at once, so the times are returned instead of being stored for the current manga
+    let mut dates = Vec::new();
     let mut json;
     let mut json_2 = String::new();
     let mut times_offset: u32;
@@ -728,7 +1008,7 @@ pub(crate) async fn get_manga(id: &str, offset: u32) -> Result<(String, usize),
                         .format("%Y-%m-%d %H:%M:%S")
                         .to_string();
 
-                    resolute::DATE_FETCHED.lock().push(naive_time_str);
+                    dates.push(naive_time_str);
                     let message = format!("{} Data fetched with offset {}   ", times, offset);
                     string(3 + times + stat, 0, &message);
                     if
@@ -765,7 +1045,124 @@ pub(crate) async fn get_manga(id: &str, offset: u32) -> Result<(String, usize),
             }
         }
 
-        return Ok((json, offset_temp));
+        return Ok((json, offset_temp, dates));
+    }
+}
+
+/// Number of changed chapters fetched with one request; the most MangaDex allows.
+const CHANGES_LIMIT: usize = 100;
+
+/// Most chapters MangaDex returns for one query, however they are paged.
+const CHANGES_MAX: usize = 10000;
+
+/// Builds the URL of a page of chapters of several manga created or updated since a time.
+///
+/// # Arguments
+/// * `ids` - The IDs of the manga.
+/// * `since` - The time in UTC, formatted as `%Y-%m-%dT%H:%M:%S`.
+/// * `offset` - The number of chapters on previous pages.
+///
+/// # Returns
+/// * `String` - The URL.
+fn get_changes_url(ids: &[String], since: &str, offset: usize) -> String {
+    let mut url = format!(
+        "https://api.mangadex.org/chapter?limit={}&offset={}&updatedAtSince={}&order[updatedAt]=asc",
+        CHANGES_LIMIT,
+        offset,
+        since
+    );
+    for id in ids.iter() {
+        url.push_str(&format!("&manga[]={}", id));
+    }
+    url
+}
+
+/// Reads a page of changed chapters.
+///
+/// # Arguments
+/// * `json` - The page as returned by MangaDex.
+///
+/// # Returns
+/// * `(Vec<(String, String)>, usize)` - The ID of the manga and the language of each chapter on the page, and the number of changed chapters on all pages.
+fn parse_changes(json: &Value) -> (Vec<(String, String)>, usize) {
+    let total = json.get("total").and_then(Value::as_u64).unwrap_or_default() as usize;
+    let mut changes = vec![];
+    for chapter in json.get("data").and_then(Value::as_array).unwrap_or(&vec![]).iter() {
+        let language = chapter
+            .get("attributes")
+            .and_then(|attributes| attributes.get("translatedLanguage"))
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        let manga = chapter
+            .get("relationships")
+            .and_then(Value::as_array)
+            .and_then(|relationships| {
+                relationships
+                    .iter()
+                    .find(|relationship| relationship.get("type").and_then(Value::as_str) == Some("manga"))
+            })
+            .and_then(|relationship| relationship.get("id"))
+            .and_then(Value::as_str);
+        if let Some(manga) = manga {
+            changes.push((manga.to_string(), language.to_string()));
+        }
+    }
+    (changes, total)
+}
+
+/// Fetches the chapters of several manga that were created or updated since a time.
+///
+/// # Arguments
+/// * `ids` - The IDs of the manga; at most about a hundred, so that the URL stays short.
+/// * `since` - The time in UTC, formatted as `%Y-%m-%dT%H:%M:%S`.
+///
+/// # Returns
+/// * `Result<Vec<(String, String)>, MdownError>` - The ID of the manga and the language of each changed chapter, or an `MdownError` if they can't be fetched.
+pub(crate) async fn get_changed_chapters(
+    ids: &[String],
+    since: &str
+) -> Result<Vec<(String, String)>, MdownError> {
+    let mut changes = vec![];
+    let mut offset = 0;
+    loop {
+        let full_url = get_changes_url(ids, since, offset);
+        debug!("sending request to: {}", full_url);
+        let response = match get_response_client(&full_url).await {
+            Ok(res) => res,
+            Err(err) => {
+                return Err(err);
+            }
+        };
+        if !response.status().is_success() {
+            return Err(MdownError::StatusError(response.status()));
+        }
+        let json = match response.text().await {
+            Ok(text) => text,
+            Err(err) => {
+                return Err(MdownError::NetworkError(err));
+            }
+        };
+        let json_value = match utils::get_json(&json) {
+            Ok(value) => value,
+            Err(err) => {
+                return Err(err);
+            }
+        };
+        let (page, total) = parse_changes(&json_value);
+        if total > CHANGES_MAX {
+            return Err(
+                MdownError::CustomError(
+                    format!("{} chapters changed since {}", total, since),
+                    String::from("TooManyChanges")
+                )
+            );
+        }
+        offset += CHANGES_LIMIT;
+        let done = page.is_empty() || offset >= total;
+        changes.extend(page);
+        if done {
+            return Ok(changes);
+        }
     }
 }
 
@@ -1107,3 +1504,76 @@ fn handles_missing_or_null_title_data() {
     let result = get_manga_name(&title_data);
     assert_eq!(result, "Unrecognized title");
 }
+
+// Keeps every file next to the executable in portable mode, even when MDOWN_HOME is set
+#[test]
+fn test_resolve_dirs_portable() {
+    let exe_path = std::env::temp_dir();
+    let dirs = resolve_dirs(&exe_path, true, |_name| Some(String::from("/opt/mdown")));
+    assert_eq!(dirs.config, exe_path);
+    assert_eq!(dirs.data, exe_path);
+    assert_eq!(dirs.cache, exe_path);
+}
+
+// Prefers MDOWN_HOME over the folders of the platform
+#[cfg(unix)]
+#[test]
+fn test_resolve_dirs_mdown_home() {
+    let dirs = resolve_dirs(Path::new("/usr/bin"), false, |name| {
+        match name {
+            "MDOWN_HOME" => Some(String::from("/opt/mdown")),
+            "HOME" => Some(String::from("/home/user")),
+            _ => None,
+        }
+    });
+    assert_eq!(dirs.config, Path::new("/opt/mdown"));
+    assert_eq!(dirs.data, Path::new("/opt/mdown"));
+    assert_eq!(dirs.cache, Path::new("/opt/mdown/cache"));
+}
+
+// Uses XDG base directories, falls back to HOME and ignores relative paths
+#[cfg(all(unix, not(target_os = "macos")))]
+#[test]
+fn test_resolve_dirs_xdg() {
+    let dirs = resolve_dirs(Path::new("/usr/bin"), false, |name| {
+        match name {
+            "XDG_DATA_HOME" => Some(String::from("/data")),
+            "XDG_CACHE_HOME" => Some(String::from("relative/cache")),
+            "HOME" => Some(String::from("/home/user")),
+            _ => None,
+        }
+    });
+    assert_eq!(dirs.config, Path::new("/home/user/.config/mdown"));
+    assert_eq!(dirs.data, Path::new("/data/mdown"));
+    assert_eq!(dirs.cache, Path::new("/home/user/.cache/mdown"));
+
+    let dirs = resolve_dirs(Path::new("/usr/bin"), false, |_name| None);
+    assert_eq!(dirs.data, Path::new("/usr/bin"));
+}
+
+// Asks for changed chapters of all supplied manga
+#[test]
+fn test_get_changes_url() {
+    let ids = [String::from("a"), String::from("b")];
+    assert_eq!(
+        get_changes_url(&ids, "2024-01-02T03:04:05", 100),
+        "https://api.mangadex.org/chapter?limit=100&offset=100&updatedAtSince=2024-01-02T03:04:05&order[updatedAt]=asc&manga[]=a&manga[]=b"
+    );
+}
+
+// Reads the manga and language of changed chapters
+#[test]
+fn test_parse_changes() {
+    let json =
+        serde_json::json!({
+        "data": [
+            {
+                "attributes": { "translatedLanguage": "en" },
+                "relationships": [{ "id": "g", "type": "scanlation_group" }, { "id": "a", "type": "manga" }]
+            },
+            { "attributes": { "translatedLanguage": "ja" }, "relationships": [] }
+        ],
+        "total": 2
+    });
+    assert_eq!(parse_changes(&json), (vec![(String::from("a"), String::from("en"))], 2));
+}
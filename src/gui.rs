@@ -275,7 +275,7 @@ impl eframe::App for App {
                             }
 
                             if self.texture_handle.is_some() {
-                                match std::fs::metadata(".cache\\preview\\preview.png") {
+                                match std::fs::metadata(getter::get_preview_path()) {
                                     Ok(metadata) => {
                                         let size = metadata.len();
 
@@ -291,16 +291,14 @@ impl eframe::App for App {
                             } else {
                                 self.times += 1;
                                 if
-                                    let Ok(metadata) = std::fs::metadata(
-                                        ".cache\\preview\\preview.png"
-                                    )
+                                    let Ok(metadata) = std::fs::metadata(getter::get_preview_path())
                                 {
                                     self.image_size = metadata.len();
                                 }
 
                                 if self.times == 10 {
                                     self.times = 0;
-                                    match image::open(".cache\\preview\\preview.png") {
+                                    match image::open(getter::get_preview_path()) {
                                         Ok(img) => {
                                             let img_rgba8 = img.to_rgba8();
                                             let size = [
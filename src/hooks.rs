@@ -0,0 +1,339 @@
+use chrono::Local;
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::{ process::Stdio, time::Duration };
+use tokio::{ process::Command, runtime::Handle, task::JoinHandle };
+
+use crate::{ args, db, debug, download, error::MdownError, log, metadata::HookMetadata, resolute };
+
+/// Events hooks can be added to.
+const EVENTS: [&str; 4] = ["new-chapter", "chapter-downloaded", "manga-finished", "error"];
+
+/// Longest time a webhook is waited for, and the longest time hooks are waited for when the program exits.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+lazy_static! {
+    /// Hooks read from the database the first time an event happens; `None` until then or after `reload`.
+    static ref HOOKS: Mutex<Option<Vec<HookMetadata>>> = Mutex::new(None);
+
+    /// Hooks that may still be running.
+    static ref RUNNING: Mutex<Vec<JoinHandle<()>>> = Mutex::new(Vec::new());
+}
+
+/// Details of an event, sent as JSON to webhooks and given to commands as environment variables.
+#[derive(Debug, Clone, Default, Serialize, PartialEq)]
+pub(crate) struct Event {
+    /// `new-chapter`, `chapter-downloaded`, `manga-finished` or `error`.
+    pub(crate) event: String,
+    pub(crate) manga_id: String,
+    pub(crate) manga_name: String,
+    /// Number of the chapter.
+    pub(crate) chapter: String,
+    pub(crate) chapter_id: String,
+    /// Archive of the downloaded chapter.
+    pub(crate) path: String,
+    /// Chapters downloaded with the finished manga.
+    pub(crate) chapters: Vec<String>,
+    /// Message of the error.
+    pub(crate) message: String,
+    pub(crate) time: String,
+}
+
+impl Event {
+    /// Creates an event of the manga being handled; the other details are left empty.
+    ///
+    /// # Arguments
+    /// * `event` - `new-chapter`, `chapter-downloaded`, `manga-finished` or `error`.
+    pub(crate) fn new(event: &str) -> Event {
+        Event {
+            event: event.to_string(),
+            manga_id: resolute::MANGA_ID.lock().clone(),
+            manga_name: resolute::MANGA_NAME.lock().clone(),
+            time: Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+            ..Event::default()
+        }
+    }
+
+    /// Returns the environment variables given to commands.
+    ///
+    /// # Arguments
+    /// * `payload` - The event as JSON.
+    fn env(&self, payload: &str) -> Vec<(&'static str, String)> {
+        vec![
+            ("MDOWN_EVENT", self.event.clone()),
+            ("MDOWN_MANGA_ID", self.manga_id.clone()),
+            ("MDOWN_MANGA_NAME", self.manga_name.clone()),
+            ("MDOWN_CHAPTER", self.chapter.clone()),
+            ("MDOWN_CHAPTER_ID", self.chapter_id.clone()),
+            ("MDOWN_PATH", self.path.clone()),
+            ("MDOWN_CHAPTERS", self.chapters.join(",")),
+            ("MDOWN_MESSAGE", self.message.clone()),
+            ("MDOWN_TIME", self.time.clone()),
+            ("MDOWN_PAYLOAD", payload.to_string())
+        ]
+    }
+}
+
+/// Returns whether a hook is run for an event.
+///
+/// # Arguments
+/// * `hook` - The event of the hook; `*` runs it for all events.
+/// * `event` - The event.
+fn is_hooked(hook: &str, event: &str) -> bool {
+    hook == "*" || hook == event
+}
+
+/// Runs a command of a hook in the shell.
+///
+/// # Arguments
+/// * `target` - The command.
+/// * `env` - The environment variables describing the event.
+async fn run_command(target: String, env: Vec<(&'static str, String)>) {
+    #[cfg(unix)]
+    let mut command = Command::new("sh");
+    #[cfg(unix)]
+    command.arg("-c");
+    #[cfg(not(unix))]
+    let mut command = Command::new("cmd");
+    #[cfg(not(unix))]
+    command.arg("/C");
+    command.arg(&target).envs(env).stdin(Stdio::null());
+    match command.status().await {
+        Ok(status) if status.success() => (),
+        Ok(status) => log!(&format!("Hook '{}' failed: {}", target, status)),
+        Err(err) => log!(&format!("Hook '{}' failed to start: {}", target, err)),
+    }
+}
+
+/// Sends an event to the URL of a webhook.
+///
+/// Failures are only logged, as errors are events themselves.
+///
+/// # Arguments
+/// * `target` - The URL.
+/// * `event` - The event.
+async fn send_webhook(target: String, event: Event) {
+    let client = match download::get_client() {
+        Ok(client) => client,
+        Err(err) => {
+            log!(&format!("Webhook '{}' failed: {}", target, err));
+            return;
+        }
+    };
+    match client.post(&target).json(&event).timeout(TIMEOUT).send().await {
+        Ok(response) if response.status().is_success() => (),
+        Ok(response) => log!(&format!("Webhook '{}' failed: {}", target, response.status())),
+        Err(err) => log!(&format!("Webhook '{}' failed: {}", target, err)),
+    }
+}
+
+/// Returns the hooks, reading them from the database if they weren't read yet.
+fn get_hooks() -> Vec<HookMetadata> {
+    let mut hooks = HOOKS.lock();
+    if hooks.is_none() {
+        *hooks = Some(match db::read_hooks() {
+            Ok(read) => read,
+            Err(err) => {
+                eprintln!("Error: reading hooks {}", err);
+                Vec::new()
+            }
+        });
+    }
+    hooks.clone().unwrap_or_default()
+}
+
+/// Reads the hooks again the next time an event happens, so that the daemon runs hooks changed with `settings` while it runs.
+pub(crate) fn reload() {
+    *HOOKS.lock() = None;
+}
+
+/// Runs the hooks of an event in the background; nothing is run by `--dry-run`.
+///
+/// # Arguments
+/// * `event` - The event.
+pub(crate) fn fire(event: Event) {
+    if *args::ARGS_DRY_RUN {
+        return;
+    }
+    let hooks: Vec<HookMetadata> = get_hooks()
+        .into_iter()
+        .filter(|hook| is_hooked(&hook.event, &event.event))
+        .collect();
+    if hooks.is_empty() {
+        return;
+    }
+    let runtime = match Handle::try_current() {
+        Ok(runtime) => runtime,
+        Err(_err) => {
+            debug!("hooks of {} not run outside of runtime", event.event);
+            return;
+        }
+    };
+    let payload = match serde_json::to_string(&event) {
+        Ok(payload) => payload,
+        Err(err) => {
+            log!(&format!("Hooks of {} failed: {}", event.event, err));
+            return;
+        }
+    };
+    debug!("running {} hooks of {}", hooks.len(), event.event);
+
+    let mut running = RUNNING.lock();
+    running.retain(|task| !task.is_finished());
+    for hook in hooks {
+        let target = hook.target.clone();
+        match hook.kind.as_str() {
+            "command" => running.push(runtime.spawn(run_command(target, event.env(&payload)))),
+            "webhook" => running.push(runtime.spawn(send_webhook(target, event.clone()))),
+            kind => log!(&format!("Hook '{}' has unknown kind {}", target, kind)),
+        }
+    }
+}
+
+/// Waits for running hooks, so that they aren't cut off when the program exits.
+pub(crate) async fn wait() {
+    let running: Vec<JoinHandle<()>> = RUNNING.lock().drain(..).collect();
+    if running.is_empty() {
+        return;
+    }
+    if tokio::time::timeout(TIMEOUT, futures::future::join_all(running)).await.is_err() {
+        eprintln!("Error: hooks didn't finish in {}s", TIMEOUT.as_secs());
+    }
+}
+
+/// Checks the event of a hook being added or removed.
+///
+/// # Arguments
+/// * `event` - The event.
+///
+/// # Returns
+/// * `Result<(), MdownError>` - Returns `Ok(())` if hooks can be added to the event, or an `MdownError` if they can't.
+fn check_event(event: &str) -> Result<(), MdownError> {
+    if event == "*" || EVENTS.contains(&event) {
+        return Ok(());
+    }
+    Err(
+        MdownError::CustomError(
+            format!("unknown event '{}'; use {} or *", event, EVENTS.join(", ")),
+            String::from("Hook")
+        )
+    )
+}
+
+/// Adds, removes or shows hooks as asked with `settings --hook`, `--webhook`, `--remove-hook` and `--show-hooks`.
+///
+/// # Returns
+/// * `Result<(), MdownError>` - Returns `Ok(())` if the hooks were changed, or an `MdownError` if an event or URL isn't valid or the database failed.
+pub(crate) fn resolve_settings() -> Result<(), MdownError> {
+    let mut added = Vec::new();
+    if let Some(hook) = args::ARGS_HOOK.as_ref() {
+        added.push(HookMetadata {
+            event: hook[0].clone(),
+            kind: String::from("command"),
+            target: hook[1].clone(),
+        });
+    }
+    if let Some(webhook) = args::ARGS_WEBHOOK.as_ref() {
+        match reqwest::Url::parse(&webhook[1]) {
+            Ok(url) if url.scheme() == "http" || url.scheme() == "https" => (),
+            _ => {
+                return Err(
+                    MdownError::CustomError(
+                        format!("'{}' is not an http or https URL", webhook[1]),
+                        String::from("Hook")
+                    )
+                );
+            }
+        }
+        added.push(HookMetadata {
+            event: webhook[0].clone(),
+            kind: String::from("webhook"),
+            target: webhook[1].clone(),
+        });
+    }
+    for hook in added.iter() {
+        match check_event(&hook.event) {
+            Ok(()) => (),
+            Err(err) => {
+                return Err(err);
+            }
+        }
+        match db::add_hook(hook) {
+            Ok(true) => println!("Added {} of {}: {}", hook.kind, hook.event, hook.target),
+            Ok(false) => println!("Hook of {} was already added: {}", hook.event, hook.target),
+            Err(err) => {
+                return Err(err);
+            }
+        }
+    }
+
+    if let Some(remove) = args::ARGS_REMOVE_HOOK.as_ref() {
+        match check_event(&remove[0]) {
+            Ok(()) => (),
+            Err(err) => {
+                return Err(err);
+            }
+        }
+        match db::remove_hooks(&remove[0], remove.get(1).map(String::as_str)) {
+            Ok(removed) => println!("Removed {} hooks of {}", removed, remove[0]),
+            Err(err) => {
+                return Err(err);
+            }
+        }
+    }
+
+    if *args::ARGS_SHOW_HOOKS {
+        let hooks = match db::read_hooks() {
+            Ok(hooks) => hooks,
+            Err(err) => {
+                return Err(err);
+            }
+        };
+        if hooks.is_empty() {
+            println!("No hooks");
+        }
+        for hook in hooks.iter() {
+            println!("{} {}: {}", hook.event, hook.kind, hook.target);
+        }
+    }
+    Ok(())
+}
+
+// Runs hooks of all events only for `*`
+#[test]
+fn test_is_hooked() {
+    assert!(is_hooked("*", "error"));
+    assert!(is_hooked("new-chapter", "new-chapter"));
+    assert!(!is_hooked("new-chapter", "chapter-downloaded"));
+    assert!(check_event("manga-finished").is_ok());
+    assert!(check_event("*").is_ok());
+    assert!(check_event("finished").is_err());
+}
+
+// Gives commands the details of the event and the whole event as JSON
+#[test]
+fn test_event_env() {
+    let event = Event {
+        event: String::from("manga-finished"),
+        manga_id: String::from("mid"),
+        chapters: vec![String::from("1"), String::from("2")],
+        ..Event::default()
+    };
+    let payload = serde_json::to_string(&event).unwrap();
+    let env = event.env(&payload);
+    assert!(env.contains(&("MDOWN_EVENT", String::from("manga-finished"))));
+    assert!(env.contains(&("MDOWN_MANGA_ID", String::from("mid"))));
+    assert!(env.contains(&("MDOWN_CHAPTERS", String::from("1,2"))));
+    let value: serde_json::Value = serde_json::from_str(&payload).unwrap();
+    assert_eq!(value["chapters"], serde_json::json!(["1", "2"]));
+    assert_eq!(value["manga_id"], "mid");
+}
+
+// Waits for hooks that were started
+#[tokio::test]
+async fn test_wait() {
+    RUNNING.lock().push(tokio::spawn(async {}));
+    wait().await;
+    assert!(RUNNING.lock().is_empty());
+}
@@ -0,0 +1,743 @@
+use image::{
+    codecs::{ avif::AvifEncoder, jpeg::JpegEncoder, webp::WebPEncoder },
+    imageops::FilterType,
+    DynamicImage,
+    GrayImage,
+    ImageFormat,
+    ImageReader,
+};
+use std::{ fs::{ self, File }, io::{ BufWriter, Write }, path::{ Path, PathBuf }, thread };
+
+use crate::{ args, debug, error::MdownError, metadata, resolute, utils };
+
+/// Extensions of files that are treated as pages of a chapter.
+pub(crate) const PAGE_EXTENSIONS: [&str; 6] = ["jpg", "jpeg", "png", "webp", "gif", "avif"];
+
+/// Quality used when pages changed by layout processing are saved in their original format.
+const LAYOUT_QUALITY: u8 = 90;
+
+/// Quality of converted jpeg and avif pages when `--image-quality` isn't supplied.
+const DEFAULT_QUALITY: u8 = 80;
+
+/// Largest difference in brightness from the border color that still counts as border when cropping.
+const CROP_TOLERANCE: u8 = 24;
+
+/// Returns the transformation requested by the user.
+///
+/// # Returns
+/// `Option<metadata::TransformMetadata>`: Returns `None` if pages should be kept as they were downloaded.
+pub(crate) fn get_transform() -> Option<metadata::TransformMetadata> {
+    let args = args::ARGS.lock();
+    if !args.image_processing() {
+        return None;
+    }
+    Some(metadata::TransformMetadata {
+        format: args.image_format.clone(),
+        quality: args.image_quality.unwrap_or(DEFAULT_QUALITY),
+        max_width: args.max_width,
+        max_height: args.max_height,
+        grayscale: args.grayscale,
+    })
+}
+
+/// Calculates the size of a page scaled down to fit the supplied bounds while keeping its aspect ratio.
+///
+/// # Parameters
+/// - `width: u32`: The width of the page.
+/// - `height: u32`: The height of the page.
+/// - `max_width: Option<u32>`: The maximum width; `None` means unbounded.
+/// - `max_height: Option<u32>`: The maximum height; `None` means unbounded.
+///
+/// # Returns
+/// `(u32, u32)`: The new width and height. Pages that already fit are never scaled up.
+pub(crate) fn fit_size(
+    width: u32,
+    height: u32,
+    max_width: Option<u32>,
+    max_height: Option<u32>
+) -> (u32, u32) {
+    let scale_width = match max_width {
+        Some(max_width) if width > max_width => (max_width as f64) / (width as f64),
+        _ => 1.0,
+    };
+    let scale_height = match max_height {
+        Some(max_height) if height > max_height => (max_height as f64) / (height as f64),
+        _ => 1.0,
+    };
+    let scale = scale_width.min(scale_height);
+    if scale >= 1.0 {
+        return (width, height);
+    }
+    (
+        (((width as f64) * scale).round() as u32).max(1),
+        (((height as f64) * scale).round() as u32).max(1),
+    )
+}
+
+/// Returns the layout processing used for the current manga.
+///
+/// # Returns
+/// `Option<metadata::LayoutMetadata>`: Returns `None` if the layout of pages should be kept.
+pub(crate) fn get_layout() -> Option<metadata::LayoutMetadata> {
+    resolute::get_layout().filter(|layout| layout.is_active())
+}
+
+/// Finds the area of a page that is left after cropping uniform borders.
+///
+/// The color of the top-left pixel is taken as the border color.
+///
+/// # Parameters
+/// - `image: &GrayImage`: The page in grayscale.
+/// - `tolerance: u8`: The largest difference in brightness that still counts as border.
+///
+/// # Returns
+/// `(u32, u32, u32, u32)`: The x and y coordinates, width and height of the content. Uniform pages are returned whole.
+pub(crate) fn crop_bounds(image: &GrayImage, tolerance: u8) -> (u32, u32, u32, u32) {
+    let (width, height) = image.dimensions();
+    if width == 0 || height == 0 {
+        return (0, 0, width, height);
+    }
+    let border = image.get_pixel(0, 0).0[0];
+    let (mut left, mut top, mut right, mut bottom) = (width, height, 0, 0);
+    for (x, y, pixel) in image.enumerate_pixels() {
+        if pixel.0[0].abs_diff(border) > tolerance {
+            left = left.min(x);
+            top = top.min(y);
+            right = right.max(x);
+            bottom = bottom.max(y);
+        }
+    }
+    if left > right || top > bottom {
+        return (0, 0, width, height);
+    }
+    (left, top, right - left + 1, bottom - top + 1)
+}
+
+/// Calculates where a long strip is cut into screen sized pages.
+///
+/// # Parameters
+/// - `width: u32`: The width of the strip.
+/// - `height: u32`: The height of the strip.
+/// - `ratio: f32`: The aspect ratio (height / width) of the target screen.
+///
+/// # Returns
+/// `Vec<(u32, u32)>`: The y coordinate and height of every page. Strips that are not taller than twice the screen are returned whole.
+pub(crate) fn split_ranges(width: u32, height: u32, ratio: f32) -> Vec<(u32, u32)> {
+    let screen_height = ((width as f32) * ratio).round().max(1.0) as u32;
+    if ratio <= 0.0 || height <= screen_height.saturating_mul(2) {
+        return vec![(0, height)];
+    }
+    let pages = height.div_ceil(screen_height);
+    let page_height = height.div_ceil(pages);
+    (0..pages)
+        .map(|page| page * page_height)
+        .filter(|y| *y < height)
+        .map(|y| (y, page_height.min(height - y)))
+        .collect()
+}
+
+/// Crops, splits and rotates a page according to a layout.
+///
+/// # Parameters
+/// - `image: DynamicImage`: The page.
+/// - `layout: &metadata::LayoutMetadata`: The layout to apply.
+///
+/// # Returns
+/// `(Vec<DynamicImage>, bool)`: The resulting pages in reading order, and whether the page was changed.
+fn apply_layout(
+    mut image: DynamicImage,
+    layout: &metadata::LayoutMetadata
+) -> (Vec<DynamicImage>, bool) {
+    let mut changed = false;
+    if layout.crop {
+        let (x, y, width, height) = crop_bounds(&image.to_luma8(), CROP_TOLERANCE);
+        if (width, height) != (image.width(), image.height()) {
+            image = image.crop_imm(x, y, width, height);
+            changed = true;
+        }
+    }
+
+    let (width, height) = (image.width(), image.height());
+    if let Some(ratio) = layout.split_tall {
+        let ranges = split_ranges(width, height, ratio);
+        if ranges.len() > 1 {
+            let pages = ranges
+                .into_iter()
+                .map(|(y, page_height)| image.crop_imm(0, y, width, page_height))
+                .collect();
+            return (pages, true);
+        }
+    }
+
+    // Pages wider than tall are double-page spreads
+    if width > height {
+        let rtl = layout.direction == "rtl";
+        match layout.spread.as_str() {
+            "split" => {
+                let left = image.crop_imm(0, 0, width / 2, height);
+                let right = image.crop_imm(width / 2, 0, width - width / 2, height);
+                return match rtl {
+                    true => (vec![right, left], true),
+                    false => (vec![left, right], true),
+                };
+            }
+            "rotate" => {
+                // The half that is read first ends up on top
+                let rotated = match rtl {
+                    true => image.rotate270(),
+                    false => image.rotate90(),
+                };
+                return (vec![rotated], true);
+            }
+            _ => (),
+        }
+    }
+    (vec![image], changed)
+}
+
+/// Lists the pages of a chapter folder in name order.
+///
+/// # Parameters
+/// - `folder_path: &str`: The chapter folder in the cache.
+///
+/// # Returns
+/// `Result<Vec<PathBuf>, MdownError>`: Returns the paths of all pages, or an `MdownError` if the folder cannot be read.
+pub(crate) fn get_pages(folder_path: &str) -> Result<Vec<PathBuf>, MdownError> {
+    let entries = match fs::read_dir(folder_path) {
+        Ok(entries) => entries,
+        Err(err) => {
+            return Err(MdownError::IoError(err, folder_path.to_string()));
+        }
+    };
+    let mut pages: Vec<PathBuf> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            match path.extension().and_then(|extension| extension.to_str()) {
+                Some(extension) => PAGE_EXTENSIONS.contains(&extension.to_lowercase().as_str()),
+                None => false,
+            }
+        })
+        .collect();
+    pages.sort_by(|a, b| utils::compare_names(&a.to_string_lossy(), &b.to_string_lossy()));
+    Ok(pages)
+}
+
+/// Decodes a page, detecting its format from the content rather than the extension.
+///
+/// # Parameters
+/// - `path: &Path`: The path to the page.
+///
+/// # Returns
+/// `Result<(DynamicImage, Option<ImageFormat>), MdownError>`: Returns the decoded page and its detected format, or an `MdownError` if it cannot be decoded.
+pub(crate) fn open_page(path: &Path) -> Result<(DynamicImage, Option<ImageFormat>), MdownError> {
+    let name = path.to_string_lossy().to_string();
+    let reader = match ImageReader::open(path) {
+        Ok(reader) => reader,
+        Err(err) => {
+            return Err(MdownError::IoError(err, name));
+        }
+    };
+    let reader = match reader.with_guessed_format() {
+        Ok(reader) => reader,
+        Err(err) => {
+            return Err(MdownError::IoError(err, name));
+        }
+    };
+    let format = reader.format();
+    match reader.decode() {
+        Ok(image) => Ok((image, format)),
+        Err(err) => Err(MdownError::CustomError(err.to_string(), String::from("ImageError"))),
+    }
+}
+
+/// Encodes a page and writes it to disk.
+///
+/// # Parameters
+/// - `image: &DynamicImage`: The page to encode.
+/// - `format: ImageFormat`: The output format.
+/// - `quality: u8`: The quality used by jpeg and avif; other formats ignore it.
+/// - `path: &Path`: The destination path.
+///
+/// # Returns
+/// `Result<(), MdownError>`: Returns `Ok(())` if the page was written, or an `MdownError` if encoding or writing fails.
+pub(crate) fn save_page(
+    image: &DynamicImage,
+    format: ImageFormat,
+    quality: u8,
+    path: &Path
+) -> Result<(), MdownError> {
+    let name = path.to_string_lossy().to_string();
+    let file = match File::create(path) {
+        Ok(file) => file,
+        Err(err) => {
+            return Err(MdownError::IoError(err, name));
+        }
+    };
+    let mut writer = BufWriter::new(file);
+
+    // Encoders only accept 8-bit pixels, jpeg additionally has no alpha channel
+    let image = match (format, image) {
+        (_, DynamicImage::ImageLuma8(_) | DynamicImage::ImageRgb8(_)) => image.clone(),
+        (ImageFormat::Jpeg, _) => DynamicImage::ImageRgb8(image.to_rgb8()),
+        (_, DynamicImage::ImageLumaA8(_) | DynamicImage::ImageRgba8(_)) => image.clone(),
+        (_, _) => DynamicImage::ImageRgba8(image.to_rgba8()),
+    };
+    let result = match format {
+        ImageFormat::Jpeg => {
+            image.write_with_encoder(JpegEncoder::new_with_quality(&mut writer, quality))
+        }
+        ImageFormat::Avif => {
+            image.write_with_encoder(AvifEncoder::new_with_speed_quality(&mut writer, 8, quality))
+        }
+        ImageFormat::WebP => image.write_with_encoder(WebPEncoder::new_lossless(&mut writer)),
+        format => image.write_to(&mut writer, format),
+    };
+    match result {
+        Ok(()) => (),
+        Err(err) => {
+            return Err(MdownError::CustomError(err.to_string(), String::from("ImageError")));
+        }
+    }
+    match writer.flush() {
+        Ok(()) => Ok(()),
+        Err(err) => Err(MdownError::IoError(err, name)),
+    }
+}
+
+/// Scales and recolors a page according to a transformation.
+///
+/// # Parameters
+/// - `image: DynamicImage`: The page.
+/// - `transform: &metadata::TransformMetadata`: The transformation to apply.
+///
+/// # Returns
+/// `DynamicImage`: The transformed page.
+fn transform_image(mut image: DynamicImage, transform: &metadata::TransformMetadata) -> DynamicImage {
+    let (width, height) = fit_size(
+        image.width(),
+        image.height(),
+        transform.max_width,
+        transform.max_height
+    );
+    if (width, height) != (image.width(), image.height()) {
+        image = image.resize_exact(width, height, FilterType::Lanczos3);
+    }
+    if transform.grayscale {
+        image = DynamicImage::ImageLuma8(image.to_luma8());
+    }
+    image
+}
+
+/// Applies the layout and transformation to a single page.
+///
+/// A page split into several pages is saved as `<name>_1`, `<name>_2`, ... so the pages keep their order.
+/// If the format changed, the original file is replaced by one with the new extension.
+///
+/// # Parameters
+/// - `path: &Path`: The path to the page.
+/// - `layout: Option<&metadata::LayoutMetadata>`: The layout to apply, if any.
+/// - `transform: Option<&metadata::TransformMetadata>`: The transformation to apply, if any.
+///
+/// # Returns
+/// `Result<(), MdownError>`: Returns `Ok(())` if the page was processed, or an `MdownError` if it cannot be decoded or saved.
+fn process_page(
+    path: &Path,
+    layout: Option<&metadata::LayoutMetadata>,
+    transform: Option<&metadata::TransformMetadata>
+) -> Result<(), MdownError> {
+    let (image, detected) = match open_page(path) {
+        Ok(page) => page,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    let (mut images, changed) = match layout {
+        Some(layout) => apply_layout(image, layout),
+        None => (vec![image], false),
+    };
+    if !changed && transform.is_none() {
+        return Ok(());
+    }
+
+    let (format, quality) = match transform {
+        Some(transform) => {
+            images = images
+                .into_iter()
+                .map(|image| transform_image(image, transform))
+                .collect();
+            let format = match transform.format.as_str() {
+                "webp" => ImageFormat::WebP,
+                "jpeg" => ImageFormat::Jpeg,
+                "avif" => ImageFormat::Avif,
+                _ => detected.unwrap_or(ImageFormat::Jpeg),
+            };
+            (format, transform.quality)
+        }
+        None => (detected.unwrap_or(ImageFormat::Jpeg), LAYOUT_QUALITY),
+    };
+    let extension = match format.extensions_str().first() {
+        Some(extension) => *extension,
+        None => "jpg",
+    };
+
+    let mut new_paths = vec![];
+    if images.len() == 1 {
+        new_paths.push(path.with_extension(extension));
+    } else {
+        let stem = match path.file_stem() {
+            Some(stem) => stem.to_string_lossy().to_string(),
+            None => String::from("page"),
+        };
+        for page in 1..=images.len() {
+            new_paths.push(path.with_file_name(format!("{}_{}.{}", stem, page, extension)));
+        }
+    }
+    for (image, new_path) in images.iter().zip(new_paths.iter()) {
+        match save_page(image, format, quality, new_path) {
+            Ok(()) => (),
+            Err(err) => {
+                return Err(err);
+            }
+        }
+    }
+    if !new_paths.iter().any(|new_path| new_path == path) {
+        if let Err(err) = fs::remove_file(path) {
+            return Err(MdownError::IoError(err, path.to_string_lossy().to_string()));
+        }
+    }
+    Ok(())
+}
+
+/// Runs a function on every page, spreading the pages over the available CPU cores.
+///
+/// # Parameters
+/// - `pages: &[PathBuf]`: The pages to process.
+/// - `function: F`: The function that is run on every page.
+///
+/// # Returns
+/// `Result<(), MdownError>`: Returns the first error encountered, if any.
+pub(crate) fn for_each_page<F>(pages: &[PathBuf], function: F) -> Result<(), MdownError>
+    where F: Fn(&Path) -> Result<(), MdownError> + Sync
+{
+    if pages.is_empty() {
+        return Ok(());
+    }
+    let threads = match thread::available_parallelism() {
+        Ok(threads) => threads.get(),
+        Err(_err) => 1,
+    };
+    let chunk_size = pages.len().div_ceil(threads);
+    let function = &function;
+    thread::scope(|scope| {
+        let handles: Vec<_> = pages
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(move || {
+                    for page in chunk.iter() {
+                        match function(page) {
+                            Ok(()) => (),
+                            Err(err) => {
+                                return Err(err);
+                            }
+                        }
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+        for handle in handles {
+            match handle.join() {
+                Ok(Ok(())) => (),
+                Ok(Err(err)) => {
+                    return Err(err);
+                }
+                Err(_err) => {
+                    return Err(
+                        MdownError::CustomError(
+                            String::from("Image processing thread panicked"),
+                            String::from("ImageError")
+                        )
+                    );
+                }
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Reads the `_metadata` file of a chapter folder, lets `update` change it and writes it back.
+///
+/// # Parameters
+/// - `folder_path: &str`: The chapter folder in the cache.
+/// - `update: F`: The function that changes the metadata.
+///
+/// # Returns
+/// `Result<(), MdownError>`: Returns `Ok(())` if the metadata was updated, or an `MdownError` if it cannot be read, parsed or written.
+pub(crate) fn update_metadata<F>(folder_path: &str, update: F) -> Result<(), MdownError>
+    where F: FnOnce(&mut metadata::ChapterMetadataIn)
+{
+    let path = Path::new(folder_path).join("_metadata");
+    let name = path.to_string_lossy().to_string();
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(err) => {
+            return Err(MdownError::IoError(err, name));
+        }
+    };
+    let mut chapter_metadata = match
+        serde_json::from_str::<metadata::ChapterMetadataIn>(&content)
+    {
+        Ok(chapter_metadata) => chapter_metadata,
+        Err(err) => {
+            return Err(MdownError::JsonError(err.to_string()));
+        }
+    };
+    update(&mut chapter_metadata);
+    let json = match serde_json::to_string_pretty(&chapter_metadata) {
+        Ok(json) => json,
+        Err(err) => {
+            return Err(MdownError::JsonError(err.to_string()));
+        }
+    };
+    match fs::write(&path, json) {
+        Ok(()) => Ok(()),
+        Err(err) => Err(MdownError::IoError(err, name)),
+    }
+}
+
+/// Applies the layout and transformation to all pages of a chapter folder.
+///
+/// The pages are copied aside first, so that the chapter keeps its original pages if any page fails to be processed.
+///
+/// # Parameters
+/// - `folder_path: &str`: The chapter folder in the cache.
+/// - `layout: Option<&metadata::LayoutMetadata>`: The layout to apply, if any.
+/// - `transform: Option<&metadata::TransformMetadata>`: The transformation to apply, if any.
+///
+/// # Returns
+/// `Result<(), MdownError>`: Returns `Ok(())` if all pages were processed, or the first `MdownError` encountered after the original pages were put back.
+fn process_pages(
+    folder_path: &str,
+    layout: Option<&metadata::LayoutMetadata>,
+    transform: Option<&metadata::TransformMetadata>
+) -> Result<(), MdownError> {
+    let pages = match get_pages(folder_path) {
+        Ok(pages) => pages,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    let backup = format!("{}.original", folder_path.trim_end_matches(['/', '\\']));
+    if let Err(err) = fs::create_dir_all(&backup) {
+        return Err(MdownError::IoError(err, backup));
+    }
+    for page in pages.iter() {
+        let name = match page.file_name() {
+            Some(name) => name,
+            None => {
+                continue;
+            }
+        };
+        if let Err(err) = fs::copy(page, Path::new(&backup).join(name)) {
+            let _ = fs::remove_dir_all(&backup);
+            return Err(MdownError::IoError(err, page.to_string_lossy().to_string()));
+        }
+    }
+
+    let result = for_each_page(&pages, |page| process_page(page, layout, transform));
+    if result.is_err() {
+        debug!("putting original pages back in {}", folder_path);
+        match restore_pages(folder_path, &backup) {
+            Ok(()) => (),
+            Err(err) => {
+                return Err(err);
+            }
+        }
+    }
+    let _ = fs::remove_dir_all(&backup);
+    result
+}
+
+/// Replaces the pages of a chapter folder with the pages copied aside before processing.
+///
+/// # Parameters
+/// - `folder_path: &str`: The chapter folder in the cache.
+/// - `backup: &str`: The folder holding the original pages.
+///
+/// # Returns
+/// `Result<(), MdownError>`: Returns `Ok(())` if the original pages are back, or an `MdownError` if they can't be moved.
+fn restore_pages(folder_path: &str, backup: &str) -> Result<(), MdownError> {
+    let pages = match get_pages(folder_path) {
+        Ok(pages) => pages,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    for page in pages.iter() {
+        if let Err(err) = fs::remove_file(page) {
+            return Err(MdownError::IoError(err, page.to_string_lossy().to_string()));
+        }
+    }
+    let originals = match get_pages(backup) {
+        Ok(originals) => originals,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    for original in originals.iter() {
+        let name = match original.file_name() {
+            Some(name) => name,
+            None => {
+                continue;
+            }
+        };
+        if let Err(err) = fs::rename(original, Path::new(folder_path).join(name)) {
+            return Err(MdownError::IoError(err, original.to_string_lossy().to_string()));
+        }
+    }
+    Ok(())
+}
+
+/// Processes the pages of a downloaded chapter before it is zipped.
+///
+/// The layout stored for the manga or supplied by the user is applied first (cropping borders,
+/// splitting long strips, splitting or rotating spreads), then pages are converted to the requested
+/// format, scaled down to the requested bounds and converted to grayscale if requested.
+/// Everything that was applied is recorded in the chapter's `_metadata`.
+///
+/// # Parameters
+/// - `folder_path: &str`: The chapter folder in the cache.
+///
+/// # Returns
+/// `Result<(), MdownError>`: Returns `Ok(())` if all pages were processed, or the first `MdownError` encountered; the chapter then keeps its original pages.
+pub(crate) fn process_chapter(folder_path: &str) -> Result<(), MdownError> {
+    let layout = get_layout();
+    let transform = get_transform();
+    if layout.is_none() && transform.is_none() {
+        return Ok(());
+    }
+    debug!("processing pages in {} with {:?} {:?}", folder_path, layout, transform);
+
+    match process_pages(folder_path, layout.as_ref(), transform.as_ref()) {
+        Ok(()) => (),
+        Err(err) => {
+            return Err(err);
+        }
+    }
+    update_metadata(folder_path, |chapter_metadata| {
+        chapter_metadata.transform = transform;
+        chapter_metadata.layout = layout;
+    })
+}
+
+// Scales pages down to the tighter bound while keeping the aspect ratio
+#[test]
+fn test_fit_size_scales_down() {
+    assert_eq!(fit_size(2000, 3000, Some(1000), None), (1000, 1500));
+    assert_eq!(fit_size(2000, 3000, Some(1000), Some(1000)), (667, 1000));
+}
+
+// Never scales pages up
+#[test]
+fn test_fit_size_keeps_small_pages() {
+    assert_eq!(fit_size(800, 1200, Some(1000), Some(1600)), (800, 1200));
+    assert_eq!(fit_size(800, 1200, None, None), (800, 1200));
+}
+
+// Finds the content inside a uniform border
+#[test]
+fn test_crop_bounds() {
+    let mut image = GrayImage::from_pixel(100, 80, image::Luma([255]));
+    for x in 20..60 {
+        for y in 10..50 {
+            image.put_pixel(x, y, image::Luma([0]));
+        }
+    }
+    assert_eq!(crop_bounds(&image, CROP_TOLERANCE), (20, 10, 40, 40));
+    assert_eq!(crop_bounds(&GrayImage::from_pixel(10, 10, image::Luma([255])), 24), (0, 0, 10, 10));
+}
+
+// Cuts long strips into equally tall pages and leaves normal pages whole
+#[test]
+fn test_split_ranges() {
+    assert_eq!(split_ranges(800, 4000, 1.5), vec![(0, 1000), (1000, 1000), (2000, 1000), (3000, 1000)]);
+    assert_eq!(split_ranges(800, 2500, 1.5), vec![(0, 834), (834, 834), (1668, 832)]);
+    assert_eq!(split_ranges(800, 1200, 1.5), vec![(0, 1200)]);
+}
+
+/// Creates an empty folder for a test.
+#[cfg(test)]
+fn test_folder(name: &str) -> String {
+    let folder = std::env::temp_dir().join(format!("mdown_test_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&folder);
+    fs::create_dir_all(&folder).unwrap();
+    folder.to_string_lossy().to_string()
+}
+
+// Converts pages to the requested format and removes the original files
+#[test]
+fn test_process_pages_converts() {
+    let folder = test_folder("convert");
+    let page = DynamicImage::ImageRgb8(image::RgbImage::from_pixel(40, 60, image::Rgb([200, 10, 10])));
+    page.save(Path::new(&folder).join("Test - 1.png")).unwrap();
+    let transform = metadata::TransformMetadata {
+        format: String::from("jpeg"),
+        quality: 50,
+        max_width: Some(20),
+        max_height: None,
+        grayscale: true,
+    };
+    process_pages(&folder, None, Some(&transform)).unwrap();
+    let pages = get_pages(&folder).unwrap();
+    assert_eq!(pages, vec![Path::new(&folder).join("Test - 1.jpg")]);
+    let (image, format) = open_page(&pages[0]).unwrap();
+    assert_eq!(format, Some(ImageFormat::Jpeg));
+    assert_eq!((image.width(), image.height()), (20, 30));
+    assert!(!Path::new(&format!("{}.original", folder)).exists());
+    fs::remove_dir_all(&folder).unwrap();
+}
+
+// Keeps the original pages when a page can't be converted
+#[test]
+fn test_process_pages_keeps_originals() {
+    let folder = test_folder("broken");
+    let page = DynamicImage::ImageRgb8(image::RgbImage::from_pixel(10, 10, image::Rgb([0, 0, 0])));
+    page.save(Path::new(&folder).join("Test - 1.png")).unwrap();
+    fs::write(Path::new(&folder).join("Test - 2.png"), b"not an image").unwrap();
+    let transform = metadata::TransformMetadata {
+        format: String::from("webp"),
+        quality: DEFAULT_QUALITY,
+        max_width: None,
+        max_height: None,
+        grayscale: false,
+    };
+    assert!(process_pages(&folder, None, Some(&transform)).is_err());
+    let names: Vec<String> = get_pages(&folder)
+        .unwrap()
+        .iter()
+        .map(|page| page.file_name().unwrap().to_string_lossy().to_string())
+        .collect();
+    assert_eq!(names, ["Test - 1.png", "Test - 2.png"]);
+    assert_eq!(fs::read(Path::new(&folder).join("Test - 2.png")).unwrap(), b"not an image");
+    assert!(!Path::new(&format!("{}.original", folder)).exists());
+    fs::remove_dir_all(&folder).unwrap();
+}
+
+// Lists pages by their number, so page 10 comes after page 9 and not after page 1
+#[test]
+fn test_get_pages_order() {
+    let folder = test_folder("order");
+    for page in (1..=11).rev() {
+        fs::write(Path::new(&folder).join(format!("Test - {}.jpg", page)), b"").unwrap();
+    }
+    fs::write(Path::new(&folder).join("_metadata"), b"").unwrap();
+    let names: Vec<String> = get_pages(&folder)
+        .unwrap()
+        .iter()
+        .map(|page| page.file_name().unwrap().to_string_lossy().to_string())
+        .collect();
+    let expected: Vec<String> = (1..=11).map(|page| format!("Test - {}.jpg", page)).collect();
+    assert_eq!(names, expected);
+    fs::remove_dir_all(&folder).unwrap();
+}
@@ -0,0 +1,2027 @@
+use chrono::Utc;
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use rusqlite::{ params, Connection, OptionalExtension, Params, TransactionBehavior };
+use std::{ collections::HashMap, fs::{ self, File }, path::{ Path, PathBuf }, time::Duration };
+
+use crate::{
+    args,
+    debug,
+    error::MdownError,
+    getter,
+    metadata::{ ChapterMetadata, Dat, LayoutMetadata, MangaMetadata, PreferencesMetadata, TagMetadata },
+    migration,
+    resolute,
+    utils,
+    version_manager::get_current_version,
+};
+
+/// How long to wait for another process writing the library.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Number of backups of the library that are kept.
+const BACKUP_COUNT: usize = 5;
+
+lazy_static! {
+    /// Whether this process already backed up the library; the daemon backs it up again each cycle.
+    static ref BACKED_UP: Mutex<bool> = Mutex::new(false);
+}
+
+/// Brings the layout of the library up to date and records the version of the program.
+///
+/// An existing library is backed up before it is migrated. Manga are kept in the order they were added;
+/// chapters, tags, languages and fetch dates in the order they were stored.
+///
+/// # Arguments
+/// * `conn` - The connection to the library.
+/// * `confirm` - Asks the user whether a migration that removes data may run.
+///
+/// # Returns
+/// * `Result<(), MdownError>` - Returns `Ok(())` if the library is up to date, or an `MdownError` on failure.
+fn initialize(
+    conn: &mut Connection,
+    confirm: impl Fn(&migration::Migration) -> bool
+) -> Result<(), MdownError> {
+    match conn.execute_batch("PRAGMA foreign_keys = ON;") {
+        Ok(()) => (),
+        Err(err) => {
+            return Err(MdownError::DatabaseError(err));
+        }
+    }
+    let pending = match migration::pending(conn) {
+        Ok(pending) => pending,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    let existing = match has_manga_table(conn) {
+        Ok(existing) => existing,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    if !pending.is_empty() && existing {
+        match backup(conn) {
+            Ok(()) => (),
+            Err(err) => {
+                return Err(err);
+            }
+        }
+    }
+    // A new library has nothing to lose
+    match migration::migrate(conn, |migration| !existing || confirm(migration)) {
+        Ok(0) => (),
+        Ok(_ran) if existing => {
+            for migration in pending.iter() {
+                println!("Migrated library to version {}: {}", migration.version, migration.description);
+            }
+        }
+        Ok(_ran) => (),
+        Err(err) => {
+            return Err(err);
+        }
+    }
+
+    let stored = match read_info(conn, "version") {
+        Ok(stored) => stored,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    let version = match stored {
+        Some(stored) => migration::newer_version(&stored),
+        None => Some(get_current_version()),
+    };
+    match version {
+        Some(version) => write_info(conn, "version", &version),
+        None => Ok(()),
+    }
+}
+
+/// Asks the user whether a migration that removes data may run.
+fn confirm_migration(migration: &migration::Migration) -> bool {
+    let prompt = format!(
+        "Library has to be migrated to version {}, which will {}; a backup of the library was saved. Continue? (y/N) > ",
+        migration.version,
+        migration.description
+    );
+    match utils::input(&prompt) {
+        Ok(confirmation) => confirmation.to_lowercase() == *"y",
+        Err(_err) => false,
+    }
+}
+
+/// Checks whether the library already holds its tables, e.g. because it was created before migrations existed.
+fn has_manga_table(conn: &Connection) -> Result<bool, MdownError> {
+    match
+        conn.query_row(
+            "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = 'manga'",
+            [],
+            |row| row.get::<_, i64>(0)
+        )
+    {
+        Ok(count) => Ok(count > 0),
+        Err(err) => Err(MdownError::DatabaseError(err)),
+    }
+}
+
+/// Opens the library, creates its tables and imports `dat.json` of older versions.
+///
+/// # Returns
+/// * `Result<Connection, MdownError>` - Returns the connection, or an `MdownError` on failure.
+fn open() -> Result<Connection, MdownError> {
+    let path = match getter::get_library_path() {
+        Ok(path) => path,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    let mut conn = match Connection::open(&path) {
+        Ok(conn) => conn,
+        Err(err) => {
+            return Err(MdownError::DatabaseError(err));
+        }
+    };
+    match conn.busy_timeout(BUSY_TIMEOUT) {
+        Ok(()) => (),
+        Err(err) => {
+            return Err(MdownError::DatabaseError(err));
+        }
+    }
+    match initialize(&mut conn, confirm_migration) {
+        Ok(()) => (),
+        Err(err) => {
+            return Err(err);
+        }
+    }
+    match import_dat(&mut conn) {
+        Ok(()) => Ok(conn),
+        Err(err) => Err(err),
+    }
+}
+
+/// Executes a statement of the library.
+///
+/// # Arguments
+/// * `conn` - The connection to the library.
+/// * `sql` - The statement.
+/// * `params` - The parameters of the statement.
+///
+/// # Returns
+/// * `Result<usize, MdownError>` - Returns the number of changed rows, or an `MdownError` on failure.
+fn execute<P: Params>(conn: &Connection, sql: &str, params: P) -> Result<usize, MdownError> {
+    let mut stmt = match conn.prepare_cached(sql) {
+        Ok(stmt) => stmt,
+        Err(err) => {
+            return Err(MdownError::DatabaseError(err));
+        }
+    };
+    match stmt.execute(params) {
+        Ok(changed) => Ok(changed),
+        Err(err) => Err(MdownError::DatabaseError(err)),
+    }
+}
+
+/// Reads one column of the rows returned by a query.
+///
+/// # Arguments
+/// * `conn` - The connection to the library.
+/// * `sql` - The query.
+/// * `id` - The ID of the manga the rows belong to.
+/// * `read` - Converts a row.
+///
+/// # Returns
+/// * `Result<Vec<T>, MdownError>` - Returns the converted rows in the order they were stored, or an `MdownError` on failure.
+fn query<T>(
+    conn: &Connection,
+    sql: &str,
+    id: &str,
+    read: impl FnMut(&rusqlite::Row<'_>) -> rusqlite::Result<T>
+) -> Result<Vec<T>, MdownError> {
+    let mut stmt = match conn.prepare_cached(sql) {
+        Ok(stmt) => stmt,
+        Err(err) => {
+            return Err(MdownError::DatabaseError(err));
+        }
+    };
+    let rows = match stmt.query_map(params![id], read) {
+        Ok(rows) => rows,
+        Err(err) => {
+            return Err(MdownError::DatabaseError(err));
+        }
+    };
+    match rows.collect::<rusqlite::Result<Vec<T>>>() {
+        Ok(rows) => Ok(rows),
+        Err(err) => Err(MdownError::DatabaseError(err)),
+    }
+}
+
+/// Reads the value of a `library_info` entry.
+fn read_info(conn: &Connection, name: &str) -> Result<Option<String>, MdownError> {
+    match
+        conn
+            .query_row("SELECT value FROM library_info WHERE name = ?1", params![name], |row|
+                row.get(0)
+            )
+            .optional()
+    {
+        Ok(value) => Ok(value),
+        Err(err) => Err(MdownError::DatabaseError(err)),
+    }
+}
+
+/// Sets the value of a `library_info` entry.
+fn write_info(conn: &Connection, name: &str, value: &str) -> Result<(), MdownError> {
+    match
+        execute(
+            conn,
+            "INSERT INTO library_info (name, value) VALUES (?1, ?2)
+            ON CONFLICT(name) DO UPDATE SET value = excluded.value",
+            params![name, value]
+        )
+    {
+        Ok(_) => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+/// Reads the root of the library that folders of manga are stored relative to.
+fn read_root(conn: &Connection) -> Result<Option<PathBuf>, MdownError> {
+    match read_info(conn, "root") {
+        Ok(root) => Ok(root.map(PathBuf::from)),
+        Err(err) => Err(err),
+    }
+}
+
+/// Returns the folder of a manga as it is used by the program.
+///
+/// # Arguments
+/// * `root` - The root of the library, if any.
+/// * `mwd` - The folder of the manga as it is stored; relative to the root if it is inside the root.
+///
+/// # Returns
+/// * `String` - The absolute folder of the manga.
+fn to_absolute(root: &Option<PathBuf>, mwd: &str) -> String {
+    match root {
+        Some(root) if Path::new(mwd).is_relative() =>
+            match mwd {
+                "." => root.to_string_lossy().to_string(),
+                _ => root.join(mwd).to_string_lossy().to_string(),
+            }
+        _ => mwd.to_string(),
+    }
+}
+
+/// Returns the folder of a manga as it is stored in the library.
+///
+/// # Arguments
+/// * `root` - The root of the library, if any.
+/// * `mwd` - The absolute folder of the manga.
+///
+/// # Returns
+/// * `String` - The folder relative to the root if it is inside the root; otherwise the folder as it is.
+fn to_stored(root: &Option<PathBuf>, mwd: &str) -> String {
+    match root.as_ref().map(|root| Path::new(mwd).strip_prefix(root)) {
+        Some(Ok(relative)) if relative.as_os_str().is_empty() => String::from("."),
+        Some(Ok(relative)) => relative.to_string_lossy().to_string(),
+        _ => mwd.to_string(),
+    }
+}
+
+/// Replaces the start of the folder of a manga.
+///
+/// # Arguments
+/// * `mwd` - The folder of the manga.
+/// * `old` - The start of the folder to replace.
+/// * `new` - The new start of the folder.
+///
+/// # Returns
+/// * `Option<String>` - The new folder, or `None` if the folder doesn't start with `old`.
+fn replace_prefix(mwd: &str, old: &Path, new: &Path) -> Option<String> {
+    match Path::new(mwd).strip_prefix(old) {
+        Ok(rest) if rest.as_os_str().is_empty() => Some(new.to_string_lossy().to_string()),
+        Ok(rest) => Some(new.join(rest).to_string_lossy().to_string()),
+        Err(_err) => None,
+    }
+}
+
+/// Reads the IDs of all manga with their folders as they are stored.
+fn read_folders(conn: &Connection) -> Result<Vec<(String, String)>, MdownError> {
+    let mut stmt = match conn.prepare("SELECT id, mwd FROM manga ORDER BY rowid") {
+        Ok(stmt) => stmt,
+        Err(err) => {
+            return Err(MdownError::DatabaseError(err));
+        }
+    };
+    let rows = match stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?))) {
+        Ok(rows) => rows,
+        Err(err) => {
+            return Err(MdownError::DatabaseError(err));
+        }
+    };
+    match rows.collect::<rusqlite::Result<Vec<(String, String)>>>() {
+        Ok(folders) => Ok(folders),
+        Err(err) => Err(MdownError::DatabaseError(err)),
+    }
+}
+
+/// Changes the folder of a manga.
+///
+/// # Arguments
+/// * `conn` - The connection to the library.
+/// * `id` - The ID of the manga.
+/// * `mwd` - The absolute folder of the manga.
+///
+/// # Returns
+/// * `Result<(), MdownError>` - Returns `Ok(())` if the folder was changed, or an `MdownError` on failure.
+fn write_folder(conn: &Connection, id: &str, mwd: &str) -> Result<(), MdownError> {
+    let root = match read_root(conn) {
+        Ok(root) => root,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    match execute(conn, "UPDATE manga SET mwd = ?2 WHERE id = ?1", params![id, to_stored(&root, mwd)]) {
+        Ok(_) => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+/// Reads a manga with its chapters, tags, languages and fetch dates.
+///
+/// # Arguments
+/// * `conn` - The connection to the library.
+/// * `id` - The ID of the manga.
+///
+/// # Returns
+/// * `Result<Option<MangaMetadata>, MdownError>` - Returns the manga, `None` if it isn't in the library, or an `MdownError` on failure.
+fn read_manga(conn: &Connection, id: &str) -> Result<Option<MangaMetadata>, MdownError> {
+    let row = match
+        conn
+            .query_row(
+                "SELECT name, mwd, cover, current_language, layout, paused, preferences FROM manga WHERE id = ?1",
+                params![id],
+                |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, bool>(2)?,
+                        row.get::<_, String>(3)?,
+                        row.get::<_, Option<String>>(4)?,
+                        row.get::<_, bool>(5)?,
+                        row.get::<_, Option<String>>(6)?,
+                    ))
+                }
+            )
+            .optional()
+    {
+        Ok(Some(row)) => row,
+        Ok(None) => {
+            return Ok(None);
+        }
+        Err(err) => {
+            return Err(MdownError::DatabaseError(err));
+        }
+    };
+    let (name, mwd, cover, current_language, layout, paused, preferences) = row;
+    let mwd = match read_root(conn) {
+        Ok(root) => to_absolute(&root, &mwd),
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    let layout = match layout {
+        Some(layout) =>
+            match serde_json::from_str::<LayoutMetadata>(&layout) {
+                Ok(layout) => Some(layout),
+                Err(err) => {
+                    return Err(MdownError::JsonError(err.to_string()));
+                }
+            }
+        None => None,
+    };
+    let preferences = match preferences {
+        Some(preferences) =>
+            match serde_json::from_str::<PreferencesMetadata>(&preferences) {
+                Ok(preferences) => preferences,
+                Err(err) => {
+                    return Err(MdownError::JsonError(err.to_string()));
+                }
+            }
+        None => PreferencesMetadata::default(),
+    };
+
+    let chapters = match
+        query(
+            conn,
+            "SELECT number, updated_at, id FROM chapters WHERE manga_id = ?1 ORDER BY rowid",
+            id,
+            |row| Ok(ChapterMetadata { number: row.get(0)?, updated_at: row.get(1)?, id: row.get(2)? })
+        )
+    {
+        Ok(chapters) => chapters,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    let mut tags = Vec::new();
+    for kind in ["genre", "theme"] {
+        let sql = format!(
+            "SELECT id, name FROM tags WHERE manga_id = ?1 AND kind = '{}' ORDER BY rowid",
+            kind
+        );
+        match query(conn, &sql, id, |row| Ok(TagMetadata { id: row.get(0)?, name: row.get(1)? })) {
+            Ok(kind_tags) => tags.push(kind_tags),
+            Err(err) => {
+                return Err(err);
+            }
+        }
+    }
+    let theme = tags.pop().unwrap_or_default();
+    let genre = tags.pop().unwrap_or_default();
+    let available_languages = match
+        query(conn, "SELECT language FROM languages WHERE manga_id = ?1 ORDER BY rowid", id, |row|
+            row.get(0)
+        )
+    {
+        Ok(languages) => languages,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    let date = match
+        query(conn, "SELECT date FROM fetch_dates WHERE manga_id = ?1 ORDER BY rowid", id, |row|
+            row.get(0)
+        )
+    {
+        Ok(dates) => dates,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+
+    Ok(
+        Some(MangaMetadata {
+            name,
+            id: id.to_string(),
+            chapters,
+            mwd,
+            cover,
+            date,
+            available_languages,
+            current_language,
+            theme,
+            genre,
+            layout,
+            paused,
+            preferences,
+        })
+    )
+}
+
+/// Reads the whole library.
+///
+/// # Arguments
+/// * `conn` - The connection to the library.
+///
+/// # Returns
+/// * `Result<Dat, MdownError>` - Returns the manga in the order they were added, or an `MdownError` on failure.
+fn read_all(conn: &Connection) -> Result<Dat, MdownError> {
+    let mut stmt = match conn.prepare("SELECT id FROM manga ORDER BY rowid") {
+        Ok(stmt) => stmt,
+        Err(err) => {
+            return Err(MdownError::DatabaseError(err));
+        }
+    };
+    let ids = match stmt.query_map([], |row| row.get::<_, String>(0)) {
+        Ok(rows) =>
+            match rows.collect::<rusqlite::Result<Vec<String>>>() {
+                Ok(ids) => ids,
+                Err(err) => {
+                    return Err(MdownError::DatabaseError(err));
+                }
+            }
+        Err(err) => {
+            return Err(MdownError::DatabaseError(err));
+        }
+    };
+    let mut data = Vec::new();
+    for id in ids.iter() {
+        match read_manga(conn, id) {
+            Ok(Some(item)) => data.push(item),
+            Ok(None) => (),
+            Err(err) => {
+                return Err(err);
+            }
+        }
+    }
+    let version = match read_info(conn, "version") {
+        Ok(version) => version.unwrap_or_else(get_current_version),
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    Ok(Dat { data, version })
+}
+
+/// Stores a manga, replacing its chapters, tags, languages and fetch dates.
+///
+/// A manga that is already in the library keeps its place in the library. Its folder is stored relative to the
+/// root of the library if it is inside the root.
+///
+/// # Arguments
+/// * `conn` - The connection to the library; should be a transaction.
+/// * `item` - The manga.
+///
+/// # Returns
+/// * `Result<(), MdownError>` - Returns `Ok(())` if the manga was stored, or an `MdownError` on failure.
+fn write_manga(conn: &Connection, item: &MangaMetadata) -> Result<(), MdownError> {
+    let mwd = match read_root(conn) {
+        Ok(root) => to_stored(&root, &item.mwd),
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    let layout = match &item.layout {
+        Some(layout) =>
+            match serde_json::to_string(layout) {
+                Ok(layout) => Some(layout),
+                Err(err) => {
+                    return Err(MdownError::JsonError(err.to_string()));
+                }
+            }
+        None => None,
+    };
+    let preferences = match item.preferences.is_empty() {
+        true => None,
+        false =>
+            match serde_json::to_string(&item.preferences) {
+                Ok(preferences) => Some(preferences),
+                Err(err) => {
+                    return Err(MdownError::JsonError(err.to_string()));
+                }
+            }
+    };
+    match
+        execute(
+            conn,
+            "INSERT INTO manga (id, name, mwd, cover, current_language, layout, paused, preferences)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+            ON CONFLICT(id) DO UPDATE SET
+                name = excluded.name,
+                mwd = excluded.mwd,
+                cover = excluded.cover,
+                current_language = excluded.current_language,
+                layout = excluded.layout,
+                paused = excluded.paused,
+                preferences = excluded.preferences,
+                checked_at = CASE
+                    WHEN manga.mwd = excluded.mwd
+                        AND manga.current_language = excluded.current_language
+                        AND manga.preferences IS excluded.preferences
+                    THEN manga.checked_at
+                END",
+            params![
+                item.id,
+                item.name,
+                mwd,
+                item.cover,
+                item.current_language,
+                layout,
+                item.paused,
+                preferences
+            ]
+        )
+    {
+        Ok(_) => (),
+        Err(err) => {
+            return Err(err);
+        }
+    }
+    for table in ["chapters", "tags", "languages", "fetch_dates"] {
+        let sql = format!("DELETE FROM {} WHERE manga_id = ?1", table);
+        match execute(conn, &sql, params![item.id]) {
+            Ok(_) => (),
+            Err(err) => {
+                return Err(err);
+            }
+        }
+    }
+
+    for chapter in item.chapters.iter() {
+        match
+            execute(
+                conn,
+                "INSERT INTO chapters (manga_id, number, updated_at, id) VALUES (?1, ?2, ?3, ?4)
+                ON CONFLICT(manga_id, number, id) DO UPDATE SET updated_at = MAX(updated_at, excluded.updated_at)",
+                params![item.id, chapter.number, chapter.updated_at, chapter.id]
+            )
+        {
+            Ok(_) => (),
+            Err(err) => {
+                return Err(err);
+            }
+        }
+    }
+    for (kind, tags) in [("genre", &item.genre), ("theme", &item.theme)] {
+        for tag in tags.iter() {
+            match
+                execute(
+                    conn,
+                    "INSERT INTO tags (manga_id, kind, id, name) VALUES (?1, ?2, ?3, ?4)",
+                    params![item.id, kind, tag.id, tag.name]
+                )
+            {
+                Ok(_) => (),
+                Err(err) => {
+                    return Err(err);
+                }
+            }
+        }
+    }
+    for language in item.available_languages.iter() {
+        match
+            execute(
+                conn,
+                "INSERT INTO languages (manga_id, language) VALUES (?1, ?2)",
+                params![item.id, language]
+            )
+        {
+            Ok(_) => (),
+            Err(err) => {
+                return Err(err);
+            }
+        }
+    }
+    for date in item.date.iter() {
+        match
+            execute(
+                conn,
+                "INSERT INTO fetch_dates (manga_id, date) VALUES (?1, ?2)",
+                params![item.id, date]
+            )
+        {
+            Ok(_) => (),
+            Err(err) => {
+                return Err(err);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Stores the manga of a `Dat`; chapters of manga listed more than once are merged.
+///
+/// # Arguments
+/// * `conn` - The connection to the library; should be a transaction.
+/// * `dat` - The content of `dat.json`.
+///
+/// # Returns
+/// * `Result<usize, MdownError>` - Returns the number of stored manga, or an `MdownError` on failure.
+fn import(conn: &Connection, dat: Dat) -> Result<usize, MdownError> {
+    let mut imported: Vec<MangaMetadata> = Vec::new();
+    for item in dat.data {
+        match imported.iter_mut().find(|existing| existing.id == item.id) {
+            Some(existing) => {
+                for chapter in item.chapters {
+                    if !existing.chapters.contains(&chapter) {
+                        existing.chapters.push(chapter);
+                    }
+                }
+            }
+            None => imported.push(item),
+        }
+    }
+    for item in imported.iter() {
+        match write_manga(conn, item) {
+            Ok(()) => (),
+            Err(err) => {
+                return Err(err);
+            }
+        }
+    }
+    match write_info(conn, "version", &dat.version) {
+        Ok(()) => Ok(imported.len()),
+        Err(err) => Err(err),
+    }
+}
+
+/// Moves the manga of `dat.json` used by older versions into the library.
+///
+/// `dat.json` is imported only once and is kept as `dat.json.bak`.
+///
+/// # Arguments
+/// * `conn` - The connection to the library.
+///
+/// # Returns
+/// * `Result<(), MdownError>` - Returns `Ok(())` if there is nothing left to import, or an `MdownError` on failure.
+fn import_dat(conn: &mut Connection) -> Result<(), MdownError> {
+    let dat_path = match getter::get_dat_path() {
+        Ok(path) => path,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    if !Path::new(&dat_path).is_file() {
+        return Ok(());
+    }
+    let tx = match conn.transaction_with_behavior(TransactionBehavior::Immediate) {
+        Ok(tx) => tx,
+        Err(err) => {
+            return Err(MdownError::DatabaseError(err));
+        }
+    };
+    // Another process may have imported dat.json while this one waited for the transaction
+    match read_info(&tx, "imported") {
+        Ok(Some(_imported)) => {
+            return Ok(());
+        }
+        Ok(None) => (),
+        Err(err) => {
+            return Err(err);
+        }
+    }
+    let dat = match resolute::get_dat_content(&dat_path) {
+        Ok(json) =>
+            match serde_json::from_value::<Dat>(json) {
+                Ok(dat) => dat,
+                Err(err) => {
+                    return Err(MdownError::JsonError(err.to_string()));
+                }
+            }
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    let imported = match import(&tx, dat) {
+        Ok(imported) => imported,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    match write_info(&tx, "imported", &dat_path) {
+        Ok(()) => (),
+        Err(err) => {
+            return Err(err);
+        }
+    }
+    match tx.commit() {
+        Ok(()) => (),
+        Err(err) => {
+            return Err(MdownError::DatabaseError(err));
+        }
+    }
+
+    let backup = format!("{}.bak", dat_path);
+    match fs::rename(&dat_path, &backup) {
+        Ok(()) => println!("Imported {} manga from {} into the library; kept it as {}", imported, dat_path, backup),
+        Err(err) => eprintln!("Error: renaming {} after importing it {}", dat_path, err),
+    }
+    Ok(())
+}
+
+/// Lists the backups of the library, oldest first.
+///
+/// # Arguments
+/// * `folder` - The folder holding the backups.
+///
+/// # Returns
+/// * `Result<Vec<PathBuf>, MdownError>` - Returns the paths to the backups, or an `MdownError` if the folder can't be read.
+fn list_backups(folder: &str) -> Result<Vec<PathBuf>, MdownError> {
+    let entries = match fs::read_dir(folder) {
+        Ok(entries) => entries,
+        Err(err) => {
+            return Err(MdownError::IoError(err, folder.to_string()));
+        }
+    };
+    let mut backups: Vec<PathBuf> = entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| {
+            let name = path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default();
+            name.starts_with("library-") && name.ends_with(".db")
+        })
+        .collect();
+    // Names contain the time of the backup, so they sort from oldest to newest
+    backups.sort();
+    Ok(backups)
+}
+
+/// Removes the oldest backups so that only `BACKUP_COUNT` backups are kept.
+///
+/// # Arguments
+/// * `folder` - The folder holding the backups.
+///
+/// # Returns
+/// * `Result<(), MdownError>` - Returns `Ok(())` if the oldest backups were removed, or an `MdownError` on failure.
+fn prune_backups(folder: &str) -> Result<(), MdownError> {
+    let backups = match list_backups(folder) {
+        Ok(backups) => backups,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    for backup in backups.iter().take(backups.len().saturating_sub(BACKUP_COUNT)) {
+        if let Err(err) = fs::remove_file(backup) {
+            return Err(MdownError::IoError(err, backup.to_string_lossy().to_string()));
+        }
+    }
+    Ok(())
+}
+
+/// Returns the path to a new backup of the library.
+fn get_new_backup_path(folder: &str) -> PathBuf {
+    Path::new(folder).join(format!("library-{}.db", Utc::now().format("%Y%m%d-%H%M%S%6f")))
+}
+
+/// Writes a consistent copy of the library into the folder of backups and removes the oldest backups.
+///
+/// # Arguments
+/// * `conn` - The connection to the library; must not be in a transaction.
+/// * `folder` - The folder holding the backups.
+///
+/// # Returns
+/// * `Result<PathBuf, MdownError>` - Returns the path to the backup, or an `MdownError` on failure.
+fn write_backup(conn: &Connection, folder: &str) -> Result<PathBuf, MdownError> {
+    let path = get_new_backup_path(folder);
+    let tmp_path = format!("{}.tmp", path.to_string_lossy());
+    match execute(conn, "VACUUM INTO ?1", params![tmp_path]) {
+        Ok(_) => (),
+        Err(err) => {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(err);
+        }
+    }
+    let synced = match File::open(&tmp_path) {
+        Ok(file) => file.sync_all(),
+        Err(err) => Err(err),
+    };
+    let renamed = match synced {
+        Ok(()) => fs::rename(&tmp_path, &path),
+        Err(err) => Err(err),
+    };
+    if let Err(err) = renamed {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(MdownError::IoError(err, path.to_string_lossy().to_string()));
+    }
+    match prune_backups(folder) {
+        Ok(()) => Ok(path),
+        Err(err) => Err(err),
+    }
+}
+
+/// Backs up the library before this process changes it for the first time.
+///
+/// An empty library is not backed up.
+///
+/// # Arguments
+/// * `conn` - The connection to the library; must not be in a transaction.
+///
+/// # Returns
+/// * `Result<(), MdownError>` - Returns `Ok(())` if the library is backed up, or an `MdownError` on failure.
+fn backup(conn: &Connection) -> Result<(), MdownError> {
+    let mut backed_up = BACKED_UP.lock();
+    if *backed_up {
+        return Ok(());
+    }
+    let count = match conn.query_row("SELECT COUNT(*) FROM manga", [], |row| row.get::<_, i64>(0)) {
+        Ok(count) => count,
+        Err(err) => {
+            return Err(MdownError::DatabaseError(err));
+        }
+    };
+    if count > 0 {
+        let folder = match getter::get_backup_folder() {
+            Ok(folder) => folder,
+            Err(err) => {
+                return Err(err);
+            }
+        };
+        match write_backup(conn, &folder) {
+            Ok(path) => debug!("backed up library to {}", path.display()),
+            Err(err) => {
+                return Err(err);
+            }
+        }
+    }
+    *backed_up = true;
+    Ok(())
+}
+
+/// Lets the library be backed up again before it is next changed.
+pub(crate) fn forget_backup() {
+    *BACKED_UP.lock() = false;
+}
+
+/// Reads the whole library.
+///
+/// # Returns
+/// * `Result<Dat, MdownError>` - Returns the manga in the order they were added, or an `MdownError` on failure.
+pub(crate) fn load() -> Result<Dat, MdownError> {
+    match open() {
+        Ok(conn) => read_all(&conn),
+        Err(err) => Err(err),
+    }
+}
+
+/// Reads a manga of the library.
+///
+/// # Arguments
+/// * `id` - The ID of the manga.
+///
+/// # Returns
+/// * `Result<Option<MangaMetadata>, MdownError>` - Returns the manga, `None` if it isn't in the library, or an `MdownError` on failure.
+pub(crate) fn find(id: &str) -> Result<Option<MangaMetadata>, MdownError> {
+    match open() {
+        Ok(conn) => read_manga(&conn, id),
+        Err(err) => Err(err),
+    }
+}
+
+/// Changes a manga of the library in one transaction, so that processes changing the same manga don't overwrite each other.
+///
+/// The manga is looked up by its ID and then by its name.
+///
+/// # Arguments
+/// * `id` - The ID of the manga.
+/// * `name` - The name of the manga.
+/// * `update` - Receives the stored manga, if any, and returns the manga to store.
+///
+/// # Returns
+/// * `Result<(), MdownError>` - Returns `Ok(())` if the manga was stored, or an `MdownError` on failure.
+pub(crate) fn update_manga<F>(id: &str, name: &str, update: F) -> Result<(), MdownError>
+    where F: FnOnce(Option<MangaMetadata>) -> Result<MangaMetadata, MdownError>
+{
+    let mut conn = match open() {
+        Ok(conn) => conn,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    match backup(&conn) {
+        Ok(()) => (),
+        Err(err) => {
+            return Err(err);
+        }
+    }
+    let tx = match conn.transaction_with_behavior(TransactionBehavior::Immediate) {
+        Ok(tx) => tx,
+        Err(err) => {
+            return Err(MdownError::DatabaseError(err));
+        }
+    };
+    let stored_id = match
+        tx
+            .query_row(
+                "SELECT id FROM manga WHERE id = ?1 OR name = ?2 ORDER BY id = ?1 DESC, rowid LIMIT 1",
+                params![id, name],
+                |row| row.get::<_, String>(0)
+            )
+            .optional()
+    {
+        Ok(stored_id) => stored_id,
+        Err(err) => {
+            return Err(MdownError::DatabaseError(err));
+        }
+    };
+    let stored = match stored_id {
+        Some(stored_id) =>
+            match read_manga(&tx, &stored_id) {
+                Ok(stored) => stored,
+                Err(err) => {
+                    return Err(err);
+                }
+            }
+        None => None,
+    };
+    let item = match update(stored) {
+        Ok(item) => item,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    match write_manga(&tx, &item) {
+        Ok(()) => (),
+        Err(err) => {
+            return Err(err);
+        }
+    }
+    match tx.commit() {
+        Ok(()) => Ok(()),
+        Err(err) => Err(MdownError::DatabaseError(err)),
+    }
+}
+
+/// Stores checked manga and removes manga from the library in one transaction.
+///
+/// Manga added or changed by other processes in the meantime are kept.
+///
+/// # Arguments
+/// * `items` - The manga to store.
+/// * `removed` - The IDs of the manga to remove.
+///
+/// # Returns
+/// * `Result<(), MdownError>` - Returns `Ok(())` if the library was changed, or an `MdownError` on failure.
+pub(crate) fn save(items: &[MangaMetadata], removed: &[String]) -> Result<(), MdownError> {
+    let mut conn = match open() {
+        Ok(conn) => conn,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    match backup(&conn) {
+        Ok(()) => (),
+        Err(err) => {
+            return Err(err);
+        }
+    }
+    let tx = match conn.transaction_with_behavior(TransactionBehavior::Immediate) {
+        Ok(tx) => tx,
+        Err(err) => {
+            return Err(MdownError::DatabaseError(err));
+        }
+    };
+    for item in items.iter() {
+        match write_manga(&tx, item) {
+            Ok(()) => (),
+            Err(err) => {
+                return Err(err);
+            }
+        }
+    }
+    for id in removed.iter() {
+        match execute(&tx, "DELETE FROM manga WHERE id = ?1", params![id]) {
+            Ok(_) => (),
+            Err(err) => {
+                return Err(err);
+            }
+        }
+    }
+    match tx.commit() {
+        Ok(()) => Ok(()),
+        Err(err) => Err(MdownError::DatabaseError(err)),
+    }
+}
+
+/// Reads when manga were last found up to date by `database --check`.
+///
+/// # Arguments
+/// * `conn` - The connection to the library.
+///
+/// # Returns
+/// * `Result<HashMap<String, String>, MdownError>` - Returns the times by the IDs of the manga, or an `MdownError` on failure.
+fn read_checked(conn: &Connection) -> Result<HashMap<String, String>, MdownError> {
+    let mut stmt = match conn.prepare("SELECT id, checked_at FROM manga WHERE checked_at IS NOT NULL") {
+        Ok(stmt) => stmt,
+        Err(err) => {
+            return Err(MdownError::DatabaseError(err));
+        }
+    };
+    let rows = match stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?))) {
+        Ok(rows) => rows,
+        Err(err) => {
+            return Err(MdownError::DatabaseError(err));
+        }
+    };
+    match rows.collect::<rusqlite::Result<HashMap<String, String>>>() {
+        Ok(checked) => Ok(checked),
+        Err(err) => Err(MdownError::DatabaseError(err)),
+    }
+}
+
+/// Reads when manga were last found up to date by `database --check`.
+///
+/// The time is forgotten when the folder, language or preferences of a manga change.
+///
+/// # Returns
+/// * `Result<HashMap<String, String>, MdownError>` - Returns the times by the IDs of the manga, or an `MdownError` on failure.
+pub(crate) fn load_checked() -> Result<HashMap<String, String>, MdownError> {
+    match open() {
+        Ok(conn) => read_checked(&conn),
+        Err(err) => Err(err),
+    }
+}
+
+/// Remembers when manga were found up to date, so that the next check only asks for chapters changed since then.
+///
+/// # Arguments
+/// * `ids` - The IDs of the manga.
+/// * `checked_at` - The time the check started, in UTC as used by `updatedAtSince` of MangaDex.
+///
+/// # Returns
+/// * `Result<(), MdownError>` - Returns `Ok(())` if the time was stored, or an `MdownError` on failure.
+pub(crate) fn save_checked(ids: &[String], checked_at: &str) -> Result<(), MdownError> {
+    let mut conn = match open() {
+        Ok(conn) => conn,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    let tx = match conn.transaction_with_behavior(TransactionBehavior::Immediate) {
+        Ok(tx) => tx,
+        Err(err) => {
+            return Err(MdownError::DatabaseError(err));
+        }
+    };
+    for id in ids.iter() {
+        match execute(&tx, "UPDATE manga SET checked_at = ?2 WHERE id = ?1", params![id, checked_at]) {
+            Ok(_) => (),
+            Err(err) => {
+                return Err(err);
+            }
+        }
+    }
+    match tx.commit() {
+        Ok(()) => Ok(()),
+        Err(err) => Err(MdownError::DatabaseError(err)),
+    }
+}
+
+/// Finds a manga of the library by its ID or, if no manga has the ID, by its name.
+///
+/// # Arguments
+/// * `manga` - The ID or name of the manga.
+///
+/// # Returns
+/// * `Result<MangaMetadata, MdownError>` - Returns the manga, or an `MdownError` if it isn't in the library.
+fn find_manga(manga: &str) -> Result<MangaMetadata, MdownError> {
+    let data = match load() {
+        Ok(dat) => dat.data,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    let position = data
+        .iter()
+        .position(|item| item.id == manga)
+        .or_else(|| data.iter().position(|item| item.name == manga));
+    match position {
+        Some(position) => Ok(data[position].clone()),
+        None => Err(MdownError::NotFoundError(format!("{} not found in library", manga))),
+    }
+}
+
+/// Sets or removes a preference of a manga.
+///
+/// # Arguments
+/// * `item` - The manga.
+/// * `name` - The name of the preference.
+/// * `value` - The value of the preference, or `None` to remove the preference.
+///
+/// # Returns
+/// * `Result<(), MdownError>` - Returns `Ok(())` if the preference was changed, or an `MdownError` if the preference or value is invalid.
+fn set_preference(item: &mut MangaMetadata, name: &str, value: Option<&str>) -> Result<(), MdownError> {
+    let invalid = |message: String| Err(MdownError::CustomError(message, String::from("PreferenceError")));
+    match (name, value) {
+        ("language", Some(value)) => {
+            item.current_language = value.to_string();
+        }
+        ("saver", Some("true")) => {
+            item.preferences.saver = Some(true);
+        }
+        ("saver", Some("false")) => {
+            item.preferences.saver = Some(false);
+        }
+        ("saver", Some(value)) => {
+            return invalid(format!("Invalid saver {}; use true or false", value));
+        }
+        ("saver", None) => {
+            item.preferences.saver = None;
+        }
+        ("group", value) => {
+            item.preferences.group = value.map(str::to_string);
+        }
+        ("bundle", Some(value)) if value == "chapter" || value == "volume" => {
+            item.preferences.bundle = Some(value.to_string());
+        }
+        ("bundle", Some(value)) => {
+            return invalid(format!("Invalid bundle {}; use chapter or volume", value));
+        }
+        ("bundle", None) => {
+            item.preferences.bundle = None;
+        }
+        ("format", Some(value)) if ["keep", "webp", "jpeg", "avif"].contains(&value) => {
+            item.preferences.format = Some(value.to_string());
+        }
+        ("format", Some(value)) => {
+            return invalid(format!("Invalid format {}; use keep, webp, jpeg or avif", value));
+        }
+        ("format", None) => {
+            item.preferences.format = None;
+        }
+        ("folder", Some(value)) =>
+            match std::path::absolute(value) {
+                Ok(folder) => {
+                    item.mwd = folder.to_string_lossy().to_string();
+                }
+                Err(err) => {
+                    return Err(MdownError::IoError(err, value.to_string()));
+                }
+            }
+        ("language" | "folder", None) => {
+            return invalid(format!("{} can't be removed; set it instead", name));
+        }
+        _ => {
+            return invalid(
+                format!("Unknown preference {}; use language, saver, group, bundle, format or folder", name)
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Handles the `database --remove` argument by removing a manga from the library; its files are kept.
+///
+/// # Returns
+/// * `Result<(), MdownError>` - Returns `Ok(())` if the manga was removed, or an `MdownError` on failure.
+pub(crate) fn resolve_remove() -> Result<(), MdownError> {
+    let manga = match args::ARGS_REMOVE.clone() {
+        Some(manga) => manga,
+        None => {
+            return Ok(());
+        }
+    };
+    let item = match find_manga(&manga) {
+        Ok(item) => item,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    match save(&[], std::slice::from_ref(&item.id)) {
+        Ok(()) => {
+            println!("Removed {} ({}) from library; files in {} were kept", item.name, item.id, item.mwd);
+            Ok(())
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Handles the `database --pause`, `--resume`, `--set` and `--unset` arguments by changing a manga.
+///
+/// # Returns
+/// * `Result<(), MdownError>` - Returns `Ok(())` if the manga was changed, or an `MdownError` on failure.
+pub(crate) fn resolve_preferences() -> Result<(), MdownError> {
+    let (manga, paused, preference) = if let Some(manga) = args::ARGS_PAUSE.clone() {
+        (manga, Some(true), None)
+    } else if let Some(manga) = args::ARGS_RESUME.clone() {
+        (manga, Some(false), None)
+    } else if let Some([manga, name, value]) = args::ARGS_SET.as_deref() {
+        (manga.clone(), None, Some((name.clone(), Some(value.clone()))))
+    } else if let Some([manga, name]) = args::ARGS_UNSET.as_deref() {
+        (manga.clone(), None, Some((name.clone(), None)))
+    } else {
+        return Ok(());
+    };
+    let found = match find_manga(&manga) {
+        Ok(found) => found,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    let mut mwd = found.mwd.clone();
+    match
+        update_manga(&found.id, &found.name, |stored| {
+            let mut item = match stored {
+                Some(item) => item,
+                None => {
+                    return Err(MdownError::NotFoundError(format!("{} not found in library", manga)));
+                }
+            };
+            if let Some(paused) = paused {
+                item.paused = paused;
+            }
+            if let Some((name, value)) = &preference {
+                match set_preference(&mut item, name, value.as_deref()) {
+                    Ok(()) => (),
+                    Err(err) => {
+                        return Err(err);
+                    }
+                }
+            }
+            mwd = item.mwd.clone();
+            Ok(item)
+        })
+    {
+        Ok(()) => (),
+        Err(err) => {
+            return Err(err);
+        }
+    }
+    match (paused, preference) {
+        (Some(true), _) => println!("Paused {}", found.name),
+        (Some(false), _) => println!("Resumed {}", found.name),
+        (None, Some((name, Some(value)))) => {
+            if name == "folder" && !Path::new(&mwd).exists() {
+                println!("Warning: {} not found", mwd);
+            }
+            println!("Set {} of {} to {}", name, found.name, value);
+        }
+        (None, Some((name, None))) => println!("Removed {} of {}", name, found.name),
+        (None, None) => (),
+    }
+    Ok(())
+}
+
+/// Handles the `database --export` argument by writing the library in the format of `dat.json`.
+///
+/// The library is printed when no file is given.
+///
+/// # Returns
+/// * `Result<(), MdownError>` - Returns `Ok(())` if the library was exported, or an `MdownError` on failure.
+pub(crate) fn resolve_export() -> Result<(), MdownError> {
+    let dat = match load() {
+        Ok(dat) => dat,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    let json = match serde_json::to_string_pretty(&dat) {
+        Ok(json) => json,
+        Err(err) => {
+            return Err(MdownError::JsonError(err.to_string()));
+        }
+    };
+    match args::ARGS_EXPORT.clone().flatten() {
+        Some(path) =>
+            match utils::write_file_atomic(&path, format!("{}\n", json).as_bytes()) {
+                Ok(()) => {
+                    println!("Exported {} manga to {}", dat.data.len(), path);
+                    Ok(())
+                }
+                Err(err) => Err(err),
+            }
+        None => {
+            println!("{}", json);
+            Ok(())
+        }
+    }
+}
+
+/// Reads the manga downloaded in a folder from the metadata stored in their archives.
+///
+/// The folder of a manga is the nearest folder containing its cover above the archives, or else the deepest
+/// folder containing all of its archives. Archives without readable metadata are skipped.
+///
+/// # Arguments
+/// * `folder` - The folder to search, including its subfolders.
+/// * `language` - The language stored for manga that are not in the library yet.
+///
+/// # Returns
+/// * `(Vec<MangaMetadata>, usize)` - The manga in the order they were found and the number of archives that were read.
+fn scan(folder: &Path, language: &str) -> (Vec<MangaMetadata>, usize) {
+    let mut items: Vec<(MangaMetadata, Vec<PathBuf>)> = Vec::new();
+    let mut archives = 0;
+    for archive in resolute::find_archives(&folder.to_string_lossy()) {
+        let path = archive.to_string_lossy().to_string();
+        let chapters = match resolute::check_for_volume_metadata(&path) {
+            Ok(volume) if !volume.is_empty() => volume,
+            Ok(_) =>
+                match resolute::check_for_metadata(&path) {
+                    Ok(chapter) => vec![chapter],
+                    Err(err) => {
+                        eprintln!("Skipping {}: {}", path, err);
+                        continue;
+                    }
+                }
+            Err(err) => {
+                eprintln!("Skipping {}: {}", path, err);
+                continue;
+            }
+        };
+        archives += 1;
+        let parent = match archive.parent() {
+            Some(parent) => parent.to_path_buf(),
+            None => folder.to_path_buf(),
+        };
+        for chapter in chapters {
+            let index = match items.iter().position(|(item, _)| item.id == chapter.manga_id) {
+                Some(index) => index,
+                None => {
+                    items.push((
+                        MangaMetadata {
+                            name: chapter.name.clone(),
+                            id: chapter.manga_id.clone(),
+                            chapters: Vec::new(),
+                            mwd: String::new(),
+                            cover: false,
+                            date: Vec::new(),
+                            available_languages: Vec::new(),
+                            current_language: language.to_string(),
+                            theme: Vec::new(),
+                            genre: Vec::new(),
+                            layout: None,
+                            paused: false,
+                            preferences: PreferencesMetadata::default(),
+                        },
+                        Vec::new(),
+                    ));
+                    items.len() - 1
+                }
+            };
+            let (item, folders) = &mut items[index];
+            // Dates are not stored in archives; the next check replaces the empty date
+            let rebuilt = ChapterMetadata::new(&chapter.chapter, "", &chapter.id);
+            if !item.chapters.contains(&rebuilt) {
+                item.chapters.push(rebuilt);
+            }
+            if !folders.contains(&parent) {
+                folders.push(parent.clone());
+            }
+        }
+    }
+
+    let mut found = Vec::new();
+    for (mut item, folders) in items {
+        let mut common = folders[0].clone();
+        for folder in folders.iter().skip(1) {
+            while !folder.starts_with(&common) && common.pop() {}
+        }
+        let mwd = common
+            .ancestors()
+            .take_while(|ancestor| ancestor.starts_with(folder))
+            .find(|ancestor| ancestor.join("_cover.png").exists())
+            .map(Path::to_path_buf);
+        item.cover = mwd.is_some();
+        item.mwd = mwd.unwrap_or(common).to_string_lossy().to_string();
+        found.push(item);
+    }
+    (found, archives)
+}
+
+/// Merges a manga read from downloaded archives into the manga stored in the library.
+///
+/// Stored chapters are kept; the folder and cover of the manga are taken from the archives.
+///
+/// # Arguments
+/// * `stored` - The manga stored in the library, if any.
+/// * `found` - The manga read from the archives.
+///
+/// # Returns
+/// * `(MangaMetadata, usize)` - The manga to store and the number of chapters that were not in the library.
+fn merge_rebuilt(stored: Option<MangaMetadata>, found: MangaMetadata) -> (MangaMetadata, usize) {
+    let mut item = match stored {
+        // A different manga with the same name is left as it is
+        Some(stored) if stored.id == found.id => stored,
+        _ => {
+            let added = found.chapters.len();
+            return (found, added);
+        }
+    };
+    let mut added = 0;
+    for chapter in found.chapters {
+        if
+            !item.chapters
+                .iter()
+                .any(|existing| existing.number == chapter.number && existing.id == chapter.id)
+        {
+            item.chapters.push(chapter);
+            added += 1;
+        }
+    }
+    item.mwd = found.mwd;
+    item.cover = found.cover;
+    (item, added)
+}
+
+/// Handles the `database --rebuild` argument by adding the manga downloaded in a folder to the library.
+///
+/// # Returns
+/// * `Result<(), MdownError>` - Returns `Ok(())` if the library was rebuilt, or an `MdownError` on failure.
+pub(crate) fn resolve_rebuild() -> Result<(), MdownError> {
+    let folder = match args::ARGS_REBUILD.clone() {
+        Some(folder) => folder,
+        None => {
+            return Ok(());
+        }
+    };
+    let folder = match fs::canonicalize(&folder) {
+        Ok(folder) => folder,
+        Err(err) => {
+            return Err(MdownError::IoError(err, folder));
+        }
+    };
+    let language = args::ARGS.lock().lang.clone();
+    let (found, archives) = scan(&folder, &language);
+    let manga = found.len();
+    for item in found {
+        let id = item.id.clone();
+        let name = item.name.clone();
+        let mwd = item.mwd.clone();
+        let mut added = 0;
+        match
+            update_manga(&id, &name, |stored| {
+                let (item, count) = merge_rebuilt(stored, item);
+                added = count;
+                Ok(item)
+            })
+        {
+            Ok(()) => println!("Rebuilt {} ({}) in {}: {} new chapters", name, id, mwd, added),
+            Err(err) => {
+                return Err(err);
+            }
+        }
+    }
+    println!("Rebuilt {} manga from {} archives in {}", manga, archives, folder.display());
+    Ok(())
+}
+
+/// Opens the library for a change of the folders of manga; the library is backed up first.
+///
+/// # Returns
+/// * `Result<Connection, MdownError>` - Returns the connection, or an `MdownError` on failure.
+fn open_for_folders() -> Result<Connection, MdownError> {
+    let conn = match open() {
+        Ok(conn) => conn,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    match backup(&conn) {
+        Ok(()) => Ok(conn),
+        Err(err) => Err(err),
+    }
+}
+
+/// Handles the `database --relocate` argument by changing the folders of manga that were moved.
+///
+/// # Returns
+/// * `Result<(), MdownError>` - Returns `Ok(())` if the folders were changed, or an `MdownError` on failure.
+pub(crate) fn resolve_relocate() -> Result<(), MdownError> {
+    let (old, new) = match args::ARGS_RELOCATE.as_deref() {
+        Some([old, new]) => (old.clone(), new.clone()),
+        _ => {
+            return Ok(());
+        }
+    };
+    // The old folder usually doesn't exist anymore, so it is only made absolute
+    let old = match std::path::absolute(&old) {
+        Ok(old) => old,
+        Err(err) => {
+            return Err(MdownError::IoError(err, old));
+        }
+    };
+    let new = match std::path::absolute(&new) {
+        Ok(new) => new,
+        Err(err) => {
+            return Err(MdownError::IoError(err, new));
+        }
+    };
+    let mut conn = match open_for_folders() {
+        Ok(conn) => conn,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    let tx = match conn.transaction_with_behavior(TransactionBehavior::Immediate) {
+        Ok(tx) => tx,
+        Err(err) => {
+            return Err(MdownError::DatabaseError(err));
+        }
+    };
+    let dat = match read_all(&tx) {
+        Ok(dat) => dat,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    let mut relocated = 0;
+    for item in dat.data.iter() {
+        let mwd = match replace_prefix(&item.mwd, &old, &new) {
+            Some(mwd) => mwd,
+            None => {
+                continue;
+            }
+        };
+        match write_folder(&tx, &item.id, &mwd) {
+            Ok(()) => (),
+            Err(err) => {
+                return Err(err);
+            }
+        }
+        if !Path::new(&mwd).exists() {
+            println!("Warning: {} not found", mwd);
+        }
+        println!("Relocated {}: {} -> {}", item.name, item.mwd, mwd);
+        relocated += 1;
+    }
+    match tx.commit() {
+        Ok(()) => (),
+        Err(err) => {
+            return Err(MdownError::DatabaseError(err));
+        }
+    }
+    println!("Relocated {} manga from {} to {}", relocated, old.display(), new.display());
+    Ok(())
+}
+
+/// Handles the `database --root` argument by setting or removing the root of the library.
+///
+/// Folders of manga inside the root are stored relative to it, so that the library keeps working after it is
+/// moved with its root; only the root has to be set again. Removing the root stores all folders as absolute
+/// folders again.
+///
+/// # Returns
+/// * `Result<(), MdownError>` - Returns `Ok(())` if the root was changed, or an `MdownError` on failure.
+pub(crate) fn resolve_root() -> Result<(), MdownError> {
+    let root = match args::ARGS_ROOT.clone().flatten() {
+        Some(root) =>
+            match fs::canonicalize(&root) {
+                Ok(root) => Some(root),
+                Err(err) => {
+                    return Err(MdownError::IoError(err, root));
+                }
+            }
+        None => None,
+    };
+    let mut conn = match open_for_folders() {
+        Ok(conn) => conn,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    let tx = match conn.transaction_with_behavior(TransactionBehavior::Immediate) {
+        Ok(tx) => tx,
+        Err(err) => {
+            return Err(MdownError::DatabaseError(err));
+        }
+    };
+    let old_root = match read_root(&tx) {
+        Ok(old_root) => old_root,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    let folders = match read_folders(&tx) {
+        Ok(folders) => folders,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    let changed = match &root {
+        Some(root) => write_info(&tx, "root", &root.to_string_lossy()),
+        None =>
+            match execute(&tx, "DELETE FROM library_info WHERE name = 'root'", []) {
+                Ok(_) => Ok(()),
+                Err(err) => Err(err),
+            }
+    };
+    match changed {
+        Ok(()) => (),
+        Err(err) => {
+            return Err(err);
+        }
+    }
+    let mut relative = 0;
+    for (id, stored) in folders.iter() {
+        // Relative folders move with the root
+        if root.is_some() && Path::new(stored).is_relative() {
+            relative += 1;
+            continue;
+        }
+        let mwd = to_absolute(&old_root, stored);
+        match write_folder(&tx, id, &mwd) {
+            Ok(()) => (),
+            Err(err) => {
+                return Err(err);
+            }
+        }
+        if to_stored(&root, &mwd) != mwd {
+            relative += 1;
+        }
+    }
+    match tx.commit() {
+        Ok(()) => (),
+        Err(err) => {
+            return Err(MdownError::DatabaseError(err));
+        }
+    }
+    match root {
+        Some(root) =>
+            println!(
+                "Set root of library to {}; {} of {} manga are stored relative to it",
+                root.display(),
+                relative,
+                folders.len()
+            ),
+        None => println!("Removed root of library"),
+    }
+    Ok(())
+}
+
+/// Handles the `app --restore-backup` argument by replacing the library with one of its backups.
+///
+/// The current library is backed up first, so restoring can be undone.
+///
+/// # Returns
+/// * `Result<(), MdownError>` - Returns `Ok(())` if the library was restored or no backup was found, or an `MdownError` on failure.
+pub(crate) fn resolve_restore() -> Result<(), MdownError> {
+    let folder = match getter::get_backup_folder() {
+        Ok(folder) => folder,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    let backups = match list_backups(&folder) {
+        Ok(backups) => backups,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    let backup = match args::ARGS_RESTORE_BACKUP.clone().flatten() {
+        Some(name) =>
+            backups
+                .iter()
+                .find(|path| {
+                    path.file_name().map(|file_name| file_name.to_string_lossy() == name) ==
+                        Some(true) || path.as_path() == Path::new(&name)
+                })
+                .cloned(),
+        None => backups.last().cloned(),
+    };
+    let backup = match backup {
+        Some(backup) => backup,
+        None => {
+            if backups.is_empty() {
+                println!("No backups found in {}", folder);
+            } else {
+                println!("Backup not found; available backups:");
+                for backup in backups.iter() {
+                    println!(" {}", backup.file_name().unwrap_or_default().to_string_lossy());
+                }
+            }
+            return Ok(());
+        }
+    };
+    let library_path = match getter::get_library_path() {
+        Ok(path) => path,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+
+    // Copied before the current library is backed up, which may remove the oldest backups
+    let content = match fs::read(&backup) {
+        Ok(content) => content,
+        Err(err) => {
+            return Err(MdownError::IoError(err, backup.to_string_lossy().to_string()));
+        }
+    };
+    if Path::new(&library_path).exists() {
+        // Copied as it is, so that even a damaged library can be recovered
+        let current_backup = get_new_backup_path(&folder);
+        if let Err(err) = fs::copy(&library_path, &current_backup) {
+            return Err(MdownError::IoError(err, current_backup.to_string_lossy().to_string()));
+        }
+        println!("Backed up current library to {}", current_backup.display());
+        match prune_backups(&folder) {
+            Ok(()) => (),
+            Err(err) => {
+                return Err(err);
+            }
+        }
+    }
+    // A journal left behind by a crash belongs to the replaced library
+    let _ = fs::remove_file(format!("{}-journal", library_path));
+    match utils::write_file_atomic(&library_path, &content) {
+        Ok(()) => {
+            println!("Restored library from {}", backup.display());
+            Ok(())
+        }
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(test)]
+fn test_manga(id: &str, chapters: &[&str]) -> MangaMetadata {
+    MangaMetadata {
+        name: format!("Manga {}", id),
+        id: id.to_string(),
+        chapters: chapters
+            .iter()
+            .map(|number| ChapterMetadata {
+                updated_at: String::from("2024-01-01T00:00:00+00:00"),
+                number: number.to_string(),
+                id: format!("{}-{}", id, number),
+            })
+            .collect(),
+        mwd: format!("/manga/{}", id),
+        cover: true,
+        date: vec![String::from("2024-01-01 00:00:00")],
+        available_languages: vec![String::from("en"), String::from("ja")],
+        current_language: String::from("en"),
+        theme: vec![TagMetadata { name: String::from("Isekai"), id: String::from("t1") }],
+        genre: vec![TagMetadata { name: String::from("Comedy"), id: String::from("g1") }],
+        layout: Some(LayoutMetadata { crop: true, ..LayoutMetadata::default() }),
+        paused: false,
+        preferences: PreferencesMetadata::default(),
+    }
+}
+
+// Reads back what was stored, keeping manga in the order they were added
+#[test]
+fn test_write_manga_round_trip() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    initialize(&mut conn, |_migration| true).unwrap();
+    let first = test_manga("a", &["1", "2"]);
+    let mut second = test_manga("b", &["1"]);
+    second.layout = None;
+    write_manga(&conn, &first).unwrap();
+    write_manga(&conn, &second).unwrap();
+
+    let mut changed = first.clone();
+    changed.chapters.push(test_manga("a", &["3"]).chapters.remove(0));
+    changed.genre.clear();
+    write_manga(&conn, &changed).unwrap();
+
+    let dat = read_all(&conn).unwrap();
+    assert_eq!(dat.data, vec![changed, second]);
+    assert_eq!(dat.version, get_current_version());
+}
+
+// Merges chapters of manga listed more than once in dat.json
+#[test]
+fn test_import_merges_duplicates() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    initialize(&mut conn, |_migration| true).unwrap();
+    let dat = Dat {
+        data: vec![test_manga("a", &["1", "2"]), test_manga("b", &["1"]), test_manga("a", &["2", "3"])],
+        version: String::from("0.1.0"),
+    };
+    assert_eq!(import(&conn, dat).unwrap(), 2);
+
+    let dat = read_all(&conn).unwrap();
+    assert_eq!(dat.version, "0.1.0");
+    assert_eq!(dat.data[0], test_manga("a", &["1", "2", "3"]));
+    assert_eq!(dat.data[1], test_manga("b", &["1"]));
+}
+
+// Keeps only the newest backups
+#[test]
+fn test_write_backup_rotation() {
+    let folder = std::env::temp_dir().join(format!("mdown_test_backups_{}", std::process::id()));
+    fs::create_dir_all(&folder).unwrap();
+    let folder = folder.to_string_lossy().to_string();
+    for day in 1..=BACKUP_COUNT {
+        fs::write(Path::new(&folder).join(format!("library-2000010{}-000000000000.db", day)), "").unwrap();
+    }
+    let mut conn = Connection::open_in_memory().unwrap();
+    initialize(&mut conn, |_migration| true).unwrap();
+    write_manga(&conn, &test_manga("a", &["1"])).unwrap();
+
+    let path = write_backup(&conn, &folder).unwrap();
+    let backups = list_backups(&folder).unwrap();
+    assert_eq!(backups.len(), BACKUP_COUNT);
+    assert!(!backups.contains(&Path::new(&folder).join("library-20000101-000000000000.db")));
+    assert_eq!(backups.last(), Some(&path));
+    let restored = Connection::open(&path).unwrap();
+    assert_eq!(read_all(&restored).unwrap().data, vec![test_manga("a", &["1"])]);
+    fs::remove_dir_all(&folder).unwrap();
+}
+
+// Creates a new library without asking about migrations that remove data
+#[test]
+fn test_initialize_new_library() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    initialize(&mut conn, |_migration| false).unwrap();
+    assert_eq!(
+        migration::get_schema_version(&conn).unwrap(),
+        migration::MIGRATIONS[migration::MIGRATIONS.len() - 1].version
+    );
+}
+
+// Groups chapters by manga and finds the folder of the manga above subfolders of the name template
+#[test]
+fn test_scan() {
+    let folder = std::env::temp_dir().join(format!("mdown_test_rebuild_{}", std::process::id()));
+    let mwd = folder.join("Manga");
+    fs::create_dir_all(mwd.join("Vol.1")).unwrap();
+    fs::create_dir_all(mwd.join("Vol.2")).unwrap();
+    fs::write(mwd.join("_cover.png"), "").unwrap();
+    crate::zip_func::write_test_chapter(&mwd.join("Vol.1").join("Ch.1.cbz"), "c1", "1", &["1.jpg"]);
+    crate::zip_func::write_test_chapter(&mwd.join("Vol.2").join("Ch.2.cbz"), "c2", "2", &["1.jpg"]);
+    fs::write(mwd.join("Vol.2").join("broken.cbz"), "").unwrap();
+
+    let (found, archives) = scan(&folder, "en");
+    assert_eq!(archives, 2);
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].id, "manga");
+    assert_eq!(found[0].name, "Manga");
+    assert_eq!(found[0].mwd, mwd.to_string_lossy());
+    assert!(found[0].cover);
+    assert_eq!(found[0].current_language, "en");
+    assert_eq!(found[0].chapters, vec![
+        ChapterMetadata::new("1", "", "c1"),
+        ChapterMetadata::new("2", "", "c2")
+    ]);
+    fs::remove_dir_all(&folder).unwrap();
+}
+
+// Keeps stored chapters and adds only the chapters missing from the library
+#[test]
+fn test_merge_rebuilt() {
+    let mut found = test_manga("a", &[]);
+    found.mwd = String::from("/moved/a");
+    found.chapters = vec![ChapterMetadata::new("1", "", "a-1"), ChapterMetadata::new("3", "", "a-3")];
+    let (item, added) = merge_rebuilt(Some(test_manga("a", &["1", "2"])), found.clone());
+    assert_eq!(added, 1);
+    assert_eq!(item.mwd, "/moved/a");
+    assert_eq!(item.chapters.len(), 3);
+    assert_eq!(item.chapters[0], test_manga("a", &["1"]).chapters[0]);
+    assert_eq!(item.chapters[2], ChapterMetadata::new("3", "", "a-3"));
+
+    let (item, added) = merge_rebuilt(Some(test_manga("b", &["1"])), found.clone());
+    assert_eq!((item, added), (found, 2));
+}
+
+// Replaces only whole folders at the start of the folder of a manga
+#[test]
+fn test_replace_prefix() {
+    let (old, new) = (Path::new("/old/manga"), Path::new("/new"));
+    assert_eq!(replace_prefix("/old/manga/a", old, new), Some(String::from("/new/a")));
+    assert_eq!(replace_prefix("/old/manga", old, new), Some(String::from("/new")));
+    assert_eq!(replace_prefix("/old/manga2/a", old, new), None);
+    assert_eq!(replace_prefix("/other/a", old, new), None);
+}
+
+// Stores folders inside the root relative to it and reads them back as absolute folders
+#[test]
+fn test_root_relative_folders() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    initialize(&mut conn, |_migration| true).unwrap();
+    write_info(&conn, "root", "/manga").unwrap();
+    write_manga(&conn, &test_manga("a", &[])).unwrap();
+    let mut outside = test_manga("b", &[]);
+    outside.mwd = String::from("/elsewhere/b");
+    write_manga(&conn, &outside).unwrap();
+    assert_eq!(read_folders(&conn).unwrap(), vec![
+        (String::from("a"), String::from("a")),
+        (String::from("b"), String::from("/elsewhere/b"))
+    ]);
+    assert_eq!(read_manga(&conn, "a").unwrap().unwrap().mwd, "/manga/a");
+
+    write_info(&conn, "root", "/moved").unwrap();
+    assert_eq!(read_manga(&conn, "a").unwrap().unwrap().mwd, "/moved/a");
+    assert_eq!(read_manga(&conn, "b").unwrap().unwrap().mwd, "/elsewhere/b");
+    assert_eq!(to_stored(&Some(PathBuf::from("/moved")), "/moved"), ".");
+    assert_eq!(to_absolute(&Some(PathBuf::from("/moved")), "."), "/moved");
+}
+
+// Validates preferences and keeps the language and folder of a manga set
+#[test]
+fn test_set_preference() {
+    let mut item = test_manga("a", &[]);
+    set_preference(&mut item, "saver", Some("true")).unwrap();
+    set_preference(&mut item, "group", Some("Group")).unwrap();
+    set_preference(&mut item, "bundle", Some("volume")).unwrap();
+    set_preference(&mut item, "format", Some("webp")).unwrap();
+    set_preference(&mut item, "language", Some("ja")).unwrap();
+    assert_eq!(item.preferences, PreferencesMetadata {
+        saver: Some(true),
+        group: Some(String::from("Group")),
+        bundle: Some(String::from("volume")),
+        format: Some(String::from("webp")),
+    });
+    assert_eq!(item.current_language, "ja");
+
+    assert!(set_preference(&mut item, "saver", Some("yes")).is_err());
+    assert!(set_preference(&mut item, "bundle", Some("page")).is_err());
+    assert!(set_preference(&mut item, "language", None).is_err());
+    assert!(set_preference(&mut item, "format", Some("cbz")).is_err());
+    assert!(set_preference(&mut item, "cover", Some("true")).is_err());
+    set_preference(&mut item, "group", None).unwrap();
+    set_preference(&mut item, "saver", None).unwrap();
+    set_preference(&mut item, "bundle", None).unwrap();
+    set_preference(&mut item, "format", None).unwrap();
+    assert!(item.preferences.is_empty());
+}
+
+// Stores whether a manga is paused and its preferences
+#[test]
+fn test_write_manga_preferences() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    initialize(&mut conn, |_migration| true).unwrap();
+    let mut item = test_manga("a", &["1"]);
+    item.paused = true;
+    item.preferences.group = Some(String::from("Group"));
+    write_manga(&conn, &item).unwrap();
+    assert_eq!(read_manga(&conn, "a").unwrap(), Some(item));
+}
+
+// Keeps when a manga was found up to date until its folder, language or preferences change
+#[test]
+fn test_checked_at_forgotten() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    initialize(&mut conn, |_migration| true).unwrap();
+    let mut item = test_manga("a", &["1"]);
+    write_manga(&conn, &item).unwrap();
+    execute(&conn, "UPDATE manga SET checked_at = 'T'", []).unwrap();
+    item.chapters.clear();
+    write_manga(&conn, &item).unwrap();
+    assert_eq!(read_checked(&conn).unwrap().get("a"), Some(&String::from("T")));
+    item.current_language = String::from("ja");
+    write_manga(&conn, &item).unwrap();
+    assert!(read_checked(&conn).unwrap().is_empty());
+}
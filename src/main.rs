@@ -21,24 +21,40 @@
 //!- `--url <URL>`: The URL of the manga to download.
 //!- `--lang <LANG>`: The language of the manga.
 //!- `--title <TITLE>`: The title of the manga.
-//!- `--folder <FOLDER>`: The folder to store downloaded manga.
+//!- `--folder <FOLDER>`: The folder to store downloaded manga; can use `{manga}`, `{lang}` and `{id}`.
+//!- `--name_template <TEMPLATE>`: Template of chapter names, e.g. `Vol.{volume:02}/{manga} - Ch.{chapter:03.1} [{group}]`.
 //!- `--volume <VOLUME>`: The volume number of the manga.
 //!- `--chapter <CHAPTER>`: The chapter number of the manga.
 //!- `--saver`: Enable the saver mode.
 //!- `--stat`: Generate a statistics file.
 //!- `--quiet`: Suppress output.
 //!- `--max_consecutive <NUMBER>`: Maximum number of consecutive downloads of images.
+//!- `--bundle <chapter|volume>`: Archive chapters one by one or bundled per volume.
+//!- `--compression <METHOD>`: Compression method of archives (`stored`, `deflate` or `zstd`).
+//!- `--compression_level <LEVEL>`: Compression level of archives.
+//!- `--image_format <FORMAT>`: Convert pages to `webp`, `jpeg` or `avif` (requires `transcode` feature).
+//!- `--image_quality <QUALITY>`: Quality of converted jpeg and avif pages.
+//!- `--max_width <PIXELS>` / `--max_height <PIXELS>`: Scale down pages larger than the supplied size.
+//!- `--grayscale`: Convert pages to grayscale.
+//!- `--direction <rtl|ltr>`: Reading direction, used when splitting or rotating spreads.
+//!- `--split_tall <RATIO>`: Split long strips into pages of the supplied aspect ratio.
+//!- `--spread <keep|split|rotate>`: Split or rotate double-page spreads.
+//!- `--crop`: Crop uniform borders of pages.
+//!- `--no_split_tall` / `--no_crop`: Stop splitting strips / cropping borders of a manga.
+//!- `--strip_credits`: Drop pages matching credit pages marked for the scanlation group.
 //!- `--force`: Force download even if the file exists.
+//!- `--dry_run`: Report which chapters would be downloaded, downloaded again or skipped without writing anything.
 //!- `--offset <OFFSET>`: The start offset for chapters.
 //!- `--database_offset <OFFSET>`: The start offset for the database.
 //!- `--unsorted`: Do not sort the database.
 //!- `--cwd <DIR>`: Change the current working directory.
+//!- `--portable`: Keep the library, the database and logs next to the executable instead of `MDOWN_HOME` or the XDG directories.
 //!- `--encode <URL>`: Print URL in a program-readable format.
 //!- `--log`: Enable logging and write to `log.json`.
 //!- `--search <TITLE>`: Search for manga by title.
 //!- `--web`: Enter web mode and open a browser on port 8080.
 //!- `--music <OPTION>`: Play music during downloading.
-//!- `--server`: Start in server mode.
+//!- `--server`: Start in server mode; Atom feeds of added chapters are served at `/feed.atom` and `/feed/<ID>.atom`.
 //!- `--gui`: Experimental GUI version.
 //!- `--debug`: Enable debugging.
 //!- `--debug_file`: Debug file-related operations.
@@ -52,15 +68,51 @@
 //!  - `--show [ID]`: Show current manga in the database or a specific manga by ID.
 //!  - `--show_all [ID]`: Show current chapters in the database or a specific chapter by ID.
 //!  - `--show_log`: Show current logs in the database.
+//!  - `--log_id <ID>`, `--log_section <SECTION>`: Show logs without asking for the run and section.
+//!  - `--output <FORMAT>`: Print `--check`, `--update` and the show commands as `table`, `json` or `ndjson`.
+//!  - `--parallel <COUNT>`: Fetch several manga at once in `--check` and `--update`.
+//!  - `--full`: Check every manga, even manga without changed chapters since they were last found up to date.
+//!  - `--recompress`: Recompress downloaded files with the current compression settings.
+//!  - `--export [FILE]`: Export the library in the format of `dat.json` used by older versions.
+//!  - `--remove <MANGA>`: Remove a manga from the library, keeping its files.
+//!  - `--pause <MANGA>`: Skip a manga in `--check` and `--update`.
+//!  - `--resume <MANGA>`: Check and update a paused manga again.
+//!  - `--set <MANGA> <PREFERENCE> <VALUE>`: Set the language, saver, group, bundle, format or folder of a manga.
+//!  - `--unset <MANGA> <PREFERENCE>`: Remove a preference of a manga.
+//!  - `--rebuild <FOLDER>`: Add the chapters downloaded in a folder to the library.
+//!  - `--relocate <OLD> <NEW>`: Change the folders of manga that were moved.
+//!  - `--root [FOLDER]`: Set or remove the root of the library that folders of manga are stored relative to.
+//!  - `--mark_credit <ARCHIVE> <PAGE>`: Mark a page as a credit page of the chapter's scanlation group.
+//!  - `--unmark_credit <ARCHIVE> <PAGE>`: Remove credit pages matching a page.
+//!
+//!- `daemon`: Stay running and update the library on a schedule until SIGTERM or Ctrl+C.
+//!  - `--every <DURATION>`: Time between updates.
+//!  - `--jitter <DURATION>`: Most time randomly added to the time between updates.
+//!  - `--parallel <COUNT>`: Fetch several manga at once.
+//!
+//!- `ctl`: Send a command to a running daemon, web or server instance.
+//!  - `queue add <URL>`: Queue a manga for download.
+//!  - `status`: Show what the instance is doing and the queued manga.
+//!  - `cancel <HANDLE>`: Cancel a queued manga.
+//!  - `check-now`: Make the daemon update the library now.
 //!
 //!- `settings`: Commands related to application settings.
 //!  - `--folder [NAME]`: Set or remove the default folder name.
+//!  - `--name_template [TEMPLATE]`: Set or remove the default template of chapter names.
+//!  - `--compression [METHOD]`: Set or remove the default compression method.
+//!  - `--compression_level [LEVEL]`: Set or remove the default compression level.
+//!  - `--scan-server [KIND URL API_KEY]`: Set or remove the Komga or Kavita server scanning folders after chapters are added.
+//!  - `--hook <EVENT> <COMMAND>`: Run a shell command when an event happens.
+//!  - `--webhook <EVENT> <URL>`: Send the details of an event as JSON to a URL when it happens.
+//!  - `--remove-hook <EVENT> [TARGET]`: Remove the hooks of an event.
+//!  - `--show-hooks`: Show the hooks.
 //!
 //!- `app`: Commands related to application management.
 //!  - `--force_setup`: Force the first-time setup.
-//!  - `--force_delete`: Force delete the `.lock` file.
-//!  - `--delete`: Delete `dat.json`.
+//!  - `--force_delete`: Force delete the lock files of manga.
+//!  - `--delete`: Delete the library of downloaded manga.
 //!  - `--reset`: Delete all files created by the program.
+//!  - `--restore_backup [BACKUP]`: Restore the library from a backup.
 //!
 //!## Example
 //!
@@ -85,6 +137,7 @@
 //!- **resolute**: Handles finalization and resolution of application state.
 //!- **utils**: Provides utility functions for various tasks.
 //!- **zip_func**: Handles zip file operations.
+//!- **image_func**: Handles processing of downloaded pages (enabled with the `transcode` feature).
 //!
 //!### Optional Features
 //!
@@ -92,6 +145,7 @@
 //!- **gui**: Provides a graphical user interface (enabled with the `gui` feature).
 //!- **server**: Enables server mode (enabled with the `server` feature).
 //!- **web**: Provides web-based interaction (enabled with the `web` feature).
+//!- **transcode**: Converts, scales and recolors downloaded pages (enabled with the `transcode` feature).
 
 use chrono::DateTime;
 use crosscurses::stdscr;
@@ -100,16 +154,33 @@ use lazy_static::lazy_static;
 use parking_lot::Mutex;
 use remove_dir_all::remove_dir_all;
 use serde_json::Value;
-use std::{ cmp::Ordering, env, fs::{ self, File }, io::Write, process::exit, sync::Arc };
+use std::{
+    cmp::Ordering,
+    collections::BTreeMap,
+    env,
+    fs::{ self, File },
+    io::Write,
+    path::Path,
+    process::exit,
+    sync::Arc,
+};
 
 mod args;
+mod ctl;
+mod daemon;
 mod db;
 mod download;
 mod error;
 mod getter;
+mod hooks;
+mod library;
 mod macros;
 mod metadata;
+mod migration;
+mod output;
 mod resolute;
+mod scan;
+mod template;
 mod utils;
 mod version_manager;
 mod zip_func;
@@ -120,6 +191,13 @@ mod music;
 #[cfg(feature = "gui")]
 mod gui;
 
+#[cfg(feature = "transcode")]
+mod credits;
+#[cfg(feature = "transcode")]
+mod image_func;
+
+#[cfg(feature = "server")]
+mod feed;
 #[cfg(feature = "server")]
 mod server;
 
@@ -204,9 +282,11 @@ async fn main() {
         Ok(()) => error::handle_suspended(),
         Err(err) => {
             error::handle_final(&err);
+            hooks::wait().await;
             exit(1);
         }
     }
+    hooks::wait().await;
 
     // Attempt to remove any cache files and ignore errors.
     match utils::remove_cache() {
@@ -241,7 +321,7 @@ async fn main() {
 /// Initializes and starts the application based on provided arguments and settings.
 ///
 /// This asynchronous function performs the following tasks in order:
-/// 1. **Setup Settings**: Loads configuration settings from the database.
+/// 1. **Setup Settings**: Moves files of older versions to the folders of the program and loads configuration settings from the database.
 /// 2. **Argument Handling**: Handles special arguments for encoding, resetting, and other operations.
 /// 3. **Database Initialization**: Initializes the database.
 /// 4. **Current Directory Setup**: Sets the working directory for the application.
@@ -278,6 +358,14 @@ async fn main() {
 ///   and GUI based on feature flags.
 /// - Debug messages are used extensively to trace the execution flow and aid in debugging.
 async fn start() -> Result<(), error::MdownError> {
+    // Move files kept next to the executable by older versions to the folders of the program
+    match getter::migrate_files() {
+        Ok(()) => (),
+        Err(err) => {
+            return Err(err);
+        }
+    }
+
     // Setup configuration settings from the database
     let settings = match db::setup_settings() {
         Ok(settings) => settings,
@@ -286,8 +374,12 @@ async fn start() -> Result<(), error::MdownError> {
         }
     };
 
-    // Update arguments with folder settings from the configuration
+    // Update arguments with folder, naming and compression settings from the configuration
     args::ARGS.lock().change("folder", args::Value::Str(settings.folder));
+    args::ARGS.lock().change("name_template", args::Value::Str(settings.name_template));
+    args::ARGS.lock().change("compression", args::Value::Str(settings.compression));
+    args::ARGS.lock().change("compression_level", args::Value::Int(settings.compression_level));
+    *scan::SERVER.lock() = settings.scan_server;
 
     // Handle encoding argument
     if !(*args::ARGS_ENCODE).is_empty() {
@@ -305,6 +397,29 @@ async fn start() -> Result<(), error::MdownError> {
         return utils::reset();
     }
 
+    // Add, remove or show hooks
+    if
+        args::ARGS_HOOK.is_some() ||
+        args::ARGS_WEBHOOK.is_some() ||
+        args::ARGS_REMOVE_HOOK.is_some() ||
+        *args::ARGS_SHOW_HOOKS
+    {
+        debug!("start hooks resolve_settings");
+        return hooks::resolve_settings();
+    }
+
+    // Send a command to a running instance
+    if args::ARGS_CTL.is_some() {
+        debug!("start ctl");
+        return ctl::send().await;
+    }
+
+    // Handle restore backup argument
+    if args::ARGS_RESTORE_BACKUP.is_some() {
+        debug!("start resolve_restore");
+        return library::resolve_restore();
+    }
+
     // Initialize the database
     match db::init().await {
         Ok(()) => (),
@@ -349,6 +464,26 @@ async fn start() -> Result<(), error::MdownError> {
         eprintln!("Music feature is not enabled; You have to enable music feature");
     }
 
+    // Handle image processing feature
+    if
+        args::ARGS.lock().image_processing() ||
+        args::ARGS.lock().layout_supplied() ||
+        args::ARGS.lock().strip_credits
+    {
+        debug!("pages will be processed after download");
+        #[cfg(not(feature = "transcode"))]
+        eprintln!("Image processing is not enabled; You have to enable transcode feature");
+    }
+    // Pages are converted to webp without loss, so a quality would be silently ignored
+    if args::ARGS.lock().image_format == "webp" && args::ARGS.lock().image_quality.is_some() {
+        return Err(
+            error::MdownError::CustomError(
+                String::from("--image-quality can't be used with --image-format webp, which is lossless"),
+                String::from("Image")
+            )
+        );
+    }
+
     // Setup subscriber for web, GUI, update, or server modes
     if *args::ARGS_WEB || *args::ARGS_GUI || *args::ARGS_UPDATE || *args::ARGS_SERVER {
         match utils::setup_subscriber() {
@@ -376,6 +511,71 @@ async fn start() -> Result<(), error::MdownError> {
         return resolute::show().await;
     }
 
+    // Mark or unmark credit pages
+    if args::ARGS_MARK_CREDIT.is_some() || args::ARGS_UNMARK_CREDIT.is_some() {
+        debug!("start resolve_mark");
+        #[cfg(feature = "transcode")]
+        return credits::resolve_mark();
+        #[cfg(not(feature = "transcode"))]
+        {
+            eprintln!("Image processing is not enabled; You have to enable transcode feature");
+            return Ok(());
+        }
+    }
+
+    // Export the library
+    if args::ARGS_EXPORT.is_some() {
+        debug!("start resolve_export");
+        return library::resolve_export();
+    }
+
+    // Remove a manga from the library
+    if args::ARGS_REMOVE.is_some() {
+        debug!("start resolve_remove");
+        return library::resolve_remove();
+    }
+
+    // Change whether a manga is paused or its preferences
+    if
+        args::ARGS_PAUSE.is_some() ||
+        args::ARGS_RESUME.is_some() ||
+        args::ARGS_SET.is_some() ||
+        args::ARGS_UNSET.is_some()
+    {
+        debug!("start resolve_preferences");
+        return library::resolve_preferences();
+    }
+
+    // Rebuild the library from downloaded files
+    if args::ARGS_REBUILD.is_some() {
+        debug!("start resolve_rebuild");
+        return library::resolve_rebuild();
+    }
+
+    // Change the folders of moved manga
+    if args::ARGS_RELOCATE.is_some() {
+        debug!("start resolve_relocate");
+        return library::resolve_relocate();
+    }
+
+    // Set or remove the root of the library
+    if args::ARGS_ROOT.is_some() {
+        debug!("start resolve_root");
+        return library::resolve_root();
+    }
+
+    // Recompress downloaded files
+    if *args::ARGS_RECOMPRESS {
+        debug!("start resolve_recompress");
+        return resolute::resolve_recompress();
+    }
+
+    // Keep updating the library until the daemon is stopped
+    if args::ARGS_DAEMON.is_some() {
+        debug!("start daemon");
+        return daemon::start().await;
+    }
+
     // Perform check or update operations
     if *args::ARGS_CHECK || *args::ARGS_UPDATE {
         debug!("start resolve_check");
@@ -504,6 +704,9 @@ async fn start() -> Result<(), error::MdownError> {
                             String::from("!")
                         }
                     };
+                    if *args::ARGS_DRY_RUN && manga_name != "!" {
+                        resolute::print_plan(&manga_name, &id, &resolute::PLANNED.lock());
+                    }
                 } else {
                     return Err(error::MdownError::JsonError(String::from("Unexpected JSON value")));
                 }
@@ -569,6 +772,8 @@ async fn start() -> Result<(), error::MdownError> {
 ///   The JSON string containing manga data to be processed.
 /// - `arg_force: bool`
 ///   A flag indicating whether to force download even if the chapter is already downloaded.
+/// - `folder: &str`
+///   The folder chapters are saved to; the folder of the manga in the library when it is updated.
 ///
 /// # Returns
 ///
@@ -592,7 +797,8 @@ async fn start() -> Result<(), error::MdownError> {
 ///
 pub(crate) async fn download_manga(
     manga_json: String,
-    arg_force: bool
+    arg_force: bool,
+    folder: &str
 ) -> Result<Vec<String>, error::MdownError> {
     debug!("");
     debug!("download_manga");
@@ -623,11 +829,19 @@ pub(crate) async fn download_manga(
         }
     };
     let mut all_ids = vec![];
+    let bundle_volume = args::ARGS.lock().bundle == "volume";
+    let name_uses_group = template::get_name_template().uses("group");
+    let mut bundles: BTreeMap<String, (String, Vec<String>)> = BTreeMap::new();
 
     debug!("checking for .cbz files");
 
     // Search for existing .cbz files and collect their metadata
-    if let Ok(value) = glob("*.cbz") {
+    let archives = format!("{}/*.cbz", glob::Pattern::escape(folder));
+    let pattern = match *args::ARGS_UPDATE {
+        true => archives.as_str(),
+        false => "*.cbz",
+    };
+    if let Ok(value) = glob(pattern) {
         for entry in value.filter_map(Result::ok) {
             if let Some(entry) = entry.to_str() {
                 debug!("found entry in glob: {}", entry);
@@ -638,6 +852,22 @@ pub(crate) async fn download_manga(
         }
     }
 
+    // Chapters bundled in volume archives count as downloaded too
+    if !arg_force {
+        if let Ok(value) = glob(&archives) {
+            for entry in value.filter_map(Result::ok) {
+                if let Some(entry) = entry.to_str() {
+                    if let Ok(chapters) = resolute::check_for_volume_metadata(entry) {
+                        debug!("found volume archive in glob: {}", entry);
+                        for chapter in chapters.into_iter() {
+                            all_ids.push(chapter.id);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
     // Parse the manga JSON to extract chapter information
     match serde_json::from_value::<metadata::MangaResponse>(json_value) {
         Ok(obj) => {
@@ -646,8 +876,32 @@ pub(crate) async fn download_manga(
             let data_len = data_array.len();
             *resolute::CURRENT_CHAPTER_PARSED_MAX.lock() = data_len as u64;
 
+            // Chapters released by the preferred group are only downloaded from that group
+            let preferred_group = resolute::PREFERRED_GROUP.lock().clone();
+            let mut preferred_ids = vec![];
+            let mut preferred_chapters = vec![];
+            if let Some(group) = preferred_group.as_deref() {
+                for item in 0..data_len {
+                    let array_item = getter::get_attr_as_same_from_vec(&data_array, item);
+                    let (_chapter_attr, lang, _pages, chapter_num, _title) =
+                        getter::get_metadata(array_item);
+                    if
+                        (lang == language || language == "*") &&
+                        resolute::is_from_group(array_item, group).await
+                    {
+                        preferred_ids.push(array_item.id.clone());
+                        preferred_chapters.push(chapter_num);
+                    }
+                }
+            }
+
             // Process each chapter
             for item in 0..data_len {
+                // The chapter being downloaded is finished and archived before stopping
+                if daemon::is_stopping() || ctl::is_cancelled() {
+                    debug!("stopping before chapter entry {}", item);
+                    break;
+                }
                 debug!("parsing chapter entry {}", item);
                 let mut date_change = false;
                 let parsed = format!(
@@ -696,12 +950,21 @@ pub(crate) async fn download_manga(
                 let con_chap = resolute::resolve_skip(arg_chapter, &chapter_num);
                 let con_vol = resolute::resolve_skip(arg_volume, &vol);
 
+                // The group is only looked up when it is part of the name
+                let group = match name_uses_group && lang == language {
+                    true => resolute::resolve_group_name(array_item).await,
+                    false => String::new(),
+                };
+
                 filename = utils::FileName {
                     manga_name: manga_name.to_string(),
                     vol: vol.to_string(),
                     chapter_num: chapter_num.to_string(),
                     title: title.to_string(),
-                    folder: getter::get_folder_name().to_string(),
+                    folder: folder.to_string(),
+                    group,
+                    lang: lang.to_string(),
+                    id: id.to_string(),
                 };
                 let folder_path = filename.get_folder_name();
 
@@ -794,6 +1057,7 @@ pub(crate) async fn download_manga(
                         resolute::CHAPTERS
                             .lock()
                             .push(metadata::ChapterMetadata::new(&chapter_num, &update_date, id));
+                        resolute::plan(&filename, "skip", Some("already downloaded"));
                         moves = utils::skip(folder_path, item, moves, hist);
                         continue;
                     }
@@ -802,18 +1066,27 @@ pub(crate) async fn download_manga(
                 // Skip chapter if conditions are not met
                 if con_vol {
                     debug!("skipping because volume didn't match");
+                    resolute::plan(&filename, "skip", Some("volume didn't match"));
                     moves = utils::skip_didnt_match("volume", item, moves, hist);
                     continue;
                 }
                 if con_chap {
                     debug!("skipping because chapter didn't match");
+                    resolute::plan(&filename, "skip", Some("chapter didn't match"));
                     moves = utils::skip_didnt_match("chapter", item, moves, hist);
                     continue;
                 }
+                if preferred_chapters.contains(&chapter_num) && !preferred_ids.contains(&value) {
+                    debug!("skipping because chapter is released by preferred group");
+                    resolute::plan(&filename, "skip", Some("chapter is released by preferred group"));
+                    moves = utils::skip_custom("chapter is released by preferred group", item, moves, hist);
+                    continue;
+                }
                 if pages == 0 {
                     debug!(
                         "skipping because variable pages is 0; probably because chapter is not supported on mangadex, third party"
                     );
+                    resolute::plan(&filename, "skip", Some("pages is 0"));
                     moves = utils::skip_custom("pages is 0", item, moves, hist);
                     continue;
                 }
@@ -845,6 +1118,7 @@ pub(crate) async fn download_manga(
                             "skipping because offset flag is set, {} times more",
                             arg_offset - times
                         );
+                        resolute::plan(&filename, "skip", Some("offset"));
                         moves = utils::skip_offset(item, moves, hist);
                         times += 1;
                         *resolute::CURRENT_CHAPTER_PARSED.lock() += 1;
@@ -890,6 +1164,22 @@ pub(crate) async fn download_manga(
                             }
                             continue;
                         }
+                        if *args::ARGS_DRY_RUN {
+                            match date_change {
+                                true =>
+                                    resolute::plan(
+                                        &filename,
+                                        "redownload",
+                                        Some("chapter was changed since it was downloaded")
+                                    ),
+                                false => resolute::plan(&filename, "download", None),
+                            }
+                            // Later releases of the same chapter are skipped as if it was downloaded
+                            resolute::CHAPTERS
+                                .lock()
+                                .push(metadata::ChapterMetadata::new(&chapter_num, &update_date, id));
+                            continue;
+                        }
                         let scanlation_group = match resolute::resolve_group(array_item).await {
                             Ok(scanlation_group) => scanlation_group,
                             Err(err) => {
@@ -935,15 +1225,21 @@ pub(crate) async fn download_manga(
                                     ).await
                                 {
                                     Ok(()) => (),
-                                    Err(err) => handle_error!(&err, String::from("chapter")),
+                                    Err(err) => {
+                                        resolute::FAILED.lock().push(chapter_num.to_string());
+                                        handle_error!(&err, String::from("chapter"));
+                                    }
                                 }
                             }
-                            Err(err) => error::suspend_error(err),
+                            Err(err) => {
+                                resolute::FAILED.lock().push(chapter_num.to_string());
+                                error::suspend_error(err);
+                            }
                         }
                         if *IS_END.lock() {
                             return Ok(downloaded);
                         }
-                        match resolute::get_scanlation_group_to_file(&scanlation_group) {
+                        match resolute::get_scanlation_group_to_file(&scanlation_group, folder) {
                             Ok(()) => (),
                             Err(err) => {
                                 return Err(err);
@@ -958,7 +1254,24 @@ pub(crate) async fn download_manga(
                                 filename.get_folder()
                             )
                         );
+                        #[cfg(feature = "transcode")]
+                        match credits::strip_chapter(folder_path) {
+                            Ok(()) => (),
+                            Err(err) => handle_error!(&err, String::from("credits")),
+                        }
+                        #[cfg(feature = "transcode")]
+                        match image_func::process_chapter(folder_path) {
+                            Ok(()) => (),
+                            Err(err) => handle_error!(&err, String::from("image")),
+                        }
                         let file_name = filename.get_file_w_folder();
+                        if let Some(parent) = Path::new(&file_name).parent() {
+                            if let Err(err) = fs::create_dir_all(parent) {
+                                return Err(
+                                    error::MdownError::IoError(err, parent.display().to_string())
+                                );
+                            }
+                        }
                         zip_func::to_zip(folder_path, &file_name);
                         match remove_dir_all(folder_path) {
                             Ok(()) => (),
@@ -969,8 +1282,28 @@ pub(crate) async fn download_manga(
                             }
                         }
 
+                        hooks::fire(hooks::Event {
+                            chapter: filename.chapter_num.clone(),
+                            chapter_id: id.to_string(),
+                            path: match std::path::absolute(&file_name) {
+                                Ok(path) => path.to_string_lossy().to_string(),
+                                Err(_err) => file_name.clone(),
+                            },
+                            ..hooks::Event::new("chapter-downloaded")
+                        });
+
                         utils::clear_screen(2);
+                        let volume_file = filename.get_volume_w_folder();
                         if
+                            !filename.vol.is_empty() &&
+                            (bundle_volume || fs::metadata(&volume_file).is_ok())
+                        {
+                            debug!("chapter will be bundled into {}", volume_file);
+                            bundles
+                                .entry(volume_file)
+                                .or_insert_with(|| (filename.get_volume_w_folder_w_cwd(), vec![]))
+                                .1.push(file_name);
+                        } else if
                             *args::ARGS_WEB ||
                             *args::ARGS_GUI ||
                             *args::ARGS_CHECK ||
@@ -985,6 +1318,12 @@ pub(crate) async fn download_manga(
                     }
                 } else {
                     debug!("skipping because language is wrong");
+                    // Chapters in the right language end up here when they are already downloaded
+                    let reason = match lang == language || language == "*" {
+                        true => "already downloaded",
+                        false => "wrong language",
+                    };
+                    resolute::plan(&filename, "skip", Some(reason));
                     string(2, 0, &" ".repeat(MAXPOINTS.max_x as usize).to_string());
                     let message = format!(
                         "Skipping because of wrong language; found '{}', target '{}' ...",
@@ -1011,6 +1350,56 @@ pub(crate) async fn download_manga(
             return Err(error::MdownError::JsonError(err.to_string()));
         }
     }
+
+    // Bundle downloaded chapters into their volume archives
+    for (volume_file, (volume_file_w_cwd, chapter_files)) in bundles.into_iter() {
+        utils::clear_screen(5);
+        string(6, 0, &format!("  Bundling chapters into volume: {}", volume_file));
+        if
+            *args::ARGS_WEB ||
+            *args::ARGS_GUI ||
+            *args::ARGS_CHECK ||
+            *args::ARGS_UPDATE ||
+            *args::ARGS_LOG
+        {
+            log!(&format!("Bundling {} chapter(s) into {}", chapter_files.len(), volume_file));
+        }
+        match zip_func::to_volume(&chapter_files, &volume_file) {
+            Ok(()) => {
+                for chapter_file in chapter_files.iter() {
+                    match fs::remove_file(chapter_file) {
+                        Ok(()) => (),
+                        Err(err) => {
+                            return Err(error::MdownError::IoError(err, chapter_file.to_string()));
+                        }
+                    }
+                }
+                if
+                    *args::ARGS_WEB ||
+                    *args::ARGS_GUI ||
+                    *args::ARGS_CHECK ||
+                    *args::ARGS_UPDATE
+                {
+                    resolute::WEB_DOWNLOADED.lock().push(volume_file);
+                } else {
+                    downloaded.push(volume_file_w_cwd);
+                }
+            }
+            Err(err) => {
+                // Chapter archives are kept, so nothing that was downloaded is lost
+                handle_error!(&err, String::from("volume"));
+                if
+                    *args::ARGS_WEB ||
+                    *args::ARGS_GUI ||
+                    *args::ARGS_CHECK ||
+                    *args::ARGS_UPDATE
+                {
+                    resolute::WEB_DOWNLOADED.lock().extend(chapter_files);
+                }
+            }
+        }
+    }
+
     Ok(downloaded)
 }
 
@@ -1155,7 +1544,7 @@ pub(crate) async fn download_chapter(
     }
     debug!("folder in cache created successfully");
 
-    let mut metadata_file = match File::create(format!("{}_metadata", filename.get_folder_w_end())) {
+    let mut metadata_file = match File::create(Path::new(&filename.get_folder()).join("_metadata")) {
         Ok(file) => file,
         Err(err) => {
             return Err(error::MdownError::IoError(err, lock_file.clone()));
@@ -1175,6 +1564,9 @@ pub(crate) async fn download_chapter(
         chapter: chapter.to_string(),
         volume: vol.to_string(),
         scanlation: scanlation.clone(),
+        transform: None,
+        layout: None,
+        removed_pages: vec![],
     };
 
     let json = match serde_json::to_string_pretty(&response_map) {
@@ -1192,7 +1584,8 @@ pub(crate) async fn download_chapter(
 
     debug!("metadata file created successfully");
 
-    let lock_file_wait = filename.get_folder_name();
+    let folder_name = filename.get_folder_name();
+    let lock_file_wait = folder_name.clone();
 
     tokio::spawn(async move { utils::wait_for_end(&lock_file_wait, images_length).await });
     let start = if MAXPOINTS.max_x / 3 < (images_length as u32) / 2 {
@@ -1246,9 +1639,7 @@ pub(crate) async fn download_chapter(
             let image_base_url = Arc::from(image_base_url.clone());
             let page = item + 1;
 
-            let folder_name = utils::process_filename(
-                &format!("{} - {}Ch.{}{}", manga_name, vol, chapter, pr_title)
-            );
+            let folder_name = folder_name.clone();
             let file_name = utils::process_filename(
                 &format!("{} - {}Ch.{}{} - {}.jpg", manga_name, vol, chapter, pr_title, page)
             );
@@ -1256,7 +1647,10 @@ pub(crate) async fn download_chapter(
                 &format!("{}Ch.{} - {}.jpg", vol, chapter, page)
             );
 
-            let full_path = format!(".cache/{}/{}", folder_name, file_name);
+            let full_path = Path::new(&getter::get_cache_path(&folder_name))
+                .join(file_name)
+                .to_string_lossy()
+                .to_string();
 
             tokio::spawn(async move {
                 match
@@ -1293,6 +1687,7 @@ pub(crate) async fn download_chapter(
 
     let chapter_met = metadata::ChapterMetadata::new(chapter, update_date, id);
     resolute::CHAPTERS.lock().push(chapter_met);
+    resolute::DOWNLOADED.lock().push(chapter.to_string());
 
     match resolute::resolve_dat() {
         Ok(()) => (),
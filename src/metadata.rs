@@ -1,13 +1,45 @@
 use chrono::Utc;
+use lazy_static::lazy_static;
 use serde::{ Deserialize, Serialize };
 use std::collections::HashMap;
 
-use crate::resolute;
+use crate::{ resolute, utils };
 
 /// Represents settings for the application, such as folder paths.
 #[derive(Debug, Clone, PartialEq)]
 pub(crate) struct Settings {
     pub(crate) folder: String,
+    pub(crate) name_template: String,
+    pub(crate) compression: String,
+    pub(crate) compression_level: Option<i32>,
+    pub(crate) scan_server: Option<ScanServerMetadata>,
+}
+
+/// A Komga or Kavita server asked to scan folders of manga after chapters are added, as set with `settings --scan-server`.
+#[derive(Clone, PartialEq)]
+pub(crate) struct ScanServerMetadata {
+    /// `komga` or `kavita`.
+    pub(crate) kind: String,
+    pub(crate) url: String,
+    pub(crate) key: String,
+}
+
+impl std::fmt::Debug for ScanServerMetadata {
+    /// Leaves out the API key, as settings are printed in debug output.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ScanServerMetadata").field("kind", &self.kind).field("url", &self.url).finish()
+    }
+}
+
+/// A command run or a URL told about an event, as set with `settings --hook` or `--webhook`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct HookMetadata {
+    /// `new-chapter`, `chapter-downloaded`, `manga-finished`, `error` or `*` for all of them.
+    pub(crate) event: String,
+    /// `command` or `webhook`.
+    pub(crate) kind: String,
+    /// The shell command or the URL.
+    pub(crate) target: String,
 }
 
 /// Contains metadata for a specific manga chapter.
@@ -51,6 +83,22 @@ pub(crate) struct ChapterMetadataIn {
     pub(crate) chapter: String,
     pub(crate) volume: String,
     pub(crate) scanlation: ScanlationMetadata,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) transform: Option<TransformMetadata>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) layout: Option<LayoutMetadata>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) removed_pages: Vec<String>,
+}
+
+/// Describes how the pages of a chapter were transformed after download.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub(crate) struct TransformMetadata {
+    pub(crate) format: String,
+    pub(crate) quality: u8,
+    pub(crate) max_width: Option<u32>,
+    pub(crate) max_height: Option<u32>,
+    pub(crate) grayscale: bool,
 }
 
 /// Contains metadata about the scanlation group.
@@ -163,6 +211,37 @@ impl Log {
     }
 }
 
+lazy_static! {
+    /// Time the current process was started; tells it apart from earlier processes with the same PID.
+    static ref PROCESS_STARTED: String = utils
+        ::get_process_started(std::process::id())
+        .unwrap_or_else(Utc::now)
+        .to_rfc3339();
+}
+
+/// Identifies the process holding a lock file.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub(crate) struct LockOwner {
+    pub(crate) pid: u32,
+    pub(crate) host: String,
+    pub(crate) started: String,
+}
+
+impl LockOwner {
+    /// Describes the current process.
+    ///
+    /// # Returns
+    ///
+    /// A `LockOwner` with the PID of the current process, the host name and the time the process was started.
+    pub(crate) fn current() -> LockOwner {
+        LockOwner {
+            pid: std::process::id(),
+            host: utils::get_host_name(),
+            started: PROCESS_STARTED.clone(),
+        }
+    }
+}
+
 /// Represents a database of items.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub(crate) struct DB {
@@ -213,6 +292,70 @@ pub(crate) struct MangaMetadata {
     pub(crate) current_language: String,
     pub(crate) theme: Vec<TagMetadata>,
     pub(crate) genre: Vec<TagMetadata>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) layout: Option<LayoutMetadata>,
+    /// Whether `database --update` leaves the manga as it is.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) paused: bool,
+    #[serde(default, skip_serializing_if = "PreferencesMetadata::is_empty")]
+    pub(crate) preferences: PreferencesMetadata,
+}
+
+/// Options of `database --update` that are set for one manga; unset options are taken from the arguments.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub(crate) struct PreferencesMetadata {
+    /// Whether images of lower quality are downloaded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) saver: Option<bool>,
+    /// ID or name of the scanlation group whose chapters are preferred over chapters of other groups.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) group: Option<String>,
+    /// How chapters are archived; `chapter` or `volume`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) bundle: Option<String>,
+    /// Format pages are converted to; `keep`, `webp`, `jpeg` or `avif`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) format: Option<String>,
+}
+
+impl PreferencesMetadata {
+    /// Returns whether no preference is set.
+    pub(crate) fn is_empty(&self) -> bool {
+        *self == PreferencesMetadata::default()
+    }
+}
+
+/// Describes how the layout of pages is processed; stored per manga in the library.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub(crate) struct LayoutMetadata {
+    /// Reading direction, `rtl` or `ltr`; decides the order of split spreads and the rotation of spreads.
+    pub(crate) direction: String,
+    /// Aspect ratio (height / width) of the target screen; strips taller than twice the screen are split.
+    pub(crate) split_tall: Option<f32>,
+    /// What happens with double-page spreads; `keep`, `split` or `rotate`.
+    pub(crate) spread: String,
+    /// Whether uniform borders are cropped.
+    pub(crate) crop: bool,
+}
+
+impl Default for LayoutMetadata {
+    fn default() -> Self {
+        LayoutMetadata {
+            direction: String::from("rtl"),
+            split_tall: None,
+            spread: String::from("keep"),
+            crop: false,
+        }
+    }
+}
+
+impl LayoutMetadata {
+    /// Returns whether the layout changes any page.
+    #[cfg(feature = "transcode")]
+    pub(crate) fn is_active(&self) -> bool {
+        self.split_tall.is_some() || self.spread != "keep" || self.crop
+    }
 }
 
 /// Defines the maximum coordinates for points.
@@ -0,0 +1,341 @@
+use rusqlite::{ Connection, TransactionBehavior };
+use semver::Version;
+
+use crate::{ error::MdownError, version_manager::get_current_version };
+
+/// A change of the layout of the library.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Migration {
+    /// Version of the layout after the migration; stored as `user_version` of the library.
+    pub(crate) version: i64,
+    /// What the migration does; shown to the user.
+    pub(crate) description: &'static str,
+    /// Whether the migration removes data; the user is asked before it runs.
+    pub(crate) lossy: bool,
+    /// Statements of the migration.
+    pub(crate) sql: &'static str,
+}
+
+/// Migrations of the library in the order they run.
+///
+/// Released migrations must never change; changes of the layout are added as new migrations.
+pub(crate) const MIGRATIONS: [Migration; 4] = [
+    Migration {
+        version: 1,
+        description: "create tables of manga, chapters, tags, languages and fetch dates",
+        lossy: false,
+        // Libraries created before migrations existed already have these tables
+        sql: "CREATE TABLE IF NOT EXISTS manga (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            mwd TEXT NOT NULL,
+            cover BOOLEAN NOT NULL,
+            current_language TEXT NOT NULL,
+            layout TEXT
+        );
+        CREATE TABLE IF NOT EXISTS chapters (
+            manga_id TEXT NOT NULL REFERENCES manga(id) ON DELETE CASCADE,
+            number TEXT NOT NULL,
+            updated_at TEXT NOT NULL,
+            id TEXT NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS chapters_manga_id ON chapters(manga_id);
+        CREATE TABLE IF NOT EXISTS tags (
+            manga_id TEXT NOT NULL REFERENCES manga(id) ON DELETE CASCADE,
+            kind TEXT NOT NULL,
+            id TEXT NOT NULL,
+            name TEXT NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS tags_manga_id ON tags(manga_id);
+        CREATE TABLE IF NOT EXISTS languages (
+            manga_id TEXT NOT NULL REFERENCES manga(id) ON DELETE CASCADE,
+            language TEXT NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS languages_manga_id ON languages(manga_id);
+        CREATE TABLE IF NOT EXISTS fetch_dates (
+            manga_id TEXT NOT NULL REFERENCES manga(id) ON DELETE CASCADE,
+            date TEXT NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS fetch_dates_manga_id ON fetch_dates(manga_id);
+        CREATE TABLE IF NOT EXISTS library_info (
+            name TEXT PRIMARY KEY,
+            value TEXT NOT NULL
+        );",
+    },
+    Migration {
+        version: 2,
+        description: "remove duplicate entries of the same chapter, keeping the one updated last",
+        lossy: true,
+        sql: "DELETE FROM chapters WHERE EXISTS (
+            SELECT 1 FROM chapters AS other
+            WHERE other.manga_id = chapters.manga_id
+                AND other.number = chapters.number
+                AND other.id = chapters.id
+                AND (
+                    other.updated_at > chapters.updated_at OR
+                    (other.updated_at = chapters.updated_at AND other.rowid > chapters.rowid)
+                )
+        );
+        DROP INDEX IF EXISTS chapters_manga_id;
+        CREATE UNIQUE INDEX chapters_manga_id ON chapters(manga_id, number, id);",
+    },
+    Migration {
+        version: 3,
+        description: "add pausing of updates and preferences of manga",
+        lossy: false,
+        sql: "ALTER TABLE manga ADD COLUMN paused BOOLEAN NOT NULL DEFAULT 0;
+        ALTER TABLE manga ADD COLUMN preferences TEXT;",
+    },
+    Migration {
+        version: 4,
+        description: "remember when manga were last found up to date",
+        lossy: false,
+        sql: "ALTER TABLE manga ADD COLUMN checked_at TEXT;",
+    },
+];
+
+/// Returns the version of the layout of a library.
+///
+/// # Arguments
+/// * `conn` - The connection to the library.
+///
+/// # Returns
+/// * `Result<i64, MdownError>` - Returns the version, `0` for a new library, or an `MdownError` on failure.
+pub(crate) fn get_schema_version(conn: &Connection) -> Result<i64, MdownError> {
+    match conn.query_row("PRAGMA user_version", [], |row| row.get(0)) {
+        Ok(version) => Ok(version),
+        Err(err) => Err(MdownError::DatabaseError(err)),
+    }
+}
+
+/// Returns the migrations a library still needs.
+///
+/// # Arguments
+/// * `conn` - The connection to the library.
+///
+/// # Returns
+/// * `Result<Vec<&'static Migration>, MdownError>` - Returns the migrations in the order they have to run, or an `MdownError` on failure.
+pub(crate) fn pending(conn: &Connection) -> Result<Vec<&'static Migration>, MdownError> {
+    match get_schema_version(conn) {
+        Ok(version) =>
+            Ok(
+                MIGRATIONS.iter()
+                    .filter(|migration| migration.version > version)
+                    .collect()
+            ),
+        Err(err) => Err(err),
+    }
+}
+
+/// Runs a migration in one transaction together with the change of the version of the layout.
+///
+/// # Arguments
+/// * `conn` - The connection to the library.
+/// * `migration` - The migration.
+///
+/// # Returns
+/// * `Result<(), MdownError>` - Returns `Ok(())` if the migration ran, or an `MdownError` on failure.
+pub(crate) fn run(conn: &mut Connection, migration: &Migration) -> Result<(), MdownError> {
+    let tx = match conn.transaction_with_behavior(TransactionBehavior::Immediate) {
+        Ok(tx) => tx,
+        Err(err) => {
+            return Err(MdownError::DatabaseError(err));
+        }
+    };
+    // Another process may have run the migration while this one waited for the transaction
+    match get_schema_version(&tx) {
+        Ok(version) if version >= migration.version => {
+            return Ok(());
+        }
+        Ok(_version) => (),
+        Err(err) => {
+            return Err(err);
+        }
+    }
+    let sql = format!("{}\nPRAGMA user_version = {};", migration.sql, migration.version);
+    match tx.execute_batch(&sql) {
+        Ok(()) => (),
+        Err(err) => {
+            return Err(MdownError::DatabaseError(err));
+        }
+    }
+    match tx.commit() {
+        Ok(()) => Ok(()),
+        Err(err) => Err(MdownError::DatabaseError(err)),
+    }
+}
+
+/// Runs the migrations a library still needs.
+///
+/// # Arguments
+/// * `conn` - The connection to the library.
+/// * `confirm` - Asks the user whether a lossy migration may run.
+///
+/// # Returns
+/// * `Result<usize, MdownError>` - Returns the number of migrations that ran, or an `MdownError` if a migration fails or is declined.
+pub(crate) fn migrate(
+    conn: &mut Connection,
+    confirm: impl Fn(&Migration) -> bool
+) -> Result<usize, MdownError> {
+    let migrations = match pending(conn) {
+        Ok(migrations) => migrations,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    for migration in migrations.iter() {
+        if migration.lossy && !confirm(migration) {
+            return Err(
+                MdownError::CustomError(
+                    format!(
+                        "Library was not migrated to version {} ({}); run mdown again and confirm the migration to use the library",
+                        migration.version,
+                        migration.description
+                    ),
+                    String::from("MigrationError")
+                )
+            );
+        }
+        match run(conn, migration) {
+            Ok(()) => (),
+            Err(err) => {
+                return Err(err);
+            }
+        }
+    }
+    Ok(migrations.len())
+}
+
+/// Returns the version of the program to store in the library after it was opened.
+///
+/// Opening a library with an older program keeps the newer version.
+///
+/// # Arguments
+/// * `stored` - The version stored in the library.
+///
+/// # Returns
+/// * `Option<String>` - The current version of the program if it is newer than the stored version.
+pub(crate) fn newer_version(stored: &str) -> Option<String> {
+    let current = get_current_version();
+    match (Version::parse(stored), Version::parse(&current)) {
+        (Ok(stored), Ok(parsed)) if stored >= parsed => None,
+        _ => Some(current),
+    }
+}
+
+#[cfg(test)]
+fn test_library(version: i64) -> Connection {
+    let mut conn = Connection::open_in_memory().unwrap();
+    for migration in MIGRATIONS.iter().filter(|migration| migration.version <= version) {
+        run(&mut conn, migration).unwrap();
+    }
+    conn
+}
+
+// Runs migrations in order and only once
+#[test]
+fn test_migrate_in_order() {
+    let mut conn = test_library(0);
+    assert_eq!(pending(&conn).unwrap().len(), MIGRATIONS.len());
+    assert_eq!(migrate(&mut conn, |_migration| true).unwrap(), MIGRATIONS.len());
+    assert_eq!(get_schema_version(&conn).unwrap(), MIGRATIONS[MIGRATIONS.len() - 1].version);
+    assert_eq!(migrate(&mut conn, |_migration| true).unwrap(), 0);
+}
+
+// Keeps the library as it is when a lossy migration is declined
+#[test]
+fn test_migrate_declined() {
+    let mut conn = test_library(1);
+    assert!(migrate(&mut conn, |migration| !migration.lossy).is_err());
+    assert_eq!(get_schema_version(&conn).unwrap(), 1);
+}
+
+// Creates the tables of the library
+#[test]
+fn test_migration_1() {
+    let conn = test_library(1);
+    for table in ["manga", "chapters", "tags", "languages", "fetch_dates", "library_info"] {
+        let count: i64 = conn
+            .query_row("SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = ?1", [table], |row|
+                row.get(0)
+            )
+            .unwrap();
+        assert_eq!(count, 1, "{}", table);
+    }
+}
+
+// Keeps only the last update of a chapter and rejects new duplicates
+#[test]
+fn test_migration_2() {
+    let mut conn = test_library(1);
+    conn.execute_batch(
+        "INSERT INTO manga (id, name, mwd, cover, current_language) VALUES ('m', 'Manga', '/m', 0, 'en');
+        INSERT INTO chapters (manga_id, number, updated_at, id) VALUES
+            ('m', '1', '2024-01-01T00:00:00+00:00', 'c1'),
+            ('m', '1', '2024-03-01T00:00:00+00:00', 'c1'),
+            ('m', '1', '2024-02-01T00:00:00+00:00', 'c1'),
+            ('m', '1', '2024-01-01T00:00:00+00:00', 'c1-other-group'),
+            ('m', '2', '2024-01-01T00:00:00+00:00', 'c2');"
+    ).unwrap();
+    run(&mut conn, &MIGRATIONS[1]).unwrap();
+
+    let mut stmt = conn.prepare("SELECT number, updated_at, id FROM chapters ORDER BY rowid").unwrap();
+    let chapters: Vec<(String, String, String)> = stmt
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+        .unwrap()
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(chapters, [
+        (String::from("1"), String::from("2024-03-01T00:00:00+00:00"), String::from("c1")),
+        (String::from("1"), String::from("2024-01-01T00:00:00+00:00"), String::from("c1-other-group")),
+        (String::from("2"), String::from("2024-01-01T00:00:00+00:00"), String::from("c2")),
+    ]);
+    assert!(
+        conn
+            .execute(
+                "INSERT INTO chapters (manga_id, number, updated_at, id) VALUES ('m', '2', '2025', 'c2')",
+                []
+            )
+            .is_err()
+    );
+}
+
+// Never lowers the stored version of the program
+#[test]
+fn test_newer_version() {
+    assert_eq!(newer_version("0.0.1"), Some(get_current_version()));
+    assert_eq!(newer_version("not a version"), Some(get_current_version()));
+    assert_eq!(newer_version(&get_current_version()), None);
+    assert_eq!(newer_version("999.0.0"), None);
+}
+
+// Keeps manga updated and without preferences
+#[test]
+fn test_migration_3() {
+    let mut conn = test_library(2);
+    conn.execute_batch(
+        "INSERT INTO manga (id, name, mwd, cover, current_language) VALUES ('m', 'Manga', '/m', 0, 'en');"
+    ).unwrap();
+    run(&mut conn, &MIGRATIONS[2]).unwrap();
+    let (paused, preferences): (bool, Option<String>) = conn
+        .query_row("SELECT paused, preferences FROM manga WHERE id = 'm'", [], |row|
+            Ok((row.get(0)?, row.get(1)?))
+        )
+        .unwrap();
+    assert!(!paused);
+    assert_eq!(preferences, None);
+}
+
+// Keeps manga updated and never checked
+#[test]
+fn test_migration_4() {
+    let mut conn = test_library(3);
+    conn.execute_batch(
+        "INSERT INTO manga (id, name, mwd, cover, current_language) VALUES ('m', 'Manga', '/m', 0, 'en');"
+    ).unwrap();
+    run(&mut conn, &MIGRATIONS[3]).unwrap();
+    let checked_at: Option<String> = conn
+        .query_row("SELECT checked_at FROM manga WHERE id = 'm'", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(checked_at, None);
+}
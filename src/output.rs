@@ -0,0 +1,293 @@
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{ args, error::MdownError, metadata::{ ChapterMetadataIn, MangaMetadata, PreferencesMetadata } };
+
+/// Format of the output of database commands.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Format {
+    /// Text meant for people.
+    Table,
+    /// One JSON array of all records.
+    Json,
+    /// One JSON record per line, printed as soon as it is known.
+    Ndjson,
+}
+
+impl Format {
+    /// Parses the value of `database --output`; unknown values are printed as a table.
+    pub(crate) fn parse(value: &str) -> Format {
+        match value {
+            "json" => Format::Json,
+            "ndjson" => Format::Ndjson,
+            _ => Format::Table,
+        }
+    }
+}
+
+/// Returns the format requested with `database --output`.
+pub(crate) fn get_format() -> Format {
+    Format::parse(&args::ARGS_OUTPUT)
+}
+
+/// Returns whether the output is meant for people.
+pub(crate) fn is_table() -> bool {
+    get_format() == Format::Table
+}
+
+/// Prints a message meant for people.
+///
+/// When records are printed, the message goes to standard error, so that standard output can be parsed.
+///
+/// # Arguments
+/// * `message` - The message.
+pub(crate) fn status(message: &str) {
+    match get_format() {
+        Format::Table => println!("{}", message),
+        Format::Json | Format::Ndjson => eprintln!("{}", message),
+    }
+}
+
+/// Result of `database --check` or `--update` for one manga.
+#[derive(Debug, Clone, Default, Serialize, PartialEq)]
+pub(crate) struct CheckReport {
+    pub(crate) id: String,
+    pub(crate) name: String,
+    pub(crate) mwd: String,
+    /// `checked`, `updated`, `planned` (by `--dry-run`), `skipped` or `removed`.
+    pub(crate) status: String,
+    /// Why the manga was skipped or removed.
+    pub(crate) reason: Option<String>,
+    /// Chapters that aren't downloaded yet.
+    pub(crate) new_chapters: Vec<String>,
+    /// Downloaded chapters that were changed since they were downloaded.
+    pub(crate) outdated_chapters: Vec<String>,
+    /// Chapters whose date in the library was ahead of MangaDex and was corrected.
+    pub(crate) fixed_dates: Vec<String>,
+    /// Chapters downloaded by `--update`.
+    pub(crate) downloaded_chapters: Vec<String>,
+    /// Whether the cover is downloaded.
+    pub(crate) cover: bool,
+    /// Whether all chapters and the cover are downloaded.
+    pub(crate) up_to_date: bool,
+    /// What `--dry-run` would do with each chapter.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) planned: Vec<PlanReport>,
+}
+
+impl CheckReport {
+    /// Creates the result for a manga; the lists of chapters are left empty.
+    ///
+    /// # Arguments
+    /// * `item` - The manga.
+    /// * `status` - `checked`, `updated`, `planned`, `skipped` or `removed`.
+    /// * `reason` - Why the manga was skipped or removed, if it was.
+    pub(crate) fn new(item: &MangaMetadata, status: &str, reason: Option<String>) -> CheckReport {
+        CheckReport {
+            id: item.id.clone(),
+            name: item.name.clone(),
+            mwd: item.mwd.clone(),
+            status: status.to_string(),
+            reason,
+            cover: item.cover,
+            ..CheckReport::default()
+        }
+    }
+}
+
+/// What `--dry-run` would do with a chapter.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub(crate) struct PlanReport {
+    pub(crate) id: String,
+    pub(crate) volume: String,
+    pub(crate) chapter: String,
+    pub(crate) title: String,
+    pub(crate) language: String,
+    /// `download`, `redownload` or `skip`.
+    pub(crate) action: String,
+    /// Why the chapter would be downloaded again or skipped.
+    pub(crate) reason: Option<String>,
+}
+
+impl PlanReport {
+    /// Returns the line printed for the chapter in a table.
+    pub(crate) fn line(&self) -> String {
+        let volume = match self.volume.as_str() {
+            "" => String::new(),
+            volume => format!("Vol.{} ", volume),
+        };
+        let reason = match &self.reason {
+            Some(reason) => format!(": {}", reason),
+            None => String::new(),
+        };
+        format!(" {} {}Ch.{} ({}) [{}]{}", self.action, volume, self.chapter, self.id, self.language, reason)
+    }
+}
+
+/// A manga of the library as shown by `database --show` and `--show-all`.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub(crate) struct MangaReport {
+    pub(crate) id: String,
+    pub(crate) name: String,
+    pub(crate) mwd: String,
+    pub(crate) cover: bool,
+    pub(crate) language: String,
+    pub(crate) available_languages: Vec<String>,
+    pub(crate) genres: Vec<String>,
+    pub(crate) themes: Vec<String>,
+    /// Dates the manga was fetched from MangaDex.
+    pub(crate) fetched: Vec<String>,
+    pub(crate) chapters: Vec<String>,
+    pub(crate) paused: bool,
+    pub(crate) preferences: PreferencesMetadata,
+    /// Downloaded chapters read from their archives; only shown by `--show-all`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) archives: Option<Vec<ArchiveReport>>,
+}
+
+/// A downloaded chapter as shown by `database --show-all`.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub(crate) struct ArchiveReport {
+    pub(crate) id: String,
+    pub(crate) name: String,
+    pub(crate) volume: String,
+    pub(crate) chapter: String,
+    pub(crate) title: String,
+    pub(crate) pages: String,
+    pub(crate) group: String,
+    pub(crate) saver: bool,
+}
+
+impl From<&ChapterMetadataIn> for ArchiveReport {
+    fn from(chapter: &ChapterMetadataIn) -> ArchiveReport {
+        ArchiveReport {
+            id: chapter.id.clone(),
+            name: chapter.name.clone(),
+            volume: chapter.volume.clone(),
+            chapter: chapter.chapter.clone(),
+            title: chapter.title.clone(),
+            pages: chapter.pages.clone(),
+            group: chapter.scanlation.name.clone(),
+            saver: chapter.saver,
+        }
+    }
+}
+
+/// A run of the program whose logs are stored, as shown by `database --show-log`.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub(crate) struct LogRunReport {
+    pub(crate) log_id: String,
+    pub(crate) name: String,
+    pub(crate) manga_id: String,
+    pub(crate) mwd: String,
+    /// `web`, `downloader` or `unknown`.
+    pub(crate) r#type: String,
+    pub(crate) time_start: String,
+    pub(crate) time_end: String,
+    /// Sections of the logs; `General` holds messages that don't belong to a chapter.
+    pub(crate) sections: Vec<String>,
+}
+
+/// A message of the logs of a run, as shown by `database --show-log --log-id`.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub(crate) struct LogEntryReport {
+    pub(crate) log_id: String,
+    pub(crate) section: String,
+    pub(crate) index: usize,
+    pub(crate) message: String,
+}
+
+/// Records printed by a database command.
+pub(crate) struct Records {
+    format: Format,
+    records: Vec<Value>,
+}
+
+impl Records {
+    /// Creates records in the format requested with `database --output`.
+    pub(crate) fn new() -> Records {
+        Records::with_format(get_format())
+    }
+
+    /// Creates records in a format.
+    pub(crate) fn with_format(format: Format) -> Records {
+        Records { format, records: Vec::new() }
+    }
+
+    /// Adds a record; `ndjson` prints it at once and tables ignore it, as their text is printed by the command.
+    ///
+    /// # Arguments
+    /// * `record` - The record.
+    ///
+    /// # Returns
+    /// * `Result<(), MdownError>` - Returns `Ok(())` if the record was added, or an `MdownError` if it can't be serialized.
+    pub(crate) fn push<T: Serialize>(&mut self, record: &T) -> Result<(), MdownError> {
+        let value = match serde_json::to_value(record) {
+            Ok(value) => value,
+            Err(err) => {
+                return Err(MdownError::JsonError(err.to_string()));
+            }
+        };
+        match self.format {
+            Format::Table => (),
+            Format::Json => self.records.push(value),
+            Format::Ndjson => println!("{}", value),
+        }
+        Ok(())
+    }
+
+    /// Returns what is left to print after all records were added.
+    fn render(&self) -> Option<String> {
+        match self.format {
+            Format::Json => Some(Value::Array(self.records.clone()).to_string()),
+            Format::Table | Format::Ndjson => None,
+        }
+    }
+
+    /// Prints the records that weren't printed yet; `json` prints all records as one array.
+    pub(crate) fn finish(self) {
+        if let Some(output) = self.render() {
+            println!("{}", output);
+        }
+    }
+}
+
+// Prints an empty array when nothing was found
+#[test]
+fn test_render_json() {
+    let mut records = Records::with_format(Format::Json);
+    assert_eq!(records.render(), Some(String::from("[]")));
+    records.push(&serde_json::json!({ "id": "a" })).unwrap();
+    records.push(&serde_json::json!({ "id": "b" })).unwrap();
+    assert_eq!(records.render(), Some(String::from(r#"[{"id":"a"},{"id":"b"}]"#)));
+}
+
+// Prints planned chapters with their reason
+#[test]
+fn test_plan_report_line() {
+    let mut plan = PlanReport {
+        id: String::from("c1"),
+        volume: String::from("2"),
+        chapter: String::from("10"),
+        title: String::new(),
+        language: String::from("en"),
+        action: String::from("download"),
+        reason: None,
+    };
+    assert_eq!(plan.line(), " download Vol.2 Ch.10 (c1) [en]");
+    plan.volume.clear();
+    plan.action = String::from("skip");
+    plan.reason = Some(String::from("already downloaded"));
+    assert_eq!(plan.line(), " skip Ch.10 (c1) [en]: already downloaded");
+}
+
+// Leaves nothing to print at the end for tables and ndjson
+#[test]
+fn test_render_streamed() {
+    let mut records = Records::with_format(Format::Table);
+    records.push(&serde_json::json!({ "id": "a" })).unwrap();
+    assert_eq!(records.render(), None);
+    assert_eq!(Records::with_format(Format::Ndjson).render(), None);
+    assert_eq!(Format::parse("ndjson"), Format::Ndjson);
+    assert_eq!(Format::parse("table"), Format::Table);
+}
@@ -1,35 +1,48 @@
 use crossterm::event::{ self, Event, KeyCode };
+use futures::stream::{ self, Stream, StreamExt };
 use lazy_static::lazy_static;
 use parking_lot::Mutex;
 use remove_dir_all::remove_dir_all;
-use semver::{ BuildMetadata, Prerelease, Version };
 use serde_json::{ Map, Value };
-use std::{ collections::HashMap, fs::{ self, File, OpenOptions }, io::{ Read, Write }, sync::Arc };
+use std::{
+    collections::{ BTreeMap, HashMap, HashSet },
+    fs::{ self, File, OpenOptions },
+    future::Future,
+    io::{ Read, Write },
+    path::{ Path, PathBuf },
+    sync::Arc,
+};
+use walkdir::WalkDir;
 
 use crate::{
     args::{ self, ARGS },
+    daemon,
     debug,
     download,
     download_manga,
     error::{ MdownError, suspend_error },
     getter::{ self, get_folder_name, get_manga, get_manga_name, get_scanlation_group },
     handle_error,
+    hooks,
+    library,
     log,
     log_end,
     MAXPOINTS,
     metadata::{
         self,
         ChapterMetadata,
-        Dat,
+        LayoutMetadata,
         Log,
         MangaDownloadLogs,
         MangaMetadata,
         MdownLogs,
+        PreferencesMetadata,
         TagMetadata,
     },
+    output,
+    scan,
     string,
     utils::{ self, clear_screen, input },
-    version_manager::{ check_ver, get_current_version },
     zip_func,
 };
 
@@ -47,6 +60,9 @@ lazy_static! {
     pub(crate) static ref MWD: Mutex<String> = Mutex::new(String::new());
     pub(crate) static ref TO_DOWNLOAD: Mutex<Vec<String>> = Mutex::new(Vec::new()); // chapter number to download
     pub(crate) static ref TO_DOWNLOAD_DATE: Mutex<Vec<String>> = Mutex::new(Vec::new()); // chapter number to download because of date
+    pub(crate) static ref DOWNLOADED: Mutex<Vec<String>> = Mutex::new(Vec::new()); // chapter number downloaded for current manga
+    pub(crate) static ref FAILED: Mutex<Vec<String>> = Mutex::new(Vec::new()); // chapter number that failed to download for current manga
+    pub(crate) static ref PLANNED: Mutex<Vec<output::PlanReport>> = Mutex::new(Vec::new()); // chapters reported by --dry-run for current manga
     pub(crate) static ref CURRENT_CHAPTER: Mutex<String> = Mutex::new(String::new()); // filename.get_folder_name()
     pub(crate) static ref CURRENT_PAGE: Mutex<u64> = Mutex::new(0);
     pub(crate) static ref CURRENT_PAGE_MAX: Mutex<u64> = Mutex::new(0);
@@ -68,11 +84,13 @@ lazy_static! {
     pub(crate) static ref FIXED_DATES: Mutex<Vec<String>> = Mutex::new(Vec::new()); // vec of chapter number which have been fixed
     pub(crate) static ref GENRES: Mutex<Vec<TagMetadata>> = Mutex::new(Vec::new());
     pub(crate) static ref THEMES: Mutex<Vec<TagMetadata>> = Mutex::new(Vec::new());
+    pub(crate) static ref LAYOUT: Mutex<Option<LayoutMetadata>> = Mutex::new(None); // layout stored in database for current manga
+    pub(crate) static ref PREFERRED_GROUP: Mutex<Option<String>> = Mutex::new(None); // ID or name of group preferred for current manga
     pub(crate) static ref MUSIC_STAGE: Mutex<String> = Mutex::new(String::new()); // 'init', 'start', 'end' these are the stages need to go in order or init => end
     pub(crate) static ref MUSIC_END: Mutex<bool> = Mutex::new(false);
 }
 pub(crate) fn args_delete() -> Result<(), MdownError> {
-    let path = match getter::get_dat_path() {
+    let path = match getter::get_library_path() {
         Ok(path) => path,
         Err(err) => {
             handle_error!(&err, String::from("program"));
@@ -107,6 +125,14 @@ pub(crate) async fn show_log() -> Result<(), MdownError> {
 
     match serde_json::from_value::<MdownLogs>(json) {
         Ok(logs) => {
+            // Selectors and machine-readable output never ask for input
+            if
+                args::ARGS_LOG_ID.is_some() ||
+                args::ARGS_LOG_SECTION.is_some() ||
+                !output::is_table()
+            {
+                return show_log_records(&logs);
+            }
             let data = logs.clone();
             if data.is_empty() {
                 println!("No logs found");
@@ -273,81 +299,137 @@ pub(crate) async fn show_log() -> Result<(), MdownError> {
     Ok(())
 }
 
-pub(crate) async fn show() -> Result<(), MdownError> {
-    let dat_path = match getter::get_dat_path() {
-        Ok(path) => path,
-        Err(err) => {
-            return Err(err);
-        }
-    };
-    if let Err(err) = fs::metadata(&dat_path) {
-        debug!("dat.json not found: {}", err.to_string());
-        return Err(MdownError::IoError(err, dat_path));
-    }
+/// Returns the names of the sections of the logs of a run; messages that don't belong to a chapter are in `General`.
+fn get_log_sections(log: &MangaDownloadLogs) -> Vec<String> {
+    let mut sections: Vec<String> = log.logs
+        .keys()
+        .map(|name| {
+            match name.as_str() {
+                "" => String::from("General"),
+                name => name.to_string(),
+            }
+        })
+        .collect();
+    sections.sort();
+    sections
+}
 
-    let json = match get_dat_content(dat_path.as_str()) {
-        Ok(value) => value,
-        Err(error) => {
-            return Err(error);
-        }
+/// Shows the logs selected with `--log-id` and `--log-section` without asking for input.
+///
+/// Without `--log-id` the runs are listed; otherwise the messages of the run are shown.
+///
+/// # Arguments
+/// * `logs` - The logs of all runs.
+///
+/// # Returns
+/// * `Result<(), MdownError>` - Returns `Ok(())` if the logs were shown, or an `MdownError` if the run or section isn't found.
+fn show_log_records(logs: &MdownLogs) -> Result<(), MdownError> {
+    let table = output::is_table();
+    let mut records = output::Records::new();
+    let log_id = match args::ARGS_LOG_ID.clone() {
+        Some(log_id) if log_id == "latest" =>
+            logs
+                .iter()
+                .max_by(|a, b| a.1.time_start.cmp(&b.1.time_start))
+                .map(|(log_id, _log)| log_id.clone())
+                .or(Some(log_id)),
+        log_id => log_id,
     };
 
-    match serde_json::from_value::<Dat>(json) {
-        Ok(mut dat) => {
-            let mut not_orig = false;
-            let version = match Version::parse(&dat.version) {
-                Ok(version) => version,
-                Err(_err) => {
-                    not_orig = true;
-                    Version {
-                        major: 0,
-                        minor: 0,
-                        patch: 0,
-                        pre: Prerelease::EMPTY,
-                        build: BuildMetadata::EMPTY,
-                    }
+    match log_id {
+        None => {
+            let mut log_ids: Vec<&String> = logs.keys().collect();
+            log_ids.sort();
+            if table && log_ids.is_empty() {
+                println!("No logs found");
+            }
+            for (times, log_id) in log_ids.into_iter().enumerate() {
+                let log = &logs[log_id];
+                let report = output::LogRunReport {
+                    log_id: log_id.clone(),
+                    name: log.name.clone(),
+                    manga_id: log.id.clone(),
+                    mwd: log.mwd.clone(),
+                    r#type: log.r#type.clone(),
+                    time_start: log.time_start.clone(),
+                    time_end: log.time_end.clone(),
+                    sections: get_log_sections(log),
+                };
+                if table {
+                    println!("{}: {} {} ({})", times, log_id, log.name, log.r#type);
                 }
-            };
-            let current_version = match Version::parse(&get_current_version()) {
-                Ok(version) => version,
-                Err(_err) => {
-                    not_orig = true;
-                    Version {
-                        major: 0,
-                        minor: 0,
-                        patch: 0,
-                        pre: Prerelease::EMPTY,
-                        build: BuildMetadata::EMPTY,
+                match records.push(&report) {
+                    Ok(()) => (),
+                    Err(err) => {
+                        return Err(err);
                     }
                 }
+            }
+        }
+        Some(log_id) => {
+            let log = match logs.get(&log_id) {
+                Some(log) => log,
+                None => {
+                    return Err(MdownError::NotFoundError(format!("log {}", log_id)));
+                }
             };
-            if not_orig {
-                println!("Version: {}", dat.version);
-            } else {
-                if current_version > version {
-                    println!(
-                        "Version: {} (Outdated version; current {})",
-                        version,
-                        current_version
+            let mut sections = get_log_sections(log);
+            if let Some(section) = args::ARGS_LOG_SECTION.clone() {
+                if !sections.contains(&section) {
+                    return Err(
+                        MdownError::NotFoundError(
+                            format!("section {} in log {}", section, log_id)
+                        )
                     );
-                    let confirmation = match check_ver(&mut dat, version, current_version) {
-                        Ok(confirmation) => confirmation,
+                }
+                sections = vec![section];
+            }
+            for section in sections.iter() {
+                let name = match section.as_str() {
+                    "General" => "",
+                    name => name,
+                };
+                if table {
+                    println!("{}", section);
+                }
+                for (index, message) in log.logs[name].iter().enumerate() {
+                    if table {
+                        println!("{}: {}", index, message);
+                    }
+                    let report = output::LogEntryReport {
+                        log_id: log_id.clone(),
+                        section: section.clone(),
+                        index,
+                        message: message.clone(),
+                    };
+                    match records.push(&report) {
+                        Ok(()) => (),
                         Err(err) => {
-                            suspend_error(err);
-                            false
+                            return Err(err);
                         }
-                    };
-                    if confirmation {
-                        println!("NOT iMPLEMENTED");
-                        println!("Run: mdown database --update-database");
                     }
-                } else {
-                    println!("Version: {}", version);
                 }
+                if table {
+                    println!();
+                }
+            }
+        }
+    }
+    records.finish();
+    Ok(())
+}
+
+pub(crate) async fn show() -> Result<(), MdownError> {
+    let table = output::is_table();
+    let mut records = output::Records::new();
+    match library::load() {
+        Ok(dat) => {
+            if table {
+                println!("Version: {}", dat.version);
             }
             let data = dat.data;
             if data.is_empty() {
-                println!("No manga found");
+                output::status("No manga found");
             }
             let mut times = 0;
             for (i, item) in data.iter().enumerate() {
@@ -397,10 +479,12 @@ pub(crate) async fn show() -> Result<(), MdownError> {
                 if cont {
                     continue;
                 }
-                if i == 0 {
-                    println!();
+                if table {
+                    if i == 0 {
+                        println!();
+                    }
+                    println!("------------------------------------");
                 }
-                println!("------------------------------------");
                 let manga_name = item.name.clone();
                 let mwd = item.mwd.clone();
                 let language = item.current_language.clone();
@@ -440,7 +524,7 @@ pub(crate) async fn show() -> Result<(), MdownError> {
                 available_languages_str = available_languages_str
                     .trim_end_matches(", ")
                     .to_string();
-                let cover = fs::metadata(format!("{}\\_cover.png", mwd)).is_ok();
+                let cover = Path::new(&mwd).join("_cover.png").exists();
                 let chapters: Vec<String> = item.chapters
                     .iter()
                     .map(|d| d.number.clone())
@@ -453,68 +537,115 @@ pub(crate) async fn show() -> Result<(), MdownError> {
                 }
                 chapter_str = chapter_str.trim_end_matches(", ").to_string();
 
-                println!("Manga name: {}", manga_name);
-                println!("MWD: {}", mwd);
-                println!("ID: {}", id);
-                println!("Database fetched: {}", date_str);
-                if !genres.is_empty() {
-                    println!("Genres: {}", genre_str);
-                }
-                if !themes.is_empty() {
-                    println!("Themes: {}", theme_str);
+                if table {
+                    println!("Manga name: {}", manga_name);
+                    println!("MWD: {}", mwd);
+                    println!("ID: {}", id);
+                    println!("Database fetched: {}", date_str);
+                    if !genres.is_empty() {
+                        println!("Genres: {}", genre_str);
+                    }
+                    if !themes.is_empty() {
+                        println!("Themes: {}", theme_str);
+                    }
+                    println!("Cover: {}", cover);
+                    println!("Language: {}", language);
+                    println!("Available language: {}", available_languages_str);
+                    if item.paused {
+                        println!("Paused: true");
+                    }
+                    if let Some(saver) = item.preferences.saver {
+                        println!("Saver: {}", saver);
+                    }
+                    if let Some(group) = &item.preferences.group {
+                        println!("Preferred group: {}", group);
+                    }
+                    if let Some(bundle) = &item.preferences.bundle {
+                        println!("Bundle: {}", bundle);
+                    }
+                    if let Some(format) = &item.preferences.format {
+                        println!("Format: {}", format);
+                    }
+                    println!("Chapters: {}", chapter_str);
+                    println!();
                 }
-                println!("Cover: {}", cover);
-                println!("Language: {}", language);
-                println!("Available language: {}", available_languages_str);
-                println!("Chapters: {}", chapter_str);
-                println!();
+                let mut report = output::MangaReport {
+                    id: id.clone(),
+                    name: manga_name,
+                    mwd: mwd.clone(),
+                    cover,
+                    language,
+                    available_languages,
+                    genres,
+                    themes,
+                    fetched: date,
+                    chapters,
+                    paused: item.paused,
+                    preferences: item.preferences.clone(),
+                    archives: None,
+                };
 
                 if args::ARGS_SHOW_ALL.is_some() {
-                    let mut chapters = vec![];
-                    if let Ok(entries) = fs::read_dir(&mwd) {
-                        for entry in entries.flatten() {
-                            let file_name = entry.file_name();
-                            if let Some(name) = file_name.to_str() {
-                                if name.ends_with(".cbz") {
-                                    chapters.push(name.to_string());
-                                }
-                            }
-                        }
-                    }
+                    let chapters = find_archives(&mwd);
                     if !chapters.is_empty() {
+                        let mut chapters_metadata = vec![];
                         for entry in chapters {
-                            let path = format!("{}\\{}", mwd, entry);
+                            let path = entry.to_string_lossy().to_string();
+                            match check_for_volume_metadata(&path) {
+                                Ok(volume) if !volume.is_empty() => {
+                                    chapters_metadata.extend(volume);
+                                    continue;
+                                }
+                                Ok(_) => (),
+                                Err(err) => {
+                                    return Err(err);
+                                }
+                            }
                             let obj = match check_for_metadata(&path) {
                                 Ok(metadata) => metadata,
                                 Err(err) => {
                                     return Err(err);
                                 }
                             };
+                            chapters_metadata.push(obj);
+                        }
+                        report.archives = Some(
+                            chapters_metadata.iter().map(output::ArchiveReport::from).collect()
+                        );
+                        if table {
+                            for obj in chapters_metadata {
+                                let name = obj.name;
 
-                            let name = obj.name;
-
-                            let pages = obj.pages;
+                                let pages = obj.pages;
 
-                            let id = obj.id;
+                                let id = obj.id;
 
-                            let title = obj.title;
+                                let title = obj.title;
 
-                            let chapter = obj.chapter;
+                                let chapter = obj.chapter;
 
-                            let volume = obj.volume;
+                                let volume = obj.volume;
 
-                            println!("Name: {}", name);
-                            if volume != "null" {
-                                println!("Volume: {}", volume);
+                                println!("Name: {}", name);
+                                if volume != "null" {
+                                    println!("Volume: {}", volume);
+                                }
+                                println!("Chapter: {}", chapter);
+                                println!("Pages: {}", pages);
+                                println!("ID: {}", id);
+                                println!("Title: {}", title);
+                                println!();
                             }
-                            println!("Chapter: {}", chapter);
-                            println!("Pages: {}", pages);
-                            println!("ID: {}", id);
-                            println!("Title: {}", title);
-                            println!();
                         }
                     } else {
-                        println!("No chapters found");
+                        report.archives = Some(Vec::new());
+                        output::status("No chapters found");
+                    }
+                }
+                match records.push(&report) {
+                    Ok(()) => (),
+                    Err(err) => {
+                        return Err(err);
                     }
                 }
             }
@@ -522,26 +653,99 @@ pub(crate) async fn show() -> Result<(), MdownError> {
             if !data.is_empty() && times == data.len() {
                 match ARGS.lock().show {
                     Some(Some(ref filter)) if !filter.is_empty() => {
-                        println!("No manga found for '{}'", filter);
+                        output::status(&format!("No manga found for '{}'", filter));
                     }
                     _ => (),
                 }
                 match ARGS.lock().show_all {
                     Some(Some(ref filter)) if !filter.is_empty() => {
-                        println!("No manga found for '{}'", filter);
+                        output::status(&format!("No manga found for '{}'", filter));
                     }
                     _ => (),
                 }
             }
         }
         Err(err) => {
-            return Err(MdownError::JsonError(err.to_string()));
+            return Err(err);
         }
     }
 
+    records.finish();
+    Ok(())
+}
+
+pub(crate) fn resolve_recompress() -> Result<(), MdownError> {
+    let dat = match library::load() {
+        Ok(dat) => dat,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+
+    let compression = ARGS.lock().compression.clone();
+    println!("Recompressing with {}", compression);
+    let (mut files, mut total_before, mut total_after) = (0, 0, 0);
+    for item in dat.data.iter() {
+        if fs::metadata(&item.mwd).is_err() {
+            eprintln!("Error: reading {} not found", item.mwd);
+            continue;
+        }
+        println!("{}", item.name);
+        for entry in find_archives(&item.mwd) {
+            let path = entry.to_string_lossy().to_string();
+            match zip_func::recompress(&path) {
+                Ok((before, after)) => {
+                    println!(
+                        "  {} ({} -> {})",
+                        entry.strip_prefix(&item.mwd).unwrap_or(&entry).display(),
+                        bytefmt::format(before),
+                        bytefmt::format(after)
+                    );
+                    files += 1;
+                    total_before += before;
+                    total_after += after;
+                }
+                Err(err) => handle_error!(&err, String::from("recompress")),
+            }
+        }
+    }
+    println!(
+        "Recompressed {} files ({} -> {})",
+        files,
+        bytefmt::format(total_before),
+        bytefmt::format(total_after)
+    );
     Ok(())
 }
 
+/// Lists the archives in a manga folder, including subfolders created by the name template.
+pub(crate) fn find_archives(mwd: &str) -> Vec<PathBuf> {
+    let mut archives: Vec<PathBuf> = WalkDir::new(mwd)
+        .into_iter()
+        .filter_entry(|entry| entry.file_name() != ".cache")
+        .filter_map(Result::ok)
+        .map(|entry| entry.into_path())
+        .filter(|path| {
+            path.is_file() && path.extension().and_then(|extension| extension.to_str()) == Some("cbz")
+        })
+        .collect();
+    archives.sort();
+    archives
+}
+
+pub(crate) fn get_layout() -> Option<LayoutMetadata> {
+    // Layout supplied by user takes precedence over the one stored in database
+    let layout = LAYOUT.lock().clone();
+    ARGS.lock().apply_layout(layout)
+}
+
+pub(crate) fn get_layout_from_dat(id: &str) -> Option<LayoutMetadata> {
+    match library::find(id) {
+        Ok(item) => item.and_then(|item| item.layout),
+        Err(_err) => None,
+    }
+}
+
 pub(crate) fn check_for_metadata_saver(file_path: &str) -> Result<bool, MdownError> {
     // Returns true if cbz file saver is different than the current one
     let obj = match check_for_metadata(file_path) {
@@ -565,73 +769,296 @@ pub(crate) fn check_for_metadata(
     zip_func::extract_file_from_zip(file_path, metadata_file_name)
 }
 
-pub(crate) async fn resolve_check() -> Result<(), MdownError> {
-    let dat_path = match getter::get_dat_path() {
-        Ok(path) => path,
-        Err(err) => {
-            return Err(err);
-        }
-    };
-    match fs::metadata(&dat_path) {
-        Ok(_metadata) => (),
-        Err(err) => {
-            return Err(MdownError::IoError(err, dat_path));
+pub(crate) fn check_for_volume_metadata(
+    file_path: &str
+) -> Result<Vec<metadata::ChapterMetadataIn>, MdownError> {
+    let metadata_file_name = "_metadata";
+
+    zip_func::extract_files_from_zip(file_path, metadata_file_name)
+}
+
+/// What was found while the chapters of a manga were looked at.
+///
+/// It is taken once the manga is handled, so nothing found for one manga is left for the next.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct MangaState {
+    /// Chapters to store in the library.
+    pub(crate) chapters: Vec<ChapterMetadata>,
+    /// Chapters to remove from the library.
+    pub(crate) removed: Vec<ChapterMetadata>,
+    /// Numbers of chapters that weren't downloaded.
+    pub(crate) new_chapters: Vec<String>,
+    /// Numbers of chapters updated after they were downloaded.
+    pub(crate) outdated_chapters: Vec<String>,
+    /// Numbers of chapters whose date was fixed.
+    pub(crate) fixed_dates: Vec<String>,
+    /// Numbers of downloaded chapters.
+    pub(crate) downloaded: Vec<String>,
+    /// Numbers of chapters that failed to download.
+    pub(crate) failed: Vec<String>,
+    /// Chapters reported by `--dry-run`.
+    pub(crate) planned: Vec<output::PlanReport>,
+}
+
+impl MangaState {
+    /// Starts looking at the chapters of a manga; whatever was left by the previous manga is dropped.
+    ///
+    /// # Arguments
+    /// * `chapters` - The chapters of the manga stored in the library.
+    pub(crate) fn start(chapters: &[ChapterMetadata]) {
+        MangaState::take();
+        let mut dates = CHAPTER_DATES.lock();
+        let mut ids = CHAPTER_IDS.lock();
+        dates.clear();
+        ids.clear();
+        for chapter in chapters.iter() {
+            dates.insert(chapter.number.clone(), chapter.updated_at.clone());
+            ids.insert(chapter.number.clone(), chapter.id.clone());
         }
     }
-    let mut json = match get_dat_content(dat_path.as_str()) {
-        Ok(value) => value,
-        Err(error) => {
-            return Err(error);
+
+    /// Takes what was found for the current manga.
+    pub(crate) fn take() -> MangaState {
+        MangaState {
+            chapters: std::mem::take(&mut *CHAPTERS.lock()),
+            removed: std::mem::take(&mut *CHAPTERS_TO_REMOVE.lock()),
+            new_chapters: std::mem::take(&mut *TO_DOWNLOAD.lock()),
+            outdated_chapters: std::mem::take(&mut *TO_DOWNLOAD_DATE.lock()),
+            fixed_dates: std::mem::take(&mut *FIXED_DATES.lock()),
+            downloaded: std::mem::take(&mut *DOWNLOADED.lock()),
+            failed: std::mem::take(&mut *FAILED.lock()),
+            planned: std::mem::take(&mut *PLANNED.lock()),
         }
-    };
+    }
+}
+
+/// Values of arguments that the preferences of a manga override while it is checked or updated.
+///
+/// They are restored when it is dropped, so the next manga, the next check of the daemon and queued downloads start from the arguments again, even when checking fails.
+struct Overrides {
+    saver: bool,
+    bundle: String,
+    image_format: String,
+    language: String,
+    group: Option<String>,
+}
+
+impl Overrides {
+    /// Saves the current values.
+    fn save() -> Overrides {
+        let saver = *SAVER.lock();
+        let bundle = ARGS.lock().bundle.clone();
+        let image_format = ARGS.lock().image_format.clone();
+        let language = LANGUAGE.lock().clone();
+        let group = PREFERRED_GROUP.lock().clone();
+        Overrides { saver, bundle, image_format, language, group }
+    }
 
-    json = match serde_json::from_value::<Dat>(json) {
+    /// Applies the preferences of a manga on top of the saved values.
+    ///
+    /// # Arguments
+    /// * `item` - The manga.
+    fn apply(&self, item: &MangaMetadata) {
+        let preferences = &item.preferences;
+        *LANGUAGE.lock() = item.current_language.clone();
+        *SAVER.lock() = preferences.saver.unwrap_or(self.saver);
+        *PREFERRED_GROUP.lock() = preferences.group.clone().or_else(|| self.group.clone());
+        let bundle = preferences.bundle.clone().unwrap_or_else(|| self.bundle.clone());
+        ARGS.lock().change("bundle", args::Value::Str(bundle));
+        let image_format = preferences.format.clone().unwrap_or_else(|| self.image_format.clone());
+        ARGS.lock().change("image_format", args::Value::Str(image_format));
+    }
+}
+
+impl Drop for Overrides {
+    fn drop(&mut self) {
+        *SAVER.lock() = self.saver;
+        ARGS.lock().change("bundle", args::Value::Str(self.bundle.clone()));
+        ARGS.lock().change("image_format", args::Value::Str(self.image_format.clone()));
+        *LANGUAGE.lock() = self.language.clone();
+        *PREFERRED_GROUP.lock() = self.group.clone();
+    }
+}
+
+pub(crate) async fn resolve_check() -> Result<(), MdownError> {
+    let table = output::is_table();
+    let mut records = output::Records::new();
+    // Chapters changed while the library is checked are found by the next check
+    let started = chrono::Utc::now().naive_utc().format("%Y-%m-%dT%H:%M:%S").to_string();
+    let checked_at = match *args::ARGS_FULL {
+        true => HashMap::new(),
+        false =>
+            match library::load_checked() {
+                Ok(checked_at) => checked_at,
+                Err(err) => {
+                    return Err(err);
+                }
+            }
+    };
+    // Manga found up to date are only remembered when all of their chapters were looked at
+    let remember = !*args::ARGS_DRY_RUN && {
+        let args = ARGS.lock();
+        getter::get_arg(&args.chapter) == "*" &&
+            getter::get_arg(&args.volume) == "*" &&
+            getter::get_arg(&args.offset) == "0"
+    };
+    let mut up_to_date = vec![];
+    // Folders of manga that chapters were downloaded to
+    let mut added = vec![];
+    let (checked, removed) = match library::load() {
         Ok(mut dat) => {
             let data = &mut dat.data;
             let mut iter: i32 = -1;
             let mut to_remove = vec![];
-            for item in data.iter_mut() {
+            let mut skipped = vec![];
+            let unchanged = find_unchanged(data, &checked_at).await;
+            let status = if *args::ARGS_CHECK {
+                "checked"
+            } else if *args::ARGS_DRY_RUN {
+                "planned"
+            } else {
+                "updated"
+            };
+            // Preferences of a manga are applied on top of the arguments
+            let overrides = Overrides::save();
+            // Several manga are fetched at once, while manga are checked and downloaded one at a time in order
+            let to_fetch: Vec<Option<String>> = data
+                .iter()
+                .map(|item| {
+                    match
+                        !item.paused &&
+                        !unchanged.contains(&item.id) &&
+                        Path::new(&item.mwd).is_dir()
+                    {
+                        true => Some(item.id.clone()),
+                        false => None,
+                    }
+                })
+                .collect();
+            let mut fetched = fetch_in_order(to_fetch, *args::ARGS_PARALLEL, |id| async move {
+                fetch_manga(&id).await
+            });
+            let mut index = 0;
+            while let Some(fetched_manga) = fetched.next().await {
+                if daemon::is_stopping() {
+                    // Manga that weren't checked are left as they are in the library
+                    for rest in index..data.len() {
+                        skipped.push(rest as i32);
+                    }
+                    break;
+                }
+                let item = &mut data[index];
+                index += 1;
                 *MUSIC_STAGE.lock() = String::from("init");
                 iter += 1;
                 let manga_name = item.name.clone();
-                println!("Checking {}\r", manga_name);
-                let past_mwd = match std::env::current_dir() {
-                    Ok(m) =>
-                        (
-                            match m.to_str() {
-                                Some(s) => s,
-                                None => {
-                                    return Err(
-                                        MdownError::ConversionError(
-                                            String::from("cwd conversion to string slice failed")
-                                        )
-                                    );
-                                }
-                            }
-                        ).to_string(),
-                    Err(err) => {
-                        return Err(MdownError::IoError(err, String::new()));
-                    }
-                };
+                output::status(&format!("Checking {}\r", manga_name));
                 let mwd: String = item.mwd.clone();
 
-                *LANGUAGE.lock() = item.current_language.clone();
-                if std::env::set_current_dir(&mwd).is_err() {
-                    println!("{} not found; deleting from database", &manga_name);
-                    to_remove.push(iter);
+                if unchanged.contains(&item.id) {
+                    // Nothing changed since the manga was last found up to date
+                    let mut report = output::CheckReport::new(item, status, None);
+                    report.up_to_date = true;
+                    match records.push(&report) {
+                        Ok(()) => (),
+                        Err(err) => {
+                            return Err(err);
+                        }
+                    }
+                    if *args::ARGS_CHECK && table {
+                        println!("Checked {} ({})", &manga_name, item.id);
+                        println!("Up to-date");
+                    }
+                    up_to_date.push(item.id.clone());
+                    skipped.push(iter);
                     continue;
                 }
 
-                match std::fs::rename(format!("{}\\.cache", past_mwd), format!("{}\\.cache", mwd)) {
-                    Ok(()) => (),
+                if item.paused {
+                    output::status(
+                        &format!("Skipping {}: paused; use database --resume to update it again", &manga_name)
+                    );
+                    let report = output::CheckReport::new(item, "skipped", Some(String::from("paused")));
+                    match records.push(&report) {
+                        Ok(()) => (),
+                        Err(err) => {
+                            return Err(err);
+                        }
+                    }
+                    skipped.push(iter);
+                    continue;
+                }
+
+                let _manga_lock = match lock_manga(&item.id) {
+                    Ok(utils::LockState::Acquired(guard)) => guard,
+                    Ok(utils::LockState::Held(owner)) => {
+                        let reason = format!(
+                            "being processed by process {} on {} started at {}",
+                            owner.pid,
+                            owner.host,
+                            owner.started
+                        );
+                        output::status(&format!("Skipping {}: {}", &manga_name, reason));
+                        let report = output::CheckReport::new(item, "skipped", Some(reason));
+                        match records.push(&report) {
+                            Ok(()) => (),
+                            Err(err) => {
+                                return Err(err);
+                            }
+                        }
+                        skipped.push(iter);
+                        continue;
+                    }
                     Err(err) => {
-                        eprintln!("Error: moving MWD from {} to {} {}", past_mwd, mwd, err);
+                        return Err(err);
                     }
+                };
+
+                *LAYOUT.lock() = item.layout.clone();
+                overrides.apply(item);
+                MangaState::start(&item.chapters);
+                if !Path::new(&mwd).is_dir() {
+                    // The folder may have been moved or be on a drive that isn't connected
+                    output::status(
+                        &format!(
+                            "Skipping {}: {} not found; use database --relocate if it was moved",
+                            &manga_name,
+                            &mwd
+                        )
+                    );
+                    let report = output::CheckReport::new(
+                        item,
+                        "skipped",
+                        Some(String::from("folder not found"))
+                    );
+                    match records.push(&report) {
+                        Ok(()) => (),
+                        Err(err) => {
+                            return Err(err);
+                        }
+                    }
+                    skipped.push(iter);
+                    continue;
                 }
+
                 let id = item.id.clone();
-                let cover_file = format!("{}\\_cover.png", mwd);
-                let mut cover = fs::metadata(cover_file).is_ok();
-                if let Ok(manga_name_json) = getter::get_manga_json(&id).await {
+                let mut cover = Path::new(&mwd).join("_cover.png").exists();
+                // Whether all chapters of the manga were fetched and looked at
+                let mut complete = false;
+                let (manga_name_json, feed) = match fetched_manga {
+                    Some(Ok(fetched_manga)) => {
+                        if !fetched_manga.dates.is_empty() {
+                            item.date = fetched_manga.dates;
+                        }
+                        (Some(fetched_manga.manga), fetched_manga.feed)
+                    }
+                    Some(Err(err)) => {
+                        handle_error!(&err, String::from("manga"));
+                        (None, None)
+                    }
+                    None => (None, None),
+                };
+                if let Some(manga_name_json) = manga_name_json {
                     match utils::get_json(&manga_name_json) {
                         Ok(obj) => {
                             let cover_data: &str = match
@@ -684,28 +1111,14 @@ pub(crate) async fn resolve_check() -> Result<(), MdownError> {
                                     );
                                 }
                             };
-                            let chapters_temp = item.chapters.clone();
 
-                            {
-                                let mut chapter_da = CHAPTER_DATES.lock();
-                                let mut chapter_id = CHAPTER_IDS.lock();
-                                for i in chapters_temp.iter() {
-                                    let number = i.number.clone();
-                                    let date = i.updated_at.clone();
-                                    let id = i.id.clone();
-                                    chapter_da.insert(number.clone(), date);
-                                    chapter_id.insert(number, id);
-                                }
-                            }
-
-                            if *args::ARGS_UPDATE && !cover {
-                                let folder = get_folder_name();
+                            if *args::ARGS_UPDATE && !cover && !*args::ARGS_DRY_RUN {
                                 *COVER.lock() = match
                                     download::download_cover(
                                         Arc::from("https://uploads.mangadex.org/"),
                                         Arc::from(id.as_str()),
                                         Arc::from(cover_data),
-                                        Arc::from(folder)
+                                        Arc::from(mwd.as_str())
                                     ).await
                                 {
                                     Ok(()) => {
@@ -719,8 +1132,11 @@ pub(crate) async fn resolve_check() -> Result<(), MdownError> {
                                 };
                             }
                             *MANGA_NAME.lock() = get_manga_name(title_data);
-                            match resolve_manga(&id, false).await {
-                                Ok(()) => (),
+                            let has_feed = feed.is_some();
+                            match resolve_feed(&id, feed, false, &mwd).await {
+                                Ok(()) => {
+                                    complete = has_feed;
+                                }
                                 Err(err) => {
                                     handle_error!(&err, String::from("manga"));
                                 }
@@ -734,55 +1150,95 @@ pub(crate) async fn resolve_check() -> Result<(), MdownError> {
                 if *args::ARGS_UPDATE {
                     item.cover = if !cover { *COVER.lock() } else { true };
                 }
-                let mut chapters_temp = item.chapters.clone();
-                {
-                    let chapters_remove = CHAPTERS_TO_REMOVE.lock();
-                    for i in chapters_remove.iter() {
-                        chapters_temp.retain(|value| {
-                            let number = value.number.clone();
-                            let date = value.updated_at.clone();
-                            let id = value.id.clone();
-                            ChapterMetadata { number, updated_at: date, id } != *i
-                        });
-                    }
-                }
-                let mut chapters = Vec::new();
-                for i in chapters_temp.iter() {
-                    let number = i.number.clone();
-                    let date = i.updated_at.clone();
-                    let id = i.id.clone();
-                    chapters.push(ChapterMetadata { number, updated_at: date, id });
-                }
-
-                for i in CHAPTERS.lock().iter() {
-                    if !chapters.contains(i) {
-                        chapters.push(i.clone());
+                let state = MangaState::take();
+                let mut chapters: Vec<ChapterMetadata> = item.chapters
+                    .iter()
+                    .filter(|chapter| !state.removed.contains(chapter))
+                    .cloned()
+                    .collect();
+                for chapter in state.chapters.iter() {
+                    if !chapters.contains(chapter) {
+                        chapters.push(chapter.clone());
                     }
                 }
                 item.chapters = chapters;
 
                 if item.chapters.is_empty() && !cover {
-                    println!("{} not found; deleting from database", &manga_name);
+                    output::status(&format!("{} not found; deleting from database", &manga_name));
+                    let report = output::CheckReport::new(
+                        item,
+                        "removed",
+                        Some(String::from("no chapters or cover found"))
+                    );
+                    match records.push(&report) {
+                        Ok(()) => (),
+                        Err(err) => {
+                            return Err(err);
+                        }
+                    }
                     to_remove.push(iter);
                     continue;
                 }
 
-                if *args::ARGS_CHECK {
+                let mut report = output::CheckReport::new(item, status, None);
+                report.cover = cover;
+                report.new_chapters = state.new_chapters.clone();
+                report.outdated_chapters = state.outdated_chapters.clone();
+                report.fixed_dates = state.fixed_dates.clone();
+                report.downloaded_chapters = state.downloaded.clone();
+                report.planned = state.planned.clone();
+                report.up_to_date =
+                    report.new_chapters.is_empty() && report.outdated_chapters.is_empty() && cover;
+                for chapter in report.new_chapters.iter() {
+                    hooks::fire(hooks::Event {
+                        manga_id: item.id.clone(),
+                        manga_name: manga_name.clone(),
+                        chapter: chapter.clone(),
+                        chapter_id: CHAPTER_IDS.lock().get(chapter).cloned().unwrap_or_default(),
+                        ..hooks::Event::new("new-chapter")
+                    });
+                }
+                if !report.downloaded_chapters.is_empty() {
+                    added.push(item.mwd.clone());
+                    hooks::fire(hooks::Event {
+                        manga_id: item.id.clone(),
+                        manga_name: manga_name.clone(),
+                        chapters: report.downloaded_chapters.clone(),
+                        ..hooks::Event::new("manga-finished")
+                    });
+                }
+                // Updates are only remembered when no chapter failed to download and the update wasn't stopped
+                let updated =
+                    *args::ARGS_UPDATE && cover && state.failed.is_empty() && !daemon::is_stopping();
+                if ((*args::ARGS_CHECK && report.up_to_date) || updated) && complete {
+                    up_to_date.push(item.id.clone());
+                }
+                match records.push(&report) {
+                    Ok(()) => (),
+                    Err(err) => {
+                        return Err(err);
+                    }
+                }
+
+                if *args::ARGS_DRY_RUN && *args::ARGS_UPDATE && table {
+                    print_plan(&manga_name, &item.id, &state.planned);
+                }
+                if *args::ARGS_CHECK && table {
                     println!("Checked {} ({})", &manga_name, item.id);
                     let to_dow;
-                    if !TO_DOWNLOAD.lock().is_empty() || !TO_DOWNLOAD_DATE.lock().is_empty() {
+                    if !state.new_chapters.is_empty() || !state.outdated_chapters.is_empty() {
                         to_dow = true;
                         println!("Chapters available");
-                        for chapter in TO_DOWNLOAD.lock().iter() {
+                        for chapter in state.new_chapters.iter() {
                             println!(" {}", chapter);
                         }
-                        for chapter in TO_DOWNLOAD_DATE.lock().iter() {
+                        for chapter in state.outdated_chapters.iter() {
                             println!(" {} (OUTDATED CHAPTER)", chapter);
                         }
-                    } else if !FIXED_DATES.lock().is_empty() {
+                    } else if !state.fixed_dates.is_empty() {
                         to_dow = false;
                         println!("Chapters ERROR");
-                        for date in FIXED_DATES.lock().iter() {
+                        for date in state.fixed_dates.iter() {
                             println!(" {} (CORRUPT DATE) (FIXED)", date);
                         }
                     } else {
@@ -795,207 +1251,172 @@ pub(crate) async fn resolve_check() -> Result<(), MdownError> {
                         println!("Up to-date");
                     }
                 }
-                CHAPTERS.lock().clear();
-                TO_DOWNLOAD.lock().clear();
-                TO_DOWNLOAD_DATE.lock().clear();
-                FIXED_DATES.lock().clear();
             }
             *MUSIC_STAGE.lock() = String::from("end");
             *MUSIC_END.lock() = true;
-            for &index in to_remove.iter().rev() {
-                data.remove(index as usize);
-            }
-            match serde_json::to_value(dat) {
-                Ok(value) => value,
-                Err(err) => {
-                    return Err(MdownError::JsonError(err.to_string()));
+            drop(overrides);
+            let mut checked = Vec::new();
+            let mut removed = Vec::new();
+            for (index, item) in data.drain(..).enumerate() {
+                if to_remove.contains(&(index as i32)) {
+                    removed.push(item.id);
+                } else if !skipped.contains(&(index as i32)) {
+                    checked.push(item);
                 }
             }
+            (checked, removed)
         }
         Err(err) => {
-            return Err(MdownError::JsonError(err.to_string()));
-        }
-    };
-
-    let mut file = match File::create(&dat_path) {
-        Ok(path) => path,
-        Err(err) => {
-            return Err(MdownError::IoError(err, dat_path));
-        }
-    };
-
-    let json_string = match serde_json::to_string_pretty(&json) {
-        Ok(value) => value,
-        Err(err) => {
-            return Err(MdownError::JsonError(err.to_string()));
+            return Err(err);
         }
     };
 
-    if let Err(err) = writeln!(file, "{}", json_string) {
-        return Err(MdownError::IoError(err, dat_path));
+    // A dry run leaves the library as it is
+    if *args::ARGS_DRY_RUN {
+        records.finish();
+        return Ok(());
     }
-    Ok(())
-}
 
-pub(crate) fn resolve_dat() -> Result<(), MdownError> {
-    let dat_path = match getter::get_dat_path() {
-        Ok(path) => path,
+    // Only checked manga are stored; other processes may have changed the library in the meantime
+    match library::save(&checked, &removed) {
+        Ok(()) => (),
         Err(err) => {
             return Err(err);
         }
-    };
-    if fs::metadata(&dat_path).is_err() {
-        let mut file = match fs::File::create(&dat_path) {
-            Ok(file) => file,
+    }
+    if remember {
+        match library::save_checked(&up_to_date, &started) {
+            Ok(()) => (),
             Err(err) => {
-                return Err(MdownError::IoError(err, dat_path));
+                return Err(err);
             }
-        };
-
-        let content = format!(
-            "{{\n  \"data\": [],\n  \"version\": \"{}\"\n}}",
-            get_current_version()
-        );
-
-        match file.write_all(content.as_bytes()) {
-            Ok(()) => (),
-            Err(_err) => (),
-        };
-    }
-    let mut json = match get_dat_content(dat_path.as_str()) {
-        Ok(value) => value,
-        Err(err) => {
-            return Err(err);
         }
-    };
-
-    json = match serde_json::from_value::<Dat>(json.clone()) {
-        Ok(mut dat) => {
-            let data = &mut dat.data;
+    }
+    scan::scan(&added).await;
+    records.finish();
+    Ok(())
+}
 
-            let manga_names: Vec<String> = data
+/// Number of manga whose changed chapters are fetched with one request.
+const CHANGES_BATCH: usize = 100;
+
+/// Finds manga that were up to date at their last check and whose chapters in their language didn't change since.
+///
+/// Changed chapters of many manga are fetched at once, so only manga with changes are checked with all of their chapters.
+///
+/// # Arguments
+/// * `data` - The manga of the library.
+/// * `checked_at` - When manga were last found up to date, by their IDs.
+///
+/// # Returns
+/// * `HashSet<String>` - The IDs of the manga that are still up to date; manga whose changes can't be fetched aren't included.
+async fn find_unchanged(
+    data: &[MangaMetadata],
+    checked_at: &HashMap<String, String>
+) -> HashSet<String> {
+    // Manga found up to date by the same check are asked for together
+    let mut by_time: BTreeMap<&str, Vec<&MangaMetadata>> = BTreeMap::new();
+    for item in data.iter() {
+        if let Some(time) = checked_at.get(&item.id) {
+            if !item.paused && Path::new(&item.mwd).join("_cover.png").exists() {
+                by_time.entry(time.as_str()).or_default().push(item);
+            }
+        }
+    }
+    let mut unchanged = HashSet::new();
+    for (since, items) in by_time.iter() {
+        for batch in items.chunks(CHANGES_BATCH) {
+            let ids: Vec<String> = batch
                 .iter()
-                .map(|item| item.name.clone())
+                .map(|item| item.id.clone())
                 .collect();
-            if data.is_empty() || !manga_names.contains(&MANGA_NAME.lock().clone()) {
-                let mwd = format!("{}", MWD.lock());
-                let cover = COVER.lock();
-                let mut chapters = Vec::new();
-                let chapters_data: Vec<ChapterMetadata> = CHAPTERS.lock().clone();
-                for i in chapters_data.iter() {
-                    chapters.push(match serde_json::to_value(i) {
-                        Ok(v) => v,
-                        Err(err) => {
-                            return Err(MdownError::JsonError(err.to_string()));
-                        }
-                    });
-                }
-                let mut genres = Vec::new();
-                let genres_data = GENRES.lock().clone();
-                for i in genres_data.iter() {
-                    genres.push(match serde_json::to_value(i) {
-                        Ok(v) => v,
-                        Err(err) => {
-                            return Err(MdownError::JsonError(err.to_string()));
-                        }
-                    });
+            let changes = match getter::get_changed_chapters(&ids, since).await {
+                Ok(changes) => changes,
+                Err(err) => {
+                    suspend_error(err);
+                    continue;
                 }
-                let mut themes = Vec::new();
-                let themes_data = THEMES.lock().clone();
-                for i in themes_data.iter() {
-                    themes.push(match serde_json::to_value(i) {
-                        Ok(v) => v,
-                        Err(err) => {
-                            return Err(MdownError::JsonError(err.to_string()));
-                        }
+            };
+            for item in batch.iter() {
+                let changed = changes
+                    .iter()
+                    .any(|(id, language)| {
+                        *id == item.id &&
+                            (item.current_language == "*" || *language == item.current_language)
                     });
+                if !changed {
+                    unchanged.insert(item.id.clone());
                 }
-                let manga_data = MangaMetadata {
-                    name: MANGA_NAME.lock().clone(),
-                    id: MANGA_ID.lock().clone(),
-                    chapters: chapters_data,
-                    mwd,
-                    cover: *cover,
+            }
+        }
+    }
+    unchanged
+}
+
+/// Locks a manga so that only one process downloads or checks it at a time.
+///
+/// # Arguments
+/// * `id` - The ID of the manga.
+///
+/// # Returns
+/// * `Ok(utils::LockState)` - Whether the manga was locked or is being processed by another process.
+/// * `Err(MdownError)` - If the lock file can't be created.
+pub(crate) fn lock_manga(id: &str) -> Result<utils::LockState, MdownError> {
+    match getter::get_lock_path(id) {
+        Ok(path) => utils::acquire_lock(&path),
+        Err(err) => Err(err),
+    }
+}
+
+pub(crate) fn resolve_dat() -> Result<(), MdownError> {
+    let manga_name = MANGA_NAME.lock().clone();
+    let manga_id = MANGA_ID.lock().clone();
+    library::update_manga(&manga_id, &manga_name, |stored| {
+        let mut item = match stored {
+            Some(item) => item,
+            None => {
+                return Ok(MangaMetadata {
+                    name: manga_name.clone(),
+                    id: manga_id.clone(),
+                    chapters: CHAPTERS.lock().clone(),
+                    mwd: MWD.lock().clone(),
+                    cover: *COVER.lock(),
                     date: DATE_FETCHED.lock().clone(),
                     available_languages: LANGUAGES.lock().clone(),
                     current_language: LANGUAGE.lock().clone(),
-                    theme: themes_data,
-                    genre: genres_data,
-                };
-
-                data.push(manga_data);
-            } else {
-                for chap_data in data.iter_mut() {
-                    let name = &chap_data.name;
-                    if name == MANGA_NAME.lock().as_str() {
-                        let existing_chapters = &mut chap_data.chapters;
-
-                        let mut existing_chapters_temp = Vec::new();
-
-                        for i in existing_chapters.iter_mut() {
-                            let number = &i.number;
-                            existing_chapters_temp.push(number);
-                        }
-
-                        let mut new_chapters: Vec<_> = CHAPTERS.lock()
-                            .iter()
-                            .filter(|&chapter| {
-                                let number = chapter.number.clone();
-                                !existing_chapters_temp.contains(&&number)
-                            })
-                            .cloned()
-                            .collect();
-
-                        new_chapters.sort_by(|a, b| {
-                            let a_num = match a.number.parse::<u32>() {
-                                Ok(value) => value,
-                                Err(_err) => 0,
-                            };
-                            let b_num = match b.number.parse::<u32>() {
-                                Ok(value) => value,
-                                Err(_err) => 0,
-                            };
-                            a_num.cmp(&b_num)
-                        });
-
-                        for i in new_chapters.iter() {
-                            existing_chapters.push(i.clone());
-                        }
-
-                        break;
-                    }
-                }
-            }
-            match serde_json::to_value(dat) {
-                Ok(json) => json,
-                Err(err) => {
-                    return Err(MdownError::JsonError(err.to_string()));
-                }
+                    theme: THEMES.lock().clone(),
+                    genre: GENRES.lock().clone(),
+                    layout: get_layout(),
+                    paused: false,
+                    preferences: PreferencesMetadata::default(),
+                });
             }
-        }
-        Err(err) => {
-            return Err(MdownError::JsonError(err.to_string()));
-        }
-    };
+        };
 
-    let mut file = match File::create(&dat_path) {
-        Ok(file) => file,
-        Err(err) => {
-            return Err(MdownError::IoError(err, dat_path));
-        }
-    };
+        let mut new_chapters: Vec<_> = CHAPTERS.lock()
+            .iter()
+            .filter(|&chapter| {
+                !item.chapters.iter().any(|existing| existing.number == chapter.number)
+            })
+            .cloned()
+            .collect();
 
-    let json_string = match serde_json::to_string_pretty(&json) {
-        Ok(value) => value,
-        Err(err) => {
-            return Err(MdownError::JsonError(err.to_string()));
-        }
-    };
+        new_chapters.sort_by(|a, b| {
+            let a_num = match a.number.parse::<u32>() {
+                Ok(value) => value,
+                Err(_err) => 0,
+            };
+            let b_num = match b.number.parse::<u32>() {
+                Ok(value) => value,
+                Err(_err) => 0,
+            };
+            a_num.cmp(&b_num)
+        });
 
-    if let Err(err) = writeln!(file, "{}", json_string) {
-        return Err(MdownError::JsonError(err.to_string()));
-    }
-    Ok(())
+        item.chapters.extend(new_chapters);
+        item.layout = get_layout();
+        Ok(item)
+    })
 }
 
 pub(crate) fn get_dat_content(dat_path: &str) -> Result<Value, MdownError> {
@@ -1014,9 +1435,31 @@ pub(crate) fn get_dat_content(dat_path: &str) -> Result<Value, MdownError> {
 }
 
 pub(crate) async fn resolve(obj: Map<String, Value>, id: &str) -> Result<String, MdownError> {
+    let _manga_lock = match lock_manga(id) {
+        Ok(utils::LockState::Acquired(guard)) => guard,
+        Ok(utils::LockState::Held(owner)) => {
+            return Err(
+                MdownError::CustomError(
+                    format!(
+                        "{} is being downloaded by process {} on {} started at {}",
+                        id,
+                        owner.pid,
+                        owner.host,
+                        owner.started
+                    ),
+                    String::from("LockError")
+                )
+            );
+        }
+        Err(err) => {
+            return Err(err);
+        }
+    };
     let handle_id = utils::generate_random_id(16);
     *HANDLE_ID.lock() = handle_id.clone();
     debug!("handle id set to {}", handle_id);
+    // Chapters of the manga downloaded before are found again from its files
+    MangaState::start(&[]);
     let title_data = match obj.get("data").and_then(|name_data| name_data.get("attributes")) {
         Some(value) => value,
         None => {
@@ -1033,6 +1476,7 @@ pub(crate) async fn resolve(obj: Map<String, Value>, id: &str) -> Result<String,
     };
     debug!("manga name set to {}", manga_name);
     *MANGA_NAME.lock() = manga_name.clone();
+    *LAYOUT.lock() = get_layout_from_dat(id);
     let folder = get_folder_name();
 
     {
@@ -1112,7 +1556,8 @@ pub(crate) async fn resolve(obj: Map<String, Value>, id: &str) -> Result<String,
 
     debug!("is there folder with same name: {}", was_rewritten);
 
-    if !was_rewritten {
+    // A dry run only reports chapters, so nothing is written
+    if !was_rewritten && !*args::ARGS_DRY_RUN {
         match fs::create_dir(folder) {
             Ok(()) => (),
             Err(err) => {
@@ -1123,7 +1568,11 @@ pub(crate) async fn resolve(obj: Map<String, Value>, id: &str) -> Result<String,
         }
         debug!("created directory {}", folder);
     }
-    *MWD.lock() = match std::fs::canonicalize(folder) {
+    let mwd = match *args::ARGS_DRY_RUN && !was_rewritten {
+        true => std::path::absolute(folder),
+        false => std::fs::canonicalize(folder),
+    };
+    *MWD.lock() = match mwd {
         Ok(value) =>
             match value.to_str() {
                 Some(value) => {
@@ -1142,41 +1591,20 @@ pub(crate) async fn resolve(obj: Map<String, Value>, id: &str) -> Result<String,
             return Err(MdownError::IoError(err, folder.to_string()));
         }
     };
-    {
+    if !*args::ARGS_DRY_RUN {
         let desc = title_data
             .get("description")
             .and_then(|description| description.get("en"))
             .and_then(Value::as_str)
             .unwrap_or_default();
 
-        let mut desc_file = if args::ARGS.lock().update {
-            match
-                OpenOptions::new()
-                    .read(true)
-                    .write(true)
-                    .create(true)
-                    .truncate(true)
-                    .open("_description.txt")
-            {
-                Ok(file) => file,
-                Err(err) => {
-                    return Err(
-                        MdownError::IoError(err, format!("{}\\_description.txt", MWD.lock()))
-                    );
-                }
-            }
-        } else {
-            match
-                OpenOptions::new()
-                    .read(true)
-                    .write(true)
-                    .create(true)
-                    .open(format!("{}\\_description.txt", folder))
-            {
-                Ok(file) => file,
-                Err(err) => {
-                    return Err(MdownError::IoError(err, format!("{}\\_description.txt", folder)));
-                }
+        let desc_path = Path::new(&folder).join("_description.txt");
+        let mut desc_file = match
+            OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&desc_path)
+        {
+            Ok(file) => file,
+            Err(err) => {
+                return Err(MdownError::IoError(err, desc_path.to_string_lossy().to_string()));
             }
         };
 
@@ -1228,7 +1656,7 @@ pub(crate) async fn resolve(obj: Map<String, Value>, id: &str) -> Result<String,
         *THEMES.lock() = theme;
     }
 
-    {
+    if !*args::ARGS_DRY_RUN {
         let cover = obj
             .get("data")
             .and_then(|name_data| name_data.get("relationships"))
@@ -1267,7 +1695,7 @@ pub(crate) async fn resolve(obj: Map<String, Value>, id: &str) -> Result<String,
         }
     }
 
-    if ARGS.lock().stat {
+    if ARGS.lock().stat && !*args::ARGS_DRY_RUN {
         debug!("starting downloading stat");
         match download::download_stat(id, &manga_name).await {
             Ok(()) => (),
@@ -1299,6 +1727,17 @@ pub(crate) async fn resolve(obj: Map<String, Value>, id: &str) -> Result<String,
         }
     }
     log_end(handle_id);
+    let downloaded = DOWNLOADED.lock().clone();
+    if !downloaded.is_empty() {
+        let mwd = MWD.lock().clone();
+        scan::scan(&[mwd]).await;
+        hooks::fire(hooks::Event {
+            manga_id: id.to_string(),
+            manga_name: manga_name.clone(),
+            chapters: downloaded,
+            ..hooks::Event::new("manga-finished")
+        });
+    }
     if
         *args::ARGS_WEB ||
         *args::ARGS_GUI ||
@@ -1310,7 +1749,6 @@ pub(crate) async fn resolve(obj: Map<String, Value>, id: &str) -> Result<String,
     }
     *DOWNLOADING.lock() = false;
     *MUSIC_STAGE.lock() = String::from("end");
-    CHAPTERS.lock().clear();
     MANGA_ID.lock().clear();
     CURRENT_CHAPTER.lock().clear();
     *CURRENT_PAGE.lock() = 0;
@@ -1357,19 +1795,55 @@ pub(crate) async fn resolve_group(
     Ok(metadata::ScanlationMetadata { name, website })
 }
 
+/// Resolves the name of the scanlation group of a chapter, e.g. for the name template.
+///
+/// Names of groups that were already resolved are taken from `SCANLATION_GROUPS`.
+///
+/// # Returns
+/// * `String` - The name of the group, or an empty string if the chapter has no known group.
+pub(crate) async fn resolve_group_name(array_item: &metadata::ChapterResponse) -> String {
+    if let Some(id) = get_scanlation_group(&array_item.relationships) {
+        if let Some(name) = SCANLATION_GROUPS.lock().get(&id) {
+            return name.clone();
+        }
+    }
+    match resolve_group(array_item).await {
+        Ok(scanlation_group) if scanlation_group.name != "null" => scanlation_group.name,
+        Ok(_scanlation_group) => String::new(),
+        Err(err) => {
+            suspend_error(err);
+            String::new()
+        }
+    }
+}
+
+/// Returns whether a chapter was released by a scanlation group.
+///
+/// # Arguments
+/// * `array_item` - The chapter.
+/// * `group` - The ID or name of the group; names are compared ignoring case.
+///
+/// # Returns
+/// * `bool` - Whether the chapter was released by the group.
+pub(crate) async fn is_from_group(array_item: &metadata::ChapterResponse, group: &str) -> bool {
+    if get_scanlation_group(&array_item.relationships).as_deref() == Some(group) {
+        return true;
+    }
+    // Names are only looked up when the group isn't given by its ID
+    !utils::is_valid_uuid(group) &&
+        resolve_group_name(array_item).await.to_lowercase() == group.to_lowercase()
+}
+
 pub(crate) fn get_scanlation_group_to_file(
-    scanlation: &metadata::ScanlationMetadata
+    scanlation: &metadata::ScanlationMetadata,
+    folder: &str
 ) -> Result<(), MdownError> {
     let name = &scanlation.name;
     let website = &scanlation.website;
     if name == "null" {
         return Ok(());
     }
-    let file_name = if ARGS.lock().update {
-        String::from("_scanlation_groups.txt")
-    } else {
-        format!("{}\\_scanlation_groups.txt", get_folder_name())
-    };
+    let file_name = Path::new(folder).join("_scanlation_groups.txt").to_string_lossy().to_string();
 
     let mut file_inst = match OpenOptions::new().create(true).append(true).open(&file_name) {
         Ok(file_inst) => file_inst,
@@ -1387,7 +1861,7 @@ pub(crate) fn get_scanlation_group_to_file(
 
 pub(crate) async fn resolve_group_metadata(id: &str) -> Result<(String, String), MdownError> {
     let base_url = "https://api.mangadex.org/group/";
-    let full_url = format!("{}\\{}", base_url, id);
+    let full_url = format!("{}{}", base_url, id);
 
     debug!("sending request to: {}", full_url);
 
@@ -1453,30 +1927,130 @@ pub(crate) async fn resolve_group_metadata(id: &str) -> Result<(String, String),
 
 async fn resolve_manga(id: &str, was_rewritten: bool) -> Result<(), MdownError> {
     debug!("\nresolve_manga");
+    let feed = match get_feed(id).await {
+        Ok((json, dates)) => {
+            *DATE_FETCHED.lock() = dates;
+            Some(json)
+        }
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            None
+        }
+    };
+    resolve_feed(id, feed, was_rewritten, get_folder_name()).await
+}
+
+/// Fetches the chapters of a manga, starting at `--database-offset`.
+///
+/// # Arguments
+/// * `id` - The ID of the manga.
+///
+/// # Returns
+/// * `Result<(String, Vec<String>), MdownError>` - The chapters as JSON and the times they were fetched, or an `MdownError` if they can't be fetched.
+async fn get_feed(id: &str) -> Result<(String, Vec<String>), MdownError> {
     let going_offset: u32 = match ARGS.lock().database_offset.as_str().parse() {
         Ok(offset) => offset,
         Err(err) => {
             return Err(MdownError::ConversionError(err.to_string()));
         }
     };
+    match get_manga(id, going_offset).await {
+        Ok((json, _offset, dates)) => Ok((json, dates)),
+        Err(err) => Err(err),
+    }
+}
+
+/// A manga fetched before it is checked.
+struct FetchedManga {
+    /// The manga as JSON.
+    manga: String,
+    /// The chapters as JSON; `None` if they couldn't be fetched.
+    feed: Option<String>,
+    /// Times the chapters were fetched, in format %Y-%m-%d %H:%M:%S.
+    dates: Vec<String>,
+}
+
+/// Fetches manga several at once, while handing them out one at a time in the order they were asked for.
+///
+/// # Arguments
+/// * `ids` - The IDs of the manga; `None` isn't fetched.
+/// * `parallel` - The number of manga fetched at once.
+/// * `fetch` - Fetches a manga.
+fn fetch_in_order<F, Fut, T>(
+    ids: Vec<Option<String>>,
+    parallel: usize,
+    fetch: F
+) -> impl Stream<Item = Option<T>>
+    where F: Fn(String) -> Fut, Fut: Future<Output = T>
+{
+    stream::iter(ids)
+        .map(move |id| {
+            let fetching = id.map(&fetch);
+            async move {
+                match fetching {
+                    Some(fetching) => Some(fetching.await),
+                    None => None,
+                }
+            }
+        })
+        .buffered(parallel)
+}
+
+/// Fetches a manga and its chapters before it is checked; several manga are fetched at once.
+///
+/// # Arguments
+/// * `id` - The ID of the manga.
+///
+/// # Returns
+/// * `Result<FetchedManga, MdownError>` - The manga and its chapters, or an `MdownError` if the manga can't be fetched; chapters that can't be fetched are `None`.
+async fn fetch_manga(id: &str) -> Result<FetchedManga, MdownError> {
+    let manga_json = match getter::get_manga_json(id).await {
+        Ok(json) => json,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    let (feed, dates) = match get_feed(id).await {
+        Ok((json, dates)) => (Some(json), dates),
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            (None, Vec::new())
+        }
+    };
+    Ok(FetchedManga { manga: manga_json, feed, dates })
+}
+
+/// Downloads the chapters of a manga from its fetched chapters.
+///
+/// # Arguments
+/// * `id` - The ID of the manga.
+/// * `feed` - The chapters as JSON; `None` if they couldn't be fetched.
+/// * `was_rewritten` - Whether the folder of the manga existed before.
+/// * `folder` - The folder chapters are saved to.
+///
+/// # Returns
+/// * `Result<(), MdownError>` - Returns `Ok(())` when the chapters were handled, or an `MdownError` if downloading failed.
+async fn resolve_feed(
+    id: &str,
+    feed: Option<String>,
+    was_rewritten: bool,
+    folder: &str
+) -> Result<(), MdownError> {
     let arg_force = ARGS.lock().force;
     let downloaded: &mut Vec<String> = &mut vec![];
     *MANGA_ID.lock() = id.to_owned();
-    match get_manga(id, going_offset).await {
-        Ok((json, _offset)) => {
-            clear_screen(1);
-            let downloaded_temp = match download_manga(json, arg_force).await {
-                Ok(value) => value,
-                Err(err) => {
-                    return Err(err);
-                }
-            };
-            for i in &downloaded_temp {
-                downloaded.push(i.clone());
+    if let Some(json) = feed {
+        clear_screen(1);
+        let downloaded_temp = match download_manga(json, arg_force, folder).await {
+            Ok(value) => value,
+            Err(err) => {
+                return Err(err);
             }
-            clear_screen(1);
+        };
+        for i in &downloaded_temp {
+            downloaded.push(i.clone());
         }
-        Err(err) => eprintln!("Error: {}", err),
+        clear_screen(1);
     }
     if !*args::ARGS_WEB && !*args::ARGS_GUI && !*args::ARGS_CHECK && !*args::ARGS_UPDATE {
         if !downloaded.is_empty() {
@@ -1484,8 +2058,8 @@ async fn resolve_manga(id: &str, was_rewritten: bool) -> Result<(), MdownError>
             for i in 0..downloaded.len() {
                 resolve_move(i as u32, downloaded, 2, 1);
             }
-        } else if !was_rewritten {
-            match remove_dir_all(get_folder_name()) {
+        } else if !was_rewritten && !*args::ARGS_DRY_RUN {
+            match remove_dir_all(folder) {
                 Ok(()) => (),
                 Err(err) => eprintln!("Error: remove directory {}", err),
             };
@@ -1526,9 +2100,100 @@ pub(crate) fn title(mut title: String) -> String {
     title
 }
 
+/// Records what `--dry-run` would do with a chapter; without `--dry-run` nothing is recorded.
+///
+/// # Arguments
+/// * `filename` - The names of the chapter.
+/// * `action` - `download`, `redownload` or `skip`.
+/// * `reason` - Why the chapter would be downloaded again or skipped.
+pub(crate) fn plan(filename: &utils::FileName, action: &str, reason: Option<&str>) {
+    if !*args::ARGS_DRY_RUN {
+        return;
+    }
+    PLANNED.lock().push(output::PlanReport {
+        id: filename.id.clone(),
+        volume: filename.vol.trim().trim_start_matches("Vol.").to_string(),
+        chapter: filename.chapter_num.clone(),
+        title: filename.title.clone(),
+        language: filename.lang.clone(),
+        action: action.to_string(),
+        reason: reason.map(String::from),
+    });
+}
+
+/// Prints what `--dry-run` would do with the chapters of a manga.
+///
+/// # Arguments
+/// * `manga_name` - The name of the manga.
+/// * `id` - The ID of the manga.
+/// * `planned` - What would be done with each chapter.
+pub(crate) fn print_plan(manga_name: &str, id: &str, planned: &[output::PlanReport]) {
+    println!("Planned {} ({})", manga_name, id);
+    if planned.is_empty() {
+        println!(" No chapters found");
+    }
+    for plan in planned.iter() {
+        println!("{}", plan.line());
+    }
+}
+
 pub(crate) fn resolve_skip(arg: &str, with: &str) -> bool {
     if arg == "*" || arg == with {
         return false;
     }
     true
 }
+
+// Fetches several manga at once and hands them out in the order they were asked for
+#[tokio::test]
+async fn test_fetch_in_order() {
+    use std::sync::atomic::{ AtomicUsize, Ordering };
+
+    let running = Arc::new(AtomicUsize::new(0));
+    let most = Arc::new(AtomicUsize::new(0));
+    let ids = vec![
+        Some(String::from("30")),
+        None,
+        Some(String::from("20")),
+        Some(String::from("10")),
+        Some(String::from("0"))
+    ];
+    let fetched: Vec<Option<String>> = fetch_in_order(ids, 2, |id| {
+        let running = running.clone();
+        let most = most.clone();
+        async move {
+            let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+            most.fetch_max(now, Ordering::SeqCst);
+            // Earlier manga take longer, so they would end last if the order wasn't kept
+            let wait = id.parse::<u64>().unwrap();
+            tokio::time::sleep(std::time::Duration::from_millis(wait)).await;
+            running.fetch_sub(1, Ordering::SeqCst);
+            id
+        }
+    }).collect().await;
+    assert_eq!(fetched, [
+        Some(String::from("30")),
+        None,
+        Some(String::from("20")),
+        Some(String::from("10")),
+        Some(String::from("0")),
+    ]);
+    assert_eq!(most.load(Ordering::SeqCst), 2);
+}
+
+// Drops what the previous manga left and seeds the chapters of the next one
+#[test]
+fn test_manga_state() {
+    CHAPTERS.lock().push(ChapterMetadata::new("1", "2024-01-01T00:00:00+00:00", "c1"));
+    DOWNLOADED.lock().push(String::from("1"));
+    MangaState::start(&[ChapterMetadata::new("2", "2024-02-01T00:00:00+00:00", "c2")]);
+    assert!(CHAPTERS.lock().is_empty());
+    assert!(DOWNLOADED.lock().is_empty());
+    assert_eq!(CHAPTER_IDS.lock().get("2").map(String::as_str), Some("c2"));
+    assert!(CHAPTER_DATES.lock().get("1").is_none());
+
+    TO_DOWNLOAD.lock().push(String::from("2"));
+    let state = MangaState::take();
+    assert_eq!(state.new_chapters, ["2"]);
+    assert!(TO_DOWNLOAD.lock().is_empty());
+}
@@ -0,0 +1,321 @@
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use serde_json::{ json, Value };
+use std::{ path::Path, time::Duration };
+
+use crate::{ args, debug, download, error::MdownError, handle_error, log, metadata::ScanServerMetadata };
+
+/// Longest time a request to the server is waited for.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+lazy_static! {
+    /// The server asked to scan folders of manga, as set with `settings --scan-server`.
+    pub(crate) static ref SERVER: Mutex<Option<ScanServerMetadata>> = Mutex::new(None);
+}
+
+/// Reads the values of `settings --scan-server`.
+///
+/// # Arguments
+/// * `values` - The kind of the server, its URL and its API key.
+///
+/// # Returns
+/// * `Result<ScanServerMetadata, MdownError>` - Returns the server, or an `MdownError` if a value is missing or not valid.
+pub(crate) fn parse_server(values: &[String]) -> Result<ScanServerMetadata, MdownError> {
+    let (kind, url, key) = match values {
+        [kind, url, key] => (kind.to_lowercase(), url.trim_end_matches('/').to_string(), key.clone()),
+        _ => {
+            return Err(
+                MdownError::CustomError(
+                    String::from("scan server needs KIND, URL and API_KEY"),
+                    String::from("Scan")
+                )
+            );
+        }
+    };
+    if kind != "komga" && kind != "kavita" {
+        return Err(
+            MdownError::CustomError(
+                format!("unknown scan server '{}'; use komga or kavita", kind),
+                String::from("Scan")
+            )
+        );
+    }
+    match reqwest::Url::parse(&url) {
+        Ok(parsed) if parsed.scheme() == "http" || parsed.scheme() == "https" => (),
+        _ => {
+            return Err(
+                MdownError::CustomError(
+                    format!("'{}' is not an http or https URL", url),
+                    String::from("Scan")
+                )
+            );
+        }
+    }
+    Ok(ScanServerMetadata { kind, url, key })
+}
+
+/// Checks the response of the server.
+///
+/// # Arguments
+/// * `response` - The response or the error of the request.
+///
+/// # Returns
+/// * `Result<reqwest::Response, MdownError>` - Returns the successful response, or an `MdownError` if the request failed.
+fn check_response(
+    response: Result<reqwest::Response, reqwest::Error>
+) -> Result<reqwest::Response, MdownError> {
+    match response {
+        Ok(response) if response.status().is_success() => Ok(response),
+        Ok(response) => Err(MdownError::StatusError(response.status())),
+        Err(err) => Err(MdownError::NetworkError(err)),
+    }
+}
+
+/// Asks Komga to scan the libraries holding the folders; Komga only scans whole libraries.
+///
+/// # Arguments
+/// * `client` - The client sending requests.
+/// * `server` - The server.
+/// * `paths` - The folders of manga.
+///
+/// # Returns
+/// * `Result<(), MdownError>` - Returns `Ok(())` if all scans were started, or an `MdownError` if a request failed or a folder isn't in a library.
+async fn scan_komga(
+    client: &reqwest::Client,
+    server: &ScanServerMetadata,
+    paths: &[String]
+) -> Result<(), MdownError> {
+    let response = check_response(
+        client
+            .get(format!("{}/api/v1/libraries", server.url))
+            .header("X-API-Key", &server.key)
+            .timeout(TIMEOUT)
+            .send().await
+    );
+    let libraries = match response {
+        Ok(response) =>
+            match response.json::<Vec<Value>>().await {
+                Ok(libraries) => libraries,
+                Err(err) => {
+                    return Err(MdownError::JsonError(err.to_string()));
+                }
+            }
+        Err(err) => {
+            return Err(err);
+        }
+    };
+
+    let mut ids: Vec<String> = Vec::new();
+    for path in paths.iter() {
+        // The library with the deepest root holding the folder
+        let library = libraries
+            .iter()
+            .filter_map(|library| {
+                let id = library.get("id").and_then(Value::as_str)?;
+                let root = library.get("root").and_then(Value::as_str)?;
+                match Path::new(path).starts_with(root) {
+                    true => Some((id, root.len())),
+                    false => None,
+                }
+            })
+            .max_by_key(|(_id, len)| *len);
+        match library {
+            Some((id, _len)) if !ids.iter().any(|added| added == id) => ids.push(id.to_string()),
+            Some(_library) => (),
+            None => {
+                return Err(
+                    MdownError::NotFoundError(format!("Komga library holding {}", path))
+                );
+            }
+        }
+    }
+
+    for id in ids.iter() {
+        match
+            check_response(
+                client
+                    .post(format!("{}/api/v1/libraries/{}/scan", server.url, id))
+                    .header("X-API-Key", &server.key)
+                    .timeout(TIMEOUT)
+                    .send().await
+            )
+        {
+            Ok(_response) => debug!("komga library {} is scanned", id),
+            Err(err) => {
+                return Err(err);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Asks Kavita to scan the folders.
+///
+/// # Arguments
+/// * `client` - The client sending requests.
+/// * `server` - The server.
+/// * `paths` - The folders of manga.
+///
+/// # Returns
+/// * `Result<(), MdownError>` - Returns `Ok(())` if all scans were started, or an `MdownError` if a request failed.
+async fn scan_kavita(
+    client: &reqwest::Client,
+    server: &ScanServerMetadata,
+    paths: &[String]
+) -> Result<(), MdownError> {
+    for path in paths.iter() {
+        match
+            check_response(
+                client
+                    .post(format!("{}/api/Library/scan-folder", server.url))
+                    .json(&json!({ "apiKey": server.key, "folderPath": path }))
+                    .timeout(TIMEOUT)
+                    .send().await
+            )
+        {
+            Ok(_response) => debug!("kavita folder {} is scanned", path),
+            Err(err) => {
+                return Err(err);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Asks a server to scan folders of manga.
+///
+/// # Arguments
+/// * `server` - The server.
+/// * `paths` - The folders of manga.
+///
+/// # Returns
+/// * `Result<(), MdownError>` - Returns `Ok(())` if all scans were started, or an `MdownError` if they weren't.
+async fn scan_with(server: &ScanServerMetadata, paths: &[String]) -> Result<(), MdownError> {
+    let client = match download::get_client() {
+        Ok(client) => client,
+        Err(err) => {
+            return Err(MdownError::NetworkError(err));
+        }
+    };
+    match server.kind.as_str() {
+        "komga" => scan_komga(&client, server, paths).await,
+        "kavita" => scan_kavita(&client, server, paths).await,
+        kind =>
+            Err(
+                MdownError::CustomError(
+                    format!("unknown scan server '{}'", kind),
+                    String::from("Scan")
+                )
+            ),
+    }
+}
+
+/// Asks the server set with `settings --scan-server` to scan folders of manga that chapters were added to.
+///
+/// Nothing is asked by `--dry-run`; a failed scan is printed, as the chapters were downloaded anyway.
+///
+/// # Arguments
+/// * `paths` - The folders of manga.
+pub(crate) async fn scan(paths: &[String]) {
+    if paths.is_empty() || *args::ARGS_DRY_RUN {
+        return;
+    }
+    let server = match SERVER.lock().clone() {
+        Some(server) => server,
+        None => {
+            return;
+        }
+    };
+    match scan_with(&server, paths).await {
+        Ok(()) => log!(&format!("Asked {} to scan {} folder(s)", server.kind, paths.len())),
+        Err(err) => handle_error!(&err, String::from("scan")),
+    }
+}
+
+/// Starts a stand-in server answering requests with JSON and returns its URL and the requests it got.
+///
+/// # Arguments
+/// * `answers` - The status and the body of each answer.
+#[cfg(test)]
+async fn stand_in(answers: Vec<(u16, &'static str)>) -> (String, tokio::task::JoinHandle<Vec<String>>) {
+    use tokio::io::{ AsyncReadExt, AsyncWriteExt };
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let task = tokio::spawn(async move {
+        let mut requests = Vec::new();
+        for (status, body) in answers {
+            let (mut stream, _addr) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0; 4096];
+            // Reads the headers and the body they announce
+            loop {
+                let read = stream.read(&mut buffer).await.unwrap();
+                request.extend_from_slice(&buffer[..read]);
+                let text = String::from_utf8_lossy(&request).to_string();
+                if let Some(end) = text.find("\r\n\r\n") {
+                    let length = text[..end]
+                        .lines()
+                        .find_map(|line| line.to_lowercase().strip_prefix("content-length: ").map(str::to_string))
+                        .and_then(|length| length.trim().parse::<usize>().ok())
+                        .unwrap_or(0);
+                    if request.len() >= end + 4 + length || read == 0 {
+                        break;
+                    }
+                }
+            }
+            requests.push(String::from_utf8_lossy(&request).to_string());
+            let answer = format!(
+                "HTTP/1.1 {} Answer\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            );
+            stream.write_all(answer.as_bytes()).await.unwrap();
+        }
+        requests
+    });
+    (url, task)
+}
+
+// Scans each library holding folders once
+#[tokio::test]
+async fn test_scan_komga() {
+    let libraries =
+        r#"[{"id":"lib","root":"/data/manga"},{"id":"deep","root":"/data/manga/deep"},{"id":"other","root":"/other"}]"#;
+    let (url, task) = stand_in(vec![(200, libraries), (202, ""), (202, "")]).await;
+    let server = parse_server(&[String::from("Komga"), format!("{}/", url), String::from("secret")]).unwrap();
+    assert_eq!(server.url, url);
+    let paths = [
+        String::from("/data/manga/A"),
+        String::from("/data/manga/B"),
+        String::from("/data/manga/deep/C"),
+    ];
+    scan_with(&server, &paths).await.unwrap();
+    let requests = task.await.unwrap();
+    assert!(requests[0].starts_with("GET /api/v1/libraries HTTP/1.1"));
+    assert!(requests[0].to_lowercase().contains("x-api-key: secret"));
+    assert!(requests[1].starts_with("POST /api/v1/libraries/lib/scan HTTP/1.1"));
+    assert!(requests[2].starts_with("POST /api/v1/libraries/deep/scan HTTP/1.1"));
+
+    let (url, _task) = stand_in(vec![(200, libraries)]).await;
+    let server = parse_server(&[String::from("komga"), url, String::from("secret")]).unwrap();
+    assert!(scan_with(&server, &[String::from("/elsewhere/A")]).await.is_err());
+}
+
+// Sends each folder with the API key to Kavita and fails on errors of the server
+#[tokio::test]
+async fn test_scan_kavita() {
+    let (url, task) = stand_in(vec![(200, ""), (401, "")]).await;
+    let server = parse_server(&[String::from("kavita"), url, String::from("secret")]).unwrap();
+    let paths = [String::from("/data/manga/A"), String::from("/data/manga/B")];
+    assert!(scan_with(&server, &paths).await.is_err());
+    let requests = task.await.unwrap();
+    assert!(requests[0].starts_with("POST /api/Library/scan-folder HTTP/1.1"));
+    assert!(requests[0].ends_with(r#"{"apiKey":"secret","folderPath":"/data/manga/A"}"#));
+    assert!(requests[1].contains("/data/manga/B"));
+
+    assert!(parse_server(&[String::from("plex"), String::from("http://a"), String::new()]).is_err());
+    assert!(parse_server(&[String::from("kavita"), String::from("ftp://a"), String::new()]).is_err());
+    assert!(parse_server(&[String::from("kavita")]).is_err());
+}
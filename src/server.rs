@@ -4,14 +4,16 @@ use std::{
     fs::{ self, File },
     io::{ self, BufRead, BufReader, Read, Write },
     net::{ TcpListener, TcpStream },
-    path::Path,
+    path::{ Component, Path, PathBuf },
     thread,
 };
 include!(concat!(env!("OUT_DIR"), "/error_404_jpg.rs"));
 
 use crate::{
     args,
+    ctl,
     error::MdownError,
+    feed,
     getter::get_query,
     handle_error,
     log,
@@ -92,6 +94,55 @@ fn get_directory_content(path: &str) -> Result<Value, MdownError> {
     Ok(Value::Object(result))
 }
 
+/// Decodes a path sent by the file manager into a path relative to the current directory.
+///
+/// The file manager may separate folders with either `/` or `\`, so both are accepted. Paths that are absolute, go up with `..` or lead out of the current directory through links are refused.
+///
+/// # Arguments
+/// * `path` - The percent-encoded path.
+///
+/// # Returns
+/// * `Result<Option<String>, MdownError>` - The decoded path, `None` if it is refused, or a `MdownError::ConversionError` if it is not valid UTF-8.
+fn decode_path(path: &str) -> Result<Option<String>, MdownError> {
+    let decoded = match percent_encoding::percent_decode_str(path).decode_utf8() {
+        Ok(decoded) => decoded.to_string(),
+        Err(err) => {
+            return Err(MdownError::ConversionError(err.to_string()));
+        }
+    };
+    if decoded.starts_with(['/', '\\']) {
+        return Ok(None);
+    }
+    let mut relative = PathBuf::new();
+    for component in decoded.split(['/', '\\']) {
+        if component.is_empty() || component == "." {
+            continue;
+        }
+        // `..` and prefixes like `C:` are refused
+        if !Path::new(component).components().all(|part| matches!(part, Component::Normal(_))) {
+            return Ok(None);
+        }
+        relative.push(component);
+    }
+    if let (Ok(real), Ok(cwd)) = (fs::canonicalize(&relative), fs::canonicalize(".")) {
+        if !real.starts_with(cwd) {
+            return Ok(None);
+        }
+    }
+    Ok(Some(relative.to_string_lossy().to_string()))
+}
+
+/// Answers that a path is refused.
+///
+/// # Arguments
+/// * `stream` - The connection.
+fn forbid(stream: &mut TcpStream) -> Result<(), MdownError> {
+    match stream.write_all(b"HTTP/1.1 403 FORBIDDEN\r\n\r\n") {
+        Ok(()) => Ok(()),
+        Err(err) => Err(MdownError::IoError(err, String::new())),
+    }
+}
+
 fn handle_client(stream: TcpStream) -> Result<(), MdownError> {
     let mut stream = BufReader::new(stream);
     let mut request_line = String::new();
@@ -141,16 +192,19 @@ fn handle_client(stream: TcpStream) -> Result<(), MdownError> {
             };
         } else if path.starts_with("/__preview__?") {
             let file_path = match query_params.get("path").cloned() {
-                Some(value) => format!(".\\{}", value),
+                Some(value) => value,
                 None => {
                     return Ok(());
                 }
             };
 
-            let decoded_str = match percent_encoding::percent_decode_str(&file_path).decode_utf8() {
-                Ok(decoded_str) => decoded_str.to_string().replace("./", "").replace("/", ""),
+            let decoded_str = match decode_path(&file_path) {
+                Ok(Some(decoded_str)) => decoded_str,
+                Ok(None) => {
+                    return forbid(stream.get_mut());
+                }
                 Err(err) => {
-                    return Err(MdownError::ConversionError(err.to_string()));
+                    return Err(err);
                 }
             };
 
@@ -197,15 +251,18 @@ fn handle_client(stream: TcpStream) -> Result<(), MdownError> {
                 }
             };
 
-            let decoded_str = match percent_encoding::percent_decode_str(&file_path).decode_utf8() {
-                Ok(decoded_str) => decoded_str.to_string().replace("./", "").replace("/", ""),
+            let decoded_str = match decode_path(&file_path) {
+                Ok(Some(decoded_str)) => decoded_str,
+                Ok(None) => {
+                    return forbid(stream.get_mut());
+                }
                 Err(err) => {
-                    return Err(MdownError::ConversionError(err.to_string()));
+                    return Err(err);
                 }
             };
 
-            let dst_file = match decoded_str.split('/').last() {
-                Some(value) => format!("{}.zip", value),
+            let dst_file = match Path::new(&decoded_str).file_name() {
+                Some(value) => format!("{}.zip", value.to_string_lossy()),
                 None => {
                     return Ok(());
                 }
@@ -284,6 +341,28 @@ fn handle_client(stream: TcpStream) -> Result<(), MdownError> {
                     return Err(MdownError::IoError(err, String::new()));
                 }
             }
+        } else if path == "/feed.atom" || (path.starts_with("/feed/") && path.ends_with(".atom")) {
+            let manga_id = path
+                .strip_prefix("/feed/")
+                .and_then(|name| name.strip_suffix(".atom"));
+            let response = match feed::get_feed(manga_id) {
+                Ok(Some(feed)) =>
+                    format!(
+                        "{}{}",
+                        "HTTP/1.1 200 OK\r\nContent-Type: application/atom+xml; charset=utf-8\r\n\r\n",
+                        feed
+                    ),
+                Ok(None) => String::from("HTTP/1.1 404 NOT FOUND\r\n\r\n"),
+                Err(err) => {
+                    return Err(err);
+                }
+            };
+            match stream.get_mut().write_all(response.as_bytes()) {
+                Ok(_n) => (),
+                Err(err) => {
+                    return Err(MdownError::IoError(err, String::new()));
+                }
+            };
         } else if path == "/" {
             let html = get_html();
             let response = format!(
@@ -451,6 +530,8 @@ pub(crate) fn start() -> Result<(), MdownError> {
         }
     }
 
+    ctl::serve();
+
     let listener = match TcpListener::bind(format!("{}:3000", ip_address)) {
         Ok(listener) => listener,
         Err(err) => {
@@ -474,3 +555,18 @@ pub(crate) fn start() -> Result<(), MdownError> {
 
     Ok(())
 }
+
+// Refuses paths leading out of the current directory
+#[test]
+fn test_decode_path() {
+    assert_eq!(decode_path("..").unwrap(), None);
+    assert_eq!(decode_path("../../../etc/passwd").unwrap(), None);
+    assert_eq!(decode_path("Manga/../../etc").unwrap(), None);
+    assert_eq!(decode_path("/etc/passwd").unwrap(), None);
+    assert_eq!(decode_path("%2Fetc%2Fpasswd").unwrap(), None);
+    assert_eq!(decode_path("..%2F..").unwrap(), None);
+    assert_eq!(decode_path("..%5C..").unwrap(), None);
+    let expected = Path::new("Manga").join("Ch.1.cbz").to_string_lossy().to_string();
+    assert_eq!(decode_path("./Manga%2FCh.1.cbz").unwrap(), Some(expected.clone()));
+    assert_eq!(decode_path("Manga\\Ch.1.cbz").unwrap(), Some(expected));
+}
@@ -0,0 +1,454 @@
+use std::collections::HashMap;
+
+use crate::{ args, error::MdownError, resolute, utils };
+
+/// Template of chapter names that matches the naming used before templates existed.
+pub(crate) const DEFAULT_NAME_TEMPLATE: &str = "{manga} - <Vol.{volume} >Ch.{chapter}< - {title}>";
+
+/// Variables that can be used in the name template of chapters.
+pub(crate) const CHAPTER_VARIABLES: [&str; 7] = [
+    "manga",
+    "volume",
+    "chapter",
+    "title",
+    "group",
+    "lang",
+    "id",
+];
+
+/// Variables that can be used in the template of the manga folder.
+pub(crate) const FOLDER_VARIABLES: [&str; 3] = ["manga", "lang", "id"];
+
+/// Variables that hold numbers and therefore accept a format.
+const NUMERIC_VARIABLES: [&str; 2] = ["volume", "chapter"];
+
+/// A part of a parsed template.
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    /// Text copied to the name as it is.
+    Text(String),
+
+    /// A variable with an optional width of the integer part and minimal number of decimals.
+    Variable {
+        name: String,
+        width: Option<usize>,
+        precision: Option<usize>,
+    },
+
+    /// A part enclosed in `<` and `>` that is left out when any of its variables is empty.
+    Optional(Vec<Token>),
+}
+
+/// A validated template of folder or file names.
+///
+/// Variables are written as `{name}`; `{chapter:03}` pads the integer part of a number with zeros
+/// to three digits and `{chapter:03.1}` additionally shows at least one decimal.
+/// Parts enclosed in `<` and `>` are left out when any variable inside them is empty, and `/`
+/// separates subfolders.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Template {
+    tokens: Vec<Token>,
+}
+
+impl Template {
+    /// Parses and validates a template.
+    ///
+    /// # Arguments
+    /// * `template` - The template.
+    /// * `variables` - The variables that can be used in the template.
+    /// * `subfolders` - Whether `/` can be used to create subfolders.
+    ///
+    /// # Returns
+    /// * `Result<Template, MdownError>` - Returns the parsed template, or `MdownError::ConversionError` describing the first problem.
+    pub(crate) fn parse(
+        template: &str,
+        variables: &[&str],
+        subfolders: bool
+    ) -> Result<Template, MdownError> {
+        let error = |message: String| {
+            MdownError::ConversionError(format!("invalid template \"{}\": {}", template, message))
+        };
+        if template.trim().is_empty() {
+            return Err(error(String::from("template is empty")));
+        }
+        if !subfolders && template.contains(['/', '\\']) {
+            return Err(error(String::from("subfolders are not allowed")));
+        }
+
+        let mut tokens = Vec::new();
+        let mut optional: Option<Vec<Token>> = None;
+        let mut text = String::new();
+        let mut chars = template.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' => {
+                    let mut variable = String::new();
+                    let mut closed = false;
+                    for c in chars.by_ref() {
+                        if c == '}' {
+                            closed = true;
+                            break;
+                        }
+                        variable.push(c);
+                    }
+                    if !closed {
+                        return Err(error(format!("missing '}}' after {{{}", variable)));
+                    }
+                    let token = match parse_variable(&variable, variables) {
+                        Ok(token) => token,
+                        Err(message) => {
+                            return Err(error(message));
+                        }
+                    };
+                    let current = optional.as_mut().unwrap_or(&mut tokens);
+                    if !text.is_empty() {
+                        current.push(Token::Text(std::mem::take(&mut text)));
+                    }
+                    current.push(token);
+                }
+                '}' => {
+                    return Err(error(String::from("unexpected '}'")));
+                }
+                '<' => {
+                    if optional.is_some() {
+                        return Err(error(String::from("optional parts can't be nested")));
+                    }
+                    if !text.is_empty() {
+                        tokens.push(Token::Text(std::mem::take(&mut text)));
+                    }
+                    optional = Some(Vec::new());
+                }
+                '>' => {
+                    let mut part = match optional.take() {
+                        Some(part) => part,
+                        None => {
+                            return Err(error(String::from("unexpected '>'")));
+                        }
+                    };
+                    if !text.is_empty() {
+                        part.push(Token::Text(std::mem::take(&mut text)));
+                    }
+                    tokens.push(Token::Optional(part));
+                }
+                c => text.push(c),
+            }
+        }
+        if optional.is_some() {
+            return Err(error(String::from("missing '>'")));
+        }
+        if !text.is_empty() {
+            tokens.push(Token::Text(text));
+        }
+        Ok(Template { tokens })
+    }
+
+    /// Returns whether the template uses a variable.
+    pub(crate) fn uses(&self, variable: &str) -> bool {
+        fn uses(tokens: &[Token], variable: &str) -> bool {
+            tokens.iter().any(|token| {
+                match token {
+                    Token::Text(_) => false,
+                    Token::Variable { name, .. } => name == variable,
+                    Token::Optional(part) => uses(part, variable),
+                }
+            })
+        }
+        uses(&self.tokens, variable)
+    }
+
+    /// Fills the template in.
+    ///
+    /// # Arguments
+    /// * `values` - The values of variables; missing variables are empty.
+    ///
+    /// # Returns
+    /// * `Vec<String>` - The subfolders followed by the name, each stripped of characters that can't be in a file name.
+    pub(crate) fn render(&self, values: &HashMap<&str, String>) -> Vec<String> {
+        let mut name = String::new();
+        for token in self.tokens.iter() {
+            match token {
+                Token::Optional(part) => {
+                    let mut rendered = String::new();
+                    let mut complete = true;
+                    for token in part.iter() {
+                        match render_token(token, values) {
+                            Some(value) => rendered.push_str(&value),
+                            None => {
+                                complete = false;
+                                break;
+                            }
+                        }
+                    }
+                    if complete {
+                        name.push_str(&rendered);
+                    }
+                }
+                token => name.push_str(&render_token(token, values).unwrap_or_default()),
+            }
+        }
+        name.split(['/', '\\'])
+            .map(|component| utils::process_filename(component).trim().to_string())
+            .filter(|component| !component.is_empty() && component != "." && component != "..")
+            .collect()
+    }
+}
+
+/// Returns the name template of chapters supplied by the user or stored in settings.
+///
+/// # Returns
+/// * `Template` - The parsed template; the default template if none was supplied or it is invalid.
+pub(crate) fn get_name_template() -> Template {
+    let name_template = args::ARGS.lock().name_template.clone();
+    if !name_template.is_empty() {
+        // Templates from settings and arguments are validated in `db::setup_settings`
+        if let Ok(template) = Template::parse(&name_template, &CHAPTER_VARIABLES, true) {
+            return template;
+        }
+    }
+    match Template::parse(DEFAULT_NAME_TEMPLATE, &CHAPTER_VARIABLES, true) {
+        Ok(template) => template,
+        Err(_err) => Template { tokens: vec![] },
+    }
+}
+
+/// Fills the template of the manga folder in.
+///
+/// # Arguments
+/// * `folder` - The folder supplied by the user or stored in settings; `name` is the name of the manga.
+///
+/// # Returns
+/// * `String` - The name of the folder.
+pub(crate) fn render_folder(folder: &str) -> String {
+    if folder == "name" {
+        return resolute::MANGA_NAME.lock().clone();
+    }
+    if !folder.contains('{') {
+        return utils::process_filename(folder);
+    }
+    let template = match Template::parse(folder, &FOLDER_VARIABLES, false) {
+        Ok(template) => template,
+        Err(_err) => {
+            return utils::process_filename(folder);
+        }
+    };
+    let values = HashMap::from([
+        ("manga", resolute::MANGA_NAME.lock().clone()),
+        ("lang", resolute::LANGUAGE.lock().clone()),
+        ("id", resolute::MANGA_ID.lock().clone()),
+    ]);
+    match template.render(&values).pop() {
+        Some(folder) => folder,
+        None => String::from("."),
+    }
+}
+
+/// Validates the folder and name templates.
+///
+/// # Arguments
+/// * `folder` - The folder of manga; only validated if it contains a variable.
+/// * `name_template` - The name template of chapters; empty selects the default template.
+///
+/// # Returns
+/// * `Result<(), MdownError>` - Returns `Ok(())` if both templates are valid, or the first `MdownError::ConversionError`.
+pub(crate) fn validate(folder: &str, name_template: &str) -> Result<(), MdownError> {
+    if folder.contains('{') {
+        match Template::parse(folder, &FOLDER_VARIABLES, false) {
+            Ok(_template) => (),
+            Err(err) => {
+                return Err(err);
+            }
+        }
+    }
+    if !name_template.is_empty() {
+        match Template::parse(name_template, &CHAPTER_VARIABLES, true) {
+            Ok(_template) => (),
+            Err(err) => {
+                return Err(err);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Parses the content of `{...}` in a template.
+///
+/// # Arguments
+/// * `variable` - The content between the braces, e.g. `chapter:03.1`.
+/// * `variables` - The variables that can be used in the template.
+///
+/// # Returns
+/// * `Result<Token, String>` - Returns the variable token, or a description of the problem.
+fn parse_variable(variable: &str, variables: &[&str]) -> Result<Token, String> {
+    let (name, format) = match variable.split_once(':') {
+        Some((name, format)) => (name.trim(), Some(format.trim())),
+        None => (variable.trim(), None),
+    };
+    if !variables.contains(&name) {
+        return Err(format!("unknown variable {{{}}}; available: {}", name, variables.join(", ")));
+    }
+    let (width, precision) = match format {
+        Some(format) => {
+            if !NUMERIC_VARIABLES.contains(&name) {
+                return Err(format!("{{{}}} can't be formatted", name));
+            }
+            let (width, precision) = match format.split_once('.') {
+                Some((width, precision)) => (width, Some(precision)),
+                None => (format, None),
+            };
+            let parse = |value: &str| {
+                value.parse::<usize>().map_err(|_err| format!("invalid format \"{}\" of {{{}}}", format, name))
+            };
+            let width = match width {
+                "" => None,
+                width => {
+                    match parse(width) {
+                        Ok(width) => Some(width),
+                        Err(err) => {
+                            return Err(err);
+                        }
+                    }
+                }
+            };
+            let precision = match precision {
+                Some(precision) =>
+                    match parse(precision) {
+                        Ok(precision) => Some(precision),
+                        Err(err) => {
+                            return Err(err);
+                        }
+                    }
+                None => None,
+            };
+            (width, precision)
+        }
+        None => (None, None),
+    };
+    Ok(Token::Variable { name: name.to_string(), width, precision })
+}
+
+/// Renders a single token.
+///
+/// Values of variables are stripped of characters that can't be in a file name, so that only `/` written in the template makes subfolders.
+///
+/// # Returns
+/// * `Option<String>` - The rendered token, or `None` if it is a variable without a value.
+fn render_token(token: &Token, values: &HashMap<&str, String>) -> Option<String> {
+    match token {
+        Token::Text(text) => Some(text.clone()),
+        Token::Variable { name, width, precision } => {
+            let value = values
+                .get(name.as_str())
+                .map(|value| utils::process_filename(value))
+                .unwrap_or_default();
+            let value = value.trim();
+            if value.is_empty() {
+                return None;
+            }
+            Some(format_number(value, *width, *precision))
+        }
+        Token::Optional(_) => None,
+    }
+}
+
+/// Pads a number to a width of its integer part and a minimal number of decimals.
+///
+/// Values that are not numbers, e.g. chapters named by the group, are kept as they are.
+///
+/// # Arguments
+/// * `value` - The number.
+/// * `width` - The minimal number of digits of the integer part.
+/// * `precision` - The minimal number of decimals.
+///
+/// # Returns
+/// * `String` - The formatted number.
+pub(crate) fn format_number(value: &str, width: Option<usize>, precision: Option<usize>) -> String {
+    let (whole, part) = match value.split_once('.') {
+        Some((whole, part)) => (whole, part),
+        None => (value, ""),
+    };
+    if
+        whole.is_empty() ||
+        !whole.chars().all(|c| c.is_ascii_digit()) ||
+        !part.chars().all(|c| c.is_ascii_digit())
+    {
+        return value.to_string();
+    }
+    let whole = format!("{:0>width$}", whole, width = width.unwrap_or_default());
+    let part = format!("{:0<precision$}", part, precision = precision.unwrap_or_default());
+    match part.is_empty() {
+        true => whole,
+        false => format!("{}.{}", whole, part),
+    }
+}
+
+// Reproduces the naming used before templates existed
+#[test]
+fn test_default_template() {
+    let template = Template::parse(DEFAULT_NAME_TEMPLATE, &CHAPTER_VARIABLES, true).unwrap();
+    let mut values = HashMap::from([
+        ("manga", String::from("Manga")),
+        ("volume", String::from("2")),
+        ("chapter", String::from("11")),
+        ("title", String::from("A: Title?")),
+    ]);
+    assert_eq!(template.render(&values), vec![String::from("Manga - Vol.2 Ch.11 - A Title")]);
+    values.insert("volume", String::new());
+    values.insert("title", String::new());
+    assert_eq!(template.render(&values), vec![String::from("Manga - Ch.11")]);
+}
+
+// Formats numbers and splits subfolders
+#[test]
+fn test_template_subfolders_and_format() {
+    let template = Template::parse(
+        "Vol.{volume:02}/{manga} - Ch.{chapter:03.1} [{group}]",
+        &CHAPTER_VARIABLES,
+        true
+    ).unwrap();
+    assert!(template.uses("group"));
+    let values = HashMap::from([
+        ("manga", String::from("Manga")),
+        ("volume", String::from("1")),
+        ("chapter", String::from("7.5")),
+        ("group", String::from("Group")),
+    ]);
+    assert_eq!(
+        template.render(&values),
+        vec![String::from("Vol.01"), String::from("Manga - Ch.007.5 [Group]")]
+    );
+    assert_eq!(format_number("12", Some(3), Some(1)), "012.0");
+
+    // Only `/` of the template makes subfolders; values can't
+    let values = HashMap::from([
+        ("manga", String::from("Manga")),
+        ("volume", String::from("1")),
+        ("chapter", String::from("5")),
+        ("title", String::from("Part 1/2")),
+        ("group", String::from("A/B\\C")),
+    ]);
+    assert_eq!(
+        template.render(&values),
+        vec![String::from("Vol.01"), String::from("Manga - Ch.005.0 [ABC]")]
+    );
+    let default = Template::parse(DEFAULT_NAME_TEMPLATE, &CHAPTER_VARIABLES, true).unwrap();
+    assert_eq!(default.render(&values), vec![String::from("Manga - Vol.1 Ch.5 - Part 12")]);
+    assert_eq!(format_number("Extra", Some(3), None), "Extra");
+}
+
+// Rejects invalid templates up front
+#[test]
+fn test_template_invalid() {
+    for template in [
+        "",
+        "{manga",
+        "{unknown}",
+        "{title:03}",
+        "{chapter:x}",
+        "<Vol.{volume}",
+        "<<{volume}>>",
+        "Ch.{chapter}>",
+    ] {
+        assert!(Template::parse(template, &CHAPTER_VARIABLES, true).is_err(), "{}", template);
+    }
+    assert!(Template::parse("{manga}/{id}", &FOLDER_VARIABLES, false).is_err());
+}
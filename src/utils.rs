@@ -5,9 +5,11 @@ use remove_dir_all::remove_dir_all;
 use serde_json::{ json, Value };
 use std::{
     cmp::Ordering,
+    collections::HashMap,
     fs::{ self, File, OpenOptions },
     io::{ Read, Write },
-    process::exit,
+    path::{ Path, PathBuf, MAIN_SEPARATOR },
+    process::{ exit, Command },
     thread::sleep,
     time::{ Duration, Instant },
 };
@@ -25,6 +27,7 @@ use crate::{
     metadata,
     resolute::{ self, resolve_move, CURRENT_PERCENT, CURRENT_SIZE, CURRENT_SIZE_MAX },
     string,
+    template,
     version_manager::get_current_version,
 };
 
@@ -63,20 +66,8 @@ pub(crate) fn log_handler() {
     loop {
         sleep(Duration::from_millis(100));
 
-        if fs::metadata(&path).is_err() {
-            let mut file = match fs::File::create(&path) {
-                Ok(file) => file,
-                Err(_err) => {
-                    continue;
-                }
-            };
-
-            let content = String::from("{}");
-
-            match file.write_all(content.as_bytes()) {
-                Ok(()) => (),
-                Err(_err) => (),
-            };
+        if fs::metadata(&path).is_err() && write_file_atomic(&path, b"{}").is_err() {
+            continue;
         }
         if *resolute::ENDED.lock() {
             let _ = fs::remove_file(&lock_path);
@@ -189,13 +180,6 @@ pub(crate) fn log_handler() {
                 }
             }
         }
-        let mut file = match File::create(&path) {
-            Ok(file) => file,
-            Err(_err) => {
-                continue;
-            }
-        };
-
         let json_string = match serde_json::to_string_pretty(&json) {
             Ok(value) => value,
             Err(_err) => {
@@ -203,7 +187,9 @@ pub(crate) fn log_handler() {
             }
         };
 
-        let _ = writeln!(file, "{}", json_string);
+        if write_file_atomic(&path, format!("{}\n", json_string).as_bytes()).is_err() {
+            continue;
+        }
         *messages_lock = vec![];
         *handle_id_lock = vec![];
         drop(messages_lock);
@@ -232,6 +218,12 @@ pub(crate) fn reset() -> Result<(), MdownError> {
             return Err(err);
         }
     };
+    let library = match getter::get_library_path() {
+        Ok(library) => library,
+        Err(err) => {
+            return Err(err);
+        }
+    };
     let db = match getter::get_db_path() {
         Ok(dat) => dat,
         Err(err) => {
@@ -258,6 +250,19 @@ pub(crate) fn reset() -> Result<(), MdownError> {
             }
         }
     }
+    match std::fs::remove_file(&library) {
+        Ok(_) => println!("library.db was successfully removed"),
+        Err(err) => {
+            match err.raw_os_error() {
+                Some(code) => {
+                    if code != 2 {
+                        push_suspended(err, "library.db");
+                    }
+                }
+                None => push_suspended(err, "library.db"),
+            }
+        }
+    }
     match std::fs::remove_file(&db) {
         Ok(_) => println!("resources.db was successfully removed"),
         Err(err) => {
@@ -295,11 +300,11 @@ fn push_suspended(err: std::io::Error, name: &str) {
 }
 
 pub(crate) fn remove_cache() -> Result<(), MdownError> {
-    if is_directory_empty(".cache\\") {
+    if is_directory_empty(".cache") {
         match remove_dir_all(".cache") {
             Ok(()) => (),
             Err(err) => {
-                suspend_error(MdownError::IoError(err, String::from(".cache\\")));
+                suspend_error(MdownError::IoError(err, String::from(".cache")));
             }
         };
     }
@@ -351,7 +356,7 @@ pub(crate) fn create_cache_folder() -> Result<(), MdownError> {
     match fs::create_dir(".cache") {
         Ok(()) => Ok(()),
         Err(err) => {
-            suspend_error(MdownError::IoError(err, String::from(".cache\\")));
+            suspend_error(MdownError::IoError(err, String::from(".cache")));
             Ok(())
         }
     }
@@ -373,14 +378,59 @@ pub(crate) fn process_filename(filename: &str) -> String {
     filename.replace(['<', '>', ':', '|', '?', '*', '/', '\\', '"'], "")
 }
 
+pub(crate) fn pad_chapter(chapter: &str) -> String {
+    let (whole, part) = match chapter.split_once('.') {
+        Some((whole, part)) => (whole, Some(part)),
+        None => (chapter, None),
+    };
+    if whole.is_empty() || !whole.chars().all(|c| c.is_ascii_digit()) {
+        return chapter.to_string();
+    }
+    match part {
+        Some(part) => format!("{:0>4}.{}", whole, part),
+        None => format!("{:0>4}", whole),
+    }
+}
+
+/// Compares names of pages so that numbers in them are compared by their value, e.g. page 2 comes before page 10.
+#[cfg(feature = "transcode")]
+pub(crate) fn compare_names(a: &str, b: &str) -> Ordering {
+    let (mut rest_a, mut rest_b) = (a, b);
+    while !rest_a.is_empty() && !rest_b.is_empty() {
+        let digits_a = rest_a.len() - rest_a.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        let digits_b = rest_b.len() - rest_b.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        let (part_a, part_b) = if digits_a > 0 && digits_b > 0 {
+            (rest_a.split_at(digits_a), rest_b.split_at(digits_b))
+        } else {
+            let char_a = rest_a.chars().next().map_or(0, char::len_utf8);
+            let char_b = rest_b.chars().next().map_or(0, char::len_utf8);
+            (rest_a.split_at(char_a), rest_b.split_at(char_b))
+        };
+        let ((value_a, next_a), (value_b, next_b)) = (part_a, part_b);
+        let ordering = if digits_a > 0 && digits_b > 0 {
+            let number_a = value_a.trim_start_matches('0');
+            let number_b = value_b.trim_start_matches('0');
+            number_a.len().cmp(&number_b.len()).then_with(|| number_a.cmp(number_b))
+        } else {
+            value_a.cmp(value_b)
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+        rest_a = next_a;
+        rest_b = next_b;
+    }
+    rest_b.is_empty().cmp(&rest_a.is_empty()).then_with(|| a.cmp(b))
+}
+
 pub(crate) async fn wait_for_end(file_path: &str, images_length: usize) -> Result<(), MdownError> {
-    let full_path = format!(".cache\\{}.lock", file_path);
+    let full_path = getter::get_cache_path(&format!("{}.lock", file_path));
     let mut full_size = 0.0;
     let start = Instant::now();
     while fs::metadata(&full_path).is_ok() {
         let mut size = 0.0;
         for i in 1..images_length + 1 {
-            let image_name = format!(".cache\\{}_{}.lock", file_path, i);
+            let image_name = getter::get_cache_path(&format!("{}_{}.lock", file_path, i));
             if fs::metadata(&image_name).is_ok() {
                 let mut image_file = match File::open(&image_name) {
                     Ok(image) => image,
@@ -405,7 +455,7 @@ pub(crate) async fn wait_for_end(file_path: &str, images_length: usize) -> Resul
             }
         }
         for i in 1..images_length + 1 {
-            let image_name = format!(".cache\\{}_{}_final.lock", file_path, i);
+            let image_name = getter::get_cache_path(&format!("{}_{}_final.lock", file_path, i));
             if fs::metadata(image_name.clone()).is_ok() {
                 let mut image_file = match File::open(image_name.clone()) {
                     Ok(image) => image,
@@ -452,7 +502,7 @@ pub(crate) async fn wait_for_end(file_path: &str, images_length: usize) -> Resul
     }
 
     for i in 1..images_length + 1 {
-        let image_name = format!(".cache\\{}_{}.lock", file_path, i);
+        let image_name = getter::get_cache_path(&format!("{}_{}.lock", file_path, i));
         if fs::metadata(&image_name).is_ok() {
             match fs::remove_file(&image_name) {
                 Ok(()) => (),
@@ -605,31 +655,389 @@ pub(crate) async fn search() -> Result<String, MdownError> {
     }
 }
 
-pub(crate) fn resolve_start() -> Result<String, MdownError> {
-    let file_path: String = format!(".cache\\mdown_{}.lock", get_current_version());
-    if *args::ARGS_FORCE_DELETE {
-        match fs::remove_file(&file_path) {
-            Ok(()) => println!("File has been deleted\nYou can now use it as normal"),
-            Err(_err) => {
-                println!("File had been already deleted");
-                match remove_cache() {
-                    Ok(()) => (),
-                    Err(err) => eprintln!("Error: removing cache {}", err),
-                }
-                exit(0);
+/// Replaces the content of a file so that a crash never leaves it half-written.
+///
+/// The content is written to a temporary file next to the file, flushed to disk and renamed over the file.
+///
+/// # Arguments
+/// * `path` - The path to the file.
+/// * `content` - The new content.
+///
+/// # Returns
+/// * `Ok(())` - If the file was replaced.
+/// * `Err(MdownError)` - If the temporary file can't be written or renamed.
+pub(crate) fn write_file_atomic(path: &str, content: &[u8]) -> Result<(), MdownError> {
+    let tmp_path = format!("{}.{}.tmp", path, std::process::id());
+    let written = match File::create(&tmp_path) {
+        Ok(mut file) =>
+            match file.write_all(content) {
+                Ok(()) => file.sync_all(),
+                Err(err) => Err(err),
             }
+        Err(err) => Err(err),
+    };
+    let renamed = match written {
+        Ok(()) => fs::rename(&tmp_path, path),
+        Err(err) => Err(err),
+    };
+    match renamed {
+        Ok(()) => Ok(()),
+        Err(err) => {
+            let _ = fs::remove_file(&tmp_path);
+            Err(MdownError::IoError(err, path.to_string()))
         }
     }
-    if fs::metadata(&file_path).is_ok() {
-        eprintln!(
-            "Lock file has been found;\nSee README.md;\nCannot run multiple instances of mdown"
+}
+
+/// Returns the name of this computer.
+///
+/// # Returns
+/// * `String` - The host name, or `unknown` if it can't be determined.
+pub(crate) fn get_host_name() -> String {
+    for name in ["COMPUTERNAME", "HOSTNAME"] {
+        if let Ok(host) = std::env::var(name) {
+            if !host.trim().is_empty() {
+                return host.trim().to_string();
+            }
+        }
+    }
+    if let Ok(host) = fs::read_to_string("/etc/hostname") {
+        if !host.trim().is_empty() {
+            return host.trim().to_string();
+        }
+    }
+    match Command::new("hostname").output() {
+        Ok(output) if output.status.success() => {
+            String::from_utf8_lossy(&output.stdout).trim().to_string()
+        }
+        _ => String::from("unknown"),
+    }
+}
+
+/// Checks whether a process is running on this computer.
+///
+/// # Arguments
+/// * `pid` - The PID of the process.
+///
+/// # Returns
+/// * `bool` - Whether the process is running; `true` when it can't be checked.
+pub(crate) fn is_process_running(pid: u32) -> bool {
+    if cfg!(windows) {
+        match
+            Command::new("tasklist")
+                .args(["/FI", &format!("PID eq {}", pid), "/NH", "/FO", "CSV"])
+                .output()
+        {
+            Ok(output) => String::from_utf8_lossy(&output.stdout).contains(&format!("\"{}\"", pid)),
+            Err(_err) => true,
+        }
+    } else if Path::new("/proc/self").exists() {
+        Path::new("/proc").join(pid.to_string()).exists()
+    } else {
+        match Command::new("kill").args(["-0", &pid.to_string()]).output() {
+            Ok(output) =>
+                output.status.success() ||
+                    String::from_utf8_lossy(&output.stderr).contains("not permitted"),
+            Err(_err) => true,
+        }
+    }
+}
+
+/// Finds when a process on this computer was started.
+///
+/// # Arguments
+/// * `pid` - The PID of the process.
+///
+/// # Returns
+/// * `Option<DateTime<Utc>>` - The start time, or `None` if the process isn't running or its start time can't be read.
+pub(crate) fn get_process_started(pid: u32) -> Option<DateTime<Utc>> {
+    if cfg!(windows) {
+        let script = format!(
+            "(Get-Process -Id {}).StartTime.ToUniversalTime().ToString('o')",
+            pid
         );
-        exit(100);
+        let output = Command::new("powershell").args(["-NoProfile", "-Command", &script]).output().ok()?;
+        let started = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        DateTime::parse_from_rfc3339(&started)
+            .ok()
+            .map(|started| started.with_timezone(&Utc))
+    } else if Path::new("/proc/self").exists() {
+        // Field 22 of stat is the start time in clock ticks after boot; the name of the process may contain spaces
+        let stat = fs::read_to_string(Path::new("/proc").join(pid.to_string()).join("stat")).ok()?;
+        let (_name, fields) = stat.rsplit_once(')')?;
+        let ticks = fields.split_whitespace().nth(19)?.parse::<i64>().ok()?;
+        let boot = fs::read_to_string("/proc/stat")
+            .ok()?
+            .lines()
+            .find_map(|line| line.strip_prefix("btime "))?
+            .trim()
+            .parse::<i64>()
+            .ok()?;
+        // Linux reports 100 clock ticks per second on all supported platforms
+        DateTime::from_timestamp(boot, 0).map(|boot| boot + chrono::Duration::milliseconds(ticks * 10))
+    } else {
+        let output = Command::new("ps").args(["-o", "lstart=", "-p", &pid.to_string()]).output().ok()?;
+        let started = String::from_utf8_lossy(&output.stdout)
+            .split_whitespace()
+            .collect::<Vec<&str>>()
+            .join(" ");
+        let started = NaiveDateTime::parse_from_str(&started, "%a %b %d %H:%M:%S %Y").ok()?;
+        Local.from_local_datetime(&started)
+            .earliest()
+            .map(|started| started.with_timezone(&Utc))
     }
-    match File::create(&file_path) {
-        Ok(_) => (),
-        Err(e) => {
-            panic!("Error creating the file: {}", e);
+}
+
+/// Checks whether a lock file was left behind by a process that is no longer running.
+///
+/// Processes of other computers sharing the folder can't be checked, so their locks are never stale.
+/// A lock file naming the PID of the current process is stale only if it was created by an earlier process with the same PID.
+/// A lock file naming a running process is stale if that process started after the lock file was created, as the PID was then reused.
+///
+/// # Arguments
+/// * `owner` - The process that created the lock file.
+///
+/// # Returns
+/// * `bool` - Whether the lock file can be removed.
+fn is_stale_lock(owner: &metadata::LockOwner) -> bool {
+    if owner.host != get_host_name() {
+        return false;
+    }
+    if owner.pid == std::process::id() {
+        return owner.started != metadata::LockOwner::current().started;
+    }
+    if !is_process_running(owner.pid) {
+        return true;
+    }
+    // Start times are read with a precision of a second at best
+    match (DateTime::parse_from_rfc3339(&owner.started), get_process_started(owner.pid)) {
+        (Ok(locked), Some(started)) => started > locked + chrono::Duration::seconds(2),
+        _ => false,
+    }
+}
+
+/// Reads the process that created a lock file.
+///
+/// # Arguments
+/// * `path` - The path to the lock file.
+///
+/// # Returns
+/// * `Option<metadata::LockOwner>` - The process, or `None` if the lock file doesn't name one, e.g. when it was created by an older version.
+fn read_lock_owner(path: &str) -> Option<metadata::LockOwner> {
+    match fs::read_to_string(path) {
+        Ok(content) => serde_json::from_str(&content).ok(),
+        Err(_err) => None,
+    }
+}
+
+/// Removes its lock file when dropped.
+#[derive(Debug, PartialEq)]
+pub(crate) struct LockGuard {
+    path: String,
+}
+
+impl Drop for LockGuard {
+    fn drop(&mut self) {
+        match fs::remove_file(&self.path) {
+            Ok(()) => (),
+            Err(err) => debug!("failed to remove lock file {}: {}", self.path, err),
+        }
+    }
+}
+
+/// Outcome of trying to create a lock file.
+#[derive(Debug, PartialEq)]
+pub(crate) enum LockState {
+    /// The lock file was created; it is removed when the guard is dropped.
+    Acquired(LockGuard),
+    /// The lock file is held by a running process.
+    Held(metadata::LockOwner),
+}
+
+/// Creates a lock file naming the current process.
+///
+/// A lock file left behind by a process that is no longer running is removed with a warning.
+///
+/// # Arguments
+/// * `path` - The path to the lock file.
+///
+/// # Returns
+/// * `Ok(LockState)` - Whether the lock file was created or is held by a running process.
+/// * `Err(MdownError)` - If the lock file can't be created or removed.
+pub(crate) fn acquire_lock(path: &str) -> Result<LockState, MdownError> {
+    for _ in 0..3 {
+        match OpenOptions::new().write(true).create_new(true).open(path) {
+            Ok(mut file) => {
+                let guard = LockGuard { path: path.to_string() };
+                let owner = match serde_json::to_string(&metadata::LockOwner::current()) {
+                    Ok(owner) => owner,
+                    Err(err) => {
+                        return Err(MdownError::JsonError(err.to_string()));
+                    }
+                };
+                return match file.write_all(owner.as_bytes()) {
+                    Ok(()) => Ok(LockState::Acquired(guard)),
+                    Err(err) => Err(MdownError::IoError(err, path.to_string())),
+                };
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => (),
+            Err(err) => {
+                return Err(MdownError::IoError(err, path.to_string()));
+            }
+        }
+
+        // The lock file may have been created a moment ago and not be written yet
+        let owner = match read_lock_owner(path) {
+            Some(owner) => Some(owner),
+            None => {
+                sleep(Duration::from_millis(200));
+                read_lock_owner(path)
+            }
+        };
+        match owner {
+            Some(ref owner) if !is_stale_lock(owner) => {
+                return Ok(LockState::Held(owner.clone()));
+            }
+            Some(ref owner) =>
+                eprintln!(
+                    "Warning: removing stale lock file {} of process {} on {} started at {}",
+                    path,
+                    owner.pid,
+                    owner.host,
+                    owner.started
+                ),
+            None => eprintln!("Warning: removing lock file {} created by an older version", path),
+        }
+        // Another process may have replaced the stale lock file with its own in the meantime
+        if read_lock_owner(path) != owner {
+            continue;
+        }
+        match fs::remove_file(path) {
+            Ok(()) => (),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => (),
+            Err(err) => {
+                return Err(MdownError::IoError(err, path.to_string()));
+            }
+        }
+    }
+    Err(MdownError::CustomError(format!("Failed to create lock file {}", path), String::from("LockError")))
+}
+
+/// Returns the name of the file that tells the threads of this process that it is running.
+///
+/// Every process has its own file so processes working on different manga in one folder don't end each other.
+pub(crate) fn get_instance_file_name() -> String {
+    format!("mdown_{}_{}.lock", get_current_version(), std::process::id())
+}
+
+/// Returns the name of the file that tells the threads of this process that it has finished.
+fn get_final_end_file_name() -> String {
+    format!("mdown_final_end_{}.lock", std::process::id())
+}
+
+/// Returns whether an instance file belongs to a process that is no longer running.
+///
+/// Files whose owner can't be read, e.g. while it is being written, are checked by the process named in the file name, as in `mdown_<version>_<pid>.lock` and `mdown_final_end_<pid>.lock`; files of older versions don't name it at all.
+///
+/// # Arguments
+/// * `path` - The path to the file.
+/// * `name` - The name of the file.
+fn is_stale_instance_file(path: &str, name: &str) -> bool {
+    match read_lock_owner(path) {
+        Some(owner) => is_stale_lock(&owner),
+        None =>
+            match
+                name
+                    .strip_suffix(".lock")
+                    .and_then(|name| name.rsplit_once('_'))
+                    .and_then(|(_name, pid)| pid.parse::<u32>().ok())
+            {
+                Some(pid) => pid != std::process::id() && !is_process_running(pid),
+                None => true,
+            },
+    }
+}
+
+/// Removes instance files of processes that are no longer running from the `.cache` folder.
+fn remove_stale_instance_files() {
+    let entries = match fs::read_dir(".cache") {
+        Ok(entries) => entries,
+        Err(_err) => {
+            return;
+        }
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !name.starts_with("mdown_") || !name.ends_with(".lock") {
+            continue;
+        }
+        let path = entry.path().to_string_lossy().to_string();
+        if is_stale_instance_file(&path, &name) {
+            debug!("removing stale instance file {}", path);
+            match fs::remove_file(&path) {
+                Ok(()) => (),
+                Err(err) => eprintln!("Error: removing file '{}' {}", path, err),
+            }
+        }
+    }
+}
+
+/// Removes lock files of manga, for `app --force-delete`.
+fn remove_lock_files() -> Result<(), MdownError> {
+    let folder = match getter::get_lock_folder() {
+        Ok(path) => path,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    let entries = match fs::read_dir(&folder) {
+        Ok(entries) => entries,
+        Err(err) => {
+            return Err(MdownError::IoError(err, folder));
+        }
+    };
+    let mut removed = 0;
+    for entry in entries.flatten() {
+        match fs::remove_file(entry.path()) {
+            Ok(()) => {
+                removed += 1;
+            }
+            Err(err) => eprintln!("Error: removing file '{}' {}", entry.path().display(), err),
+        }
+    }
+    println!("Removed {} lock file(s)", removed);
+    Ok(())
+}
+
+pub(crate) fn resolve_start() -> Result<String, MdownError> {
+    if *args::ARGS_FORCE_DELETE {
+        match remove_lock_files() {
+            Ok(()) => (),
+            Err(err) => {
+                return Err(err);
+            }
+        }
+        remove_stale_instance_files();
+        match remove_cache() {
+            Ok(()) => (),
+            Err(err) => eprintln!("Error: removing cache {}", err),
+        }
+        exit(0);
+    }
+    remove_stale_instance_files();
+
+    let file_path = getter::get_cache_path(&get_instance_file_name());
+    let owner = match serde_json::to_string(&metadata::LockOwner::current()) {
+        Ok(owner) => owner,
+        Err(err) => {
+            return Err(MdownError::JsonError(err.to_string()));
+        }
+    };
+    // Other instances starting at the same time must not read a half-written owner
+    match write_file_atomic(&file_path, owner.as_bytes()) {
+        Ok(()) => (),
+        Err(err) => {
+            return Err(err);
         }
     }
 
@@ -637,10 +1045,10 @@ pub(crate) fn resolve_start() -> Result<String, MdownError> {
 }
 
 pub(crate) async fn ctrl_handler(file: &str) {
-    if fs::metadata(".cache\\mdown_final_end.lock").is_ok() {
-        match fs::remove_file(".cache\\mdown_final_end.lock") {
+    if fs::metadata(getter::get_cache_path(&get_final_end_file_name())).is_ok() {
+        match fs::remove_file(getter::get_cache_path(&get_final_end_file_name())) {
             Ok(()) => (),
-            Err(err) => eprintln!("Error: removing file mdown_final_end.lock {}", err),
+            Err(err) => eprintln!("Error: removing file {} {}", get_final_end_file_name(), err),
         };
     }
     loop {
@@ -675,7 +1083,7 @@ pub(crate) async fn ctrl_handler(file: &str) {
     delete_dir_if_unfinished(getter::get_folder_name());
     delete_dir();
 
-    if is_directory_empty(".cache\\") {
+    if is_directory_empty(".cache") {
         match remove_dir_all(".cache") {
             Ok(()) => (),
             Err(err) => eprintln!("Error removing .cache, {}", err),
@@ -685,12 +1093,12 @@ pub(crate) async fn ctrl_handler(file: &str) {
 }
 
 pub(crate) fn resolve_final_end() -> bool {
-    if fs::metadata(".cache\\mdown_final_end.lock").is_ok() {
-        match fs::remove_file(".cache\\mdown_final_end.lock") {
+    if fs::metadata(getter::get_cache_path(&get_final_end_file_name())).is_ok() {
+        match fs::remove_file(getter::get_cache_path(&get_final_end_file_name())) {
             Ok(()) => (),
-            Err(err) => eprintln!("Error: removing mdown_final_end.lock {}", err),
+            Err(err) => eprintln!("Error: removing {} {}", get_final_end_file_name(), err),
         }
-        if is_directory_empty(".cache\\") {
+        if is_directory_empty(".cache") {
             match remove_dir_all(".cache") {
                 Ok(()) => (),
                 Err(err) => eprintln!("Error: removing .cache, {}", err),
@@ -706,7 +1114,16 @@ pub(crate) fn delete_dir() {
         for entry in entries.flatten() {
             let path = entry.path();
 
-            if path.is_file() && path.extension().map_or(false, |ext| ext == "lock") {
+            let name = entry.file_name().to_string_lossy().to_string();
+
+            // Instance files of other processes working in the same folder are kept
+            if
+                path.is_file() &&
+                path.extension().map_or(false, |ext| ext == "lock") &&
+                (!name.starts_with("mdown_") ||
+                    name == get_instance_file_name() ||
+                    name == get_final_end_file_name())
+            {
                 match fs::remove_file(&path) {
                     Ok(()) => (),
                     Err(err) => eprintln!("Error: removing file '{:?}' {}", path, err),
@@ -789,7 +1206,7 @@ pub(crate) fn resolve_end(
             .write(true)
             .create(true)
             .truncate(true)
-            .open(".cache\\mdown_final_end.lock")
+            .open(getter::get_cache_path(&get_final_end_file_name()))
     {
         Ok(_file) => (),
         Err(err) => {
@@ -838,18 +1255,12 @@ pub(crate) fn resolve_end(
 }
 
 pub(crate) fn is_directory_empty(path: &str) -> bool {
+    // Files of this process don't count; files of other processes working in the same folder do
+    let own_files = [get_instance_file_name(), get_final_end_file_name()];
     if let Ok(entries) = std::fs::read_dir(path) {
-        let mut count = 0;
-
-        for entry in entries.flatten() {
-            count += 1;
-            if let Some(entry_name) = entry.file_name().to_str() {
-                if entry_name.ends_with("mdown_final_end.lock") {
-                    return true;
-                }
-            }
-        }
-        count <= 1
+        entries
+            .flatten()
+            .all(|entry| own_files.contains(&entry.file_name().to_string_lossy().to_string()))
     } else {
         false
     }
@@ -862,47 +1273,74 @@ pub(crate) struct FileName {
     pub(crate) chapter_num: String,
     pub(crate) title: String,
     pub(crate) folder: String,
+    pub(crate) group: String,
+    pub(crate) lang: String,
+    pub(crate) id: String,
 }
 
 impl FileName {
-    pub(crate) fn get_folder_name(&self) -> String {
-        if !self.title.is_empty() {
-            process_filename(
-                &format!(
-                    "{} - {}Ch.{} - {}",
-                    self.manga_name,
-                    self.vol,
-                    self.chapter_num,
-                    self.title
-                )
-            )
-        } else {
-            process_filename(&format!("{} - {}Ch.{}", self.manga_name, self.vol, self.chapter_num))
+    /// Fills the name template in; returns the subfolders followed by the name of the chapter.
+    fn render(&self) -> Vec<String> {
+        let values = HashMap::from([
+            ("manga", self.manga_name.clone()),
+            ("volume", self.vol.trim().trim_start_matches("Vol.").to_string()),
+            ("chapter", self.chapter_num.clone()),
+            ("title", self.title.clone()),
+            ("group", self.group.clone()),
+            ("lang", self.lang.clone()),
+            ("id", self.id.clone()),
+        ]);
+        let mut components = template::get_name_template().render(&values);
+        if components.is_empty() {
+            components.push(process_filename(&self.chapter_num));
         }
+        components
+    }
+    pub(crate) fn get_folder_name(&self) -> String {
+        self.render().pop().unwrap_or_default()
+    }
+    pub(crate) fn get_subfolder(&self) -> String {
+        let mut components = self.render();
+        components.pop();
+        components.iter().collect::<PathBuf>().to_string_lossy().to_string()
+    }
+    fn get_file(&self) -> PathBuf {
+        Path::new(&self.get_subfolder()).join(format!("{}.cbz", self.get_folder_name()))
     }
     pub(crate) fn get_file_w_folder(&self) -> String {
-        if args::ARGS.lock().update {
-            format!("{}.cbz", process_filename(&self.get_folder_name()))
-        } else {
-            format!("{}\\{}.cbz", self.folder, process_filename(&self.get_folder_name()))
-        }
+        Path::new(&self.folder).join(self.get_file()).to_string_lossy().to_string()
     }
     pub(crate) fn get_file_w_folder_w_cwd(&self) -> String {
-        format!(
-            "{}{}\\{}.cbz",
-            *args::ARGS_CWD,
-            self.folder,
-            process_filename(&self.get_folder_name())
-        )
+        Path::new(&*args::ARGS_CWD)
+            .join(&self.folder)
+            .join(self.get_file())
+            .to_string_lossy()
+            .to_string()
+    }
+    pub(crate) fn get_volume_name(&self) -> String {
+        process_filename(&format!("{} - {}", self.manga_name, self.vol.trim()))
+    }
+    pub(crate) fn get_volume_w_folder(&self) -> String {
+        Path::new(&self.folder)
+            .join(format!("{}.cbz", self.get_volume_name()))
+            .to_string_lossy()
+            .to_string()
+    }
+    pub(crate) fn get_volume_w_folder_w_cwd(&self) -> String {
+        Path::new(&*args::ARGS_CWD)
+            .join(&self.folder)
+            .join(format!("{}.cbz", self.get_volume_name()))
+            .to_string_lossy()
+            .to_string()
     }
     pub(crate) fn get_folder_w_end(&self) -> String {
-        format!(".cache\\{}\\", self.get_folder_name())
+        format!("{}{}", self.get_folder(), MAIN_SEPARATOR)
     }
     pub(crate) fn get_folder(&self) -> String {
-        format!(".cache\\{}", self.get_folder_name())
+        getter::get_cache_path(&self.get_folder_name())
     }
     pub(crate) fn get_lock(&self) -> String {
-        format!(".cache\\{}.lock", self.get_folder_name())
+        getter::get_cache_path(&format!("{}.lock", self.get_folder_name()))
     }
 }
 
@@ -1063,3 +1501,147 @@ fn should_return_empty_string() {
     let result = process_filename(filename);
     assert_eq!(result, "");
 }
+
+// Pads the whole part of a chapter number so chapters sort correctly by name
+#[test]
+fn test_pad_chapter() {
+    assert_eq!(pad_chapter("3"), "0003");
+    assert_eq!(pad_chapter("12.5"), "0012.5");
+    assert_eq!(pad_chapter("1042"), "1042");
+}
+
+// Leaves chapter numbers that are not numeric untouched
+#[test]
+fn test_pad_chapter_not_numeric() {
+    assert_eq!(pad_chapter("Oneshot"), "Oneshot");
+    assert_eq!(pad_chapter(""), "");
+}
+
+// Sorts numbers in names of pages by their value
+#[cfg(feature = "transcode")]
+#[test]
+fn test_compare_names() {
+    let mut names = vec!["Test - 10.jpg", "Test - 2_1.jpg", "Test - 1.jpg", "Test - 2_10.jpg", "Test - 2_2.jpg"];
+    names.sort_by(|a, b| compare_names(a, b));
+    assert_eq!(names, ["Test - 1.jpg", "Test - 2_1.jpg", "Test - 2_2.jpg", "Test - 2_10.jpg", "Test - 10.jpg"]);
+    assert_eq!(compare_names("Ch.2/Test - 9.png", "Ch.10/Test - 1.png"), Ordering::Less);
+    assert_eq!(compare_names("a", "a1"), Ordering::Less);
+    assert_eq!(compare_names("1", "01"), Ordering::Greater);
+}
+
+// Builds chapter paths from path components instead of separators of one platform
+#[test]
+fn test_file_name_paths() {
+    let filename = FileName {
+        manga_name: String::from("Manga"),
+        vol: String::new(),
+        chapter_num: String::from("1"),
+        title: String::new(),
+        folder: String::from("library"),
+        group: String::new(),
+        lang: String::from("en"),
+        id: String::new(),
+    };
+    let folder = filename.get_folder();
+    assert_eq!(Path::new(&folder), Path::new(".cache").join("Manga - Ch.1"));
+    assert_eq!(Path::new(&filename.get_lock()), Path::new(".cache").join("Manga - Ch.1.lock"));
+    assert_eq!(filename.get_folder_w_end(), format!("{}{}", folder, MAIN_SEPARATOR));
+    assert!(
+        Path::new(&filename.get_file_w_folder_w_cwd()).ends_with(
+            Path::new("library").join("Manga - Ch.1.cbz")
+        )
+    );
+}
+
+// Replaces a lock file of a process that is no longer running with one naming the current process
+#[test]
+fn test_acquire_lock_stale() {
+    let path = std::env::temp_dir().join(format!("mdown_test_stale_{}.lock", std::process::id()));
+    let path = path.to_string_lossy().to_string();
+    let owner = metadata::LockOwner {
+        pid: u32::MAX - 1,
+        host: get_host_name(),
+        started: String::from("2020-01-01T00:00:00+00:00"),
+    };
+    fs::write(&path, serde_json::to_string(&owner).unwrap()).unwrap();
+    let state = acquire_lock(&path).unwrap();
+    assert!(matches!(state, LockState::Acquired(_)));
+    assert_eq!(read_lock_owner(&path).unwrap().pid, std::process::id());
+    drop(state);
+    assert!(!Path::new(&path).exists());
+}
+
+// Keeps lock files of running processes and of other computers
+#[cfg(unix)]
+#[test]
+fn test_acquire_lock_live() {
+    let path = std::env::temp_dir().join(format!("mdown_test_live_{}.lock", std::process::id()));
+    let path = path.to_string_lossy().to_string();
+    for owner in [
+        metadata::LockOwner {
+            pid: std::os::unix::process::parent_id(),
+            host: get_host_name(),
+            started: get_process_started(std::os::unix::process::parent_id()).unwrap().to_rfc3339(),
+        },
+        metadata::LockOwner {
+            pid: u32::MAX - 1,
+            host: format!("{}-other", get_host_name()),
+            started: String::from("2020-01-01T00:00:00+00:00"),
+        },
+    ] {
+        fs::write(&path, serde_json::to_string(&owner).unwrap()).unwrap();
+        assert_eq!(acquire_lock(&path).unwrap(), LockState::Held(owner));
+    }
+    fs::remove_file(&path).unwrap();
+}
+
+// Replaces a lock file naming a running process that started after the lock file was created
+#[cfg(unix)]
+#[test]
+fn test_acquire_lock_reused_pid() {
+    let started = get_process_started(std::process::id()).unwrap();
+    assert!(started <= Utc::now());
+    assert!(Utc::now() - started < chrono::Duration::hours(1));
+
+    let path = std::env::temp_dir().join(format!("mdown_test_reused_{}.lock", std::process::id()));
+    let path = path.to_string_lossy().to_string();
+    let owner = metadata::LockOwner {
+        pid: std::os::unix::process::parent_id(),
+        host: get_host_name(),
+        started: String::from("2020-01-01T00:00:00+00:00"),
+    };
+    fs::write(&path, serde_json::to_string(&owner).unwrap()).unwrap();
+    let state = acquire_lock(&path).unwrap();
+    assert!(matches!(state, LockState::Acquired(_)));
+    assert_eq!(read_lock_owner(&path).unwrap(), metadata::LockOwner::current());
+    drop(state);
+    assert!(!Path::new(&path).exists());
+}
+
+// Replaces the content of a file without leaving the temporary file behind
+#[test]
+fn test_write_file_atomic() {
+    let path = std::env::temp_dir().join(format!("mdown_test_atomic_{}.json", std::process::id()));
+    let path = path.to_string_lossy().to_string();
+    write_file_atomic(&path, b"{}").unwrap();
+    write_file_atomic(&path, b"{\"a\": 1}").unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "{\"a\": 1}");
+    assert!(!Path::new(&format!("{}.{}.tmp", path, std::process::id())).exists());
+    fs::remove_file(&path).unwrap();
+}
+
+// Keeps instance files being written by running processes and removes those of processes that ended
+#[cfg(unix)]
+#[test]
+fn test_is_stale_instance_file() {
+    let path = std::env::temp_dir().join(format!("mdown_test_instance_{}.lock", std::process::id()));
+    let path = path.to_string_lossy().to_string();
+    fs::write(&path, "").unwrap();
+    let parent = std::os::unix::process::parent_id();
+    assert!(!is_stale_instance_file(&path, &format!("mdown_0.15.0_{}.lock", parent)));
+    assert!(!is_stale_instance_file(&path, &format!("mdown_0.15.0_{}.lock", std::process::id())));
+    assert!(!is_stale_instance_file(&path, &format!("mdown_final_end_{}.lock", parent)));
+    assert!(is_stale_instance_file(&path, &format!("mdown_0.15.0_{}.lock", u32::MAX)));
+    assert!(is_stale_instance_file(&path, "mdown_0.15.0.lock"));
+    fs::remove_file(&path).unwrap();
+}
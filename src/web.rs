@@ -5,6 +5,7 @@ use std::{ collections::HashMap, fs::File, io::{ Read, Write }, net::TcpListener
 
 use crate::{
     args,
+    ctl,
     db,
     error::MdownError,
     getter,
@@ -182,6 +183,8 @@ async fn handle_client(mut stream: std::net::TcpStream) -> Result<(), MdownError
                 };
                 let decoded_url = decode(&manga_url);
 
+                // Manga queued with `ctl queue add` are downloaded one at a time with these
+                let download = ctl::DOWNLOAD.lock().await;
                 *resolute::HANDLE_ID.lock() = handle_id.clone();
                 let json = match resolve_web_download(&decoded_url).await {
                     Ok(response) =>
@@ -198,6 +201,7 @@ async fn handle_client(mut stream: std::net::TcpStream) -> Result<(), MdownError
 
                 log_end(handle_id);
                 *resolute::HANDLE_ID.lock() = String::new().into_boxed_str();
+                drop(download);
                 response = json;
             } else {
                 response = String::from(
@@ -494,5 +498,6 @@ pub(crate) async fn start() -> Result<(), MdownError> {
             );
         }
     }
+    ctl::serve();
     web().await
 }
@@ -1,6 +1,6 @@
-use std::{ fs::File, io::{ Read, Seek, Write }, path::Path };
+use std::{ collections::BTreeMap, fs::{ self, File }, io::{ self, Read, Seek, Write }, path::Path };
 use walkdir::{ DirEntry, WalkDir };
-use zip::{ result::ZipError, write::FileOptions, ZipArchive };
+use zip::{ result::ZipError, write::FileOptions, CompressionMethod, ZipArchive };
 
 use crate::{
     args,
@@ -12,6 +12,66 @@ use crate::{
     utils::{ self, progress_bar_preparation },
 };
 
+/// Parses a compression method and level.
+///
+/// # Parameters
+/// - `method: &str`: The compression method; one of `stored`, `deflate` or `zstd`.
+/// - `level: Option<i32>`: The compression level; `None` selects the default level of the method.
+///
+/// # Returns
+/// `Result<(CompressionMethod, Option<i32>), MdownError>`: Returns the compression method and level, or an `MdownError` if the method is unknown or the level is out of range.
+///
+/// # Errors
+/// - Returns `MdownError::ConversionError` if the method is unknown, if the level is outside `0 - 9` for deflate or `-7 - 22` for zstd, or if a level is supplied for stored files.
+pub(crate) fn get_compression(
+    method: &str,
+    level: Option<i32>
+) -> Result<(CompressionMethod, Option<i32>), error::MdownError> {
+    let (method, range) = match method {
+        "stored" => (CompressionMethod::Stored, None),
+        "deflate" => (CompressionMethod::Deflated, Some(0..=9)),
+        "zstd" => (CompressionMethod::Zstd, Some(-7..=22)),
+        method => {
+            return Err(
+                error::MdownError::ConversionError(format!("Unknown compression method '{}'", method))
+            );
+        }
+    };
+    match (level, range) {
+        (None, _) => Ok((method, None)),
+        (Some(level), Some(range)) if range.contains(&level) => Ok((method, Some(level))),
+        (Some(level), Some(range)) =>
+            Err(
+                error::MdownError::ConversionError(
+                    format!(
+                        "Compression level {} is out of range {} - {}",
+                        level,
+                        range.start(),
+                        range.end()
+                    )
+                )
+            ),
+        (Some(level), None) =>
+            Err(
+                error::MdownError::ConversionError(
+                    format!("Compression level {} can't be used with stored files", level)
+                )
+            ),
+    }
+}
+
+/// Returns the compression method and level selected by the user or stored in settings.
+///
+/// # Returns
+/// `Result<(CompressionMethod, Option<i32>), MdownError>`: Returns the compression method and level, or an `MdownError` if they are invalid.
+fn get_compression_from_args() -> Result<(CompressionMethod, Option<i32>), error::MdownError> {
+    let args = args::ARGS.lock();
+    match args.compression.as_str() {
+        "" => get_compression("stored", args.compression_level),
+        method => get_compression(method, args.compression_level),
+    }
+}
+
 /// Compresses a directory and its contents into a ZIP file.
 ///
 /// # Parameters
@@ -33,7 +93,12 @@ fn zip_dir<T>(
 ) -> Result<(), error::MdownError>
     where T: Write + Seek
 {
-    let method = zip::CompressionMethod::Stored;
+    let (method, level) = match get_compression_from_args() {
+        Ok(compression) => compression,
+        Err(err) => {
+            return Err(err);
+        }
+    };
     let walkdir = WalkDir::new(prefix);
     let dir_entries_vec: Vec<DirEntry> = walkdir
         .into_iter()
@@ -51,7 +116,10 @@ fn zip_dir<T>(
 
     // Initialize the ZIP writer and file options.
     let mut zip = zip::ZipWriter::new(writer);
-    let options = FileOptions::default().compression_method(method).unix_permissions(0o755);
+    let options = FileOptions::default()
+        .compression_method(method)
+        .compression_level(level)
+        .unix_permissions(0o755);
 
     let mut buffer = Vec::new();
     for (times, entry) in it.enumerate() {
@@ -192,6 +260,315 @@ pub(crate) fn to_zip(src_dir: &str, dst_file: &str) {
     }
 }
 
+/// Recompresses a ZIP archive in place with the current compression settings.
+///
+/// The archive is written next to `zip_file_path` first and renamed over it once it is complete,
+/// so an interrupted run never leaves a truncated archive behind.
+///
+/// # Parameters
+/// - `zip_file_path: &str`: The path to the ZIP file.
+///
+/// # Returns
+/// `Result<(u64, u64), MdownError>`: Returns the size of the archive before and after recompression, or an `MdownError` if an error occurs.
+///
+/// # Errors
+/// - Returns `MdownError::IoError` if the archive cannot be read, written or renamed.
+/// - Returns `MdownError::ZipError` if the archive is corrupted.
+/// - Returns `MdownError::ConversionError` if the compression settings are invalid.
+pub(crate) fn recompress(zip_file_path: &str) -> Result<(u64, u64), error::MdownError> {
+    let (method, level) = match get_compression_from_args() {
+        Ok(compression) => compression,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    let options = FileOptions::default()
+        .compression_method(method)
+        .compression_level(level)
+        .unix_permissions(0o755);
+    let mut archive = match open_archive(zip_file_path) {
+        Ok(archive) => archive,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+
+    let tmp_file = format!("{}.tmp", zip_file_path);
+    let file = match File::create(&tmp_file) {
+        Ok(file) => file,
+        Err(err) => {
+            return Err(error::MdownError::IoError(err, tmp_file));
+        }
+    };
+    let mut zip = zip::ZipWriter::new(file);
+    for i in 0..archive.len() {
+        let mut entry = match archive.by_index(i) {
+            Ok(entry) => entry,
+            Err(err) => {
+                return Err(error::MdownError::ZipError(err));
+            }
+        };
+        let name = entry.name().to_string();
+        let options = options.last_modified_time(entry.last_modified());
+        if entry.is_dir() {
+            match zip.add_directory(name, options) {
+                Ok(()) => (),
+                Err(err) => {
+                    return Err(error::MdownError::ZipError(err));
+                }
+            }
+            continue;
+        }
+        match zip.start_file(name.clone(), options) {
+            Ok(()) => (),
+            Err(err) => {
+                return Err(error::MdownError::ZipError(err));
+            }
+        }
+        match io::copy(&mut entry, &mut zip) {
+            Ok(_size) => (),
+            Err(err) => {
+                return Err(error::MdownError::IoError(err, name));
+            }
+        }
+    }
+    match zip.finish() {
+        Ok(_writer) => (),
+        Err(err) => {
+            return Err(error::MdownError::ZipError(err));
+        }
+    }
+    drop(archive);
+
+    let before = match fs::metadata(zip_file_path) {
+        Ok(metadata) => metadata.len(),
+        Err(err) => {
+            return Err(error::MdownError::IoError(err, zip_file_path.to_string()));
+        }
+    };
+    let after = match fs::metadata(&tmp_file) {
+        Ok(metadata) => metadata.len(),
+        Err(err) => {
+            return Err(error::MdownError::IoError(err, tmp_file));
+        }
+    };
+    match fs::rename(&tmp_file, zip_file_path) {
+        Ok(()) => Ok((before, after)),
+        Err(err) => Err(error::MdownError::IoError(err, zip_file_path.to_string())),
+    }
+}
+
+/// Bundles chapter archives into a single volume archive.
+///
+/// Every chapter archive is placed into its own subfolder named after the zero-padded chapter number
+/// (e.g. `Ch.0003/`), so readers list the chapters of a volume in order. If `dst_file` already exists,
+/// its content is kept and only the subfolders of the supplied chapters are replaced, which is what
+/// allows `database --update` to add a new chapter to an already bundled volume.
+///
+/// Entries are copied without being decompressed, and the new archive is written next to `dst_file`
+/// first and renamed over it once it is complete.
+///
+/// # Parameters
+/// - `chapter_files: &[String]`: Paths to the chapter `.cbz` files to be bundled.
+/// - `dst_file: &str`: The destination volume archive path.
+///
+/// # Returns
+/// `Result<(), MdownError>`: Returns `Ok(())` if the volume archive was written, or an `MdownError` if an error occurs.
+///
+/// # Errors
+/// - Returns `MdownError::IoError` if the archives cannot be opened, created or renamed.
+/// - Returns `MdownError::ZipError` if any of the archives is corrupted.
+/// - Returns `MdownError::NotFoundError` if a chapter archive does not contain `_metadata`.
+pub(crate) fn to_volume(chapter_files: &[String], dst_file: &str) -> Result<(), error::MdownError> {
+    let mut archives = Vec::new();
+    let mut entries = BTreeMap::new();
+    let mut prefixes = Vec::new();
+
+    for chapter_file in chapter_files.iter() {
+        let chapter = match extract_file_from_zip(chapter_file, "_metadata") {
+            Ok(metadata) => metadata.chapter,
+            Err(err) => {
+                return Err(err);
+            }
+        };
+        let prefix = format!("Ch.{}/", utils::pad_chapter(&chapter));
+        let mut archive = match open_archive(chapter_file) {
+            Ok(archive) => archive,
+            Err(err) => {
+                return Err(err);
+            }
+        };
+        let names = match entry_names(&mut archive) {
+            Ok(names) => names,
+            Err(err) => {
+                return Err(err);
+            }
+        };
+        for (index, name) in names.iter().enumerate() {
+            if !name.ends_with('/') {
+                entries.insert(format!("{}{}", prefix, name), (archives.len(), index));
+            }
+        }
+        archives.push(archive);
+        prefixes.push(prefix);
+    }
+
+    // Keep everything from the previous volume archive that is not being replaced.
+    if Path::new(dst_file).is_file() {
+        let mut archive = match open_archive(dst_file) {
+            Ok(archive) => archive,
+            Err(err) => {
+                return Err(err);
+            }
+        };
+        let names = match entry_names(&mut archive) {
+            Ok(names) => names,
+            Err(err) => {
+                return Err(err);
+            }
+        };
+        for (index, name) in names.iter().enumerate() {
+            if
+                !name.ends_with('/') &&
+                !prefixes.iter().any(|prefix| name.starts_with(prefix.as_str()))
+            {
+                entries.insert(name.to_string(), (archives.len(), index));
+            }
+        }
+        archives.push(archive);
+    }
+
+    let tmp_file = format!("{}.tmp", dst_file);
+    let file = match File::create(&tmp_file) {
+        Ok(file) => file,
+        Err(err) => {
+            return Err(error::MdownError::IoError(err, tmp_file));
+        }
+    };
+    let mut zip = zip::ZipWriter::new(file);
+    for (name, (archive, index)) in entries.iter() {
+        let entry = match archives[*archive].by_index_raw(*index) {
+            Ok(entry) => entry,
+            Err(err) => {
+                return Err(error::MdownError::ZipError(err));
+            }
+        };
+        match zip.raw_copy_file_rename(entry, name) {
+            Ok(()) => (),
+            Err(err) => {
+                return Err(error::MdownError::ZipError(err));
+            }
+        }
+    }
+    match zip.finish() {
+        Ok(_writer) => (),
+        Err(err) => {
+            return Err(error::MdownError::ZipError(err));
+        }
+    }
+    drop(archives);
+
+    match fs::rename(&tmp_file, dst_file) {
+        Ok(()) => Ok(()),
+        Err(err) => Err(error::MdownError::IoError(err, dst_file.to_string())),
+    }
+}
+
+/// Opens a ZIP archive for reading.
+///
+/// # Parameters
+/// - `zip_file_path: &str`: The path to the ZIP file.
+///
+/// # Returns
+/// `Result<ZipArchive<File>, MdownError>`: Returns the opened archive, or an `MdownError` if the file cannot be opened or is not a valid ZIP archive.
+fn open_archive(zip_file_path: &str) -> Result<ZipArchive<File>, error::MdownError> {
+    let zip_file = match File::open(zip_file_path) {
+        Ok(zip_file) => zip_file,
+        Err(err) => {
+            return Err(error::MdownError::IoError(err, zip_file_path.to_string()));
+        }
+    };
+    match ZipArchive::new(zip_file) {
+        Ok(archive) => Ok(archive),
+        Err(err) => Err(error::MdownError::ZipError(err)),
+    }
+}
+
+/// Lists the entry names of a ZIP archive in the order they are stored.
+///
+/// # Parameters
+/// - `archive: &mut ZipArchive<File>`: The opened archive.
+///
+/// # Returns
+/// `Result<Vec<String>, MdownError>`: Returns the entry names, or an `MdownError` if an entry cannot be read.
+fn entry_names(archive: &mut ZipArchive<File>) -> Result<Vec<String>, error::MdownError> {
+    let mut names = Vec::new();
+    for i in 0..archive.len() {
+        match archive.by_index_raw(i) {
+            Ok(file) => names.push(file.name().to_string()),
+            Err(err) => {
+                return Err(error::MdownError::ZipError(err));
+            }
+        }
+    }
+    Ok(names)
+}
+
+/// Extracts the metadata of every chapter bundled in a volume archive.
+///
+/// # Parameters
+/// - `zip_file_path: &str`: The path to the volume archive.
+/// - `metadata_file_name: &str`: The name of the metadata file inside each chapter subfolder.
+///
+/// # Returns
+/// `Result<Vec<metadata::ChapterMetadataIn>, MdownError>`: Returns the metadata of all bundled chapters (empty for chapter archives), or an `MdownError` if an error occurs.
+pub(crate) fn extract_files_from_zip(
+    zip_file_path: &str,
+    metadata_file_name: &str
+) -> Result<Vec<metadata::ChapterMetadataIn>, error::MdownError> {
+    let mut archive = match open_archive(zip_file_path) {
+        Ok(archive) => archive,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    let suffix = format!("/{}", metadata_file_name);
+    let names = match entry_names(&mut archive) {
+        Ok(names) => names,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+
+    let mut chapters = Vec::new();
+    for name in names.iter().filter(|name| name.ends_with(&suffix)) {
+        let mut metadata_content = String::new();
+        match archive.by_name(name) {
+            Ok(mut file) => {
+                if let Err(err) = file.read_to_string(&mut metadata_content) {
+                    return Err(error::MdownError::IoError(err, name.to_string()));
+                }
+            }
+            Err(err) => {
+                return Err(error::MdownError::ZipError(err));
+            }
+        }
+        let json_value = match utils::get_json(&metadata_content) {
+            Ok(value) => value,
+            Err(err) => {
+                return Err(err);
+            }
+        };
+        match serde_json::from_value::<metadata::ChapterMetadataIn>(json_value) {
+            Ok(obj) => chapters.push(obj),
+            Err(err) => {
+                return Err(error::MdownError::JsonError(err.to_string()));
+            }
+        }
+    }
+    Ok(chapters)
+}
+
 /// Extracts a specific file from a ZIP archive.
 ///
 /// # Parameters
@@ -289,7 +666,7 @@ pub(crate) fn extract_image_from_zip(zip_file_path: &str) -> Result<Vec<u8>, err
         };
         if let Some(file_name) = file.name().to_lowercase().split('.').last() {
             match file_name {
-                "jpg" | "jpeg" | "png" | "gif" | "bmp" | "webp" => {
+                "jpg" | "jpeg" | "png" | "gif" | "bmp" | "webp" | "avif" => {
                     let mut content = Vec::new();
                     if let Err(err) = file.read_to_end(&mut content) {
                         return Err(error::MdownError::IoError(err, file.name().to_string()));
@@ -306,6 +683,87 @@ pub(crate) fn extract_image_from_zip(zip_file_path: &str) -> Result<Vec<u8>, err
     Err(error::MdownError::NotFoundError("File not found in the zip archive".to_owned()))
 }
 
+/// Extracts a page of a chapter together with the metadata of its chapter.
+///
+/// Pages are numbered from 1 in name order, so volume archives are numbered across all bundled chapters.
+///
+/// # Parameters
+/// - `zip_file_path: &str`: The path to the chapter or volume archive.
+/// - `page: usize`: The number of the page.
+/// - `extensions: &[&str]`: The extensions of files that are treated as pages.
+///
+/// # Returns
+/// `Result<(String, Vec<u8>, metadata::ChapterMetadataIn), MdownError>`: Returns the name and content of the page and the metadata of its chapter, or an `MdownError` if the page or metadata does not exist.
+#[cfg(feature = "transcode")]
+pub(crate) fn extract_page_from_zip(
+    zip_file_path: &str,
+    page: usize,
+    extensions: &[&str]
+) -> Result<(String, Vec<u8>, metadata::ChapterMetadataIn), error::MdownError> {
+    let mut archive = match open_archive(zip_file_path) {
+        Ok(archive) => archive,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    let mut names = match entry_names(&mut archive) {
+        Ok(names) => names,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+    names.retain(|name| {
+        match Path::new(name).extension().and_then(|extension| extension.to_str()) {
+            Some(extension) => extensions.contains(&extension.to_lowercase().as_str()),
+            None => false,
+        }
+    });
+    names.sort_by(|a, b| utils::compare_names(a, b));
+    let name = match page.checked_sub(1).and_then(|index| names.get(index)) {
+        Some(name) => name.clone(),
+        None => {
+            return Err(
+                error::MdownError::NotFoundError(
+                    format!("page {} in {} ({} pages)", page, zip_file_path, names.len())
+                )
+            );
+        }
+    };
+
+    let mut content = Vec::new();
+    match archive.by_name(&name) {
+        Ok(mut file) => {
+            if let Err(err) = file.read_to_end(&mut content) {
+                return Err(error::MdownError::IoError(err, name));
+            }
+        }
+        Err(err) => {
+            return Err(error::MdownError::ZipError(err));
+        }
+    }
+
+    // Pages of bundled chapters have the metadata next to them in the chapter subfolder
+    let metadata_name = match name.rsplit_once('/') {
+        Some((folder, _page)) => format!("{}/_metadata", folder),
+        None => String::from("_metadata"),
+    };
+    let mut metadata_content = String::new();
+    match archive.by_name(&metadata_name) {
+        Ok(mut file) => {
+            if let Err(err) = file.read_to_string(&mut metadata_content) {
+                return Err(error::MdownError::IoError(err, metadata_name));
+            }
+        }
+        Err(err) => {
+            return Err(error::MdownError::ZipError(err));
+        }
+    }
+    match serde_json::from_str::<metadata::ChapterMetadataIn>(&metadata_content) {
+        Ok(chapter_metadata) => Ok((name, content, chapter_metadata)),
+        Err(err) => Err(error::MdownError::JsonError(err.to_string())),
+    }
+}
+
 /// Extracts multiple images from a set of ZIP files, selecting up to 10 images randomly.
 ///
 /// # Returns
@@ -345,7 +803,7 @@ pub(crate) fn extract_images_from_zip() -> Result<Vec<Vec<u8>>, error::MdownErro
                 };
                 if let Some(file_name) = file.name().to_lowercase().split('.').last() {
                     match file_name {
-                        "jpg" | "jpeg" | "png" | "gif" | "bmp" | "webp" => {
+                        "jpg" | "jpeg" | "png" | "gif" | "bmp" | "webp" | "avif" => {
                             let mut content = Vec::new();
                             if let Err(err) = file.read_to_end(&mut content) {
                                 return Err(
@@ -368,3 +826,112 @@ pub(crate) fn extract_images_from_zip() -> Result<Vec<Vec<u8>>, error::MdownErro
     images.truncate(10);
     Ok(images)
 }
+
+#[cfg(test)]
+pub(crate) fn write_test_chapter(path: &Path, id: &str, chapter: &str, pages: &[&str]) {
+    let file = File::create(path).unwrap();
+    let mut zip = zip::ZipWriter::new(file);
+    let metadata = metadata::ChapterMetadataIn {
+        name: String::from("Manga"),
+        id: id.to_string(),
+        manga_id: String::from("manga"),
+        saver: false,
+        title: String::new(),
+        pages: pages.len().to_string(),
+        chapter: chapter.to_string(),
+        volume: String::from("Vol.1 "),
+        scanlation: metadata::ScanlationMetadata {
+            name: String::from("null"),
+            website: String::from("null"),
+        },
+        transform: None,
+        layout: None,
+        removed_pages: vec![],
+    };
+    zip.start_file("_metadata", FileOptions::default()).unwrap();
+    zip.write_all(serde_json::to_string(&metadata).unwrap().as_bytes()).unwrap();
+    for page in pages.iter() {
+        zip.start_file(*page, FileOptions::default()).unwrap();
+        zip.write_all(page.as_bytes()).unwrap();
+    }
+    zip.finish().unwrap();
+}
+
+// Bundles chapters into ordered subfolders and replaces only the re-bundled chapter on rebuild
+#[test]
+fn test_to_volume_rebuild() {
+    let dir = std::env::temp_dir().join(format!("mdown_to_volume_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let chapter_2 = dir.join("Manga - Vol.1 Ch.2.cbz");
+    let chapter_10 = dir.join("Manga - Vol.1 Ch.10.cbz");
+    let volume = dir.join("Manga - Vol.1.cbz");
+    write_test_chapter(&chapter_2, "a", "2", &["1.jpg", "2.jpg"]);
+    write_test_chapter(&chapter_10, "b", "10", &["1.jpg"]);
+
+    let chapters = vec![
+        chapter_10.to_string_lossy().to_string(),
+        chapter_2.to_string_lossy().to_string()
+    ];
+    to_volume(&chapters, &volume.to_string_lossy()).unwrap();
+    let names = entry_names(&mut open_archive(&volume.to_string_lossy()).unwrap()).unwrap();
+    assert_eq!(names, vec![
+        "Ch.0002/1.jpg",
+        "Ch.0002/2.jpg",
+        "Ch.0002/_metadata",
+        "Ch.0010/1.jpg",
+        "Ch.0010/_metadata"
+    ]);
+
+    write_test_chapter(&chapter_2, "c", "2", &["1.jpg"]);
+    to_volume(&[chapter_2.to_string_lossy().to_string()], &volume.to_string_lossy()).unwrap();
+    let bundled = extract_files_from_zip(&volume.to_string_lossy(), "_metadata").unwrap();
+    let ids: Vec<String> = bundled
+        .into_iter()
+        .map(|chapter| chapter.id)
+        .collect();
+    assert_eq!(ids, vec!["c", "b"]);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+// Counts pages by their number, so page 10 comes after page 9 and not after page 1
+#[cfg(feature = "transcode")]
+#[test]
+fn test_extract_page_from_zip_order() {
+    let dir = std::env::temp_dir().join(format!("mdown_page_order_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let chapter = dir.join("Manga - Vol.1 Ch.1.cbz");
+    let names: Vec<String> = (1..=12).map(|page| format!("Manga - {}.jpg", page)).collect();
+    let pages: Vec<&str> = names.iter().map(String::as_str).collect();
+    write_test_chapter(&chapter, "a", "1", &pages);
+
+    for page in [2, 9, 10, 12] {
+        let (name, content, _metadata) = extract_page_from_zip(
+            &chapter.to_string_lossy(),
+            page,
+            &["jpg"]
+        ).unwrap();
+        assert_eq!(name, format!("Manga - {}.jpg", page));
+        assert_eq!(content, name.as_bytes());
+    }
+    assert!(extract_page_from_zip(&chapter.to_string_lossy(), 13, &["jpg"]).is_err());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+// Accepts levels inside the range of each compression method
+#[test]
+fn test_get_compression_valid() {
+    assert_eq!(get_compression("stored", None).unwrap(), (CompressionMethod::Stored, None));
+    assert_eq!(get_compression("deflate", Some(9)).unwrap(), (CompressionMethod::Deflated, Some(9)));
+    assert_eq!(get_compression("zstd", Some(-7)).unwrap(), (CompressionMethod::Zstd, Some(-7)));
+}
+
+// Rejects unknown methods and levels outside the range of the method
+#[test]
+fn test_get_compression_invalid() {
+    assert!(get_compression("bzip2", None).is_err());
+    assert!(get_compression("deflate", Some(10)).is_err());
+    assert!(get_compression("zstd", Some(23)).is_err());
+    assert!(get_compression("stored", Some(1)).is_err());
+}